@@ -0,0 +1,127 @@
+#ifndef WMINSPECT_H
+#define WMINSPECT_H
+
+/* generated by cbindgen from src/wm/ffi.rs, do not edit */
+
+#include <stdbool.h>
+#include <stdint.h>
+#include <stddef.h>
+
+// Opaque connection to the X server along with the cached windows.
+typedef struct WmiContext WmiContext;
+
+// Opaque compiled filter rule.
+typedef struct WmiFilter WmiFilter;
+
+typedef struct sqlite3 sqlite3;
+
+typedef struct sqlite3_stmt sqlite3_stmt;
+
+// Plain copy of a window record, `name` is NUL terminated.
+typedef struct WmiWindow {
+  uint32_t id;
+  int16_t x;
+  int16_t y;
+  uint16_t width;
+  uint16_t height;
+  bool mapped;
+  bool override_redirect;
+  bool xwayland;
+  // when returned by `wmi_context_window`, valid until next refresh
+  const char *name;
+} WmiWindow;
+
+// Parse `rule` (see `--show-grammar`), return NULL if `rule` is malformed.
+//
+// # Safety
+// `rule` must be NULL or a NUL terminated string.
+struct WmiFilter *wmi_filter_parse(const char *rule);
+
+// Test `w` against the filter, true means `w` is kept.
+//
+// # Safety
+// `filter` must come from `wmi_filter_parse`, `w.name` must be NULL or
+// a NUL terminated string.
+bool wmi_filter_apply(const struct WmiFilter *filter, const struct WmiWindow *w);
+
+// # Safety
+// `filter` must be NULL or come from `wmi_filter_parse`.
+void wmi_filter_free(struct WmiFilter *filter);
+
+// Connect to `display` (NULL for $DISPLAY), windows are filtered by `filter`
+// which is consumed (NULL for no filtering). Return NULL on failure.
+//
+// # Safety
+// `display` must be NULL or a NUL terminated string, `filter` must be NULL
+// or come from `wmi_filter_parse`.
+struct WmiContext *wmi_context_new(const char *display, struct WmiFilter *filter);
+
+// Re-collect windows from the server, return the number of filtered windows
+// or -1 on failure.
+//
+// # Safety
+// `ctx` must come from `wmi_context_new`.
+ptrdiff_t wmi_context_refresh(struct WmiContext *ctx);
+
+// Fill `out` with the `idx`th filtered window in stacking order (bottom -> top)
+// as of the last refresh, return false if out of range.
+//
+// # Safety
+// `ctx` must come from `wmi_context_new`, `out` must be writable.
+bool wmi_context_window(const struct WmiContext *ctx, size_t idx, struct WmiWindow *out);
+
+// # Safety
+// `ctx` must be NULL or come from `wmi_context_new`.
+void wmi_context_free(struct WmiContext *ctx);
+
+extern int sqlite3_open_v2(const char *filename, struct sqlite3 **db, int flags, const char *vfs);
+
+extern int sqlite3_close(struct sqlite3 *db);
+
+extern const char *sqlite3_errmsg(struct sqlite3 *db);
+
+extern int sqlite3_exec(struct sqlite3 *db,
+                        const char *sql,
+                        const void *callback,
+                        void *arg,
+                        char **errmsg);
+
+extern int sqlite3_prepare_v2(struct sqlite3 *db,
+                              const char *sql,
+                              int n,
+                              struct sqlite3_stmt **stmt,
+                              const char **tail);
+
+extern int sqlite3_bind_int64(struct sqlite3_stmt *stmt, int i, int64_t value);
+
+extern int sqlite3_bind_double(struct sqlite3_stmt *stmt, int i, double value);
+
+extern int sqlite3_bind_text(struct sqlite3_stmt *stmt,
+                             int i,
+                             const char *value,
+                             int n,
+                             ptrdiff_t destructor);
+
+extern int sqlite3_bind_null(struct sqlite3_stmt *stmt, int i);
+
+extern int sqlite3_step(struct sqlite3_stmt *stmt);
+
+extern int sqlite3_finalize(struct sqlite3_stmt *stmt);
+
+extern int sqlite3_column_count(struct sqlite3_stmt *stmt);
+
+extern int sqlite3_column_type(struct sqlite3_stmt *stmt, int i);
+
+extern int64_t sqlite3_column_int64(struct sqlite3_stmt *stmt, int i);
+
+extern double sqlite3_column_double(struct sqlite3_stmt *stmt, int i);
+
+extern const uint8_t *sqlite3_column_text(struct sqlite3_stmt *stmt, int i);
+
+extern int sqlite3_column_bytes(struct sqlite3_stmt *stmt, int i);
+
+extern void sqlite3_free(void *p);
+
+extern int64_t sqlite3_last_insert_rowid(struct sqlite3 *db);
+
+#endif  /* WMINSPECT_H */
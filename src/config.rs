@@ -0,0 +1,353 @@
+//! Settings read from `$XDG_CONFIG_HOME/wminspect/config.toml`, which is
+//! `~/.config/wminspect/config.toml` by default.
+
+extern crate toml;
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use wm::{self, Theme, WmError, WmResult};
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Config {
+    /// TUI key bindings by command, e.g. `down = ["j", "Down"]`, replacing
+    /// the default keys of the command
+    pub keys: BTreeMap<String, KeyList>,
+    /// `dark` (the default) or `light`, for colored listings and the TUI
+    pub theme: Option<String>,
+    /// styles replacing those of the theme, e.g. `pinned = "bold red"`
+    pub colors: BTreeMap<String, String>,
+    /// defaults of command line options
+    pub defaults: Preset,
+    /// option bundles picked by `--profile`, e.g. `[profile.osd-debug]`
+    pub profile: BTreeMap<String, Preset>,
+    /// windows `--no-special` leaves out
+    pub special: Special,
+}
+
+/// The `[special]` table, e.g. `presets = ["kwin"]` on KDE. Names are
+/// globs of whole window names.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Special {
+    /// window managers whose own windows are special, those of
+    /// `wm::DEFAULT_SPECIAL_PRESETS` if None
+    pub presets: Option<Vec<String>>,
+    /// more names of special windows
+    pub names: Vec<String>,
+}
+
+impl Special {
+    /// names of the presets followed by `names`
+    pub fn names(&self) -> WmResult<Vec<String>> {
+        let presets: Vec<&str> = match self.presets {
+            Some(ref presets) => presets.iter().map(|p| p.as_str()).collect(),
+            None => wm::DEFAULT_SPECIAL_PRESETS.to_vec(),
+        };
+        let mut names = Vec::new();
+        for preset in presets {
+            let found = wm::special_preset(preset)
+                .ok_or_else(|| WmError::Config(format!("unknown special preset {}", preset)))?;
+            names.extend(found.iter().map(|name| name.to_string()));
+        }
+        names.extend(self.names.iter().cloned());
+        Ok(names)
+    }
+}
+
+/// Command line options to use when not given, from the `[defaults]`
+/// table or a profile. Flags given on the command line add to the ones
+/// here, values given there replace these.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Preset {
+    pub colored: bool,
+    pub only_mapped: bool,
+    pub omit_hidden: bool,
+    pub no_special: bool,
+    pub diff: bool,
+    pub clients_only: bool,
+    /// ring while monitoring, see `--bell`
+    pub bell: bool,
+    /// run instead of the terminal bell, like `--bell CMD`
+    pub bell_command: Option<String>,
+    /// ms configure events have to settle before the monitor dumps windows
+    pub debounce: Option<u64>,
+    /// sheet to load unless `sheet --load` gives one, `~/` and paths
+    /// relative to the config directory are taken
+    pub sheet: Option<PathBuf>,
+    /// more sheets loaded after `sheet`
+    pub sheets: Vec<PathBuf>,
+    /// rules added before those of `--filter`
+    pub filter: Option<String>,
+    /// format of listings, as `--output`
+    pub output: Option<String>,
+}
+
+impl Preset {
+    /// whether the flag named as on the command line, e.g. `only-mapped`,
+    /// is on
+    pub fn flag(&self, name: &str) -> bool {
+        match name {
+            "colored" => self.colored,
+            "only-mapped" => self.only_mapped,
+            "omit-hidden" => self.omit_hidden,
+            "no-special" => self.no_special,
+            "diff" => self.diff,
+            "clients-only" => self.clients_only,
+            "bell" => self.bell,
+            _ => false,
+        }
+    }
+
+    /// where `sheet` and `sheets` are, in order, see `Config::resolve`
+    pub fn sheets(&self) -> Vec<PathBuf> {
+        self.sheet.iter().chain(&self.sheets).map(Config::resolve).collect()
+    }
+
+    /// `other` on top of these, its flags, sheets and rules add to these
+    /// and its values replace them
+    pub fn overlay(&mut self, other: &Preset) {
+        self.colored |= other.colored;
+        self.only_mapped |= other.only_mapped;
+        self.omit_hidden |= other.omit_hidden;
+        self.no_special |= other.no_special;
+        self.diff |= other.diff;
+        self.clients_only |= other.clients_only;
+        self.bell |= other.bell;
+        self.bell_command = other.bell_command.clone().or(self.bell_command.take());
+        self.debounce = other.debounce.or(self.debounce);
+        self.sheet = other.sheet.clone().or(self.sheet.take());
+        self.sheets.extend(other.sheets.iter().cloned());
+        self.filter = match (self.filter.take(), other.filter.clone()) {
+            (Some(a), Some(b)) => Some(format!("{}; {}", a, b)),
+            (a, b) => b.or(a),
+        };
+        self.output = other.output.clone().or(self.output.take());
+    }
+}
+
+/// one key or several keys bound to a command
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum KeyList {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl KeyList {
+    pub fn keys(&self) -> Vec<&str> {
+        match *self {
+            KeyList::One(ref k) => vec![k.as_str()],
+            KeyList::Many(ref ks) => ks.iter().map(|k| k.as_str()).collect(),
+        }
+    }
+}
+
+impl Config {
+    /// where the config file is looked for, None if there is no home
+    pub fn path() -> Option<PathBuf> {
+        let dir = match env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+        };
+        Some(dir.join("wminspect").join("config.toml"))
+    }
+
+    /// `sheets/default.rule` (or `.json`, `.bin`) of the config directory if
+    /// there is one, loaded before any other rules
+    pub fn default_sheet() -> Option<PathBuf> {
+        Config::default_sheet_in(Config::path()?.parent()?)
+    }
+
+    fn default_sheet_in(dir: &Path) -> Option<PathBuf> {
+        ["rule", "json", "bin"].iter()
+            .map(|ext| dir.join("sheets").join("default").with_extension(ext))
+            .find(|path| path.is_file())
+    }
+
+    /// `path` written in the config file with `~/` expanded, relative to the
+    /// directory of the config file if not absolute
+    pub fn resolve<P: AsRef<Path>>(path: P) -> PathBuf {
+        let path = path.as_ref();
+        if let Ok(rest) = path.strip_prefix("~") {
+            if let Some(home) = env::var_os("HOME") {
+                return PathBuf::from(home).join(rest);
+            }
+        }
+        match Config::path() {
+            Some(ref config) if path.is_relative() => config.with_file_name(path),
+            _ => path.to_path_buf(),
+        }
+    }
+
+    /// load the config file at `Config::path`, the defaults if there is none
+    pub fn load() -> WmResult<Config> {
+        match Config::path() {
+            Some(path) => Config::load_from(path),
+            None => Ok(Config::default()),
+        }
+    }
+
+    /// the defaults if `path` does not exist
+    pub fn load_from<P: AsRef<Path>>(path: P) -> WmResult<Config> {
+        let path = path.as_ref();
+        match fs::read_to_string(path) {
+            Ok(text) => Config::parse(&text)
+                .map_err(|e| WmError::Config(format!("{}: {}", path.display(), e))),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(e) => Err(WmError::Config(format!("{}: {}", path.display(), e))),
+        }
+    }
+
+    /// `[defaults]` with the profile `name` on top if any
+    pub fn preset(&self, name: Option<&str>) -> WmResult<Preset> {
+        let mut preset = self.defaults.clone();
+        if let Some(name) = name {
+            let profile = self.profile.get(name)
+                .ok_or_else(|| WmError::Config(format!("unknown profile {}", name)))?;
+            preset.overlay(profile);
+        }
+        Ok(preset)
+    }
+
+    /// the theme named `name`, or by the config if None, with `[colors]`
+    /// applied
+    pub fn theme(&self, name: Option<&str>) -> WmResult<Theme> {
+        let name = name.or(self.theme.as_deref()).unwrap_or("dark");
+        let mut theme = Theme::by_name(name).ok_or_else(|| WmError::Config(format!("unknown theme {}", name)))?;
+        for (part, style) in &self.colors {
+            theme.set(part, style)?;
+        }
+        Ok(theme)
+    }
+
+    pub fn parse(text: &str) -> WmResult<Config> {
+        toml::from_str(text).map_err(|e| WmError::Config(e.message().to_string()))
+    }
+}
+
+/// `2s`, `500ms` or `1m`, a bare number counts seconds
+pub fn parse_duration(s: &str) -> Option<Duration> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let n: u64 = s[..split].parse().ok()?;
+    match &s[split..] {
+        "ms" => Some(Duration::from_millis(n)),
+        "" | "s" => Some(Duration::from_secs(n)),
+        "m" => Some(Duration::from_secs(n * 60)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let config = Config::parse("[keys]\ndown = [\"j\", \"C-n\"]\ntop = \"gg\"\n").unwrap();
+        assert_eq!(config.keys["down"].keys(), vec!["j", "C-n"]);
+        assert_eq!(config.keys["top"].keys(), vec!["gg"]);
+
+        assert!(Config::parse("").unwrap().keys.is_empty());
+        assert!(Config::parse("[keys]\ndown = 1\n").is_err());
+        assert!(Config::load_from("/nonexistent/wminspect.toml").unwrap().keys.is_empty());
+    }
+
+    #[test]
+    fn test_defaults() {
+        let config = Config::parse("[defaults]\ncolored = true\nclients-only = true\ndebounce = 120\n\
+                                    bell = true\nbell-command = \"paplay ding.oga\"\n\
+                                    sheet = \"/etc/osd.rule\"\noutput = \"json\"\n").unwrap();
+        let defaults = &config.defaults;
+        assert!(defaults.flag("colored") && defaults.flag("clients-only"));
+        assert!(!defaults.flag("diff") && !defaults.flag("nope"));
+        assert_eq!(defaults.debounce, Some(120));
+        assert!(defaults.flag("bell"));
+        assert_eq!(defaults.bell_command.as_deref(), Some("paplay ding.oga"));
+        assert_eq!(defaults.sheets(), vec![PathBuf::from("/etc/osd.rule")]);
+        assert_eq!(defaults.output.as_deref(), Some("json"));
+
+        assert_eq!(Config::parse("").unwrap().defaults, Preset::default());
+        assert!(Config::parse("[defaults]\ncolour = true\n").is_err());
+    }
+
+    #[test]
+    fn test_profiles() {
+        let config = Config::parse("[defaults]\ncolored = true\nsheet = \"/a.rule\"\nfilter = \"name = x*\"\n\
+                                    [profile.osd-debug]\ndiff = true\nsheets = [\"/b.json\"]\n\
+                                    filter = \"name = dde-osd: pin\"\noutput = \"wmctrl\"\n\
+                                    [profile.full-audit]\nsheet = \"/c.bin\"\n").unwrap();
+        let preset = config.preset(Some("osd-debug")).unwrap();
+        assert!(preset.flag("colored") && preset.flag("diff"));
+        assert_eq!(preset.sheets(), vec![PathBuf::from("/a.rule"), PathBuf::from("/b.json")]);
+        assert_eq!(preset.filter.as_deref(), Some("name = x*; name = dde-osd: pin"));
+        assert_eq!(preset.output.as_deref(), Some("wmctrl"));
+
+        assert_eq!(config.preset(Some("full-audit")).unwrap().sheets(), vec![PathBuf::from("/c.bin")]);
+        assert_eq!(config.preset(None).unwrap(), config.defaults);
+        assert!(config.preset(Some("nope")).is_err());
+    }
+
+    #[test]
+    fn test_default_sheet() {
+        let dir = env::temp_dir().join(format!("wminspect-config-{}", ::std::process::id()));
+        fs::create_dir_all(dir.join("sheets")).unwrap();
+        assert_eq!(Config::default_sheet_in(&dir), None);
+        fs::write(dir.join("sheets/default.json"), "[]").unwrap();
+        assert_eq!(Config::default_sheet_in(&dir), Some(dir.join("sheets/default.json")));
+        fs::write(dir.join("sheets/default.rule"), "").unwrap();
+        assert_eq!(Config::default_sheet_in(&dir), Some(dir.join("sheets/default.rule")));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_resolve() {
+        if let Some(home) = env::var_os("HOME") {
+            assert_eq!(Config::resolve("~/x.rule"), PathBuf::from(home).join("x.rule"));
+        }
+        assert_eq!(Config::resolve("/x.rule"), PathBuf::from("/x.rule"));
+        if let Some(config) = Config::path() {
+            assert_eq!(Config::resolve("sheets/x.rule"), config.parent().unwrap().join("sheets/x.rule"));
+        }
+    }
+
+    #[test]
+    fn test_theme() {
+        let config = Config::parse("theme = \"light\"\n[colors]\npinned = \"underline\"\n").unwrap();
+        let theme = config.theme(None).unwrap();
+        assert_eq!(theme.pinned.to_string(), "4");
+        assert_eq!(theme.id, Theme::light().id);
+        assert_eq!(config.theme(Some("dark")).unwrap().name, Theme::dark().name);
+        assert!(config.theme(Some("sepia")).is_err());
+        assert!(Config::parse("[colors]\npinned = \"pink\"\n").unwrap().theme(None).is_err());
+    }
+
+    #[test]
+    fn test_special() {
+        let names = Config::parse("").unwrap().special.names().unwrap();
+        assert!(names.contains(&"mutter guard window".to_string()));
+
+        let config = Config::parse("[special]\npresets = [\"kwin\", \"openbox\"]\nnames = [\"*edge window\"]\n").unwrap();
+        assert_eq!(config.special.names().unwrap(), vec!["KWin", "Openbox", "*edge window"]);
+        assert_eq!(Config::parse("[special]\npresets = []\n").unwrap().special.names().unwrap(), Vec::<String>::new());
+        assert!(Config::parse("[special]\npresets = [\"twm\"]\n").unwrap().special.names().is_err());
+        assert!(Config::parse("[special]\nname = []\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("2s"), Some(Duration::from_secs(2)));
+        assert_eq!(parse_duration("500ms"), Some(Duration::from_millis(500)));
+        assert_eq!(parse_duration("1m"), Some(Duration::from_secs(60)));
+        assert_eq!(parse_duration("3"), Some(Duration::from_secs(3)));
+        assert_eq!(parse_duration("s"), None);
+        assert_eq!(parse_duration("2h"), None);
+    }
+}
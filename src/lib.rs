@@ -0,0 +1,13 @@
+extern crate xcb;
+extern crate xcb_util;
+
+#[macro_use]
+extern crate serde_derive;
+extern crate serde;
+
+pub mod wm;
+pub mod prelude;
+pub mod config;
+pub mod man;
+#[cfg(feature = "tui")]
+pub mod ui;
@@ -1,40 +1,204 @@
 //#![feature(core_intrinsics)]
 
-extern crate xcb;
-extern crate xcb_util;
 extern crate clap;
-
-#[macro_use]
-extern crate serde_derive;
-extern crate serde;
+extern crate serde_json;
+extern crate wminspect;
 
 use clap::{Arg, App, SubCommand};
-pub mod wm;
+use wminspect::wm;
+use wminspect::config::{self, Config};
+use std::time::Duration;
 
-pub fn main() {
-    let matches = App::new("window manager inspector")
+const FLASH_DURATION: Duration = Duration::from_secs(1);
+
+/// exit codes like grep, 0 if some window passes the filter
+const EXIT_NO_MATCH: i32 = 1;
+const EXIT_ERROR: i32 = 2;
+
+fn cli() -> App<'static, 'static> {
+    App::new("window manager inspector")
         .version("0.3.0")
         .args(&[
-              Arg::with_name("only-mapped").short("v").long("only-mapped").help("show only mapped windows"),
+              Arg::with_name("only-mapped").short("M").long("only-mapped").help("show only mapped windows, -v used to be its short flag"),
               Arg::from_usage("-c --colored 'output info with color'"),
+              Arg::from_usage("--no-color 'never color output, even if colored in the config file'").conflicts_with("colored"),
               Arg::from_usage("-m --monitor 'run in monitor mode.'"),
-              Arg::from_usage("-f --filter [RULE EXPR] 'filter rule.'"),
+              Arg::from_usage("-t --tui 'browse windows interactively'"),
+              Arg::from_usage("--daemon 'the same as daemon subcommand with its defaults'"),
+              Arg::from_usage("--flash [WINDOW] 'outline window WINDOW on screen for a moment'"),
+              Arg::from_usage("-f --filter [RULE EXPR] 'filter rule, - reads rules from stdin'"),
               Arg::from_usage("-o --omit-hidden 'omit hidden windows'"),
               Arg::from_usage("-O --no-override-redirect 'ignore override-redirect windows'"),
               Arg::from_usage("-s --no-special 'ignore special windows'"),
+              Arg::from_usage("--special-name [GLOB]... 'also a special window, by its name, like \"*edge window\"'")
+                  .number_of_values(1),
               Arg::from_usage("-n --num 'show event sequence count'"),
+              Arg::from_usage("-q --quiet 'list nothing, only exit 0 if any window passes the filter and 1 otherwise'"),
+              Arg::from_usage("--count 'print only how many windows pass the filter'").conflicts_with("quiet"),
               Arg::from_usage("-d --diff 'highlight diffs between events'"),
+              Arg::from_usage("--bell [CMD] 'ring when a pinned window is created, mapped or destroyed while monitoring, or run CMD'")
+                  .min_values(0).max_values(1),
               Arg::from_usage("-C --clients-only 'trace clients of window manager only'"),
+              Arg::from_usage("--max-depth [N] 'collect windows N levels below root, 1 (children of root) by default, 0 for all'"),
+              Arg::from_usage("--tree 'list the whole hierarchy, windows indented below their parents, unless --max-depth says otherwise'"),
+              Arg::from_usage("--jobs [N] 'query windows with N threads at once, for sessions of thousands of windows'"),
+              Arg::from_usage("--no-input-only 'skip InputOnly windows and what is below them'"),
+              Arg::from_usage("--desktop [N] 'only windows on desktop N, or on the current one following switches'"),
+              Arg::from_usage("--on-output [NAME] 'only windows intersecting RandR output NAME, like HDMI-1'"),
+              Arg::from_usage("--no-names 'fetch names of windows passing the filter only, unless rules look at names'"),
+              Arg::from_usage("--anonymize 'hash names and classes and leave hosts and pids out, to share dumps'"),
+              Arg::from_usage("--timings 'print on stderr how long querying, filtering and output of the listing took'"),
               Arg::from_usage("--show-grammar 'show detailed grammar for filter rule'"),
+              Arg::from_usage("--rule-stats 'print how often each filter rule matched on exit'"),
+              Arg::from_usage("--theme [NAME] 'colors of listing and tui, default to the one of config file'")
+                  .possible_values(wm::theme::THEMES),
+              Arg::from_usage("--output [FORMAT] 'output format of listing'")
+                  .possible_values(wm::OUTPUT_FORMATS),
+              Arg::from_usage("--no-default-sheet 'do not load sheets/default.rule of the config directory'"),
+              Arg::from_usage("--profile [NAME] 'use options of [profile.NAME] in the config file'"),
+              Arg::from_usage("--debounce [MS] 'how long configure events settle before dumping windows, 50 by default'"),
+              Arg::with_name("verbose").short("v").long("verbose").multiple(true)
+                  .help("log more, -v for info, -vv for debug and -vvv for trace; -M is --only-mapped now")
+                  .conflicts_with("log-level"),
+              Arg::from_usage("--log-level [LEVEL] 'how much to log, debug for debug builds and warn otherwise'")
+                  .possible_values(wm::logging::LOG_LEVELS),
+              Arg::from_usage("--log-file [FILE] 'log to FILE, rotated as it grows, instead of stderr'"),
+              Arg::from_usage("--log-format [FORMAT] 'format of log lines, compact by default'")
+                  .possible_values(wm::logging::LOG_FORMATS),
+              Arg::from_usage("--metrics-addr [ADDR] 'serve prometheus metrics at http://ADDR/metrics'"),
+              Arg::from_usage("--ws-addr [ADDR] 'publish events and dumps as json over websocket at ws://ADDR'"),
+              Arg::from_usage("--log-db [FILE] 'record events and snapshots of windows into the SQLite database FILE'"),
+              Arg::from_usage("--display [DISPLAY] 'X display to connect to, default to $DISPLAY'"),
+              Arg::from_usage("--screen [N] 'screen to inspect, default to the one of display'"),
+              Arg::from_usage("--timeout [DURATION] 'give up when the X server does not answer in time, like 2s or 500ms, 5s by default'"),
+              Arg::from_usage("--xauthority [FILE] 'Xauthority file to use, default to $XAUTHORITY'"),
         ])
         .subcommand(SubCommand::with_name("monitor").about("the same as -m flag"))
+        .subcommand(
+            SubCommand::with_name("daemon").about("keep windows cached and answer json queries over a unix socket")
+            .args(&[
+                  Arg::from_usage("--socket [PATH] 'socket to listen at, default to wminspect$DISPLAY.sock in $XDG_RUNTIME_DIR'"),
+                  Arg::from_usage("--journal 'log events to journald, the default when run by systemd'"),
+            ]))
+        .subcommand(
+            SubCommand::with_name("query").about("list windows passing a rule, asking the daemon if one is running")
+            .args(&[
+                  Arg::from_usage("<RULE> 'filter rule windows have to pass'"),
+                  Arg::from_usage("--fields [FIELDS] 'comma separated fields to print like id,attrs.map_state, id,name by default'"),
+                  Arg::from_usage("--socket [PATH] 'socket of the daemon, default to the one of daemon'"),
+            ]))
+        .subcommand(
+            SubCommand::with_name("history").about("print the last titles or geometries of a window, asking the running daemon")
+            .args(&[
+                  Arg::from_usage("<WINDOW> 'id of the window'"),
+                  Arg::from_usage("-g --geometry 'moves and resizes instead of titles'"),
+                  Arg::from_usage("--socket [PATH] 'socket of the daemon, default to the one of daemon'"),
+            ]))
+        .subcommand(
+            SubCommand::with_name("watch").about("poll windows periodically and print what changed, without watching events")
+            .args(&[
+                  Arg::from_usage("--interval [DURATION] 'time between polls like 500ms, 2s or 1m, 2s by default'"),
+                  Arg::from_usage("-j --json 'report changes as json'"),
+            ]))
+        .subcommand(
+            SubCommand::with_name("snapshot").about("save all collected windows as json for offline analysis")
+            .args(&[
+                  Arg::from_usage("-o --out [FILE] 'file to write, stdout by default'"),
+                  Arg::from_usage("-a --all 'keep windows filtered out as well'"),
+            ]))
+        .subcommand(
+            SubCommand::with_name("compare").about("report what changed between two snapshots")
+            .args(&[
+                  Arg::from_usage("<BEFORE> 'snapshot taken first'"),
+                  Arg::from_usage("<AFTER> 'snapshot taken later'"),
+                  Arg::from_usage("-j --json 'report as json'"),
+            ]))
+        .subcommand(
+            SubCommand::with_name("props").about("list all properties of a window, like xprop")
+            .arg(Arg::from_usage("<WINDOW> 'id of the window'")))
+        .subcommand(
+            SubCommand::with_name("window").about("print all about a window: its fields, parents, hints and how each rule takes it")
+            .args(&[
+                  Arg::from_usage("[WINDOW] 'id of the window'"),
+                  Arg::from_usage("-s --select 'click the window instead, like xprop'"),
+                  Arg::from_usage("-j --json 'report as json'"),
+            ]))
+        .subcommand(
+            SubCommand::with_name("explain").about("show how a filter rule is tokenized and parsed, and its normalized form")
+            .args(&[
+                  Arg::from_usage("<RULE> 'filter rule to explain'"),
+                  Arg::from_usage("--dot 'print the tree of the rule as a graphviz graph instead'"),
+            ]))
+        .subcommand(
+            SubCommand::with_name("pick").about("click a window and print a rule passing windows like it")
+            .args(&[
+                  Arg::from_usage("-a --append [SHEET] 'add the rule to .rule sheet SHEET as well'"),
+                  Arg::from_usage("--pin 'make it a pin rule'"),
+            ]))
+        .subcommand(
+            SubCommand::with_name("replay").about("list events recorded by --log-db, exits 1 if none is selected")
+            .args(&[
+                  Arg::from_usage("<DB> 'database written by --log-db'"),
+                  Arg::from_usage("-q --query [RULE] 'only events passing RULE, e.g. all(name = xterm*, event = configure)'"),
+                  Arg::from_usage("-s --session [N] 'only events of session N'"),
+                  Arg::from_usage("--trace 'print them as Chrome trace-event json, for Perfetto or chrome://tracing'"),
+            ]))
+        .subcommand(
+            SubCommand::with_name("compare-sessions").about("report what apps did differently in two sessions recorded by --log-db, e.g. under two window managers")
+            .args(&[
+                  Arg::from_usage("<A> 'database of the first session'"),
+                  Arg::from_usage("<B> 'database of the other session'"),
+                  Arg::from_usage("--session-a [N] 'session N of A rather than the last one'"),
+                  Arg::from_usage("--session-b [N] 'session N of B rather than the last one'"),
+                  Arg::from_usage("-j --json 'report as json'"),
+            ]))
+        .subcommand(SubCommand::with_name("repl").about("try filter rules on live windows and build a sheet of them, :help for commands"))
+        .subcommand(SubCommand::with_name("man").about("print the man page in roff"))
         .subcommand(
             SubCommand::with_name("sheet").about("sheet management")
             .args(&[
-                  Arg::from_usage("--load [SHEET_PATH] 'load sheet from file, could be a .json, .bin or raw unparsed .rule'"),
+                  Arg::from_usage("--load [SHEET_PATH] 'load sheet from file, could be a .json, .bin or raw unparsed .rule, all of a directory or - for stdin'"),
                   Arg::from_usage("--compile [rule] [binfile|json] 'compile .rule into .bin or .json'").conflicts_with("load")
             ]))
-        .get_matches();
+}
+
+pub fn main() {
+    let matches = cli().get_matches();
+
+    init_logging(&matches);
+
+    if matches.subcommand_matches("man").is_some() {
+        print!("{}", man_page());
+        return;
+    }
+
+    if let Some(sub) = matches.subcommand_matches("explain") {
+        let rule = sub.value_of("RULE").unwrap();
+        let text = if sub.is_present("dot") { wm::explain_dot(rule) } else { wm::explain(rule) };
+        match text {
+            Ok(text) => print!("{}", text),
+            Err(e) => fail(e),
+        }
+        return;
+    }
+
+    if let Some(sub) = matches.subcommand_matches("replay") {
+        let query = sub.value_of("query").map(|q| wm::EventQuery::parse(q).unwrap_or_else(|e| fail(e)));
+        let session = sub.value_of("session")
+            .map(|n| n.parse().unwrap_or_else(|_| fail(format!("invalid session {}", n))));
+        if replay(sub.value_of("DB").unwrap(), query.as_ref(), session, sub.is_present("trace")) == 0 {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(sub) = matches.subcommand_matches("compare-sessions") {
+        let session = |arg| sub.value_of(arg)
+            .map(|n| n.parse().unwrap_or_else(|_| fail(format!("invalid session {}", n))));
+        compare_sessions((sub.value_of("A").unwrap(), session("session-a")),
+                         (sub.value_of("B").unwrap(), session("session-b")), sub.is_present("json"));
+        return;
+    }
 
     if matches.is_present("show-grammar") {
         println!("{}", wm::filter_grammar());
@@ -42,40 +206,555 @@ pub fn main() {
     }
 
 
-    let (c, _) = xcb::Connection::connect(None).unwrap();
-    let ewmh = xcb_util::ewmh::Connection::connect(c).ok().unwrap();
+    if let Some(sub) = matches.subcommand_matches("compare") {
+        let load = |arg| wm::Snapshot::load(sub.value_of(arg).unwrap()).unwrap_or_else(|e| fail(e));
+        let report = load("BEFORE").compare(&load("AFTER"));
+        let stdout = std::io::stdout();
+        let res = if sub.is_present("json") {
+            report.write_json(&mut stdout.lock())
+        } else {
+            report.write_text(&mut stdout.lock())
+        };
+        if let Err(e) = res {
+            fail(e);
+        }
+        return;
+    }
 
-    let mut f = match matches.value_of("filter") {
-        None => wm::Filter::new(),
-        Some(rule) => wm::Filter::parse(rule)
-    };
+    // the daemon answers from its cache, connect only if there is none
+    let mut query = None;
+    if let Some(sub) = matches.subcommand_matches("query") {
+        let q = wm::daemon::Query::Filter { rule: sub.value_of("RULE").unwrap().to_string() };
+        let fields: Vec<_> = sub.value_of("fields").unwrap_or("id,name").split(',').map(str::trim).collect();
+        let path = sub.value_of("socket").map(std::path::PathBuf::from)
+            .unwrap_or_else(|| wm::daemon::socket_path(matches.value_of("display")));
+        if let Ok(reply) = wm::daemon::request(&path, &q) {
+            print_reply(reply, &fields);
+            return;
+        }
+        query = Some((q, fields));
+    }
+
+    if let Some(sub) = matches.subcommand_matches("history") {
+        print_history(sub, matches.value_of("display"));
+        return;
+    }
+
+    if let Some(xauth) = matches.value_of("xauthority") {
+        std::env::set_var("XAUTHORITY", xauth);
+    }
+
+    // defaults of the config file and the profile, given options replace them
+    let config = Config::load().unwrap_or_else(|e| fail(e));
+    let preset = &config.preset(matches.value_of("profile")).unwrap_or_else(|e| fail(e));
+
+    let mut f = wm::Filter::new();
+    if let Some(path) = Config::default_sheet().filter(|_| !matches.is_present("no-default-sheet")) {
+        load_sheet(&mut f, &path);
+    }
+    let sheet_given = matches.subcommand_matches("sheet").is_some_and(|sub| sub.is_present("load"));
+    if !sheet_given {
+        for path in preset.sheets() {
+            load_sheet(&mut f, &path);
+        }
+    }
+    if let Some(rule) = preset.filter.as_ref() {
+        f.add_rules(rule).unwrap_or_else(|e| fail(e));
+    }
+    match matches.value_of("filter") {
+        Some("-") => load_sheet(&mut f, std::path::Path::new("-")),
+        Some(rule) => f.add_rules(rule).unwrap_or_else(|e| fail(e)),
+        None => {},
+    }
 
     if let Some(sub) = matches.subcommand_matches("sheet") {
         if let Some(vals) = sub.values_of("compile") {
             let vals = vals.collect::<Vec<&str>>();
-            wm::Filter::compile(vals[0], vals[1]);
+            if let Err(e) = wm::Filter::compile(vals[0], vals[1]) {
+                fail(e);
+            }
             return;
         } 
 
         if let Some(val) = sub.value_of("load") {
-            f.load_sheet(val);
+            load_sheet(&mut f, std::path::Path::new(val));
+        }
+    }
+
+    let options = [
+        ("only-mapped", wm::Options::MAPPED_ONLY),
+        ("colored", wm::Options::COLORFUL),
+        ("omit-hidden", wm::Options::OMIT_HIDDEN),
+        ("no-special", wm::Options::NO_SPECIAL),
+        ("diff", wm::Options::SHOW_DIFF),
+        ("clients-only", wm::Options::CLIENTS_ONLY),
+        ("no-input-only", wm::Options::NO_INPUT_ONLY),
+        ("no-names", wm::Options::NO_NAMES),
+        ("anonymize", wm::Options::ANONYMIZE),
+        ("tree", wm::Options::TREE),
+    ];
+
+    let theme = config.theme(matches.value_of("theme")).unwrap_or_else(|e| fail(e));
+
+    let mut builder = wm::Context::builder()
+        .filter(f)
+        .theme(theme)
+        .options(options.iter()
+                 .filter(|&&(arg, _)| matches.is_present(arg) || preset.flag(arg))
+                 .filter(|&&(arg, _)| arg != "colored" || !matches.is_present("no-color"))
+                 .map(|&(_, opt)| opt).collect());
+    if matches.is_present("bell") || preset.bell {
+        builder = builder.bell(match matches.value_of("bell").or(preset.bell_command.as_deref()) {
+            Some(cmd) => wm::Bell::Command(cmd.to_string()),
+            None => wm::Bell::Terminal,
+        });
+    }
+    let mut specials = config.special.names().unwrap_or_else(|e| fail(e));
+    specials.extend(matches.values_of("special-name").into_iter().flatten().map(|name| name.to_string()));
+    builder = builder.special_names(specials);
+    if let Some(output) = matches.value_of("output").or(preset.output.as_deref()) {
+        builder = builder.output(output.parse().unwrap_or_else(|e| fail(e)));
+    }
+    let debounce = match matches.value_of("debounce") {
+        Some(ms) => Some(ms.parse().unwrap_or_else(|_| fail(format!("invalid debounce {}", ms)))),
+        None => preset.debounce,
+    };
+    if let Some(ms) = debounce {
+        builder = builder.debounce(Duration::from_millis(ms));
+    }
+    if let Some(depth) = matches.value_of("max-depth") {
+        builder = builder.max_depth(depth.parse().unwrap_or_else(|_| fail(format!("invalid max depth {}", depth))));
+    } else if matches.is_present("tree") {
+        builder = builder.max_depth(0);
+    }
+    if let Some(jobs) = matches.value_of("jobs") {
+        builder = builder.jobs(jobs.parse().unwrap_or_else(|_| fail(format!("invalid jobs {}", jobs))));
+    }
+    if let Some(timeout) = matches.value_of("timeout") {
+        builder = builder.timeout(config::parse_duration(timeout).unwrap_or_else(|| fail(format!("invalid timeout {}", timeout))));
+    }
+    if let Some(desktop) = matches.value_of("desktop") {
+        builder = builder.desktop(desktop.parse().unwrap_or_else(|e| fail(e)));
+    }
+    if let Some(output) = matches.value_of("on-output") {
+        builder = builder.on_output(output);
+    }
+    if let Some(display) = matches.value_of("display") { builder = builder.display(display); }
+    if let Some(screen) = matches.value_of("screen") {
+        builder = builder.screen(screen.parse().unwrap_or_else(|_| fail(format!("invalid screen {}", screen))));
+    }
+
+    let mut ctx = builder.connect().unwrap_or_else(|e| fail(e));
+    if let Some(output) = matches.value_of("on-output") {
+        let outputs: Vec<_> = ctx.backend().outputs().into_iter().map(|(name, _)| name).collect();
+        if !outputs.iter().any(|name| name == output) {
+            fail(format!("no output {}, the lit ones are {}", output, outputs.join(", ")));
+        }
+    }
+    if ctx.backend().is_xwayland() {
+        eprintln!("warning: running under XWayland, native Wayland windows are invisible \
+                  and only XWayland clients (tagged XWL) are listed");
+    }
+
+    if let Some(addr) = matches.value_of("metrics-addr") {
+        if let Err(e) = wm::metrics::serve(addr, ctx.metrics()) {
+            fail(format!("can not serve metrics at {}: {}", addr, e));
+        }
+    }
+
+    if let Some(addr) = matches.value_of("ws-addr") {
+        match wm::ws::WsPublisher::serve(addr) {
+            Ok((publisher, _)) => ctx.add_sink(publisher),
+            Err(e) => fail(format!("can not serve websocket at {}: {}", addr, e)),
+        }
+    }
+
+    if let Some(path) = matches.value_of("log-db") {
+        let display = matches.value_of("display").map(String::from).or_else(|| std::env::var("DISPLAY").ok());
+        log_to_db(&mut ctx, path, display.as_deref());
+    }
+
+    if let Some(id) = matches.value_of("flash") {
+        let id = id.parse().unwrap_or_else(|_| fail(format!("invalid window id {}", id)));
+        if let Err(e) = ctx.flash(id, FLASH_DURATION) {
+            fail(e);
+        }
+        return;
+    }
+
+    if let Some(sub) = matches.subcommand_matches("snapshot") {
+        if let Err(e) = ctx.refresh_windows() {
+            fail(e);
+        }
+        let mut snapshot = ctx.snapshot();
+        if !sub.is_present("all") {
+            snapshot = snapshot.filtered_only();
+        }
+        let res = match sub.value_of("out") {
+            Some(path) => snapshot.save(path),
+            None => snapshot.write_to(std::io::stdout().lock()),
+        };
+        if let Err(e) = res {
+            fail(e);
         }
+        return;
+    }
+
+    if let Some(sub) = matches.subcommand_matches("props") {
+        let id = sub.value_of("WINDOW").unwrap();
+        let id = id.parse().unwrap_or_else(|_| fail(format!("invalid window id {}", id)));
+        for p in ctx.backend().properties(id) {
+            println!("{}", p);
+        }
+        // only a running daemon has watched the names change
+        let path = wm::daemon::socket_path(matches.value_of("display"));
+        let titles = wm::daemon::request(&path, &wm::daemon::Query::Titles { id }).ok()
+            .and_then(|reply| reply.result)
+            .and_then(|v| serde_json::from_value::<Vec<wm::history::Entry<String>>>(v).ok())
+            .unwrap_or_default();
+        if titles.len() > 1 {
+            println!("title history:");
+            for e in titles.iter().rev() {
+                println!("\t{}  {}", wm::history::clock(e.at), e.value);
+            }
+        }
+        return;
+    }
+
+    if let Some(sub) = matches.subcommand_matches("window") {
+        let id = match sub.value_of("WINDOW") {
+            Some(id) => id.parse().unwrap_or_else(|_| fail(format!("invalid window id {}", id))),
+            None if sub.is_present("select") => {
+                eprintln!("click the window to inspect, any other button than the first cancels");
+                ctx.pick().unwrap_or_else(|| fail("no window picked")).id
+            },
+            None => fail("a window id or --select is needed"),
+        };
+        if let Err(e) = ctx.refresh_windows() {
+            fail(e);
+        }
+        let report = ctx.window_report(id).unwrap_or_else(|e| fail(e));
+        let stdout = std::io::stdout();
+        let res = if sub.is_present("json") {
+            report.write_json(&mut stdout.lock())
+        } else {
+            report.write_text(&mut stdout.lock())
+        };
+        if let Err(e) = res {
+            fail(e);
+        }
+        return;
+    }
+
+    if let Some((ref query, ref fields)) = query {
+        if let Err(e) = ctx.refresh_windows() {
+            fail(e);
+        }
+        print_reply(wm::daemon::answer(&ctx, query), fields);
+        return;
     }
 
-    let mut ctx = wm::Context::new(&ewmh, f);
+    let daemon = matches.subcommand_matches("daemon");
+    if daemon.is_some() || matches.is_present("daemon") {
+        let path = daemon.and_then(|sub| sub.value_of("socket")).map(std::path::PathBuf::from)
+            .unwrap_or_else(|| wm::daemon::socket_path(matches.value_of("display")));
+        if daemon.is_some_and(|sub| sub.is_present("journal")) || wm::systemd::connected_to_journal() {
+            match wm::systemd::JournalSink::new() {
+                Ok(journal) => ctx.add_sink(std::sync::Arc::new(journal)),
+                Err(e) => eprintln!("wminspect: can not log to journald: {}", e),
+            }
+        }
+        run_daemon(ctx, &path);
+        return;
+    }
+
+    if let Some(sub) = matches.subcommand_matches("pick") {
+        eprintln!("click the window to pick, any other button than the first cancels");
+        let w = ctx.pick().unwrap_or_else(|| fail("no window picked"));
+        let mut rule = wm::suggest_rule(&w);
+        if sub.is_present("pin") {
+            rule.push_str(": pin");
+        }
+        println!("{}", rule);
+        if let Some(sheet) = sub.value_of("append") {
+            if let Err(e) = wm::append_rule(sheet, &rule) {
+                fail(e);
+            }
+        }
+        return;
+    }
 
-    if matches.is_present("only-mapped") { ctx.set_mapped_only(); }
-    if matches.is_present("colored") { ctx.set_colorful(); }
-    if matches.is_present("omit-hidden") { ctx.set_omit_hidden(); }
-    if matches.is_present("no-special") { ctx.set_no_special(); }
-    if matches.is_present("diff") { ctx.set_show_diff(); }
-    if matches.is_present("clients-only") { ctx.set_clients_only(); }
+    if matches.subcommand_matches("repl").is_some() {
+        let stdin = std::io::stdin();
+        if let Err(e) = wm::repl::run(&ctx, stdin.lock(), std::io::stdout()) {
+            fail(e);
+        }
+        return;
+    }
 
-    if matches.is_present("monitor") || matches.subcommand_matches("monitor").is_some() {
-        wm::monitor(&mut ctx);
+    if let Some(sub) = matches.subcommand_matches("watch") {
+        let interval = sub.value_of("interval").unwrap_or("2s");
+        let interval = config::parse_duration(interval).unwrap_or_else(|| fail(format!("invalid interval {}", interval)));
+        if sub.is_present("json") {
+            ctx.set_output_format(wm::OutputFormat::Json);
+        }
+        if let Err(e) = wm::watch(&ctx, interval) {
+            fail(e);
+        }
+        return;
+    }
+
+    if matches.is_present("tui") {
+        let ctx = run_tui(ctx, &config);
+        if matches.is_present("rule-stats") {
+            print_rule_stats(&ctx.state().rules());
+        }
+        return;
+    }
+
+    let monitoring = matches.is_present("monitor") || matches.subcommand_matches("monitor").is_some();
+    let count = matches.is_present("count");
+    if (matches.is_present("quiet") || count) && !monitoring {
+        ctx.set_writer(std::io::sink());
+    }
+
+    let mut matched = None;
+    if ctx.output_format() == wm::OutputFormat::Wmctrl {
+        if monitoring {
+            fail("wmctrl output is only available for listing");
+        }
+        matched = Some(ctx.dump_wmctrl());
+    } else if monitoring {
+        if let Err(e) = wm::monitor(&mut ctx) {
+            fail(e);
+        }
     } else {
-        ctx.refresh_windows();
+        if let Err(e) = ctx.refresh_windows() {
+            fail(e);
+        }
         ctx.dump_windows(None);
+        matched = Some(ctx.windows().len());
+    }
+    if let (true, Some(n)) = (count, matched) {
+        println!("{}", n);
+    }
+    if matches.is_present("timings") && !monitoring {
+        eprintln!("timings: {}", ctx.timings());
+    }
+
+    if matches.is_present("rule-stats") {
+        print_rule_stats(&ctx.state().rules());
+    }
+    if matched == Some(0) {
+        std::process::exit(EXIT_NO_MATCH);
+    }
+}
+
+/// the man page of `cli()`, with a section for each subcommand
+fn man_page() -> String {
+    let mut help = Vec::new();
+    if let Err(e) = cli().bin_name("wminspect").write_help(&mut help) {
+        fail(e);
+    }
+    let sub_help = |name: &str| match cli().get_matches_from_safe(vec!["wminspect", name, "--help"]) {
+        Err(ref e) if e.kind == clap::ErrorKind::HelpDisplayed && name != "help" => Some(e.message.clone()),
+        _ => None,
+    };
+    wminspect::man::render(env!("CARGO_PKG_VERSION"), &String::from_utf8_lossy(&help), sub_help)
+}
+
+fn init_logging(matches: &clap::ArgMatches) {
+    if let Some(level) = matches.value_of("log-level") {
+        wm::logging::set_level(level.parse().unwrap_or_else(|e| fail(e)));
+    } else if matches.is_present("verbose") {
+        wm::logging::set_level(wm::logging::Level::from_verbosity(matches.occurrences_of("verbose")));
+    }
+    if let Some(format) = matches.value_of("log-format") {
+        wm::logging::set_format(format.parse().unwrap_or_else(|e| fail(e)));
+    }
+    if let Some(path) = matches.value_of("log-file") {
+        if let Err(e) = wm::logging::set_file(path) {
+            fail(format!("can not log to {}: {}", path, e));
+        }
     }
 }
 
+/// one line of `fields` separated by tabs per window of `reply`
+fn print_reply(reply: wm::daemon::Reply, fields: &[&str]) {
+    if let Some(e) = reply.error {
+        fail(e);
+    }
+    let windows = match reply.result {
+        Some(serde_json::Value::Array(windows)) => windows,
+        _ => fail("unexpected reply of daemon"),
+    };
+    for w in &windows {
+        let values: Vec<_> = fields.iter().map(|f| wm::daemon::field(w, f).unwrap_or_default()).collect();
+        println!("{}", values.join("\t"));
+    }
+}
+
+/// only the daemon has watched windows long enough to tell their history
+fn print_history(sub: &clap::ArgMatches, display: Option<&str>) {
+    use wm::daemon::Query;
+    use wm::history::{self, Entry};
+
+    let id = sub.value_of("WINDOW").unwrap();
+    let id = id.parse().unwrap_or_else(|_| fail(format!("invalid window id {}", id)));
+    let path = sub.value_of("socket").map(std::path::PathBuf::from)
+        .unwrap_or_else(|| wm::daemon::socket_path(display));
+    let geometry = sub.is_present("geometry");
+    let query = if geometry { Query::Geometries { id } } else { Query::Titles { id } };
+    let reply = wm::daemon::request(&path, &query)
+        .unwrap_or_else(|e| fail(format!("no daemon at {}: {}", path.display(), e)));
+    if let Some(e) = reply.error {
+        fail(e);
+    }
+
+    let result = reply.result.unwrap_or_default();
+    let lines = if geometry {
+        let trail: Vec<Entry<wm::Geometry>> = serde_json::from_value(result).unwrap_or_else(|e| fail(e));
+        history::describe_trail(&trail)
+    } else {
+        let titles: Vec<Entry<String>> = serde_json::from_value(result).unwrap_or_else(|e| fail(e));
+        titles.iter().map(|e| format!("{}  {}", history::clock_ms(e.at), e.value)).collect()
+    };
+    for line in lines {
+        println!("{}", line);
+    }
+}
+
+/// answer queries at `path` while a monitor keeps the cache of `ctx` up to
+/// date, its output is dropped unless `--output` asks for some
+fn run_daemon(ctx: wm::Context, path: &std::path::Path) {
+    if ctx.output_format() == wm::OutputFormat::Default {
+        ctx.set_writer(std::io::sink());
+    }
+    if let Err(e) = ctx.refresh_windows() {
+        fail(e);
+    }
+    let ctx = std::sync::Arc::new(ctx);
+    if let Err(e) = wm::daemon::serve(path, ctx.clone()) {
+        fail(format!("can not listen at {}: {}", path.display(), e));
+    }
+    eprintln!("wminspect: listening at {}", path.display());
+    if let Err(e) = wm::systemd::notify("READY=1") {
+        eprintln!("wminspect: can not notify systemd: {}", e);
+    }
+
+    let res = wm::spawn_monitor(ctx).and_then(|monitor| monitor.join());
+    let _ = wm::systemd::notify("STOPPING=1");
+    let _ = std::fs::remove_file(path);
+    if let Err(e) = res {
+        fail(e);
+    }
+}
+
+#[cfg(feature = "tui")]
+fn run_tui(mut ctx: wm::Context, config: &Config) -> std::sync::Arc<wm::Context> {
+    let log = std::sync::Arc::new(wminspect::ui::EventLog::new());
+    ctx.add_sink(log.clone());
+    let keymap = wminspect::ui::Keymap::from_config(&config.keys).unwrap_or_else(|e| fail(e));
+    let theme = ctx.theme().clone();
+    let ctx = std::sync::Arc::new(ctx);
+    if let Err(e) = wminspect::ui::run(ctx.clone(), log, keymap, theme) {
+        fail(e);
+    }
+    ctx
+}
+
+#[cfg(not(feature = "tui"))]
+fn run_tui(_: wm::Context, _: &Config) -> std::sync::Arc<wm::Context> {
+    fail("built without the tui feature")
+}
+
+#[cfg(feature = "sqlite")]
+fn log_to_db(ctx: &mut wm::Context, path: &str, display: Option<&str>) {
+    match wm::sqlite::SqliteSink::open(path, display) {
+        Ok(sink) => ctx.add_sink(std::sync::Arc::new(sink)),
+        Err(e) => fail(e),
+    }
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn log_to_db(_: &mut wm::Context, _: &str, _: Option<&str>) {
+    fail("built without the sqlite feature")
+}
+
+#[cfg(feature = "sqlite")]
+fn replay(path: &str, query: Option<&wm::EventQuery>, session: Option<i64>, trace: bool) -> usize {
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+    let res = if trace {
+        wm::sqlite::select_events(path, query, session)
+            .and_then(|events| wm::trace::write_trace(&mut out, &events).map(|_| events.len()))
+    } else {
+        wm::sqlite::replay(path, query, session, &mut out)
+    };
+    res.unwrap_or_else(|e| fail(e))
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn replay(_: &str, _: Option<&wm::EventQuery>, _: Option<i64>, _: bool) -> usize {
+    fail("built without the sqlite feature")
+}
+
+#[cfg(feature = "sqlite")]
+fn compare_sessions(a: (&str, Option<i64>), b: (&str, Option<i64>), json: bool) {
+    let load = |(path, session)| wm::sessions::load_session(path, session).unwrap_or_else(|e| fail(e));
+    let report = wm::sessions::compare_sessions(&load(a), &load(b));
+    let stdout = std::io::stdout();
+    let res = if json {
+        report.write_json(&mut stdout.lock())
+    } else {
+        report.write_text(&mut stdout.lock())
+    };
+    if let Err(e) = res {
+        fail(e);
+    }
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn compare_sessions(_: (&str, Option<i64>), _: (&str, Option<i64>), _: bool) {
+    fail("built without the sqlite feature")
+}
+
+fn print_rule_stats(rules: &[wm::RuleInfo]) {
+    eprintln!("{:>4} {:>10} {:>10} {:>10}  rule", "id", "evaluated", "matched", "time(us)");
+    for r in rules {
+        let origin = match r.origin {
+            wm::RuleOrigin::Sheet(ref path) => format!("  [{}]", path.display()),
+            wm::RuleOrigin::Builtin => "  [builtin]".to_string(),
+            wm::RuleOrigin::Stdin => "  [stdin]".to_string(),
+            wm::RuleOrigin::Inline => String::new(),
+        };
+        eprintln!("{:>4} {:>10} {:>10} {:>10}  {}{}", r.id, r.stats.evaluated, r.stats.matched,
+                  r.stats.time.as_micros(), r.source, origin);
+    }
+}
+
+/// load a sheet, or every sheet of a directory where a broken one is only
+/// warned about
+/// `-` reads the sheet from stdin
+fn load_sheet(f: &mut wm::Filter, path: &std::path::Path) {
+    if path == std::path::Path::new("-") {
+        if let Err(e) = f.load_sheet_from(std::io::stdin().lock()) {
+            fail(format!("stdin: {}", e));
+        }
+    } else if path.is_dir() {
+        match f.load_sheet_dir(path) {
+            Ok(errors) => for (sheet, e) in errors {
+                eprintln!("wminspect: skipped {}: {}", sheet.display(), e);
+            },
+            Err(e) => fail(e),
+        }
+    } else if let Err(e) = f.load_sheet(path) {
+        fail(format!("{}: {}", path.display(), e));
+    }
+}
+
+fn fail<E: std::fmt::Display>(e: E) -> ! {
+    eprintln!("wminspect: {}", e);
+    std::process::exit(EXIT_ERROR);
+}
+
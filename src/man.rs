@@ -0,0 +1,163 @@
+//! `wminspect(1)` in roff, rendered from the help text of the command line
+//! so it never drifts from the options actually taken.
+
+use std::fmt::Write;
+
+use wm::filter_grammar;
+
+/// `text` with roff control characters escaped
+fn escape(text: &str) -> String {
+    let text = text.replace('\\', "\\e").replace('-', "\\-");
+    if text.starts_with('.') || text.starts_with('\'') {
+        format!("\\&{}", text)
+    } else {
+        text
+    }
+}
+
+/// Sections of help text as clap prints it, `FLAGS:` and such headers
+/// followed by indented entries, an entry being a name and a description
+/// after two or more spaces. Lines indented deeper continue the last entry.
+fn sections(help: &str) -> Vec<(String, Vec<(String, String)>)> {
+    let mut sections: Vec<(String, Vec<(String, String)>)> = Vec::new();
+    let mut entry_indent = 0;
+    for line in help.lines() {
+        if !line.starts_with(' ') && line.ends_with(':') {
+            sections.push((line.trim_end_matches(':').to_string(), Vec::new()));
+            continue;
+        }
+
+        let entries = match sections.last_mut() {
+            Some(&mut (_, ref mut entries)) if !line.trim().is_empty() => entries,
+            _ => continue,
+        };
+        let indent = line.len() - line.trim_start().len();
+        let line = line.trim();
+        match (line.find("  "), entries.last_mut()) {
+            (None, Some(&mut (_, ref mut desc))) if indent > entry_indent => {
+                if !desc.is_empty() {
+                    desc.push(' ');
+                }
+                desc.push_str(line);
+            },
+            (split, _) => {
+                let (name, desc) = split.map(|i| (&line[..i], line[i..].trim())).unwrap_or((line, ""));
+                entries.push((name.to_string(), desc.to_string()));
+                entry_indent = indent;
+            },
+        }
+    }
+    sections
+}
+
+fn write_entries(page: &mut String, entries: &[(String, String)]) {
+    for (name, desc) in entries {
+        let _ = writeln!(page, ".TP\n\\fB{}\\fR\n{}", escape(name), escape(desc));
+    }
+}
+
+fn write_usage(page: &mut String, entries: &[(String, String)]) {
+    for (usage, _) in entries {
+        let _ = writeln!(page, ".PP\n{}", escape(usage));
+    }
+}
+
+/// Render the page from `help`, the help text of the whole command line,
+/// and `sub_help`, giving the help text of a subcommand by its name.
+pub fn render<F: Fn(&str) -> Option<String>>(version: &str, help: &str, sub_help: F) -> String {
+    let mut page = String::new();
+    let _ = writeln!(page, ".TH WMINSPECT 1 \"\" \"wminspect {}\" \"User Commands\"", escape(version));
+    page.push_str(".SH NAME\nwminspect \\- window manager inspector\n");
+
+    for (title, entries) in sections(help) {
+        match title.as_str() {
+            "USAGE" => {
+                page.push_str(".SH SYNOPSIS\n");
+                write_usage(&mut page, &entries);
+            },
+            "SUBCOMMANDS" => {
+                page.push_str(".SH SUBCOMMANDS\n");
+                write_entries(&mut page, &entries);
+                for (name, help) in entries.iter().filter_map(|(name, _)| sub_help(name).map(|h| (name, h))) {
+                    let _ = writeln!(page, ".SS {}", escape(name));
+                    for (sub_title, sub_entries) in sections(&help) {
+                        if sub_title == "USAGE" {
+                            write_usage(&mut page, &sub_entries);
+                            continue;
+                        }
+                        let _ = writeln!(page, ".PP\n{}:", escape(&sub_title.to_lowercase()));
+                        write_entries(&mut page, &sub_entries);
+                    }
+                }
+            },
+            _ => {
+                let _ = writeln!(page, ".SH {}", escape(&title));
+                write_entries(&mut page, &entries);
+            },
+        }
+    }
+
+    page.push_str(".SH FILTER GRAMMAR\n.nf\n");
+    for line in filter_grammar().lines() {
+        let _ = writeln!(page, "{}", escape(line));
+    }
+    page.push_str(".fi\n");
+
+    page.push_str(".SH EXIT STATUS\n\
+                   Listing exits 0 if any window passes the filter, 1 if none does and 2 on errors.\n\
+                   .SH FILES\n\
+                   .TP\n\\fI~/.config/wminspect/config.toml\\fR\n\
+                   keys, colors, special windows, defaults and profiles of options\n\
+                   .TP\n\\fI~/.config/wminspect/sheets/default.rule\\fR\n\
+                   rules loaded before any other\n");
+    page
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HELP: &str = "\
+window manager inspector 0.3.0
+
+USAGE:
+    wminspect [FLAGS] [OPTIONS] [SUBCOMMAND]
+
+FLAGS:
+    -c, --colored     output info with color
+        --no-color    never color output
+
+OPTIONS:
+        --log-level <LEVEL>    how much to log [values: off, error,
+                               warn]
+    -f, --filter <RULE>
+            filter rule
+
+SUBCOMMANDS:
+    query    list windows passing a rule
+";
+
+    #[test]
+    fn test_sections() {
+        let sections = sections(HELP);
+        let titles: Vec<&str> = sections.iter().map(|s| s.0.as_str()).collect();
+        assert_eq!(titles, vec!["USAGE", "FLAGS", "OPTIONS", "SUBCOMMANDS"]);
+        assert_eq!(sections[0].1, vec![("wminspect [FLAGS] [OPTIONS] [SUBCOMMAND]".to_string(), String::new())]);
+        assert_eq!(sections[1].1[1], ("--no-color".to_string(), "never color output".to_string()));
+        assert_eq!(sections[2].1[0].1, "how much to log [values: off, error, warn]");
+        assert_eq!(sections[2].1[1], ("-f, --filter <RULE>".to_string(), "filter rule".to_string()));
+    }
+
+    #[test]
+    fn test_render() {
+        let query = "USAGE:\n    wminspect query <RULE>\n\nOPTIONS:\n        --fields <FIELDS>    fields to print\n";
+        let page = render("0.3.0", HELP, |name| if name == "query" { Some(query.to_string()) } else { None });
+        assert!(page.starts_with(".TH WMINSPECT 1 \"\" \"wminspect 0.3.0\""), "{}", page);
+        assert!(page.contains(".SH SYNOPSIS\n.PP\nwminspect [FLAGS] [OPTIONS] [SUBCOMMAND]\n"), "{}", page);
+        assert!(page.contains(".TP\n\\fB\\-c, \\-\\-colored\\fR\noutput info with color\n"), "{}", page);
+        assert!(page.contains(".SS query\n.PP\nwminspect query <RULE>\n"), "{}", page);
+        assert!(page.contains("\\fB\\-\\-fields <FIELDS>\\fR\nfields to print\n"), "{}", page);
+        assert!(page.contains(".SH FILTER GRAMMAR\n.nf\n"), "{}", page);
+        assert!(!page.contains("\n.SH SUBCOMMANDS\n.SH"), "{}", page);
+    }
+}
@@ -0,0 +1,6 @@
+//! The types most users need, `use wminspect::prelude::*;` to get them all.
+//!
+//! Everything else lives in `wminspect::wm` and its submodules.
+
+pub use wm::{Context, ContextBuilder, Options, OutputFormat, Filter, Window, WindowId,
+             Geometry, MapState, Snapshot, WindowEvent, WmError, WmResult};
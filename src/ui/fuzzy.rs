@@ -0,0 +1,41 @@
+/// Score of `text` matching `pattern` as a case insensitive subsequence,
+/// None if it does not match. Consecutive chars and chars starting a word
+/// score more, so `ddock` prefers `dde-dock` to `dde-desktop-osd-clock`.
+pub(crate) fn score(pattern: &str, text: &str) -> Option<i32> {
+    let text: Vec<char> = text.chars().flat_map(|c| c.to_lowercase()).collect();
+    let mut score = 0;
+    let mut pos = 0;
+    let mut last: Option<usize> = None;
+
+    for p in pattern.chars().flat_map(|c| c.to_lowercase()) {
+        let found = text[pos..].iter().position(|&c| c == p)? + pos;
+        score += 1;
+        if last.is_some_and(|l| l + 1 == found) {
+            score += 4;
+        } else if let Some(l) = last {
+            // gaps cost, but never more than a match is worth
+            score -= ((found - l - 1) as i32).min(3);
+        }
+        if found == 0 || !text[found - 1].is_alphanumeric() {
+            score += 2;
+        }
+        last = Some(found);
+        pos = found + 1;
+    }
+
+    Some(score)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_score() {
+        assert!(score("xyz", "dde-dock").is_none());
+        assert_eq!(score("", "dde-dock"), Some(0));
+        assert!(score("DDock", "dde-dock").is_some());
+        assert!(score("ddock", "dde-dock") > score("ddock", "dde-desktop-osd-clock"));
+        assert!(score("0x1a", "0x1a00003") > score("0x1a", "0x100a003"));
+    }
+}
@@ -0,0 +1,267 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use config::KeyList;
+use wm::{WmError, WmResult};
+use super::term::Key;
+
+/// What a key does out of the input boxes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    Top,
+    Bottom,
+    /// scroll the event log back
+    LogUp,
+    LogDown,
+    /// stop or resume following new events
+    TogglePause,
+    /// open the filter input box
+    EditFilter,
+    /// open the search box
+    Search,
+    /// order the list by the next of the sort keys
+    CycleSort,
+    /// show windows as a tree of transients
+    ToggleTree,
+    Expand,
+    /// collapse the selected node, or go to its parent
+    Collapse,
+    /// show only the subtree of the selected window, or all again
+    Reroot,
+    /// show or hide the pinned windows panel
+    TogglePinned,
+    /// outline the selected window on screen
+    Flash,
+    /// write the listed windows to a file
+    Export,
+    /// write details of the selected window to a file
+    ExportWindow,
+    /// show all properties of the selected window in place of its details
+    Properties,
+    /// open the command palette
+    Palette,
+    /// show the key bindings
+    Help,
+    Quit,
+}
+
+/// commands by their names in the config file, in the order of the help
+const COMMANDS: &[(&str, Command)] = &[
+    ("up", Command::Up),
+    ("down", Command::Down),
+    ("page-up", Command::PageUp),
+    ("page-down", Command::PageDown),
+    ("top", Command::Top),
+    ("bottom", Command::Bottom),
+    ("log-up", Command::LogUp),
+    ("log-down", Command::LogDown),
+    ("pause", Command::TogglePause),
+    ("filter", Command::EditFilter),
+    ("search", Command::Search),
+    ("cycle-sort", Command::CycleSort),
+    ("tree", Command::ToggleTree),
+    ("expand", Command::Expand),
+    ("collapse", Command::Collapse),
+    ("reroot", Command::Reroot),
+    ("pinned", Command::TogglePinned),
+    ("flash", Command::Flash),
+    ("export", Command::Export),
+    ("export-window", Command::ExportWindow),
+    ("props", Command::Properties),
+    ("palette", Command::Palette),
+    ("help", Command::Help),
+    ("quit", Command::Quit),
+];
+
+const DEFAULTS: &[(&str, &[&str])] = &[
+    ("up", &["k", "Up"]),
+    ("down", &["j", "Down"]),
+    ("page-up", &["C-b", "PageUp"]),
+    ("page-down", &["C-f", "PageDown"]),
+    ("top", &["gg", "Home"]),
+    ("bottom", &["G", "End"]),
+    ("log-up", &["["]),
+    ("log-down", &["]"]),
+    ("pause", &["p"]),
+    ("filter", &["f"]),
+    ("search", &["/"]),
+    ("cycle-sort", &["s"]),
+    ("tree", &["t"]),
+    ("expand", &["l", "Right"]),
+    ("collapse", &["h", "Left"]),
+    ("reroot", &["r"]),
+    ("pinned", &["P"]),
+    ("flash", &["o"]),
+    ("export", &["e"]),
+    ("export-window", &["E"]),
+    ("props", &["i"]),
+    ("palette", &[":"]),
+    ("help", &["?"]),
+    ("quit", &["q", "C-c"]),
+];
+
+/// how a sequence of keys relates to the bindings
+#[derive(Debug, PartialEq, Eq)]
+pub enum Lookup {
+    Command(Command),
+    /// the start of a longer binding, e.g. `g` of `gg`
+    Prefix,
+    Unbound,
+}
+
+/// Key sequences bound to commands, vim-style by default.
+#[derive(Debug, Clone)]
+pub struct Keymap {
+    bindings: Vec<(Vec<Key>, Command)>,
+}
+
+impl Default for Keymap {
+    fn default() -> Keymap {
+        Keymap::from_config(&BTreeMap::new()).expect("bad default key bindings")
+    }
+}
+
+impl Keymap {
+    /// the defaults with commands in `keys` rebound, as read from the
+    /// `[keys]` table of the config file
+    pub fn from_config(keys: &BTreeMap<String, KeyList>) -> WmResult<Keymap> {
+        let mut all: BTreeMap<String, KeyList> = DEFAULTS.iter()
+            .map(|&(name, keys)| (name.to_string(), KeyList::Many(keys.iter().map(|k| k.to_string()).collect())))
+            .collect();
+        for (name, keys) in keys {
+            all.insert(name.clone(), keys.clone());
+        }
+        Keymap::with_bindings(&all)
+    }
+
+    fn with_bindings(keys: &BTreeMap<String, KeyList>) -> WmResult<Keymap> {
+        let mut bindings = Vec::new();
+        for (name, keys) in keys {
+            let cmd = command(name)
+                .ok_or_else(|| WmError::Config(format!("unknown command {} in [keys]", name)))?;
+            for k in keys.keys() {
+                let seq = parse_binding(k)
+                    .ok_or_else(|| WmError::Config(format!("bad key {:?} for {}", k, name)))?;
+                bindings.push((seq, cmd));
+            }
+        }
+        Ok(Keymap { bindings })
+    }
+
+    pub fn lookup(&self, seq: &[Key]) -> Lookup {
+        let mut prefix = false;
+        for &(ref keys, cmd) in &self.bindings {
+            if keys.as_slice() == seq {
+                return Lookup::Command(cmd);
+            }
+            prefix = prefix || keys.starts_with(seq);
+        }
+        if prefix { Lookup::Prefix } else { Lookup::Unbound }
+    }
+
+    /// key sequences bound to `cmd`, as written in the config file
+    pub fn keys(&self, cmd: Command) -> Vec<String> {
+        self.bindings.iter().filter(|b| b.1 == cmd)
+            .map(|b| b.0.iter().map(|k| k.to_string()).collect())
+            .collect()
+    }
+
+    /// (keys, command name) of every command, for the help overlay
+    pub fn help(&self) -> Vec<(String, &'static str)> {
+        COMMANDS.iter().map(|&(name, cmd)| (self.keys(cmd).join(", "), name)).collect()
+    }
+}
+
+/// the command called `name` in the config file
+pub(crate) fn command(name: &str) -> Option<Command> {
+    COMMANDS.iter().find(|&&(n, _)| n == name).map(|&(_, cmd)| cmd)
+}
+
+/// names of all commands, in the order of the help
+pub(crate) fn command_names() -> impl Iterator<Item = &'static str> {
+    COMMANDS.iter().map(|&(name, _)| name)
+}
+
+/// a key name like `Down` or `C-x`, or chars typed in a row like `gg`
+fn parse_binding(s: &str) -> Option<Vec<Key>> {
+    let key = match s {
+        "Up" => Key::Up,
+        "Down" => Key::Down,
+        "Left" => Key::Left,
+        "Right" => Key::Right,
+        "PageUp" => Key::PageUp,
+        "PageDown" => Key::PageDown,
+        "Home" => Key::Home,
+        "End" => Key::End,
+        "Enter" => Key::Enter,
+        "Tab" => Key::Tab,
+        "Backspace" => Key::Backspace,
+        "Esc" => Key::Esc,
+        _ if s.starts_with("C-") && s.chars().count() == 3 => {
+            let c = s.chars().last().unwrap().to_ascii_lowercase();
+            if !c.is_ascii_lowercase() {
+                return None;
+            }
+            Key::Ctrl(c)
+        },
+        "" => return None,
+        _ => return Some(s.chars().map(Key::Char).collect()),
+    };
+    Some(vec![key])
+}
+
+/// the name used in the config file
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Key::Char(c) => write!(f, "{}", c),
+            Key::Ctrl(c) => write!(f, "C-{}", c),
+            Key::Up => write!(f, "Up"),
+            Key::Down => write!(f, "Down"),
+            Key::Left => write!(f, "Left"),
+            Key::Right => write!(f, "Right"),
+            Key::PageUp => write!(f, "PageUp"),
+            Key::PageDown => write!(f, "PageDown"),
+            Key::Home => write!(f, "Home"),
+            Key::End => write!(f, "End"),
+            Key::Enter => write!(f, "Enter"),
+            Key::Tab => write!(f, "Tab"),
+            Key::Backspace => write!(f, "Backspace"),
+            Key::Esc => write!(f, "Esc"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keymap() {
+        let keymap = Keymap::default();
+        assert_eq!(keymap.lookup(&[Key::Char('j')]), Lookup::Command(Command::Down));
+        assert_eq!(keymap.lookup(&[Key::Char('g')]), Lookup::Prefix);
+        assert_eq!(keymap.lookup(&[Key::Char('g'), Key::Char('g')]), Lookup::Command(Command::Top));
+        assert_eq!(keymap.lookup(&[Key::Ctrl('c')]), Lookup::Command(Command::Quit));
+        assert_eq!(keymap.lookup(&[Key::Char('x')]), Lookup::Unbound);
+        assert_eq!(keymap.keys(Command::PageDown), vec!["C-f", "PageDown"]);
+
+        // rebinding a command drops its default keys only
+        let mut keys = BTreeMap::new();
+        keys.insert("down".to_string(), KeyList::One("n".to_string()));
+        let keymap = Keymap::from_config(&keys).unwrap();
+        assert_eq!(keymap.lookup(&[Key::Char('n')]), Lookup::Command(Command::Down));
+        assert_eq!(keymap.lookup(&[Key::Char('j')]), Lookup::Unbound);
+        assert_eq!(keymap.lookup(&[Key::Char('k')]), Lookup::Command(Command::Up));
+
+        keys.insert("jump".to_string(), KeyList::One("J".to_string()));
+        assert!(Keymap::from_config(&keys).is_err());
+        keys.remove("jump");
+        keys.insert("up".to_string(), KeyList::One("C-1".to_string()));
+        assert!(Keymap::from_config(&keys).is_err());
+    }
+}
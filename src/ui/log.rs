@@ -0,0 +1,74 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use wm::{EventSink, Window, WindowEvent};
+
+/// A monitor event and when it was seen, relative to the start of the log.
+#[derive(Debug, Clone)]
+pub struct LogEntry {
+    pub elapsed: Duration,
+    pub event: WindowEvent,
+}
+
+impl LogEntry {
+    /// one line for the log pane, without colors
+    pub fn describe(&self) -> String {
+        let what = match self.event {
+            WindowEvent::Created { window, parent } => format!("create {}, parent {}", window, parent),
+            WindowEvent::Destroyed { window } => format!("destroy {}", window),
+            WindowEvent::Reparented { window, parent } => format!("reparent {} to {}", window, parent),
+            WindowEvent::Configured { window, above_sibling, new, .. } => {
+                format!("configure {} {} above: {}", window, new, above_sibling)
+            },
+            WindowEvent::Mapped { window } => format!("map {}", window),
+            WindowEvent::Unmapped { window } => format!("unmap {}", window),
+            WindowEvent::PropertyChanged { window, kind } => format!("property {:?} of {}", kind, window),
+        };
+        format!("{:>5}.{:03} {}", self.elapsed.as_secs(), self.elapsed.subsec_millis(), what)
+    }
+
+    /// ansi color of the entry, by event type
+    pub fn color(&self) -> &'static str {
+        match self.event {
+            WindowEvent::Created { .. } => "\x1b[32m",
+            WindowEvent::Destroyed { .. } => "\x1b[31m",
+            WindowEvent::Reparented { .. } => "\x1b[34m",
+            WindowEvent::Configured { .. } => "\x1b[33m",
+            WindowEvent::Mapped { .. } => "\x1b[36m",
+            WindowEvent::Unmapped { .. } => "\x1b[35m",
+            WindowEvent::PropertyChanged { .. } => "",
+        }
+    }
+}
+
+/// `EventSink` collecting monitor events until the TUI takes them.
+pub struct EventLog {
+    start: Instant,
+    pending: Mutex<Vec<LogEntry>>,
+}
+
+impl EventLog {
+    pub fn new() -> EventLog {
+        EventLog { start: Instant::now(), pending: Mutex::new(Vec::new()) }
+    }
+
+    /// events published since last time
+    pub fn take(&self) -> Vec<LogEntry> {
+        ::std::mem::take(&mut *self.pending.lock().unwrap())
+    }
+}
+
+impl Default for EventLog {
+    fn default() -> EventLog {
+        EventLog::new()
+    }
+}
+
+impl EventSink for EventLog {
+    fn publish(&self, ev: Option<&WindowEvent>, _windows: &[Window]) {
+        if let Some(ev) = ev {
+            let entry = LogEntry { elapsed: self.start.elapsed(), event: *ev };
+            self.pending.lock().unwrap().push(entry);
+        }
+    }
+}
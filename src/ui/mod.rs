@@ -0,0 +1,1410 @@
+//! Interactive window browser: the filtered windows on the left and details
+//! of the selected one on the right, kept live by a background monitor.
+
+mod fuzzy;
+mod keymap;
+mod log;
+mod palette;
+mod term;
+mod tree;
+
+use std::collections::{HashSet, VecDeque};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::mpsc::Receiver;
+use std::thread;
+use std::time::Duration;
+
+use wm::history::{Entry, clock};
+use wm::{Context, Filter, GlobalState, LayoutChange, Options, OutputFormat, Property, Theme, Window, WindowAction,
+         WindowEvent, WindowId, WmError, WmResult, spawn_monitor, write_window, write_windows};
+use self::palette::Action;
+use self::term::Terminal;
+use self::tree::Node;
+pub use self::term::Key;
+pub use self::keymap::{Command, Keymap, Lookup};
+pub use self::log::{EventLog, LogEntry};
+
+/// how long to wait for keys before checking for layout changes
+const TICK: Duration = Duration::from_millis(100);
+
+/// events kept in the log pane
+const SCROLLBACK: usize = 1000;
+
+/// most windows shown in the pinned panel
+const PINNED_ROWS: usize = 5;
+
+/// how long the selected window is outlined on screen
+const FLASH: Duration = Duration::from_millis(800);
+
+/// Rule being typed into the filter input box.
+#[derive(Default)]
+struct FilterInput {
+    text: String,
+    /// the rule parsed from `text`, None if empty or broken
+    preview: Option<Filter>,
+    error: Option<String>,
+}
+
+impl FilterInput {
+    fn update(&mut self) {
+        self.preview = None;
+        self.error = None;
+        if self.text.trim().is_empty() {
+            return;
+        }
+
+        // the same check as committing it to the active filter
+        let mut filter = Filter::new();
+        match filter.add_rule(&self.text) {
+            Ok(_) => self.preview = Some(filter),
+            Err(e) => self.error = Some(short_error(&e)),
+        }
+    }
+}
+
+/// Fuzzy search over names and ids of the listed windows.
+struct Search {
+    query: String,
+    /// selected window before searching, back to it if canceled
+    origin: Option<WindowId>,
+}
+
+impl Search {
+    /// how well `w` matches, None if it does not
+    fn score(&self, w: &Window) -> Option<i32> {
+        let by_name = fuzzy::score(&self.query, &w.name);
+        let by_id = fuzzy::score(&self.query, &w.id.to_string());
+        by_name.max(by_id)
+    }
+}
+
+/// Path being typed to export windows to.
+struct ExportInput {
+    path: String,
+    /// the selected window only, not the whole list
+    window: bool,
+    error: Option<String>,
+}
+
+/// Properties of the selected window shown in place of its details.
+#[derive(Default)]
+struct PropsTab {
+    /// whose properties `list` has, fetched by the main loop
+    window: Option<WindowId>,
+    list: Vec<Property>,
+    /// a property of `window` changed since it was fetched
+    stale: bool,
+    /// narrows the list by property names
+    query: String,
+    /// typing into `query`
+    editing: bool,
+}
+
+impl PropsTab {
+    fn matches(&self, p: &Property) -> bool {
+        p.name.to_lowercase().contains(&self.query.to_lowercase())
+    }
+}
+
+/// Line typed into the command palette.
+#[derive(Default)]
+struct PaletteInput {
+    text: String,
+    error: Option<String>,
+}
+
+/// Order of the window list, `Stack` is bottom to top.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum SortKey {
+    #[default]
+    Stack,
+    Name,
+    Id,
+    /// largest first
+    Area,
+}
+
+impl SortKey {
+    /// by names of the `sort` command
+    pub(crate) const ALL: &'static [(&'static str, SortKey)] = &[
+        ("stack", SortKey::Stack), ("name", SortKey::Name), ("id", SortKey::Id), ("area", SortKey::Area),
+    ];
+
+    fn sort(self, windows: &mut [&Window]) {
+        match self {
+            SortKey::Stack => {},
+            SortKey::Name => windows.sort_by_cached_key(|w| w.name.to_lowercase()),
+            SortKey::Id => windows.sort_by_key(|w| w.id.0),
+            SortKey::Area => windows.sort_by_key(|w| ::std::cmp::Reverse(w.geom.width as u32 * w.geom.height as u32)),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        SortKey::ALL.iter().find(|&&(_, key)| key == self).map(|&(name, _)| name).unwrap()
+    }
+
+    /// the one after it in `ALL`, back to the first after the last
+    fn next(self) -> SortKey {
+        let i = SortKey::ALL.iter().position(|&(_, key)| key == self).unwrap_or(0);
+        SortKey::ALL[(i + 1) % SortKey::ALL.len()].1
+    }
+}
+
+/// Something only the main loop can do, it takes them with `take_requests`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Request {
+    /// outline the window on screen
+    Flash(WindowId),
+    /// flip an option of the context
+    Toggle(Options),
+    Control(WindowId, WindowAction),
+}
+
+/// format of an exported file by its extension, text by default
+fn export_format(path: &str) -> OutputFormat {
+    match Path::new(path).extension().and_then(|e| e.to_str()) {
+        Some("json") => OutputFormat::Json,
+        Some("csv") => OutputFormat::Csv,
+        _ => OutputFormat::Default,
+    }
+}
+
+/// one line version of `e`, the input box shows where the error is
+fn short_error(e: &WmError) -> String {
+    match *e {
+        WmError::Dsl(ref e) => format!("{} at col {}", e.kind, e.column),
+        ref e => e.to_string(),
+    }
+}
+
+/// Browsing state of the window list, rendering is independent of the
+/// terminal so it can be checked in tests.
+#[derive(Default)]
+pub struct App {
+    /// filtered windows in stacking order (bottom -> top)
+    windows: Vec<Window>,
+    pinned: HashSet<WindowId>,
+    /// windows matched by pin rules, filtered or not, in stacking order
+    pinned_windows: Vec<Window>,
+    /// the pinned panel is hidden
+    hide_pinned: bool,
+    selected: usize,
+    /// first window shown in the list
+    offset: usize,
+    log: VecDeque<LogEntry>,
+    /// entries scrolled back from the newest one
+    log_scroll: usize,
+    /// keep the log view still while new events come
+    paused: bool,
+    /// Some while editing a filter rule
+    input: Option<FilterInput>,
+    /// Some while searching
+    search: Option<Search>,
+    keymap: Keymap,
+    /// keys typed so far of a longer binding, e.g. `g` of `gg`
+    pending: Vec<Key>,
+    /// the key bindings overlay is shown
+    help: bool,
+    /// windows are shown as a tree of transients
+    tree: bool,
+    /// nodes of the tree with their children hidden
+    collapsed: HashSet<WindowId>,
+    /// only the subtree of this window is shown
+    tree_root: Option<WindowId>,
+    /// what the main loop has to do, oldest first
+    requests: Vec<Request>,
+    /// Some while typing into the command palette
+    palette: Option<PaletteInput>,
+    sort: SortKey,
+    /// Some while typing where to export
+    export: Option<ExportInput>,
+    /// Some while the properties tab is shown
+    props: Option<PropsTab>,
+    /// names the selected window had, fetched again on changes
+    titles: Option<(WindowId, Vec<Entry<String>>)>,
+    /// result of the last action, shown until the next key
+    status: Option<String>,
+    theme: Theme,
+}
+
+impl App {
+    pub fn new() -> App {
+        App::default()
+    }
+
+    pub fn with_keymap(keymap: Keymap) -> App {
+        App { keymap, ..App::default() }
+    }
+
+    pub fn set_theme(&mut self, theme: Theme) {
+        self.theme = theme;
+    }
+
+    /// reload windows from `state`, keeping the same window selected if
+    /// it is still there
+    pub fn refresh(&mut self, state: &GlobalState) {
+        let current = self.selected_window().map(|w| w.id);
+        let snapshot = state.snapshot();
+        let filtered: HashSet<WindowId> = snapshot.filtered.iter().cloned().collect();
+
+        self.pinned = snapshot.pinned.into_iter().collect();
+        self.pinned_windows = snapshot.windows.iter().filter(|w| self.pinned.contains(&w.id)).cloned().collect();
+        self.windows = snapshot.windows.into_iter().filter(|w| filtered.contains(&w.id)).collect();
+        self.titles = None;
+        self.reselect(current);
+    }
+
+    /// select window `id` again if still visible
+    fn reselect(&mut self, id: Option<WindowId>) {
+        let visible = self.visible();
+        let selected = id.and_then(|id| visible.iter().position(|w| w.id == id))
+            .unwrap_or_else(|| self.selected.min(visible.len().saturating_sub(1)));
+        self.selected = selected;
+    }
+
+    /// windows in the list, narrowed by the rule being edited or by the
+    /// search if any
+    fn visible(&self) -> Vec<&Window> {
+        self.rows().into_iter().map(|(w, _)| w).collect()
+    }
+
+    /// visible windows with their place in the tree in tree mode
+    fn rows(&self) -> Vec<(&Window, Option<Node>)> {
+        let preview = self.input.as_ref().and_then(|input| input.preview.as_ref());
+        let search = self.search.as_ref().filter(|search| !search.query.is_empty());
+        let mut windows: Vec<&Window> = self.windows.iter()
+            .filter(|w| preview.is_none_or(|f| f.apply_to(w)))
+            .filter(|w| search.is_none_or(|search| search.score(w).is_some()))
+            .collect();
+        self.sort.sort(&mut windows);
+        if self.tree {
+            tree::flatten(&windows, self.tree_root, &self.collapsed).into_iter()
+                .map(|(w, node)| (w, Some(node)))
+                .collect()
+        } else {
+            windows.into_iter().map(|w| (w, None)).collect()
+        }
+    }
+
+    pub fn push_events<I: IntoIterator<Item = LogEntry>>(&mut self, entries: I) {
+        for entry in entries {
+            if self.log.len() == SCROLLBACK {
+                self.log.pop_front();
+            }
+            if let WindowEvent::PropertyChanged { window, .. } = entry.event {
+                if let Some(ref mut props) = self.props {
+                    props.stale |= props.window == Some(window);
+                }
+            }
+            self.log.push_back(entry);
+            if self.paused {
+                self.log_scroll = (self.log_scroll + 1).min(self.log.len().saturating_sub(1));
+            }
+        }
+    }
+
+    pub fn selected_window(&self) -> Option<&Window> {
+        self.visible().get(self.selected).cloned()
+    }
+
+    /// the window whose properties the tab needs, if they are not fetched
+    /// yet or changed since
+    pub fn props_wanted(&self) -> Option<WindowId> {
+        let props = self.props.as_ref()?;
+        let current = self.selected_window()?.id;
+        if props.stale || props.window != Some(current) { Some(current) } else { None }
+    }
+
+    /// fill the properties tab with `list` of window `id`
+    pub fn set_props(&mut self, id: WindowId, list: Vec<Property>) {
+        if let Some(ref mut props) = self.props {
+            props.window = Some(id);
+            props.list = list;
+            props.stale = false;
+        }
+    }
+
+    /// the window whose past names the details need, if not fetched yet
+    pub fn titles_wanted(&self) -> Option<WindowId> {
+        let current = self.selected_window()?.id;
+        match self.titles {
+            Some((id, _)) if id == current => None,
+            _ => Some(current),
+        }
+    }
+
+    /// past names of window `id`, oldest first
+    pub fn set_titles(&mut self, id: WindowId, titles: Vec<Entry<String>>) {
+        self.titles = Some((id, titles));
+    }
+
+    /// what was asked of the main loop since last time
+    pub fn take_requests(&mut self) -> Vec<Request> {
+        ::std::mem::take(&mut self.requests)
+    }
+
+    /// show `status` until the next key, e.g. how a request went
+    pub fn set_status<S: Into<String>>(&mut self, status: S) {
+        self.status = Some(status.into());
+    }
+
+    /// `page` is the rows of the window list, rules typed in the filter
+    /// input box go to the filter of `state`.
+    /// return false when asked to quit
+    pub fn handle_key(&mut self, key: Key, page: usize, state: &GlobalState) -> bool {
+        if self.search.is_some() {
+            self.edit_search(key);
+            return key != Key::Ctrl('c');
+        }
+        if self.input.is_some() {
+            self.edit_filter(key, state);
+            return key != Key::Ctrl('c');
+        }
+        if self.export.is_some() {
+            self.edit_export(key);
+            return key != Key::Ctrl('c');
+        }
+        if self.props.as_ref().is_some_and(|props| props.editing) {
+            self.edit_props_query(key);
+            return key != Key::Ctrl('c');
+        }
+        if self.palette.is_some() {
+            return self.edit_palette(key, page, state) && key != Key::Ctrl('c');
+        }
+        self.status = None;
+
+        // any key closes the help
+        if self.help {
+            self.help = false;
+            return key != Key::Ctrl('c');
+        }
+
+        self.pending.push(key);
+        let cmd = match self.keymap.lookup(&self.pending) {
+            Lookup::Command(cmd) => Some(cmd),
+            Lookup::Prefix => return true,
+            // a broken sequence like `gj` starts over from its last key
+            Lookup::Unbound if self.pending.len() > 1 => {
+                self.pending = vec![key];
+                match self.keymap.lookup(&self.pending) {
+                    Lookup::Command(cmd) => Some(cmd),
+                    Lookup::Prefix => return true,
+                    Lookup::Unbound => None,
+                }
+            },
+            Lookup::Unbound => None,
+        };
+        self.pending.clear();
+        match cmd {
+            Some(cmd) => self.run(cmd, page),
+            None => true,
+        }
+    }
+
+    fn edit_filter(&mut self, key: Key, state: &GlobalState) {
+        let current = self.selected_window().map(|w| w.id);
+        {
+            let input = self.input.as_mut().unwrap();
+            match key {
+                Key::Char(c) => input.text.push(c),
+                Key::Backspace => { input.text.pop(); },
+                Key::Enter if input.text.trim().is_empty() => self.input = None,
+                Key::Enter => match state.add_rule(&input.text) {
+                    Ok(_) => self.input = None,
+                    Err(e) => input.error = Some(short_error(&e)),
+                },
+                Key::Esc | Key::Ctrl('c') => self.input = None,
+                _ => {},
+            }
+        }
+
+        if let Some(ref mut input) = self.input {
+            if key != Key::Enter {
+                input.update();
+            }
+        } else {
+            // committed or canceled
+            self.refresh(state);
+        }
+        self.reselect(current);
+    }
+
+    fn edit_export(&mut self, key: Key) {
+        let export = self.export.as_mut().unwrap();
+        match key {
+            Key::Char(c) => export.path.push(c),
+            Key::Backspace => { export.path.pop(); },
+            Key::Enter if export.path.trim().is_empty() => self.export = None,
+            Key::Enter => {
+                let (path, window) = (export.path.trim().to_string(), export.window);
+                match self.export_to(&path, window) {
+                    Ok(status) => {
+                        self.status = Some(status);
+                        self.export = None;
+                    },
+                    Err(e) => self.export.as_mut().unwrap().error = Some(short_error(&e)),
+                }
+            },
+            Key::Esc | Key::Ctrl('c') => self.export = None,
+            _ => {},
+        }
+    }
+
+    /// write the listed windows, or the selected one if `window`, to
+    /// `path` in the format told by its extension
+    fn export_to(&self, path: &str, window: bool) -> WmResult<String> {
+        let format = export_format(path);
+        let mut out = BufWriter::new(File::create(path)?);
+        let status = if window {
+            let w = self.selected_window().ok_or(WmError::Request("export"))?;
+            write_window(&mut out, w, format)?;
+            format!("exported {} to {}", w.id, path)
+        } else {
+            let windows: Vec<Window> = self.visible().into_iter().cloned().collect();
+            write_windows(&mut out, &windows, format)?;
+            format!("exported {} windows to {}", windows.len(), path)
+        };
+        out.flush()?;
+        Ok(status)
+    }
+
+    /// return false when the palette runs `quit`
+    fn edit_palette(&mut self, key: Key, page: usize, state: &GlobalState) -> bool {
+        let palette = self.palette.as_mut().unwrap();
+        palette.error = None;
+        let action = match key {
+            Key::Char(c) => { palette.text.push(c); return true; },
+            Key::Backspace => { palette.text.pop(); return true; },
+            Key::Tab => { palette.text = palette::complete(&palette.text); return true; },
+            Key::Enter if palette.text.trim().is_empty() => { self.palette = None; return true; },
+            Key::Enter => match palette::parse(&palette.text) {
+                Ok(action) => action,
+                Err(e) => { palette.error = Some(e); return true; },
+            },
+            Key::Esc | Key::Ctrl('c') => { self.palette = None; return true; },
+            _ => return true,
+        };
+
+        let current = self.selected_window().map(|w| w.id);
+        let done = match action {
+            Action::Run(cmd) => {
+                self.palette = None;
+                return self.run(cmd, page);
+            },
+            Action::AddRule(rule) => match state.add_rule(&rule) {
+                Ok(_) => { self.refresh(state); Ok(()) },
+                Err(e) => Err(short_error(&e)),
+            },
+            Action::Export(path, window) => match self.export_to(&path, window) {
+                Ok(status) => { self.status = Some(status); Ok(()) },
+                Err(e) => Err(short_error(&e)),
+            },
+            Action::Toggle(opt) => { self.requests.push(Request::Toggle(opt)); Ok(()) },
+            Action::Sort(key) => {
+                self.sort = key;
+                self.reselect(current);
+                Ok(())
+            },
+            Action::Control(action) => match current {
+                Some(id) => { self.requests.push(Request::Control(id, action)); Ok(()) },
+                None => Err("no window selected".to_string()),
+            },
+        };
+        match done {
+            Ok(()) => self.palette = None,
+            Err(e) => self.palette.as_mut().unwrap().error = Some(e),
+        }
+        true
+    }
+
+    fn edit_props_query(&mut self, key: Key) {
+        let props = self.props.as_mut().unwrap();
+        match key {
+            Key::Char(c) => props.query.push(c),
+            Key::Backspace => { props.query.pop(); },
+            Key::Enter => props.editing = false,
+            Key::Esc | Key::Ctrl('c') => {
+                props.query.clear();
+                props.editing = false;
+            },
+            _ => {},
+        }
+    }
+
+    fn edit_search(&mut self, key: Key) {
+        let search = self.search.as_mut().unwrap();
+        match key {
+            Key::Char(c) => search.query.push(c),
+            Key::Backspace => { search.query.pop(); },
+            // keep the match selected in the whole list
+            Key::Enter => {
+                let current = self.selected_window().map(|w| w.id);
+                self.search = None;
+                return self.reselect(current);
+            },
+            Key::Esc | Key::Ctrl('c') => {
+                let origin = search.origin;
+                self.search = None;
+                return self.reselect(origin);
+            },
+            _ => return,
+        }
+
+        // jump to the best match, the topmost one of equal scores
+        let best = {
+            let search = self.search.as_ref().unwrap();
+            self.visible().iter().enumerate()
+                .max_by_key(|&(i, w)| (search.score(w), i))
+                .map(|(i, _)| i)
+        };
+        self.selected = best.unwrap_or(0);
+    }
+
+    fn run(&mut self, cmd: Command, page: usize) -> bool {
+        let last = self.visible().len().saturating_sub(1);
+        self.selected = match cmd {
+            Command::Up => self.selected.saturating_sub(1),
+            Command::Down => (self.selected + 1).min(last),
+            Command::PageUp => self.selected.saturating_sub(page.max(1)),
+            Command::PageDown => (self.selected + page.max(1)).min(last),
+            Command::Top => 0,
+            Command::Bottom => last,
+            Command::LogUp => {
+                self.log_scroll = (self.log_scroll + 1).min(self.log.len().saturating_sub(1));
+                self.paused = true;
+                self.selected
+            },
+            Command::LogDown => {
+                self.log_scroll = self.log_scroll.saturating_sub(1);
+                self.selected
+            },
+            Command::TogglePause => {
+                self.paused = !self.paused;
+                if !self.paused {
+                    self.log_scroll = 0;
+                }
+                self.selected
+            },
+            Command::EditFilter => {
+                self.input = Some(FilterInput::default());
+                self.selected
+            },
+            // searches property names while the properties tab is shown
+            Command::Search if self.props.is_some() => {
+                self.props.as_mut().unwrap().editing = true;
+                self.selected
+            },
+            Command::Search => {
+                let origin = self.selected_window().map(|w| w.id);
+                self.search = Some(Search { query: String::new(), origin });
+                self.selected
+            },
+            Command::CycleSort => {
+                let current = self.selected_window().map(|w| w.id);
+                self.sort = self.sort.next();
+                self.reselect(current);
+                self.selected
+            },
+            Command::ToggleTree | Command::Expand | Command::Collapse | Command::Reroot => {
+                self.run_tree(cmd);
+                self.selected
+            },
+            Command::Export | Command::ExportWindow => {
+                let window = cmd == Command::ExportWindow;
+                if !window || self.selected_window().is_some() {
+                    self.export = Some(ExportInput { path: String::new(), window, error: None });
+                }
+                self.selected
+            },
+            Command::Properties => {
+                self.props = match self.props {
+                    Some(_) => None,
+                    None => Some(PropsTab::default()),
+                };
+                self.selected
+            },
+            Command::TogglePinned => {
+                self.hide_pinned = !self.hide_pinned;
+                self.selected
+            },
+            Command::Flash => {
+                if let Some(w) = self.selected_window() {
+                    self.requests.push(Request::Flash(w.id));
+                }
+                self.selected
+            },
+            Command::Palette => {
+                self.palette = Some(PaletteInput::default());
+                self.selected
+            },
+            Command::Help => {
+                self.help = true;
+                self.selected
+            },
+            Command::Quit => return false,
+        };
+        true
+    }
+
+    fn run_tree(&mut self, cmd: Command) {
+        let (current, node) = match self.rows().get(self.selected) {
+            Some(&(w, node)) => (Some(w.id), node),
+            None => (None, None),
+        };
+        match (cmd, node) {
+            (Command::ToggleTree, _) => self.tree = !self.tree,
+            (Command::Expand, Some(_)) => { self.collapsed.remove(&current.unwrap()); },
+            (Command::Collapse, Some(node)) if node.children > 0 && node.expanded => {
+                self.collapsed.insert(current.unwrap());
+            },
+            (Command::Collapse, Some(node)) => return self.reselect(node.parent.or(current)),
+            (Command::Reroot, _) if current.is_some() => {
+                self.tree = true;
+                self.tree_root = if self.tree_root == current { None } else { current };
+            },
+            _ => {},
+        }
+        self.reselect(current);
+    }
+
+    /// rows of the window list and of the event log for a screen `height`
+    pub fn layout(height: usize) -> (usize, usize) {
+        // title and help lines, plus the title of the log pane if any
+        let rest = height.saturating_sub(2);
+        if rest < 6 {
+            (rest, 0)
+        } else {
+            let log_rows = rest / 3;
+            (rest - log_rows - 1, log_rows)
+        }
+    }
+
+    /// rows of the pinned panel out of `body` rows, its title included
+    fn pinned_rows(&self, body: usize) -> usize {
+        if self.hide_pinned || self.help || self.pinned_windows.is_empty() {
+            return 0;
+        }
+        match self.pinned_windows.len().min(PINNED_ROWS).min(body / 2) {
+            0 => 0,
+            n => n + 1,
+        }
+    }
+
+    /// rows of the window list for a screen `height`, a page to move by
+    pub fn list_rows(&self, height: usize) -> usize {
+        let body = App::layout(height).0;
+        body - self.pinned_rows(body)
+    }
+
+    /// lay out the screen of `width` x `height` into lines
+    pub fn render(&mut self, width: usize, height: usize) -> Vec<String> {
+        let (body, log_rows) = App::layout(height);
+        let pinned_rows = self.pinned_rows(body);
+        let body = body - pinned_rows;
+        if self.selected < self.offset {
+            self.offset = self.selected;
+        } else if body > 0 && self.selected >= self.offset + body {
+            self.offset = self.selected + 1 - body;
+        }
+
+        let list_width = (width * 2 / 5).max(20).min(width);
+        let detail_width = width.saturating_sub(list_width + 1);
+        let details = match self.props {
+            Some(ref props) => self.props_details(props),
+            None => self.selected_window().map(|w| self.details(w)).unwrap_or_default(),
+        };
+        let detail_title = match self.props {
+            Some(PropsTab { window: Some(id), ref query, .. }) if query.is_empty() => format!(" properties of {}", id),
+            Some(PropsTab { window: Some(id), ref query, .. }) => format!(" properties of {} /{}", id, query),
+            Some(_) => " properties".to_string(),
+            None => " details".to_string(),
+        };
+        let rows = self.rows();
+        let visible: Vec<&Window> = rows.iter().map(|&(w, _)| w).collect();
+
+        let mut title = if self.input.as_ref().is_some_and(|input| input.preview.is_some()) {
+            format!(" windows ({}/{}) preview", visible.len(), self.windows.len())
+        } else if self.search.as_ref().is_some_and(|search| !search.query.is_empty()) {
+            format!(" windows ({}/{}) search", visible.len(), self.windows.len())
+        } else {
+            format!(" windows ({})", self.windows.len())
+        };
+        if self.sort != SortKey::Stack {
+            title.push_str(&format!(" by {}", self.sort.name()));
+        }
+        if self.tree {
+            title.push_str(" tree");
+            if let Some(root) = self.tree_root {
+                title.push_str(&format!(" of {}", root));
+            }
+        }
+
+        let mut lines = Vec::with_capacity(height);
+        if pinned_rows > 0 {
+            self.render_pinned(&mut lines, width, pinned_rows - 1);
+        }
+        if self.help {
+            self.render_help(&mut lines, width, body);
+        } else {
+            lines.push(format!("{}|{}", self.theme.title.paint(&fit(&title, list_width)),
+                               self.theme.title.paint(&fit(&detail_title, detail_width))));
+            for row in 0..body {
+                let idx = self.offset + row;
+                let item = match rows.get(idx) {
+                    Some(&(w, node)) => {
+                        let pin = if self.pinned.contains(&w.id) { '*' } else { ' ' };
+                        let branch = match node {
+                            Some(node) => {
+                                let mark = match (node.children, node.expanded) {
+                                    (0, _) => "  ",
+                                    (_, true) => "- ",
+                                    (_, false) => "+ ",
+                                };
+                                format!("{}{}", "  ".repeat(node.depth), mark)
+                            },
+                            None => String::new(),
+                        };
+                        let item = fit(&format!("{}{}{} {}", pin, branch, w.id, w.name), list_width);
+                        if idx == self.selected {
+                            self.theme.selected.paint(&item)
+                        } else if self.pinned.contains(&w.id) {
+                            self.theme.pinned.paint(&item)
+                        } else {
+                            item
+                        }
+                    },
+                    None => fit("", list_width),
+                };
+                let detail = details.get(row).map(|s| s.as_str()).unwrap_or("");
+                lines.push(format!("{}|{}", item, fit(detail, detail_width)));
+            }
+        }
+        if log_rows > 0 {
+            self.render_log(&mut lines, width, log_rows);
+        }
+        if height > 1 {
+            lines.push(match self.input {
+                _ if self.search.is_some() => {
+                    fit(&format!(" /{}_", self.search.as_ref().unwrap().query), width)
+                },
+                Some(ref input) => {
+                    let error = input.error.as_ref().map(|e| format!("  ! {}", e)).unwrap_or_default();
+                    fit(&format!(" filter> {}_{}", input.text, error), width)
+                },
+                None if self.export.is_some() => {
+                    let export = self.export.as_ref().unwrap();
+                    let what = if export.window { "window" } else { "list" };
+                    let error = export.error.as_ref().map(|e| format!("  ! {}", e)).unwrap_or_default();
+                    fit(&format!(" export {} to> {}_{}", what, export.path, error), width)
+                },
+                None if self.palette.is_some() => {
+                    let palette = self.palette.as_ref().unwrap();
+                    let hint = match palette.error {
+                        Some(ref e) => format!("  ! {}", e),
+                        None => format!("  {}", palette::candidates(&palette.text).join(" ")),
+                    };
+                    fit(&format!(" :{}_{}", palette.text, hint), width)
+                },
+                None if self.props.as_ref().is_some_and(|props| props.editing) => {
+                    fit(&format!(" property /{}_", self.props.as_ref().unwrap().query), width)
+                },
+                None if self.status.is_some() => fit(&format!(" {}", self.status.as_ref().unwrap()), width),
+                None => {
+                    let first = |cmd| self.keymap.keys(cmd).into_iter().next().unwrap_or_default();
+                    fit(&format!(" {} help  {} quit  {} filter  {} search  {} tree  * pinned", first(Command::Help),
+                                 first(Command::Quit), first(Command::EditFilter), first(Command::Search),
+                                 first(Command::ToggleTree)), width)
+                },
+            });
+        }
+        lines
+    }
+
+    /// the topmost pinned windows with their live geometry and state
+    fn render_pinned(&self, lines: &mut Vec<String>, width: usize, rows: usize) {
+        let title = format!(" pinned ({})", self.pinned_windows.len());
+        lines.push(self.theme.title.paint(&fit(&title, width)));
+        for w in self.pinned_windows.iter().rev().take(rows) {
+            lines.push(self.theme.pinned.paint(&fit(&format!("*{}", w), width)));
+        }
+    }
+
+    /// the key bindings in place of the window list and details
+    fn render_help(&self, lines: &mut Vec<String>, width: usize, rows: usize) {
+        lines.push(fit(" key bindings, any key to close", width));
+        let help = self.keymap.help();
+        for row in 0..rows {
+            let line = help.get(row).map(|&(ref keys, name)| format!(" {:<12} {}", name, keys));
+            lines.push(fit(line.as_deref().unwrap_or(""), width));
+        }
+    }
+
+    fn render_log(&self, lines: &mut Vec<String>, width: usize, rows: usize) {
+        let title = format!(" events ({}){}", self.log.len(), if self.paused { " paused" } else { "" });
+        lines.push(self.theme.title.paint(&fit(&title, width)));
+
+        // the newest shown entry goes to the last row
+        let end = self.log.len() - self.log_scroll.min(self.log.len());
+        let start = end.saturating_sub(rows);
+        for entry in self.log.range(start..end) {
+            let color = entry.color();
+            let text = fit(&entry.describe(), width);
+            lines.push(if color.is_empty() { text } else { format!("{}{}\x1b[0m", color, text) });
+        }
+        for _ in (end - start)..rows {
+            lines.push(fit("", width));
+        }
+    }
+
+    /// properties of the selected window whose names match the query,
+    /// nothing until they are fetched
+    fn props_details(&self, props: &PropsTab) -> Vec<String> {
+        match self.selected_window() {
+            Some(w) if props.window == Some(w.id) => {
+                props.list.iter().filter(|p| props.matches(p)).map(|p| format!(" {}", p)).collect()
+            },
+            _ => Vec::new(),
+        }
+    }
+
+    fn details(&self, w: &Window) -> Vec<String> {
+        let desktop = match w.desktop {
+            Some(0xFFFFFFFF) => "all".to_string(),
+            Some(d) => d.to_string(),
+            None => "-".to_string(),
+        };
+        let yes_no = |b: bool| if b { "yes" } else { "no" };
+
+        let mut lines = vec![
+            format!(" id         {}", w.id),
+            format!(" name       {}", w.name),
+            format!(" name from  {}", w.name_source.map(|s| s.to_string()).unwrap_or_else(|| "-".to_string())),
+            format!(" geometry   {}", w.geom),
+            format!(" border     {}", w.border_width),
+            format!(" depth      {}", w.depth),
+            format!(" opacity    {}", w.opacity.map(|_| format!("{}%", w.opacity_percent())).unwrap_or_else(|| "-".to_string())),
+            format!(" parent     {}", w.parent.map(|id| id.to_string()).unwrap_or_else(|| "-".to_string())),
+            format!(" map state  {}", w.attrs.map_state),
+            format!(" override   {}", yes_no(w.attrs.override_redirect)),
+            format!(" desktop    {}", desktop),
+            format!(" machine    {}{}", w.client_machine.as_deref().unwrap_or("-"),
+                    if w.remote_host().is_some() { " (remote)" } else { "" }),
+            format!(" xwayland   {}", yes_no(w.xwayland)),
+            format!(" pinned     {}", yes_no(self.pinned.contains(&w.id))),
+            format!(" transient  {}", w.transient_for.map(|id| id.to_string()).unwrap_or_else(|| "-".to_string())),
+        ];
+        match self.titles {
+            Some((id, ref titles)) if id == w.id && titles.len() > 1 => {
+                lines.push(" titles".to_string());
+                lines.extend(titles.iter().rev().map(|e| format!("  {}  {}", clock(e.at), e.value)));
+            },
+            _ => {},
+        }
+        lines
+    }
+}
+
+/// pad or cut `s` to exactly `width` chars
+fn fit(s: &str, width: usize) -> String {
+    let mut s: String = s.chars().take(width).collect();
+    let len = s.chars().count();
+    s.extend(::std::iter::repeat_n(' ', width - len));
+    s
+}
+
+/// Browse windows of `ctx` until the user quits, the monitor output of
+/// `ctx` is discarded meanwhile. `log` should be a sink of `ctx` already,
+/// it feeds the event pane.
+pub fn run(ctx: Arc<Context>, log: Arc<EventLog>, keymap: Keymap, theme: Theme) -> WmResult<()> {
+    ctx.set_writer(io::sink());
+    let changes = ctx.state().subscribe();
+    let monitor = spawn_monitor(ctx.clone())?;
+
+    let mut app = App::with_keymap(keymap);
+    app.set_theme(theme);
+    let res = browse(ctx.clone(), &changes, &log, app);
+    monitor.stop();
+    res.and(monitor.join())
+}
+
+fn browse(ctx: Arc<Context>, changes: &Receiver<LayoutChange>, log: &EventLog, mut app: App) -> WmResult<()> {
+    let mut term = Terminal::new()?;
+    app.refresh(ctx.state());
+
+    let mut dirty = true;
+    let mut size = term.size();
+    loop {
+        if term.size() != size {
+            size = term.size();
+            dirty = true;
+        }
+        if dirty {
+            term.draw(&app.render(size.0, size.1))?;
+            dirty = false;
+        }
+
+        for key in term.read_keys(TICK)? {
+            if !app.handle_key(key, app.list_rows(size.1), ctx.state()) {
+                return Ok(());
+            }
+            dirty = true;
+        }
+
+        if let Some(id) = app.props_wanted() {
+            app.set_props(id, ctx.backend().properties(id));
+            dirty = true;
+        }
+        if let Some(id) = app.titles_wanted() {
+            app.set_titles(id, ctx.titles(id));
+            dirty = true;
+        }
+
+        for request in app.take_requests() {
+            match request {
+                Request::Flash(id) => {
+                    // in the background, flash blocks until the outline is gone
+                    let ctx = ctx.clone();
+                    thread::spawn(move || ctx.flash(id, FLASH));
+                },
+                Request::Toggle(opt) => ctx.toggle_option(opt),
+                Request::Control(id, action) => {
+                    if let Err(e) = ctx.control(id, action) {
+                        app.set_status(format!("{:?} {}: {}", action, id, e));
+                    }
+                },
+            }
+            dirty = true;
+        }
+
+        if changes.try_iter().count() > 0 {
+            app.refresh(ctx.state());
+            dirty = true;
+        }
+
+        let events = log.take();
+        if !events.is_empty() {
+            app.push_events(events);
+            dirty = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::term::parse_keys;
+    use wm::{Filter, Geometry, PropertyKind};
+    use wm::mock::window;
+
+    #[test]
+    fn test_app() {
+        let state = GlobalState::new(Filter::parse("name = dde*; name = dde-dock: pin").unwrap());
+        state.reset(vec![window(WindowId(0x1), "dde-osd"), window(WindowId(0x2), "xterm"),
+                         window(WindowId(0x3), "dde-dock")]);
+
+        let mut app = App::new();
+        app.refresh(&state);
+        assert_eq!(app.selected_window().unwrap().id, WindowId(0x1));
+        assert!(app.handle_key(Key::Char('G'), 10, &state));
+        assert_eq!(app.selected_window().unwrap().id, WindowId(0x3));
+
+        // the pinned panel is checked in test_pinned
+        app.handle_key(Key::Char('P'), 10, &state);
+        let lines = app.render(60, 5);
+        assert_eq!(lines.len(), 5);
+        assert!(lines.iter().all(|l| l.replace("\x1b[7m", "").replace("\x1b[0m", "").chars().count() == 60));
+        assert!(lines[2].starts_with("\x1b[7m*0x3 dde-dock"), "{}", lines[2]);
+        assert!(lines[1].contains("| id         0x3"), "{}", lines[1]);
+        assert!(lines[2].contains("| name       dde-dock"), "{}", lines[2]);
+
+        // selection follows the window when others go away
+        state.remove(WindowId(0x1));
+        app.refresh(&state);
+        assert_eq!(app.selected_window().unwrap().id, WindowId(0x3));
+        assert!(app.handle_key(Key::Up, 10, &state));
+        assert_eq!(app.selected_window().unwrap().id, WindowId(0x3));
+        app.handle_key(Key::Char('o'), 10, &state);
+        assert_eq!(app.take_requests(), vec![Request::Flash(WindowId(0x3))]);
+        assert_eq!(app.take_requests(), vec![]);
+
+        let mut theme = Theme::light();
+        theme.set("selected", "on blue").unwrap();
+        app.set_theme(theme);
+        let lines = app.render(60, 5);
+        assert!(lines[0].starts_with("\x1b[1m windows (1)"), "{}", lines[0]);
+        assert!(lines[1].starts_with("\x1b[44m*0x3 dde-dock"), "{}", lines[1]);
+        assert!(!app.handle_key(Key::Char('q'), 10, &state));
+    }
+
+    #[test]
+    fn test_key_bindings() {
+        let state = GlobalState::new(Filter::new());
+        state.reset((1..5).map(|i| window(WindowId(i), "xterm")).collect());
+        let mut app = App::new();
+        app.refresh(&state);
+
+        app.handle_key(Key::Char('G'), 10, &state);
+        assert_eq!(app.selected_window().unwrap().id, WindowId(0x4));
+        assert!(app.handle_key(Key::Char('g'), 10, &state));
+        assert_eq!(app.selected_window().unwrap().id, WindowId(0x4));
+        app.handle_key(Key::Char('g'), 10, &state);
+        assert_eq!(app.selected_window().unwrap().id, WindowId(0x1));
+
+        // a broken sequence still runs its last key
+        app.handle_key(Key::Char('g'), 10, &state);
+        app.handle_key(Key::Char('j'), 10, &state);
+        assert_eq!(app.selected_window().unwrap().id, WindowId(0x2));
+
+        app.handle_key(Key::Char('?'), 10, &state);
+        let lines = app.render(60, 20);
+        assert!(lines[0].starts_with(" key bindings"), "{}", lines[0]);
+        assert!(lines.iter().any(|l| l.starts_with(" top          gg, Home ")), "{:?}", lines);
+        // closing the help does not quit
+        assert!(app.handle_key(Key::Char('q'), 10, &state));
+        assert!(app.render(60, 20)[0].starts_with(" windows (4)"));
+
+        let mut keys = ::std::collections::BTreeMap::new();
+        keys.insert("quit".to_string(), ::config::KeyList::One("x".to_string()));
+        let mut app = App::with_keymap(Keymap::from_config(&keys).unwrap());
+        assert!(app.handle_key(Key::Char('q'), 10, &state));
+        assert!(app.render(60, 5)[4].starts_with(" ? help  x quit  f filter  / search"));
+        assert!(!app.handle_key(Key::Char('x'), 10, &state));
+    }
+
+    #[test]
+    fn test_tree() {
+        let state = GlobalState::new(Filter::new());
+        let mut dialog = window(WindowId(0x2), "dialog");
+        dialog.transient_for = Some(WindowId(0x1));
+        let mut popup = window(WindowId(0x4), "popup");
+        popup.transient_for = Some(WindowId(0x2));
+        state.reset(vec![window(WindowId(0x1), "gimp"), dialog, window(WindowId(0x3), "xterm"), popup]);
+        let mut app = App::new();
+        app.refresh(&state);
+
+        app.handle_key(Key::Char('t'), 10, &state);
+        let ids = |app: &App| app.visible().iter().map(|w| w.id.0).collect::<Vec<_>>();
+        assert_eq!(ids(&app), vec![0x1, 0x2, 0x4, 0x3]);
+        let lines = app.render(60, 6);
+        assert!(lines[0].starts_with(" windows (4) tree "), "{}", lines[0]);
+        assert!(lines[1].starts_with("\x1b[7m - 0x1 gimp "), "{}", lines[1]);
+        assert!(lines[3].starts_with("       0x4 popup "), "{}", lines[3]);
+
+        // collapsing a leaf goes to its parent, then folds it
+        app.handle_key(Key::Char('j'), 10, &state);
+        app.handle_key(Key::Char('j'), 10, &state);
+        app.handle_key(Key::Char('h'), 10, &state);
+        assert_eq!(app.selected_window().unwrap().id, WindowId(0x2));
+        app.handle_key(Key::Char('h'), 10, &state);
+        assert_eq!(ids(&app), vec![0x1, 0x2, 0x3]);
+        assert!(app.render(60, 6)[2].contains(" + 0x2 dialog"));
+        app.handle_key(Key::Right, 10, &state);
+        assert_eq!(ids(&app), vec![0x1, 0x2, 0x4, 0x3]);
+
+        app.handle_key(Key::Char('r'), 10, &state);
+        assert_eq!(ids(&app), vec![0x2, 0x4]);
+        assert!(app.render(60, 6)[0].starts_with(" windows (4) tree of 0x2|"));
+        assert_eq!(app.selected_window().unwrap().id, WindowId(0x2));
+        app.handle_key(Key::Char('r'), 10, &state);
+        assert_eq!(ids(&app).len(), 4);
+
+        app.handle_key(Key::Char('t'), 10, &state);
+        assert_eq!(ids(&app), vec![0x1, 0x2, 0x3, 0x4]);
+        assert_eq!(app.selected_window().unwrap().id, WindowId(0x2));
+    }
+
+    #[test]
+    fn test_export() {
+        let state = GlobalState::new(Filter::parse("name = x*").unwrap());
+        state.reset(vec![window(WindowId(0x1), "xterm"), window(WindowId(0x2), "dde-dock"),
+                         window(WindowId(0x3), "xclock")]);
+        let mut app = App::new();
+        app.refresh(&state);
+
+        let dir = ::std::env::temp_dir();
+        let csv = dir.join(format!("wminspect-export-{}.csv", ::std::process::id()));
+        app.handle_key(Key::Char('e'), 10, &state);
+        for c in csv.to_str().unwrap().chars() {
+            app.handle_key(Key::Char(c), 10, &state);
+        }
+        assert!(app.render(200, 5)[4].starts_with(" export list to> /"));
+        app.handle_key(Key::Enter, 10, &state);
+        assert!(app.render(200, 5)[4].starts_with(&format!(" exported 2 windows to {}", csv.display())));
+        let text = ::std::fs::read_to_string(&csv).unwrap();
+        assert_eq!(text.lines().count(), 3);
+        assert!(text.lines().nth(2).unwrap().starts_with("0x3,xclock,"), "{}", text);
+        ::std::fs::remove_file(&csv).unwrap();
+
+        // the selected window only, broken paths are told in the box
+        app.handle_key(Key::Char('E'), 10, &state);
+        for c in "/nonexistent/w.json".chars() {
+            app.handle_key(Key::Char(c), 10, &state);
+        }
+        app.handle_key(Key::Enter, 10, &state);
+        assert!(app.render(200, 5)[4].contains("! No such file"), "{}", app.render(200, 5)[4]);
+        app.handle_key(Key::Esc, 10, &state);
+        assert!(app.render(200, 5)[4].starts_with(" ? help"));
+    }
+
+    #[test]
+    fn test_pinned() {
+        let state = GlobalState::new(Filter::parse("name = x*; name = dde*: pin").unwrap());
+        state.reset(vec![window(WindowId(0x1), "dde-dock"), window(WindowId(0x2), "xterm"),
+                         window(WindowId(0x3), "dde-osd"), window(WindowId(0x4), "xclock")]);
+        let mut app = App::new();
+        app.refresh(&state);
+
+        // pinned windows are listed topmost first, and pass the filter
+        assert_eq!(app.visible().len(), 4);
+        assert_eq!(app.list_rows(20), 8);
+        let lines = app.render(60, 20);
+        assert_eq!(lines.len(), 20);
+        assert_eq!(lines[0].trim_end(), " pinned (2)");
+        assert!(lines[1].starts_with("\x1b[33m*0x3(dde-osd) 100x100+0+0 Unmapped"), "{}", lines[1]);
+        assert!(lines[2].starts_with("\x1b[33m*0x1(dde-dock) "), "{}", lines[2]);
+        assert!(lines[3].starts_with(" windows (4)"), "{}", lines[3]);
+
+        // geometry is live
+        state.with_window_mut(WindowId(0x1), |w| w.geom.x = 10);
+        app.refresh(&state);
+        assert!(app.render(60, 20)[2].contains("100x100+10+0"));
+
+        app.handle_key(Key::Char('P'), 10, &state);
+        assert_eq!(app.list_rows(20), 11);
+        assert!(app.render(60, 20)[0].starts_with(" windows (4)"));
+    }
+
+    #[test]
+    fn test_props() {
+        let state = GlobalState::new(Filter::new());
+        state.reset(vec![window(WindowId(0x1), "xterm"), window(WindowId(0x2), "xclock")]);
+        let mut app = App::new();
+        app.refresh(&state);
+        assert_eq!(app.props_wanted(), None);
+
+        let prop = |name: &str, value: &str| Property {
+            name: name.to_string(), type_name: "STRING".to_string(), value: value.to_string()
+        };
+        app.handle_key(Key::Char('i'), 10, &state);
+        assert_eq!(app.props_wanted(), Some(WindowId(0x1)));
+        app.set_props(WindowId(0x1), vec![prop("WM_CLASS", "\"xterm\""), prop("WM_NAME", "\"xterm\"")]);
+        assert_eq!(app.props_wanted(), None);
+        let lines = app.render(100, 20);
+        assert!(lines[0].ends_with(&fit(" properties of 0x1", 59)), "{}", lines[0]);
+        assert!(lines[1].ends_with(&fit(" WM_CLASS(STRING) = \"xterm\"", 59)), "{}", lines[1]);
+
+        // refetched on changes of the window and on moving to another
+        let changed = |id: u32| LogEntry {
+            elapsed: Duration::from_millis(0),
+            event: WindowEvent::PropertyChanged { window: WindowId(id), kind: PropertyKind::Other },
+        };
+        app.push_events(vec![changed(0x2)]);
+        assert_eq!(app.props_wanted(), None);
+        app.push_events(vec![changed(0x1)]);
+        assert_eq!(app.props_wanted(), Some(WindowId(0x1)));
+        app.handle_key(Key::Char('j'), 10, &state);
+        assert_eq!(app.props_wanted(), Some(WindowId(0x2)));
+        app.set_props(WindowId(0x2), vec![prop("WM_CLASS", "\"xclock\""), prop("WM_NAME", "\"xclock\"")]);
+
+        // the search box narrows property names
+        for key in parse_keys(b"/na") {
+            app.handle_key(key, 10, &state);
+        }
+        let lines = app.render(100, 20);
+        assert!(lines[0].contains(" properties of 0x2 /na"), "{}", lines[0]);
+        assert!(lines[1].contains("WM_NAME(STRING)"), "{}", lines[1]);
+        assert!(!lines[2].contains("WM_"), "{}", lines[2]);
+        assert!(lines[19].starts_with(" property /na_"), "{}", lines[19]);
+        app.handle_key(Key::Enter, 10, &state);
+        assert_eq!(app.selected_window().unwrap().id, WindowId(0x2));
+
+        app.handle_key(Key::Char('i'), 10, &state);
+        assert!(app.render(100, 20)[0].contains(" details"));
+    }
+
+    #[test]
+    fn test_titles() {
+        let state = GlobalState::new(Filter::new());
+        state.reset(vec![window(WindowId(0x1), "vim")]);
+        state.with_window_mut(WindowId(0x1), |w| w.name = "vim - main.rs".to_string());
+        let mut app = App::new();
+        app.refresh(&state);
+        assert_eq!(app.titles_wanted(), Some(WindowId(0x1)));
+        app.set_titles(WindowId(0x1), state.titles(WindowId(0x1)));
+        assert_eq!(app.titles_wanted(), None);
+
+        let lines = app.render(100, 40);
+        let at = lines.iter().position(|l| l.ends_with(&fit(" titles", 59))).unwrap();
+        assert!(lines[at + 1].trim_end().ends_with("vim - main.rs"), "{}", lines[at + 1]);
+        assert!(lines[at + 2].trim_end().ends_with("  vim"), "{}", lines[at + 2]);
+
+        app.refresh(&state);
+        assert_eq!(app.titles_wanted(), Some(WindowId(0x1)));
+    }
+
+    #[test]
+    fn test_palette() {
+        let state = GlobalState::new(Filter::new());
+        let mut w = window(WindowId(0x3), "big");
+        w.geom = Geometry { x: 0, y: 0, width: 1000, height: 800 };
+        state.reset(vec![window(WindowId(0x1), "xterm"), window(WindowId(0x2), "Chrome"), w]);
+        let mut app = App::new();
+        app.refresh(&state);
+        let type_line = |app: &mut App, keys: &[u8]| {
+            for key in parse_keys(keys) {
+                if !app.handle_key(key, 10, &state) {
+                    return false;
+                }
+            }
+            true
+        };
+
+        // Tab completes, candidates are listed as you type
+        type_line(&mut app, b":so\t");
+        assert!(app.render(60, 20)[19].starts_with(" :sort _  stack name id area"), "{}", app.render(60, 20)[19]);
+        type_line(&mut app, b"n\t\r");
+        let names: Vec<&str> = app.visible().iter().map(|w| w.name.as_str()).collect();
+        assert_eq!(names, vec!["big", "Chrome", "xterm"]);
+        assert!(app.render(60, 20)[0].starts_with(" windows (3) by name"));
+        type_line(&mut app, b":sort area\r");
+        assert_eq!(app.visible()[0].id, WindowId(0x3));
+        // the selected window stays selected
+        assert_eq!(app.selected_window().unwrap().id, WindowId(0x1));
+        // s goes on to the next key, and round to the first
+        app.handle_key(Key::Char('s'), 10, &state);
+        assert!(app.render(60, 20)[0].starts_with(" windows (3)  "), "{}", app.render(60, 20)[0]);
+        app.handle_key(Key::Char('s'), 10, &state);
+        assert!(app.render(60, 20)[0].starts_with(" windows (3) by name"));
+        assert_eq!(app.selected_window().unwrap().id, WindowId(0x1));
+        type_line(&mut app, b":sort area\r");
+
+        // errors keep the palette open
+        type_line(&mut app, b":toggle nope\r");
+        assert!(app.render(60, 20)[19].starts_with(" :toggle nope_  ! unknown option nope"));
+        type_line(&mut app, b"\x1b:toggle mapped-only\r:window raise\r:flash\r");
+        assert_eq!(app.take_requests(), vec![Request::Toggle(Options::MAPPED_ONLY),
+                                             Request::Control(WindowId(0x1), WindowAction::Raise),
+                                             Request::Flash(WindowId(0x1))]);
+
+        type_line(&mut app, b":filter name = xterm\r");
+        assert_eq!(app.visible().len(), 1);
+        assert_eq!(state.rules().len(), 1);
+        assert!(!type_line(&mut app, b":quit\r"));
+    }
+
+    #[test]
+    fn test_parse_key() {
+        assert_eq!(parse_keys(b"\x1b[A\x1bOD"), vec![Key::Up, Key::Left]);
+        assert_eq!(parse_keys(b"j\x1b[6~\x1b"), vec![Key::Char('j'), Key::PageDown, Key::Esc]);
+        assert_eq!(parse_keys("é\r\x7f\x03".as_bytes()), vec![Key::Char('é'), Key::Enter, Key::Backspace, Key::Ctrl('c')]);
+        assert_eq!(parse_keys(b"\t\x09"), vec![Key::Tab, Key::Tab]);
+        assert_eq!(parse_keys(b"\x1b[99;5u"), vec![]);
+    }
+
+    #[test]
+    fn test_event_log() {
+        let state = GlobalState::new(Filter::new());
+        let mut app = App::new();
+        let entry = |i: u32| LogEntry {
+            elapsed: Duration::from_millis(1500 + i as u64),
+            event: WindowEvent::Mapped { window: WindowId(i) },
+        };
+        app.push_events((1..5).map(entry));
+
+        // 20 rows leave 6 to the log
+        assert_eq!(App::layout(20), (11, 6));
+        let lines = app.render(60, 20);
+        assert_eq!(lines.len(), 20);
+        assert_eq!(lines[12].trim_end(), " events (4)");
+        assert_eq!(lines[13], format!("\x1b[36m{}\x1b[0m", fit("    1.501 map 0x1", 60)));
+        assert_eq!(lines[16], format!("\x1b[36m{}\x1b[0m", fit("    1.504 map 0x4", 60)));
+
+        // scrolling back pauses, so new events do not move the view
+        app.handle_key(Key::Char('['), 10, &state);
+        app.push_events((5..7).map(entry));
+        let lines = app.render(60, 20);
+        assert_eq!(lines[12].trim_end(), " events (6) paused");
+        assert!(lines[15].contains("map 0x3"), "{}", lines[15]);
+
+        app.handle_key(Key::Char('p'), 10, &state);
+        let lines = app.render(60, 20);
+        assert!(lines[18].contains("map 0x6"), "{}", lines[18]);
+    }
+
+    #[test]
+    fn test_edit_filter() {
+        let state = GlobalState::new(Filter::new());
+        state.reset(vec![window(WindowId(0x1), "dde-osd"), window(WindowId(0x2), "xterm"),
+                         window(WindowId(0x3), "dde-dock")]);
+        let mut app = App::new();
+        app.refresh(&state);
+
+        let type_in = |app: &mut App, text: &str| {
+            for c in text.chars() {
+                assert!(app.handle_key(Key::Char(c), 10, &state));
+            }
+        };
+
+        app.handle_key(Key::Char('f'), 10, &state);
+        type_in(&mut app, "name = dde*");
+        let lines = app.render(60, 5);
+        assert!(lines[0].starts_with(" windows (2/3) preview"), "{}", lines[0]);
+        assert!(lines[4].starts_with(" filter> name = dde*_ "), "{}", lines[4]);
+
+        // keys are text while editing, and broken rules show why
+        type_in(&mut app, " q)");
+        let lines = app.render(100, 5);
+        assert!(lines[4].contains("! unexpected ')', expecting ';' at col 14"), "{}", lines[4]);
+        assert!(lines[0].starts_with(" windows (3) "), "{}", lines[0]);
+
+        for _ in 0..3 {
+            app.handle_key(Key::Backspace, 10, &state);
+        }
+        app.handle_key(Key::Enter, 10, &state);
+        assert_eq!(state.filtered_windows().len(), 2);
+        assert_eq!(state.rules()[0].source, "name = dde*");
+        assert!(app.render(60, 5)[0].starts_with(" windows (2) "));
+
+        // canceled rules are not added
+        app.handle_key(Key::Char('f'), 10, &state);
+        type_in(&mut app, "name = dde-osd");
+        app.handle_key(Key::Esc, 10, &state);
+        assert_eq!(state.rules().len(), 1);
+        assert_eq!(app.visible().len(), 2);
+    }
+
+    #[test]
+    fn test_search() {
+        let state = GlobalState::new(Filter::new());
+        state.reset(vec![window(WindowId(0x1), "dde-dock"), window(WindowId(0x2), "xterm"),
+                         window(WindowId(0x3), "dde-desktop-osd-clock"), window(WindowId(0x4), "urxvt")]);
+        let mut app = App::new();
+        app.refresh(&state);
+        app.handle_key(Key::Char('G'), 10, &state);
+
+        app.handle_key(Key::Char('/'), 10, &state);
+        for c in "ddock".chars() {
+            assert!(app.handle_key(Key::Char(c), 10, &state));
+        }
+        assert_eq!(app.visible().iter().map(|w| w.id).collect::<Vec<_>>(), vec![WindowId(0x1), WindowId(0x3)]);
+        assert_eq!(app.selected_window().unwrap().id, WindowId(0x1));
+        let lines = app.render(60, 5);
+        assert!(lines[0].starts_with(" windows (2/4) search"), "{}", lines[0]);
+        assert!(lines[4].starts_with(" /ddock_ "), "{}", lines[4]);
+
+        // the match stays selected in the whole list
+        app.handle_key(Key::Enter, 10, &state);
+        assert_eq!(app.visible().len(), 4);
+        assert_eq!(app.selected_window().unwrap().id, WindowId(0x1));
+
+        // ids match as well, canceling goes back
+        app.handle_key(Key::Char('/'), 10, &state);
+        app.handle_key(Key::Char('4'), 10, &state);
+        assert_eq!(app.selected_window().unwrap().id, WindowId(0x4));
+        app.handle_key(Key::Esc, 10, &state);
+        assert_eq!(app.selected_window().unwrap().id, WindowId(0x1));
+    }
+}
@@ -0,0 +1,155 @@
+use wm::{Options, WindowAction, WINDOW_ACTIONS};
+use super::SortKey;
+use super::keymap::{self, Command};
+
+/// commands of the palette on top of the key bound ones
+const EXTRA: &[&str] = &["toggle", "sort", "window"];
+
+/// options `toggle` flips, by their command line names
+pub(crate) const OPTIONS: &[(&str, Options)] = &[
+    ("mapped-only", Options::MAPPED_ONLY),
+    ("omit-hidden", Options::OMIT_HIDDEN),
+    ("no-special", Options::NO_SPECIAL),
+    ("clients-only", Options::CLIENTS_ONLY),
+];
+
+/// What a line typed into the palette asks for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Action {
+    /// what the key bound to it does, e.g. `tree`
+    Run(Command),
+    /// `filter RULE` adds a rule to the active filter
+    AddRule(String),
+    /// `export PATH` and `export-window PATH`, true for the window only
+    Export(String, bool),
+    Toggle(Options),
+    Sort(SortKey),
+    /// `window ACTION` on the selected window
+    Control(WindowAction),
+}
+
+/// every word the palette takes in first place
+fn commands() -> impl Iterator<Item = &'static str> {
+    keymap::command_names().filter(|&name| name != "palette").chain(EXTRA.iter().cloned())
+}
+
+/// words taken after `command`
+fn arguments(command: &str) -> Vec<&'static str> {
+    match command {
+        "toggle" => OPTIONS.iter().map(|&(name, _)| name).collect(),
+        "sort" => SortKey::ALL.iter().map(|&(name, _)| name).collect(),
+        "window" => WINDOW_ACTIONS.to_vec(),
+        _ => Vec::new(),
+    }
+}
+
+/// the words typed in full and the one being typed at the end of `line`
+fn split(line: &str) -> (Vec<&str>, &str) {
+    let mut words: Vec<&str> = line.split_whitespace().collect();
+    if line.is_empty() || line.ends_with(char::is_whitespace) {
+        (words, "")
+    } else {
+        let last = words.pop().unwrap();
+        (words, last)
+    }
+}
+
+/// words that may complete the one at the end of `line`
+pub(crate) fn candidates(line: &str) -> Vec<&'static str> {
+    let (done, current) = split(line);
+    let words = match done.as_slice() {
+        [] => commands().collect(),
+        [command] => arguments(command),
+        _ => Vec::new(),
+    };
+    words.into_iter().filter(|w| w.starts_with(current)).collect()
+}
+
+/// `line` with its last word completed as far as all candidates agree,
+/// plus a space if only one is left
+pub(crate) fn complete(line: &str) -> String {
+    let candidates = candidates(line);
+    let (_, current) = split(line);
+    let common = match candidates.split_first() {
+        Some((first, rest)) => rest.iter().fold(*first, |common, w| {
+            let len = common.chars().zip(w.chars()).take_while(|&(a, b)| a == b).count();
+            &common[..len]
+        }),
+        None => return line.to_string(),
+    };
+
+    let mut completed = format!("{}{}", &line[..line.len() - current.len()], common);
+    if candidates.len() == 1 {
+        completed.push(' ');
+    }
+    completed
+}
+
+pub(crate) fn parse(line: &str) -> Result<Action, String> {
+    let line = line.trim();
+    let (command, rest) = match line.find(char::is_whitespace) {
+        Some(i) => (&line[..i], line[i..].trim()),
+        None => (line, ""),
+    };
+    let argument = |what: &str| if rest.is_empty() { Err(format!("{} needs {}", command, what)) } else { Ok(rest) };
+
+    match command {
+        "filter" if !rest.is_empty() => Ok(Action::AddRule(rest.to_string())),
+        "export" | "export-window" if !rest.is_empty() => {
+            Ok(Action::Export(rest.to_string(), command == "export-window"))
+        },
+        "toggle" => {
+            let name = argument("an option")?;
+            OPTIONS.iter().find(|&&(n, _)| n == name).map(|&(_, opt)| Action::Toggle(opt))
+                .ok_or_else(|| format!("unknown option {}", name))
+        },
+        "sort" => {
+            let name = argument("a key")?;
+            SortKey::ALL.iter().find(|&&(n, _)| n == name).map(|&(_, key)| Action::Sort(key))
+                .ok_or_else(|| format!("unknown sort key {}", name))
+        },
+        "window" => argument("an action")?.parse().map(Action::Control),
+        "palette" | "" => Err("no command".to_string()),
+        _ if !rest.is_empty() => Err(format!("{} takes no argument", command)),
+        _ => keymap::command(command).map(Action::Run).ok_or_else(|| format!("unknown command {}", command)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_complete() {
+        assert_eq!(candidates("pa"), vec!["page-up", "page-down", "pause"]);
+        assert_eq!(complete("pa"), "pa");
+        assert_eq!(complete("page-"), "page-");
+        assert_eq!(complete("tog"), "toggle ");
+        assert_eq!(complete("toggle "), "toggle ");
+        assert_eq!(complete("toggle om"), "toggle omit-hidden ");
+        assert_eq!(complete("window c"), "window close ");
+        assert_eq!(complete("so  n"), "so  n");
+        assert_eq!(candidates("sort "), vec!["stack", "name", "id", "area"]);
+        assert!(candidates("").contains(&"quit"));
+        assert!(!candidates("").contains(&"palette"));
+        assert!(candidates("tree x").is_empty());
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse(" tree "), Ok(Action::Run(Command::ToggleTree)));
+        assert_eq!(parse("filter"), Ok(Action::Run(Command::EditFilter)));
+        assert_eq!(parse("filter name = xterm"), Ok(Action::AddRule("name = xterm".to_string())));
+        assert_eq!(parse("export-window /tmp/w.json"), Ok(Action::Export("/tmp/w.json".to_string(), true)));
+        assert_eq!(parse("toggle mapped-only"), Ok(Action::Toggle(Options::MAPPED_ONLY)));
+        assert_eq!(parse("sort name"), Ok(Action::Sort(SortKey::Name)));
+        assert_eq!(parse("window kill"), Ok(Action::Control(WindowAction::Kill)));
+
+        assert!(parse("toggle").is_err());
+        assert!(parse("sort size").is_err());
+        assert!(parse("window shake").is_err());
+        assert!(parse("quit now").is_err());
+        assert!(parse("jump").is_err());
+        assert!(parse("palette").is_err());
+    }
+}
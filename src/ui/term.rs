@@ -0,0 +1,158 @@
+extern crate libc;
+
+use std::io::{self, Write};
+use std::mem::MaybeUninit;
+use std::time::Duration;
+
+/// A key press as read from the terminal, what it does is up to the `App`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Char(char),
+    /// a control character, e.g. `Ctrl('c')`
+    Ctrl(char),
+    Up,
+    Down,
+    Left,
+    Right,
+    PageUp,
+    PageDown,
+    Home,
+    End,
+    Enter,
+    Tab,
+    Backspace,
+    Esc,
+}
+
+/// decode key presses read from the terminal at once, unknown escape
+/// sequences are dropped
+pub(crate) fn parse_keys(buf: &[u8]) -> Vec<Key> {
+    let mut keys = Vec::new();
+    let mut i = 0;
+    while i < buf.len() {
+        let rest = &buf[i..];
+        if rest[0] == 0x1b && rest.len() > 1 && (rest[1] == b'[' || rest[1] == b'O') {
+            // CSI or SS3 sequence ends with a byte in 0x40..=0x7e
+            let end = rest[2..].iter().position(|b| (0x40..=0x7e).contains(b))
+                .map(|p| p + 3).unwrap_or(rest.len());
+            keys.extend(match &rest[..end] {
+                b"\x1b[A" | b"\x1bOA" => Some(Key::Up),
+                b"\x1b[B" | b"\x1bOB" => Some(Key::Down),
+                b"\x1b[C" | b"\x1bOC" => Some(Key::Right),
+                b"\x1b[D" | b"\x1bOD" => Some(Key::Left),
+                b"\x1b[5~" => Some(Key::PageUp),
+                b"\x1b[6~" => Some(Key::PageDown),
+                b"\x1b[H" | b"\x1bOH" | b"\x1b[1~" => Some(Key::Home),
+                b"\x1b[F" | b"\x1bOF" | b"\x1b[4~" => Some(Key::End),
+                _ => None,
+            });
+            i += end;
+            continue;
+        }
+
+        let key = match rest[0] {
+            0x1b => Key::Esc,
+            b'\r' | b'\n' => Key::Enter,
+            b'\t' => Key::Tab,
+            0x7f | 0x08 => Key::Backspace,
+            b @ 0x01..=0x1a => Key::Ctrl((b'a' + b - 1) as char),
+            _ => {
+                // one utf-8 encoded char
+                let len = match rest[0] {
+                    0xf0..=0xff => 4,
+                    0xe0..=0xef => 3,
+                    0xc0..=0xdf => 2,
+                    _ => 1,
+                }.min(rest.len());
+                if let Some(c) = ::std::str::from_utf8(&rest[..len]).ok().and_then(|s| s.chars().next()) {
+                    keys.push(Key::Char(c));
+                }
+                i += len;
+                continue;
+            }
+        };
+        keys.push(key);
+        i += 1;
+    }
+    keys
+}
+
+/// Controlling terminal in raw mode on the alternate screen, both are
+/// restored on drop.
+pub(crate) struct Terminal {
+    saved: libc::termios,
+    out: io::Stdout,
+}
+
+impl Terminal {
+    pub(crate) fn new() -> io::Result<Terminal> {
+        let saved = unsafe {
+            let mut t = MaybeUninit::<libc::termios>::uninit();
+            if libc::tcgetattr(libc::STDIN_FILENO, t.as_mut_ptr()) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            t.assume_init()
+        };
+
+        let mut raw = saved;
+        unsafe {
+            libc::cfmakeraw(&mut raw);
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+
+        let mut term = Terminal { saved, out: io::stdout() };
+        term.out.write_all(b"\x1b[?1049h\x1b[?25l")?;
+        term.out.flush()?;
+        Ok(term)
+    }
+
+    /// (columns, rows), 80x24 if unknown
+    pub(crate) fn size(&self) -> (usize, usize) {
+        unsafe {
+            let mut winsz = MaybeUninit::<libc::winsize>::uninit();
+            match libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, winsz.as_mut_ptr()) {
+                0 => {
+                    let winsz = winsz.assume_init();
+                    (winsz.ws_col as usize, winsz.ws_row as usize)
+                },
+                _ => (80, 24)
+            }
+        }
+    }
+
+    pub(crate) fn draw(&mut self, lines: &[String]) -> io::Result<()> {
+        let mut buf = String::from("\x1b[H");
+        for (i, line) in lines.iter().enumerate() {
+            buf.push_str(&format!("\x1b[{};1H\x1b[2K{}", i + 1, line));
+        }
+        buf.push_str("\x1b[J");
+        self.out.write_all(buf.as_bytes())?;
+        self.out.flush()
+    }
+
+    /// wait at most `timeout` for key presses
+    pub(crate) fn read_keys(&self, timeout: Duration) -> io::Result<Vec<Key>> {
+        let mut fds = [libc::pollfd { fd: libc::STDIN_FILENO, events: libc::POLLIN, revents: 0 }];
+        let n = unsafe { libc::poll(fds.as_mut_ptr(), 1, timeout.as_millis() as libc::c_int) };
+        if n <= 0 {
+            return Ok(Vec::new());
+        }
+
+        let mut buf = [0u8; 64];
+        let n = unsafe { libc::read(libc::STDIN_FILENO, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+        if n < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(parse_keys(&buf[..n as usize]))
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let _ = self.out.write_all(b"\x1b[?25h\x1b[?1049l");
+        let _ = self.out.flush();
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.saved) };
+    }
+}
@@ -0,0 +1,100 @@
+use std::collections::{HashMap, HashSet};
+
+use wm::{Window, WindowId};
+
+/// Where a window is in the tree view.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Node {
+    pub depth: usize,
+    /// the window it is transient for, None for top level ones
+    pub parent: Option<WindowId>,
+    pub children: usize,
+    pub expanded: bool,
+}
+
+/// Lay `windows` out as a tree by WM_TRANSIENT_FOR, depth first with
+/// siblings in the order of `windows`. Windows transient for one not in
+/// `windows` are top level. With `root` only its subtree is laid out, the
+/// whole tree if it is not there. Children of `collapsed` are left out.
+pub(crate) fn flatten<'a>(windows: &[&'a Window], root: Option<WindowId>,
+                          collapsed: &HashSet<WindowId>) -> Vec<(&'a Window, Node)> {
+    let ids: HashSet<WindowId> = windows.iter().map(|w| w.id).collect();
+    let parent_of = |w: &Window| w.transient_for.filter(|p| *p != w.id && ids.contains(p));
+
+    let mut children: HashMap<WindowId, Vec<&'a Window>> = HashMap::new();
+    let mut tops = Vec::new();
+    for &w in windows {
+        match parent_of(w) {
+            Some(p) => children.entry(p).or_default().push(w),
+            None => tops.push(w),
+        }
+    }
+
+    if let Some(&w) = root.and_then(|id| windows.iter().find(|w| w.id == id)) {
+        tops = vec![w];
+    }
+
+    let mut rows = Vec::with_capacity(windows.len());
+    let mut seen = HashSet::new();
+    walk(&tops, &children, collapsed, &mut seen, &mut rows);
+    if root.is_none() && seen.len() < windows.len() {
+        // transient loops have no top, list them from the first one
+        let rest: Vec<&Window> = windows.iter().cloned().filter(|w| !seen.contains(&w.id)).collect();
+        for w in rest {
+            walk(&[w], &children, collapsed, &mut seen, &mut rows);
+        }
+    }
+    rows
+}
+
+fn walk<'a>(tops: &[&'a Window], children: &HashMap<WindowId, Vec<&'a Window>>, collapsed: &HashSet<WindowId>,
+            seen: &mut HashSet<WindowId>, rows: &mut Vec<(&'a Window, Node)>) {
+    // (window, parent, depth), pushed in reverse to pop in order, windows
+    // under collapsed ones are only marked seen
+    let mut todo: Vec<(&Window, Option<WindowId>, Option<usize>)> =
+        tops.iter().rev().map(|&w| (w, None, Some(0))).collect();
+    while let Some((w, parent, depth)) = todo.pop() {
+        if !seen.insert(w.id) {
+            continue;
+        }
+        let kids = children.get(&w.id).map(|v| v.as_slice()).unwrap_or(&[]);
+        let expanded = !collapsed.contains(&w.id);
+        if let Some(depth) = depth {
+            rows.push((w, Node { depth, parent, children: kids.len(), expanded }));
+        }
+        let depth = depth.filter(|_| expanded).map(|d| d + 1);
+        todo.extend(kids.iter().rev().map(|&k| (k, Some(w.id), depth)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wm::mock::window;
+
+    #[test]
+    fn test_flatten() {
+        let child = |id, of| window(WindowId(id), "").transient(WindowId(of));
+        let windows = [window(WindowId(1), ""), child(2, 1), window(WindowId(3), ""), child(4, 2), child(5, 1), child(6, 0x99)];
+        let refs: Vec<&Window> = windows.iter().collect();
+        let shape = |rows: Vec<(&Window, Node)>| -> Vec<(u32, usize)> {
+            rows.iter().map(|&(w, n)| (w.id.0, n.depth)).collect()
+        };
+
+        assert_eq!(shape(flatten(&refs, None, &HashSet::new())),
+                   vec![(1, 0), (2, 1), (4, 2), (5, 1), (3, 0), (6, 0)]);
+        let rows = flatten(&refs, None, &HashSet::new());
+        assert_eq!(rows[0].1, Node { depth: 0, parent: None, children: 2, expanded: true });
+        assert_eq!(rows[2].1.parent, Some(WindowId(2)));
+
+        let collapsed = vec![WindowId(2)].into_iter().collect();
+        assert_eq!(shape(flatten(&refs, None, &collapsed)), vec![(1, 0), (2, 1), (5, 1), (3, 0), (6, 0)]);
+
+        assert_eq!(shape(flatten(&refs, Some(WindowId(2)), &HashSet::new())), vec![(2, 0), (4, 1)]);
+        assert_eq!(shape(flatten(&refs, Some(WindowId(0x99)), &HashSet::new())).len(), 6);
+
+        let windows = [child(1, 2), child(2, 1)];
+        let refs: Vec<&Window> = windows.iter().collect();
+        assert_eq!(shape(flatten(&refs, None, &HashSet::new())), vec![(1, 0), (2, 1)]);
+    }
+}
@@ -0,0 +1,1193 @@
+extern crate xcb;
+extern crate xcb_util;
+extern crate libc;
+
+use xcb::{randr, xproto};
+use xcb_util::{ewmh, icccm};
+use std::env;
+use std::fmt;
+use std::num::ParseIntError;
+use std::str::FromStr;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::collections::{HashMap, HashSet};
+use std::sync::{mpsc, Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::wm::*;
+use super::event::*;
+use super::props::{self, Property, RawValue};
+
+/// X window id, shown in hex, serialized as a plain number
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct WindowId(pub xcb::Window);
+
+pub const WINDOW_NONE: WindowId = WindowId(0);
+
+impl WindowId {
+    pub fn raw(self) -> xcb::Window {
+        self.0
+    }
+}
+
+impl From<xcb::Window> for WindowId {
+    fn from(id: xcb::Window) -> WindowId {
+        WindowId(id)
+    }
+}
+
+impl PartialEq<xcb::Window> for WindowId {
+    fn eq(&self, other: &xcb::Window) -> bool {
+        self.0 == *other
+    }
+}
+
+impl fmt::Display for WindowId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "0x{:x}", self.0)
+    }
+}
+
+impl fmt::Debug for WindowId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl fmt::LowerHex for WindowId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::LowerHex::fmt(&self.0, f)
+    }
+}
+
+/// accepts `0x` prefixed hex or decimal
+impl FromStr for WindowId {
+    type Err = ParseIntError;
+
+    fn from_str(s: &str) -> Result<WindowId, ParseIntError> {
+        let s = s.trim();
+        match s.get(..2) {
+            Some("0x") | Some("0X") => xcb::Window::from_str_radix(&s[2..], 16).map(WindowId),
+            _ => s.parse().map(WindowId),
+        }
+    }
+}
+
+/// What can be asked of a window, by `Context::control`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WindowAction {
+    Raise,
+    Lower,
+    /// ask the window manager to focus it, as _NET_ACTIVE_WINDOW
+    Activate,
+    /// ask the window manager to close it, as _NET_CLOSE_WINDOW
+    Close,
+    /// disconnect its client from the server
+    Kill,
+}
+
+/// names of `WindowAction`s, lowercase
+pub const WINDOW_ACTIONS: &[&str] = &["raise", "lower", "activate", "close", "kill"];
+
+impl FromStr for WindowAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<WindowAction, String> {
+        match s {
+            "raise" => Ok(WindowAction::Raise),
+            "lower" => Ok(WindowAction::Lower),
+            "activate" => Ok(WindowAction::Activate),
+            "close" => Ok(WindowAction::Close),
+            "kill" => Ok(WindowAction::Kill),
+            _ => Err(format!("unknown window action {}", s)),
+        }
+    }
+}
+
+/// Everything `Context` and `monitor` need from a display server.
+pub trait Backend: Send + Sync {
+    /// root window of the default screen
+    fn root(&self) -> WindowId;
+
+    /// true if the server is XWayland, whose native Wayland clients can not be seen
+    fn is_xwayland(&self) -> bool {
+        false
+    }
+
+    /// (width, height) of the default screen in pixels
+    fn screen_size(&self) -> (u16, u16);
+
+    /// RandR outputs lit by a CRTC, by name like `HDMI-1`, with the
+    /// rectangle of the CRTC in root coordinates
+    fn outputs(&self) -> Vec<(String, Geometry)> {
+        Vec::new()
+    }
+
+    /// (vendor, release number) of the server if known
+    fn vendor(&self) -> Option<(String, u32)> {
+        None
+    }
+
+    /// children of root in stacking order (bottom -> top)
+    fn query_tree(&self) -> Option<Vec<WindowId>>;
+
+    /// children of window `id` in stacking order, None if unknown
+    fn query_children(&self, _id: WindowId) -> Option<Vec<WindowId>> {
+        None
+    }
+
+    /// children of each of `ids`, like `query_children` but all asked at once
+    fn query_children_of(&self, ids: &[WindowId]) -> Vec<Option<Vec<WindowId>>> {
+        ids.iter().map(|&id| self.query_children(id)).collect()
+    }
+
+    /// collect windows info, the result keeps the order of `ids`
+    fn query_windows(&self, ids: &[WindowId]) -> Vec<Window> {
+        self.query_windows_with(ids, true)
+    }
+
+    /// like `query_windows`, names are left empty unless `names`
+    fn query_windows_with(&self, ids: &[WindowId], names: bool) -> Vec<Window>;
+
+    fn query_window(&self, id: WindowId) -> Window {
+        self.query_windows(&[id]).pop().unwrap_or_else(|| Window::new(id))
+    }
+
+    /// name of window `id` and the property it comes from
+    fn window_name(&self, id: WindowId) -> Option<(String, NameSource)>;
+
+    /// names of each of `ids`, like `window_name` but all asked at once
+    fn window_names(&self, ids: &[WindowId]) -> Vec<Option<(String, NameSource)>> {
+        ids.iter().map(|&id| self.window_name(id)).collect()
+    }
+
+    /// refresh the value of `kind` cached in `w` after it changed, by
+    /// querying the whole window unless done better
+    fn update_property(&self, w: &mut Window, kind: PropertyKind) {
+        copy_property(self.query_window(w.id), w, kind);
+    }
+
+    /// all properties of window `id` decoded, sorted by name
+    fn properties(&self, _id: WindowId) -> Vec<Property> {
+        Vec::new()
+    }
+
+    /// translate (x, y) in window `id` coordinates into root coordinates
+    fn translate_coordinates(&self, id: WindowId, x: i16, y: i16) -> Option<(i16, i16)>;
+
+    /// _NET_CLIENT_LIST of the window manager
+    fn client_list(&self) -> Vec<WindowId>;
+
+    /// _NET_CURRENT_DESKTOP of the window manager
+    fn current_desktop(&self) -> Option<u32> {
+        None
+    }
+
+    /// outline `geom` (in root coordinates) with a `color` (0xRRGGBB)
+    /// border of override-redirect windows on top of everything, return
+    /// the windows to pass to `unhighlight`. Events of them are not reported.
+    fn highlight(&self, _geom: Geometry, _color: u32) -> Vec<WindowId> {
+        Vec::new()
+    }
+
+    fn unhighlight(&self, _overlay: &[WindowId]) {}
+
+    /// carry out `action` on window `id`, false if it is not supported
+    fn control(&self, _id: WindowId, _action: WindowAction) -> bool {
+        false
+    }
+
+    /// let the user click a window and return the client under the pointer,
+    /// None if cancelled or not supported
+    fn pick_window(&self) -> Option<WindowId> {
+        None
+    }
+
+    /// subscribe to substructure changes of root
+    fn watch_root(&self);
+
+    /// block until next interesting event, None means no more events will come
+    fn wait_for_event(&self) -> Option<WindowEvent>;
+
+    /// next queued interesting event without blocking
+    fn poll_event(&self) -> Option<WindowEvent>;
+
+    /// fd that turns readable when events arrive, None if all events are
+    /// queued in advance (so `poll_event` returning None means the end)
+    fn event_fd(&self) -> Option<RawFd> {
+        None
+    }
+
+    /// true once the connection to the server is broken
+    fn connection_lost(&self) -> bool {
+        false
+    }
+
+    /// make a round trip to the server, false if it does not answer
+    fn ping(&self) -> bool {
+        !self.connection_lost()
+    }
+
+    /// how long the server was waited for if the connection was given up
+    /// for not answering
+    fn timed_out(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// move the value of `kind` from `from` into `to`
+fn copy_property(from: Window, to: &mut Window, kind: PropertyKind) {
+    match kind {
+        PropertyKind::Name => {
+            to.name = from.name;
+            to.name_source = from.name_source;
+        },
+        PropertyKind::Desktop => to.desktop = from.desktop,
+        PropertyKind::ClientMachine => to.client_machine = from.client_machine,
+        PropertyKind::TransientFor => to.transient_for = from.transient_for,
+        PropertyKind::Class => to.class = from.class,
+        PropertyKind::Pid => to.pid = from.pid,
+        PropertyKind::WindowType => to.window_type = from.window_type,
+        PropertyKind::State | PropertyKind::WmState => {
+            to.state = from.state;
+            to.attrs.iconified = from.attrs.iconified;
+        },
+        PropertyKind::Opacity => to.opacity = from.opacity,
+        PropertyKind::ClientListStacking | PropertyKind::CurrentDesktop | PropertyKind::Other => {},
+    }
+}
+
+/// _NET_WM_NAME, WM_NAME and WM_LOCALE_NAME of a window
+pub struct NameCookies<'a> {
+    net: xcb::GetPropertyCookie<'a>,
+    icccm: xcb::GetPropertyCookie<'a>,
+    locale: Option<xcb::GetPropertyCookie<'a>>,
+}
+
+//TODO: property changes over time
+pub enum XcbRequest<'a> {
+    GWA(xcb::GetWindowAttributesCookie<'a>),
+    GE(xcb::GetGeometryCookie<'a>),
+    GP(xcb::GetPropertyCookie<'a>),
+    GWN(NameCookies<'a>),
+    GWD(ewmh::GetWmDesktopCookie<'a>),
+    GCM(icccm::GetTextPropertyCookie<'a>),
+    /// WM_TRANSIENT_FOR
+    GTF(xcb::GetPropertyCookie<'a>),
+    GWC(icccm::GetWmClassCookie<'a>),
+    GPID(ewmh::GetWmPidCookie<'a>),
+    GWT(ewmh::GetWmWindowTypeCookie<'a>),
+    GWS(ewmh::GetWmStateCookie<'a>),
+    /// the parent of the window
+    GQT(xcb::QueryTreeCookie<'a>),
+    /// _NET_WM_WINDOW_OPACITY
+    GOP(xcb::GetPropertyCookie<'a>),
+    /// ICCCM WM_STATE
+    GIS(xcb::GetPropertyCookie<'a>),
+}
+
+/// state of WM_STATE of a minimized window, ICCCM 4.1.3.1
+const ICONIC_STATE: u32 = 3;
+
+fn as_event<T>(e: &xcb::GenericEvent) -> &T {
+    unsafe { xcb::cast_event::<T>(e) }
+}
+
+#[derive(Debug)]
+pub enum ConnectError {
+    InvalidDisplay(String),
+    InvalidScreen(String),
+    /// nothing is listening at the display's socket
+    NoServer(String),
+    /// server is there but refused us, most likely an Xauthority problem
+    AuthFailed(String),
+    Timeout(String, Duration),
+    Other(String, String),
+}
+
+impl fmt::Display for ConnectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::ConnectError::*;
+        match *self {
+            InvalidDisplay(ref d) => write!(f, "invalid display name {:?}", d),
+            InvalidScreen(ref d) => write!(f, "X server {} has no such screen", d),
+            NoServer(ref d) => write!(f, "cannot connect to X server {}: no server is listening", d),
+            AuthFailed(ref d) => write!(f, "X server {} refused the connection: authorization failed \
+                                        (check XAUTHORITY or run xauth)", d),
+            Timeout(ref d, t) => write!(f, "timed out after {}s connecting to X server {}", t.as_secs(), d),
+            Other(ref d, ref e) => write!(f, "cannot connect to X server {}: {}", d, e),
+        }
+    }
+}
+
+impl ::std::error::Error for ConnectError {}
+
+/// where an X display lives, parsed from `[protocol/][host]:display[.screen]`
+#[derive(Debug, PartialEq, Eq)]
+enum DisplayAddr {
+    Unix(String),
+    Tcp(String, u16),
+}
+
+fn parse_display(name: &str) -> Option<DisplayAddr> {
+    if name.starts_with('/') {
+        // launchd socket path of XQuartz
+        return Some(DisplayAddr::Unix(name.to_string()));
+    }
+
+    let (proto, rest) = match name.find('/') {
+        Some(i) => (&name[..i], &name[i+1..]),
+        None => ("", name)
+    };
+    let colon = rest.rfind(':')?;
+    let host = &rest[..colon];
+    let num = rest[colon+1..].split('.').next().unwrap_or("");
+    let num = num.parse::<u16>().ok()?;
+
+    if (host.is_empty() || host == "unix") && proto != "tcp" {
+        Some(DisplayAddr::Unix(format!("/tmp/.X11-unix/X{}", num)))
+    } else {
+        let host = if host.is_empty() { "localhost" } else { host };
+        Some(DisplayAddr::Tcp(host.to_string(), 6000 + num))
+    }
+}
+
+/// tell if anything listens at the display's address
+fn probe_server(addr: &DisplayAddr, timeout: Duration) -> bool {
+    match *addr {
+        DisplayAddr::Unix(ref path) => {
+            if UnixStream::connect(path).is_ok() {
+                return true;
+            }
+
+            #[cfg(target_os = "linux")]
+            {
+                use std::os::linux::net::SocketAddrExt;
+                use std::os::unix::net::SocketAddr;
+                if let Ok(sa) = SocketAddr::from_abstract_name(path.as_bytes()) {
+                    return UnixStream::connect_addr(&sa).is_ok();
+                }
+            }
+            false
+        },
+        DisplayAddr::Tcp(ref host, port) => {
+            match (host.as_str(), port).to_socket_addrs() {
+                Ok(mut addrs) => addrs.any(|sa| TcpStream::connect_timeout(&sa, timeout).is_ok()),
+                Err(_) => false
+            }
+        }
+    }
+}
+
+/// how long a request waits for its reply unless told otherwise
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// how long to wait for events before checking that the server is alive
+pub const PING_INTERVAL: Duration = Duration::from_secs(30);
+
+/// how often the watchdog looks at pending requests
+const WATCHDOG_TICK: Duration = Duration::from_millis(100);
+
+struct WatchState {
+    timeout: Duration,
+    /// requests waiting for their replies
+    pending: usize,
+    /// when the first pending request was sent or a reply last came
+    progress: Instant,
+    expired: bool,
+    closed: bool,
+}
+
+/// Shuts down the connection at `fd` once requests wait for replies longer
+/// than the timeout, so that a frozen server makes the blocked get_reply()
+/// fail instead of hanging forever.
+struct Watchdog {
+    state: Arc<Mutex<WatchState>>,
+}
+
+/// a request in flight, see `Watchdog::guard`
+struct WatchGuard<'a>(&'a Watchdog);
+
+impl Watchdog {
+    fn new(fd: RawFd, timeout: Duration) -> Watchdog {
+        let state = Arc::new(Mutex::new(WatchState {
+            timeout, pending: 0, progress: Instant::now(), expired: false, closed: false,
+        }));
+
+        let watched = state.clone();
+        thread::spawn(move || loop {
+            thread::sleep(WATCHDOG_TICK);
+            let mut s = watched.lock().unwrap();
+            if s.closed {
+                return;
+            }
+            if s.pending > 0 && s.progress.elapsed() > s.timeout {
+                // still locked, so the connection can not be closed meanwhile
+                s.expired = true;
+                unsafe { libc::shutdown(fd, libc::SHUT_RDWR) };
+                return;
+            }
+        });
+
+        Watchdog { state }
+    }
+
+    fn set_timeout(&self, timeout: Duration) {
+        self.state.lock().unwrap().timeout = timeout;
+    }
+
+    /// keep the returned guard while waiting for replies
+    fn guard(&self) -> WatchGuard<'_> {
+        let mut s = self.state.lock().unwrap();
+        if s.pending == 0 {
+            s.progress = Instant::now();
+        }
+        s.pending += 1;
+        WatchGuard(self)
+    }
+
+    fn timed_out(&self) -> Option<Duration> {
+        let s = self.state.lock().unwrap();
+        if s.expired { Some(s.timeout) } else { None }
+    }
+}
+
+impl<'a> Drop for WatchGuard<'a> {
+    fn drop(&mut self) {
+        let mut s = self.0.state.lock().unwrap();
+        s.pending -= 1;
+        s.progress = Instant::now();
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        self.state.lock().unwrap().closed = true;
+    }
+}
+
+pub struct XcbBackend {
+    /// dropped before the connection it watches
+    watchdog: Watchdog,
+    c: ewmh::Connection,
+    screen: i32,
+    root: WindowId,
+    screen_size: (u16, u16),
+    xwayland: bool,
+    /// set by the compositor on windows backed by a wl_surface
+    wl_surface_id: xcb::Atom,
+    /// WM_LOCALE_NAME, none if no client had set it when connecting
+    locale_name: xcb::Atom,
+    /// _NET_WM_WINDOW_OPACITY of compositors
+    opacity: xcb::Atom,
+    /// ICCCM WM_STATE, `ewmh::Connection::WM_STATE` is _NET_WM_STATE
+    wm_state: xcb::Atom,
+    /// highlight windows, kept until their DestroyNotify is seen
+    overlays: Mutex<HashSet<xcb::Window>>,
+    /// names of atoms seen so far, atoms live as long as the server
+    atom_names: Mutex<HashMap<xcb::Atom, String>>,
+}
+
+impl XcbBackend {
+    /// Connect to `display` (DISPLAY if None), giving up after `timeout`.
+    /// XAUTHORITY is honored by xcb itself.
+    pub fn connect(display: Option<&str>, timeout: Duration) -> Result<XcbBackend, ConnectError> {
+        XcbBackend::connect_screen(display, None, timeout)
+    }
+
+    /// like `connect`, but use `screen` instead of the one given by display name
+    pub fn connect_screen(display: Option<&str>, screen: Option<i32>, timeout: Duration)
+        -> Result<XcbBackend, ConnectError> {
+        let name = match display {
+            Some(d) => d.to_string(),
+            None => env::var("DISPLAY").unwrap_or_default()
+        };
+        if name.is_empty() {
+            return Err(ConnectError::InvalidDisplay(name));
+        }
+
+        // xcb_connect can block forever on a frozen server, so do it aside
+        let (tx, rx) = mpsc::channel();
+        let display_name = name.clone();
+        thread::spawn(move || {
+            let _ = tx.send(xcb::Connection::connect(Some(&display_name)));
+        });
+
+        let (c, preferred) = match rx.recv_timeout(timeout) {
+            Ok(Ok(v)) => v,
+            Ok(Err(xcb::ConnError::ClosedParseErr)) => return Err(ConnectError::InvalidDisplay(name)),
+            Ok(Err(xcb::ConnError::ClosedInvalidScreen)) => return Err(ConnectError::InvalidScreen(name)),
+            Ok(Err(xcb::ConnError::Connection)) => {
+                return Err(match parse_display(&name) {
+                    None => ConnectError::InvalidDisplay(name),
+                    Some(ref addr) if probe_server(addr, timeout) => ConnectError::AuthFailed(name),
+                    Some(_) => ConnectError::NoServer(name),
+                });
+            },
+            Ok(Err(e)) => return Err(ConnectError::Other(name, format!("{:?}", e))),
+            Err(_) => return Err(ConnectError::Timeout(name, timeout)),
+        };
+
+        let ewmh = match ewmh::Connection::connect(c) {
+            Ok(ewmh) => ewmh,
+            Err((e, _)) => return Err(ConnectError::Other(name, format!("ewmh init failed: {}", e.error_code()))),
+        };
+
+        let backend = XcbBackend::new(ewmh, screen.unwrap_or(preferred)).ok_or(ConnectError::InvalidScreen(name))?;
+        backend.set_request_timeout(timeout);
+        Ok(backend)
+    }
+
+    /// None if `screen` does not exist
+    pub fn new(c: ewmh::Connection, screen: i32) -> Option<XcbBackend> {
+        let (root, screen_size) = match c.get_setup().roots().nth(screen as usize) {
+            Some(s) => (WindowId(s.root()), (s.width_in_pixels(), s.height_in_pixels())),
+            None => return None,
+        };
+
+        let watchdog = Watchdog::new(c.as_raw_fd(), REQUEST_TIMEOUT);
+        let _guard = watchdog.guard();
+        let wl_surface_id = xcb::intern_atom(&c, true, "WL_SURFACE_ID").get_reply()
+            .map(|r| r.atom()).unwrap_or(xcb::ATOM_NONE);
+        // XWAYLAND extension shows up since xwayland 21.1, older ones can only
+        // be told by the compositor having interned WL_SURFACE_ID
+        let xwayland = xcb::query_extension(&c, "XWAYLAND").get_reply()
+            .map(|r| r.present()).unwrap_or(false) || wl_surface_id != xcb::ATOM_NONE;
+
+        let locale_name = xcb::intern_atom(&c, true, "WM_LOCALE_NAME").get_reply()
+            .map(|r| r.atom()).unwrap_or(xcb::ATOM_NONE);
+        // created if need be, so changes are told even if set later
+        let opacity = xcb::intern_atom(&c, false, "_NET_WM_WINDOW_OPACITY").get_reply()
+            .map(|r| r.atom()).unwrap_or(xcb::ATOM_NONE);
+        let wm_state = xcb::intern_atom(&c, false, "WM_STATE").get_reply()
+            .map(|r| r.atom()).unwrap_or(xcb::ATOM_NONE);
+
+        drop(_guard);
+        Some(XcbBackend { watchdog, c, screen, root, screen_size, xwayland, wl_surface_id, locale_name, opacity, wm_state,
+                          overlays: Mutex::new(HashSet::new()), atom_names: Mutex::new(HashMap::new()) })
+    }
+
+    pub fn connection(&self) -> &ewmh::Connection {
+        &self.c
+    }
+
+    /// the client window of `frame`, the first one carrying WM_STATE found
+    /// below it, or `frame` itself if there is none
+    fn client_of(&self, frame: WindowId) -> WindowId {
+        if self.wm_state == xcb::ATOM_NONE {
+            return frame;
+        }
+        let _guard = self.watchdog.guard();
+        let c = &self.c;
+        let has_state = |w: WindowId| xcb::get_property(c, false, w.raw(), self.wm_state, xcb::ATOM_ANY, 0, 0)
+            .get_reply().map(|r| r.type_() != xcb::ATOM_NONE).unwrap_or(false);
+
+        // reparenting window managers nest clients a level or two down
+        let mut level = vec![frame];
+        for _ in 0..4 {
+            if let Some(&client) = level.iter().find(|&&w| has_state(w)) {
+                return client;
+            }
+            level = self.query_children_of(&level).into_iter().flatten().flatten().collect();
+            if level.is_empty() {
+                break;
+            }
+        }
+        frame
+    }
+
+    fn request_name(&self, w: xcb::Window) -> NameCookies<'_> {
+        let get = |atom| xcb::get_property(&self.c, false, w, atom, xcb::ATOM_ANY, 0, 1024);
+        let locale = if self.locale_name != xcb::ATOM_NONE { Some(get(self.locale_name)) } else { None };
+        NameCookies { net: get(self.c.WM_NAME()), icccm: get(xcb::ATOM_WM_NAME), locale }
+    }
+
+    /// _NET_WM_NAME, or WM_NAME decoded from its type and the locale of the
+    /// window if there is none. Bytes that can not be decoded are replaced,
+    /// and logged to tell broken clients.
+    fn name_of(&self, id: WindowId, cookies: NameCookies) -> Option<(String, NameSource)> {
+        let text = |cookie: xcb::GetPropertyCookie| cookie.get_reply().ok()
+            .filter(|r| r.type_() != xcb::ATOM_NONE && r.format() == 8)
+            .map(|r| (r.type_(), r.value::<u8>().to_vec()));
+        let (net, icccm, locale) = (text(cookies.net), text(cookies.icccm), cookies.locale.and_then(text));
+        let locale = locale.map(|(_, b)| String::from_utf8_lossy(&b).trim_end_matches('\0').to_string());
+
+        let names = self.atom_names(net.iter().chain(&icccm).map(|&(type_, _)| type_).collect());
+        let net = net.map(|n| (n, NameSource::NetWmName));
+        let icccm = icccm.map(|n| (n, NameSource::WmName));
+        net.into_iter().chain(icccm).find_map(|((type_, b), source)| {
+            let type_name = names.get(&type_)?;
+            let (name, exact) = props::decode_text(type_name, &b, locale.as_deref())?;
+            if !exact {
+                wm_info!("{} of {} is not valid {}: {:?}", source, id, type_name, name);
+            } else if source == NameSource::WmName {
+                wm_debug!("name of {} from WM_NAME({})", id, type_name);
+            }
+            Some((name, source))
+        })
+    }
+
+    /// names of atoms seen so far, with those of `wanted` not seen yet
+    /// asked in one round trip
+    fn atom_names(&self, mut wanted: Vec<xcb::Atom>) -> MutexGuard<'_, HashMap<xcb::Atom, String>> {
+        let mut names = self.atom_names.lock().unwrap();
+        wanted.retain(|a| !names.contains_key(a));
+        wanted.sort();
+        wanted.dedup();
+        let cookies: Vec<_> = wanted.iter().map(|&a| xcb::get_atom_name(&self.c, a)).collect();
+        for (&a, cookie) in wanted.iter().zip(cookies) {
+            if let Ok(r) = cookie.get_reply() {
+                names.insert(a, r.name().to_string());
+            }
+        }
+        names
+    }
+
+    /// give up the connection once a request waits longer than `timeout`
+    /// for its reply, `REQUEST_TIMEOUT` by default
+    pub fn set_request_timeout(&self, timeout: Duration) {
+        self.watchdog.set_timeout(timeout);
+    }
+
+    fn translate(&self, ev: &xcb::GenericEvent) -> Option<WindowEvent> {
+        let ev = self.translate_event(ev)?;
+        let mut overlays = self.overlays.lock().unwrap();
+        if overlays.is_empty() || !overlays.contains(&ev.window().raw()) {
+            return Some(ev);
+        }
+        if let WindowEvent::Destroyed { window } = ev {
+            overlays.remove(&window.raw());
+        }
+        None
+    }
+
+    fn translate_event(&self, ev: &xcb::GenericEvent) -> Option<WindowEvent> {
+        match ev.response_type() & !0x80 {
+            xproto::CREATE_NOTIFY => {
+                let cne = as_event::<xcb::CreateNotifyEvent>(ev);
+                Some(WindowEvent::Created { window: cne.window().into(), parent: cne.parent().into() })
+            },
+            xproto::DESTROY_NOTIFY => {
+                let dne = as_event::<xcb::DestroyNotifyEvent>(ev);
+                Some(WindowEvent::Destroyed { window: dne.window().into() })
+            },
+            xproto::REPARENT_NOTIFY => {
+                let rne = as_event::<xcb::ReparentNotifyEvent>(ev);
+                Some(WindowEvent::Reparented { window: rne.window().into(), parent: rne.parent().into() })
+            },
+            xproto::CONFIGURE_NOTIFY => {
+                let cne = as_event::<xcb::ConfigureNotifyEvent>(ev);
+                Some(WindowEvent::Configured {
+                    window: cne.window().into(),
+                    above_sibling: cne.above_sibling().into(),
+                    old: None,
+                    new: Geometry { x: cne.x(), y: cne.y(), width: cne.width(), height: cne.height() },
+                    override_redirect: cne.override_redirect(),
+                })
+            },
+            xproto::MAP_NOTIFY => {
+                let mn = as_event::<xcb::MapNotifyEvent>(ev);
+                Some(WindowEvent::Mapped { window: mn.window().into() })
+            },
+            xproto::UNMAP_NOTIFY => {
+                let un = as_event::<xcb::UnmapNotifyEvent>(ev);
+                Some(WindowEvent::Unmapped { window: un.window().into() })
+            },
+            xproto::PROPERTY_NOTIFY => {
+                let pn = as_event::<xcb::PropertyNotifyEvent>(ev);
+                let atom = pn.atom();
+                let c = &self.c;
+                let kind = match atom {
+                    _ if atom == c.WM_NAME() => PropertyKind::Name,
+                    _ if atom == self.locale_name => PropertyKind::Name,
+                    _ if atom == self.opacity && atom != xcb::ATOM_NONE => PropertyKind::Opacity,
+                    _ if atom == self.wm_state && atom != xcb::ATOM_NONE => PropertyKind::WmState,
+                    xcb::ATOM_WM_NAME => PropertyKind::Name,
+                    _ if atom == c.CLIENT_LIST_STACKING() => PropertyKind::ClientListStacking,
+                    _ if atom == c.CURRENT_DESKTOP() => PropertyKind::CurrentDesktop,
+                    _ if atom == c.WM_DESKTOP() => PropertyKind::Desktop,
+                    _ if atom == c.WM_PID() => PropertyKind::Pid,
+                    _ if atom == c.WM_WINDOW_TYPE() => PropertyKind::WindowType,
+                    _ if atom == c.WM_STATE() => PropertyKind::State,
+                    xcb::ATOM_WM_CLIENT_MACHINE => PropertyKind::ClientMachine,
+                    xcb::ATOM_WM_TRANSIENT_FOR => PropertyKind::TransientFor,
+                    xcb::ATOM_WM_CLASS => PropertyKind::Class,
+                    _ => PropertyKind::Other,
+                };
+                Some(WindowEvent::PropertyChanged { window: pn.window().into(), kind })
+            },
+            _ => None
+        }
+    }
+}
+
+impl Backend for XcbBackend {
+    fn root(&self) -> WindowId {
+        self.root
+    }
+
+    fn is_xwayland(&self) -> bool {
+        self.xwayland
+    }
+
+    fn screen_size(&self) -> (u16, u16) {
+        self.screen_size
+    }
+
+    fn outputs(&self) -> Vec<(String, Geometry)> {
+        let _guard = self.watchdog.guard();
+        let res = match randr::get_screen_resources_current(&self.c, self.root.raw()).get_reply() {
+            Ok(res) => res,
+            Err(_) => return Vec::new(),
+        };
+        let ts = res.config_timestamp();
+        let cookies: Vec<_> = res.outputs().iter().map(|&o| randr::get_output_info(&self.c, o, ts)).collect();
+        cookies.into_iter()
+            .filter_map(|cookie| cookie.get_reply().ok().filter(|info| info.crtc() != xcb::NONE))
+            .filter_map(|info| {
+                let crtc = randr::get_crtc_info(&self.c, info.crtc(), ts).get_reply().ok()?;
+                let geom = Geometry { x: crtc.x(), y: crtc.y(), width: crtc.width(), height: crtc.height() };
+                Some((String::from_utf8_lossy(info.name()).into_owned(), geom))
+            })
+            .collect()
+    }
+
+    fn vendor(&self) -> Option<(String, u32)> {
+        let setup = self.c.get_setup();
+        Some((setup.vendor().to_string(), setup.release_number()))
+    }
+
+    fn query_tree(&self) -> Option<Vec<WindowId>> {
+        self.query_children(self.root)
+    }
+
+    fn query_children(&self, id: WindowId) -> Option<Vec<WindowId>> {
+        self.query_children_of(&[id]).pop().unwrap_or(None)
+    }
+
+    fn query_children_of(&self, ids: &[WindowId]) -> Vec<Option<Vec<WindowId>>> {
+        let _guard = self.watchdog.guard();
+        let cookies: Vec<_> = ids.iter().map(|id| xcb::query_tree(&self.c, id.raw())).collect();
+        cookies.into_iter()
+            .map(|cookie| cookie.get_reply().ok().map(|res| res.children().iter().map(|&id| WindowId(id)).collect()))
+            .collect()
+    }
+
+    fn query_windows_with(&self, window_ids: &[WindowId], names: bool) -> Vec<Window> {
+        let _guard = self.watchdog.guard();
+        let c = &self.c;
+
+        let check_surface = self.wl_surface_id != xcb::ATOM_NONE;
+
+        // issue all requests first, then collect replies in one go
+        let mut qs: Vec<(usize, XcbRequest)> = Vec::new();
+        for (idx, w) in window_ids.iter().map(|w| w.raw()).enumerate() {
+            qs.push((idx, XcbRequest::GWA(xcb::get_window_attributes(c, w))));
+            qs.push((idx, XcbRequest::GE(xcb::get_geometry(c, w))));
+            if names {
+                qs.push((idx, XcbRequest::GWN(self.request_name(w))));
+            }
+            qs.push((idx, XcbRequest::GWD(ewmh::get_wm_desktop_unchecked(c, w))));
+            qs.push((idx, XcbRequest::GCM(icccm::get_wm_client_machine_unchecked(c, w))));
+            qs.push((idx, XcbRequest::GTF(xcb::get_property(c, false, w, xcb::ATOM_WM_TRANSIENT_FOR,
+                                                            xcb::ATOM_WINDOW, 0, 1))));
+            qs.push((idx, XcbRequest::GWC(icccm::get_wm_class_unchecked(c, w))));
+            qs.push((idx, XcbRequest::GPID(ewmh::get_wm_pid_unchecked(c, w))));
+            qs.push((idx, XcbRequest::GWT(ewmh::get_wm_window_type_unchecked(c, w))));
+            qs.push((idx, XcbRequest::GWS(ewmh::get_wm_state_unchecked(c, w))));
+            qs.push((idx, XcbRequest::GQT(xcb::query_tree(c, w))));
+            if self.wm_state != xcb::ATOM_NONE {
+                qs.push((idx, XcbRequest::GIS(xcb::get_property(c, false, w, self.wm_state, self.wm_state, 0, 1))));
+            }
+            if self.opacity != xcb::ATOM_NONE {
+                qs.push((idx, XcbRequest::GOP(xcb::get_property(c, false, w, self.opacity, xcb::ATOM_CARDINAL, 0, 1))));
+            }
+            if check_surface {
+                qs.push((idx, XcbRequest::GP(xcb::get_property(c, false, w, self.wl_surface_id,
+                                                               xcb::ATOM_ANY, 0, 1))));
+            }
+        }
+
+        macro_rules! apply_reply {
+            ($win:ident $cookie:ident $reply:ident $e:expr) => (
+                match $cookie.get_reply() {
+                    Ok($reply) => $e,
+                    Err(err) => {
+                        wm_debug!("---######### {:?}", err);
+                        $win.valid = false
+                    },
+                })
+        }
+
+        let mut windows: Vec<Window> = window_ids.iter().map(|&id| Window::new(id)).collect();
+        // _NET_WM_WINDOW_TYPE and _NET_WM_STATE atoms, named once all came
+        let mut types: Vec<Vec<xcb::Atom>> = vec![Vec::new(); windows.len()];
+        let mut states: Vec<Vec<xcb::Atom>> = vec![Vec::new(); windows.len()];
+
+        let ev_mask: u32 = xproto::EVENT_MASK_STRUCTURE_NOTIFY | xproto::EVENT_MASK_PROPERTY_CHANGE |
+            xproto::EVENT_MASK_FOCUS_CHANGE;
+        for &id in window_ids {
+            xcb::xproto::change_window_attributes(c, id.raw(), &[(xcb::xproto::CW_EVENT_MASK, ev_mask)]);
+        }
+
+        for (idx, query) in qs {
+            let win = &mut windows[idx];
+            match query {
+                XcbRequest::GWA(cookie) => {
+                    apply_reply!(win cookie reply {
+                        win.attrs.override_redirect = reply.override_redirect();
+                        win.attrs.input_only = u32::from(reply.class()) == xcb::WINDOW_CLASS_INPUT_ONLY;
+                        win.attrs.map_state = match reply.map_state() {
+                            0 => MapState::Unmapped,
+                            2 => MapState::Viewable,
+                            _ => MapState::Unviewable,
+                        };
+                    })
+                },
+                XcbRequest::GE(cookie) => {
+                    apply_reply!(win cookie reply {
+                        win.geom = Geometry {
+                            x: reply.x(),
+                            y: reply.y(),
+                            width: reply.width(),
+                            height: reply.height(),
+                        };
+                        win.border_width = reply.border_width();
+                        win.depth = reply.depth();
+                    })
+                },
+                // optional properties, missing ones do not invalidate the window
+                XcbRequest::GWN(cookies) => {
+                    if let Some((name, source)) = self.name_of(win.id, cookies) {
+                        win.name = name;
+                        win.name_source = Some(source);
+                    }
+                },
+                XcbRequest::GWD(cookie) => {
+                    win.desktop = cookie.get_reply().ok();
+                },
+                XcbRequest::GCM(cookie) => {
+                    win.client_machine = cookie.get_reply().ok().map(|r| r.name().to_string());
+                },
+                XcbRequest::GTF(cookie) => {
+                    win.transient_for = cookie.get_reply().ok()
+                        .and_then(|r| r.value::<xcb::Window>().first().cloned())
+                        .filter(|&id| id != xcb::WINDOW_NONE)
+                        .map(WindowId);
+                },
+                XcbRequest::GP(cookie) => {
+                    if let Ok(reply) = cookie.get_reply() {
+                        win.xwayland = reply.type_() != xcb::ATOM_NONE;
+                    }
+                },
+                XcbRequest::GWC(cookie) => {
+                    win.class = cookie.get_reply().ok().map(|r| r.class().to_string());
+                },
+                XcbRequest::GPID(cookie) => {
+                    win.pid = cookie.get_reply().ok();
+                },
+                XcbRequest::GWT(cookie) => {
+                    types[idx] = cookie.get_reply().map(|r| r.atoms().to_vec()).unwrap_or_default();
+                },
+                XcbRequest::GWS(cookie) => {
+                    states[idx] = cookie.get_reply().map(|r| r.atoms().to_vec()).unwrap_or_default();
+                },
+                XcbRequest::GQT(cookie) => {
+                    win.parent = cookie.get_reply().ok().map(|r| r.parent())
+                        .filter(|&id| id != xcb::WINDOW_NONE)
+                        .map(WindowId);
+                },
+                XcbRequest::GOP(cookie) => {
+                    win.opacity = cookie.get_reply().ok().and_then(|r| r.value::<u32>().first().cloned());
+                },
+                XcbRequest::GIS(cookie) => {
+                    let state = cookie.get_reply().ok().and_then(|r| r.value::<u32>().first().cloned());
+                    win.attrs.iconified = state == Some(ICONIC_STATE);
+                },
+            }
+        }
+
+        let wanted: Vec<xcb::Atom> = types.iter().chain(states.iter()).flatten().cloned().collect();
+        let names = self.atom_names(wanted);
+        let name = |a: &xcb::Atom| names.get(a).cloned().unwrap_or_else(|| a.to_string());
+        for (win, (types, states)) in windows.iter_mut().zip(types.iter().zip(&states)) {
+            win.window_type = types.iter().map(&name).collect();
+            win.state = states.iter().map(&name).collect();
+            win.attrs.iconified |= win.state.iter().any(|s| s == "_NET_WM_STATE_HIDDEN");
+        }
+
+        windows
+    }
+
+    fn translate_coordinates(&self, id: WindowId, x: i16, y: i16) -> Option<(i16, i16)> {
+        let _guard = self.watchdog.guard();
+        xcb::translate_coordinates(&self.c, id.raw(), self.root.raw(), x, y).get_reply().ok()
+            .map(|r| (r.dst_x(), r.dst_y()))
+    }
+
+    fn window_name(&self, id: WindowId) -> Option<(String, NameSource)> {
+        let _guard = self.watchdog.guard();
+        self.name_of(id, self.request_name(id.raw()))
+    }
+
+    fn window_names(&self, ids: &[WindowId]) -> Vec<Option<(String, NameSource)>> {
+        let _guard = self.watchdog.guard();
+        let cookies: Vec<_> = ids.iter().map(|id| self.request_name(id.raw())).collect();
+        ids.iter().zip(cookies).map(|(&id, cookies)| self.name_of(id, cookies)).collect()
+    }
+
+    fn properties(&self, id: WindowId) -> Vec<Property> {
+        let _guard = self.watchdog.guard();
+        let c = &self.c;
+        let atoms = match xcb::list_properties(c, id.raw()).get_reply() {
+            Ok(reply) => reply.atoms().to_vec(),
+            Err(_) => return Vec::new(),
+        };
+
+        let cookies: Vec<_> = atoms.iter()
+            .map(|&atom| xcb::get_property(c, false, id.raw(), atom, xcb::ATOM_ANY, 0, 1024))
+            .collect();
+        let mut raws = Vec::new();
+        for (&atom, cookie) in atoms.iter().zip(cookies) {
+            if let Ok(reply) = cookie.get_reply() {
+                let raw = match reply.format() {
+                    8 => RawValue::Bytes(reply.value::<u8>().to_vec()),
+                    16 => RawValue::Words(reply.value::<u16>().iter().map(|&v| v as u32).collect()),
+                    _ => RawValue::Words(reply.value::<u32>().to_vec()),
+                };
+                raws.push((atom, reply.type_(), raw));
+            }
+        }
+
+        // names of properties, types and atom values
+        let mut wanted: Vec<xcb::Atom> = raws.iter().map(|r| r.0).chain(raws.iter().map(|r| r.1)).collect();
+        for (_, type_, raw) in &raws {
+            if let (xcb::ATOM_ATOM, RawValue::Words(w)) = (*type_, raw) {
+                wanted.extend(w.iter().cloned());
+            }
+        }
+        let names = self.atom_names(wanted);
+        let name = |a: u32| names.get(&a).cloned().unwrap_or_else(|| a.to_string());
+
+        let mut result: Vec<Property> = raws.iter().map(|&(atom, type_, ref raw)| {
+            let type_name = name(type_);
+            let value = props::decode(&type_name, raw, &name);
+            Property { name: name(atom), type_name, value }
+        }).collect();
+        result.sort_by(|a, b| a.name.cmp(&b.name));
+        result
+    }
+
+    fn client_list(&self) -> Vec<WindowId> {
+        let _guard = self.watchdog.guard();
+        let cookie = ewmh::get_client_list_unchecked(&self.c, self.screen);
+        match cookie.get_reply() {
+            Ok(ref reply) => {
+                let list: Vec<WindowId> = reply.windows().iter().map(|&id| WindowId(id)).collect();
+                wm_debug!("CLIENT_LIST: {:#?}", HexedVec(&list));
+                list
+            },
+            _ => Vec::new()
+        }
+    }
+
+    fn current_desktop(&self) -> Option<u32> {
+        let _guard = self.watchdog.guard();
+        ewmh::get_current_desktop(&self.c, self.screen).get_reply().ok()
+    }
+
+    fn highlight(&self, geom: Geometry, color: u32) -> Vec<WindowId> {
+        const BORDER: u16 = 4;
+        let Geometry { x, y, width, height } = geom;
+        let (w, h) = (width + 2 * BORDER, height + 2 * BORDER);
+        let (x, y) = (x - BORDER as i16, y - BORDER as i16);
+        let edges = [
+            (x, y, w, BORDER),
+            (x, y + h as i16 - BORDER as i16, w, BORDER),
+            (x, y, BORDER, h),
+            (x + w as i16 - BORDER as i16, y, BORDER, h),
+        ];
+
+        let mut overlay = Vec::with_capacity(edges.len());
+        for &(x, y, width, height) in &edges {
+            let id = self.c.generate_id();
+            xcb::create_window(&self.c, xcb::COPY_FROM_PARENT as u8, id, self.root.raw(), x, y, width, height, 0,
+                               xproto::WINDOW_CLASS_INPUT_OUTPUT as u16, xcb::COPY_FROM_PARENT,
+                               &[(xproto::CW_BACK_PIXEL, color), (xproto::CW_OVERRIDE_REDIRECT, 1)]);
+            xcb::map_window(&self.c, id);
+            overlay.push(WindowId(id));
+        }
+        self.overlays.lock().unwrap().extend(overlay.iter().map(|id| id.raw()));
+        self.c.flush();
+        overlay
+    }
+
+    fn unhighlight(&self, overlay: &[WindowId]) {
+        for id in overlay {
+            xcb::destroy_window(&self.c, id.raw());
+        }
+        self.c.flush();
+    }
+
+    fn control(&self, id: WindowId, action: WindowAction) -> bool {
+        let c = &self.c;
+        match action {
+            WindowAction::Raise | WindowAction::Lower => {
+                let mode = if action == WindowAction::Raise { xproto::STACK_MODE_ABOVE } else { xproto::STACK_MODE_BELOW };
+                xcb::configure_window(c, id.raw(), &[(xproto::CONFIG_WINDOW_STACK_MODE as u16, mode)]);
+            },
+            WindowAction::Activate => {
+                ewmh::request_change_active_window(c, self.screen, id.raw(), ewmh::CLIENT_SOURCE_TYPE_OTHER,
+                                                   xcb::CURRENT_TIME, xcb::NONE);
+            },
+            WindowAction::Close => {
+                ewmh::request_close_window(c, self.screen, id.raw(), xcb::CURRENT_TIME, ewmh::CLIENT_SOURCE_TYPE_OTHER);
+            },
+            WindowAction::Kill => { xcb::kill_client(c, id.raw()); },
+        }
+        c.flush()
+    }
+
+    fn pick_window(&self) -> Option<WindowId> {
+        let c = &self.c;
+        // the crosshair of the cursor font, like xwininfo shows
+        let (font, cursor) = (c.generate_id(), c.generate_id());
+        xcb::open_font(c, font, "cursor");
+        xcb::create_glyph_cursor(c, cursor, font, font, 34, 35, 0, 0, 0, 0xffff, 0xffff, 0xffff);
+        let mask = (xproto::EVENT_MASK_BUTTON_PRESS | xproto::EVENT_MASK_BUTTON_RELEASE) as u16;
+        let grabbed = {
+            let _guard = self.watchdog.guard();
+            xcb::grab_pointer(c, false, self.root.raw(), mask, xproto::GRAB_MODE_ASYNC as u8,
+                              xproto::GRAB_MODE_ASYNC as u8, xcb::NONE, cursor, xcb::CURRENT_TIME)
+                .get_reply().map(|r| r.status() == xproto::GRAB_STATUS_SUCCESS as u8).unwrap_or(false)
+        };
+        xcb::free_cursor(c, cursor);
+        xcb::close_font(c, font);
+        if !grabbed {
+            c.flush();
+            return None;
+        }
+
+        // the first button picks, any other cancels; done once it is released
+        let mut picked = None;
+        while let Some(ev) = c.wait_for_event() {
+            match ev.response_type() & !0x80 {
+                xproto::BUTTON_PRESS => {
+                    let press = as_event::<xcb::ButtonPressEvent>(&ev);
+                    picked = match (press.detail(), press.child()) {
+                        (1, xcb::NONE) => Some(Some(self.root)),
+                        (1, child) => Some(Some(WindowId(child))),
+                        _ => Some(None),
+                    };
+                },
+                xproto::BUTTON_RELEASE if picked.is_some() => break,
+                _ => {},
+            }
+        }
+        xcb::ungrab_pointer(c, xcb::CURRENT_TIME);
+        c.flush();
+        picked.and_then(|w| w).map(|w| self.client_of(w))
+    }
+
+    fn watch_root(&self) {
+        let ev_mask: u32 = xproto::EVENT_MASK_SUBSTRUCTURE_NOTIFY | xproto::EVENT_MASK_PROPERTY_CHANGE;
+        xcb::xproto::change_window_attributes(&self.c, self.root.raw(),
+                                              &[(xcb::xproto::CW_EVENT_MASK, ev_mask)]);
+        self.c.flush();
+    }
+
+    fn wait_for_event(&self) -> Option<WindowEvent> {
+        loop {
+            if let Some(ev) = self.poll_event() {
+                return Some(ev);
+            }
+            if self.connection_lost() {
+                return None;
+            }
+
+            // a quiet server may as well be a frozen one, check while idle
+            let mut fds = [libc::pollfd { fd: self.c.as_raw_fd(), events: libc::POLLIN, revents: 0 }];
+            let ms = PING_INTERVAL.as_millis() as libc::c_int;
+            if unsafe { libc::poll(fds.as_mut_ptr(), 1, ms) } == 0 && !self.ping() {
+                return None;
+            }
+        }
+    }
+
+    fn poll_event(&self) -> Option<WindowEvent> {
+        loop {
+            let ev = self.c.poll_for_event()?;
+            if let Some(ev) = self.translate(&ev) {
+                return Some(ev);
+            }
+        }
+    }
+
+    fn event_fd(&self) -> Option<RawFd> {
+        Some(self.c.as_raw_fd())
+    }
+
+    fn connection_lost(&self) -> bool {
+        self.c.has_error().is_err()
+    }
+
+    fn ping(&self) -> bool {
+        let _guard = self.watchdog.guard();
+        xcb::get_input_focus(&self.c).get_reply().is_ok()
+    }
+
+    fn timed_out(&self) -> Option<Duration> {
+        self.watchdog.timed_out()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate serde_json;
+
+    use super::*;
+    use super::DisplayAddr::*;
+
+    #[test]
+    fn test_window_id() {
+        assert_eq!(WindowId(0x3c00003).to_string(), "0x3c00003");
+        assert_eq!(format!("{:?}", vec![WindowId(0x10)]), "[0x10]");
+        assert_eq!("0x3C00003".parse::<WindowId>(), Ok(WindowId(0x3c00003)));
+        assert_eq!("16".parse::<WindowId>(), Ok(WindowId(0x10)));
+        assert!("0xzz".parse::<WindowId>().is_err());
+        assert_eq!(self::serde_json::to_string(&WindowId(16)).unwrap(), "16");
+    }
+
+    #[test]
+    fn test_parse_display() {
+        assert_eq!(parse_display(":0"), Some(Unix("/tmp/.X11-unix/X0".to_string())));
+        assert_eq!(parse_display(":1.0"), Some(Unix("/tmp/.X11-unix/X1".to_string())));
+        assert_eq!(parse_display("unix:2"), Some(Unix("/tmp/.X11-unix/X2".to_string())));
+        assert_eq!(parse_display("localhost:10.0"), Some(Tcp("localhost".to_string(), 6010)));
+        assert_eq!(parse_display("tcp/:3"), Some(Tcp("localhost".to_string(), 6003)));
+        assert_eq!(parse_display("/tmp/launch-x/org.xquartz:0"),
+                   Some(Unix("/tmp/launch-x/org.xquartz:0".to_string())));
+        assert_eq!(parse_display("nodisplay"), None);
+        assert_eq!(parse_display(":abc"), None);
+    }
+
+    #[test]
+    fn test_watchdog() {
+        use std::io::Read;
+
+        let (ours, mut theirs) = UnixStream::pair().unwrap();
+        let watchdog = Watchdog::new(ours.as_raw_fd(), Duration::from_millis(200));
+        thread::sleep(Duration::from_millis(400));
+        assert_eq!(watchdog.timed_out(), None, "no request is pending");
+
+        let guard = watchdog.guard();
+        thread::sleep(Duration::from_millis(500));
+        assert_eq!(watchdog.timed_out(), Some(Duration::from_millis(200)));
+        drop(guard);
+        // the connection is shut down, the peer sees the end
+        assert_eq!(theirs.read(&mut [0u8; 1]).unwrap(), 0);
+    }
+}
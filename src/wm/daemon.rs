@@ -0,0 +1,221 @@
+extern crate serde_json;
+
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+
+use self::serde_json::Value;
+
+use super::wm::*;
+use super::backend::*;
+use super::filter::Filter;
+
+/// A request to the daemon, one json object per line like
+/// `{"cmd": "filter", "rule": "name = xterm"}`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+pub enum Query {
+    /// windows passing the filter of the daemon
+    List,
+    /// all cached windows passing `rule` instead
+    Filter { rule: String },
+    /// cached window `id`, filtered or not
+    Window { id: WindowId },
+    /// a `Snapshot` of all cached windows
+    Snapshot,
+    /// names window `id` had, oldest first, with their time
+    Titles { id: WindowId },
+    /// geometries window `id` had, oldest first, with their time
+    Geometries { id: WindowId },
+}
+
+/// Answer to a `Query`, `{"ok": true, "result": ...}` or
+/// `{"ok": false, "error": "..."}` on a single line.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Reply {
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Reply {
+    fn ok<T: ::serde::Serialize>(result: T) -> Reply {
+        match serde_json::to_value(result) {
+            Ok(v) => Reply { ok: true, result: Some(v), error: None },
+            Err(e) => Reply::error(e.to_string()),
+        }
+    }
+
+    fn error<S: Into<String>>(error: S) -> Reply {
+        Reply { ok: false, result: None, error: Some(error.into()) }
+    }
+}
+
+/// where the daemon of `display` listens unless told otherwise,
+/// in `$XDG_RUNTIME_DIR` or the temp dir
+pub fn socket_path(display: Option<&str>) -> PathBuf {
+    let display = display.map(str::to_string).or_else(|| env::var("DISPLAY").ok()).unwrap_or_default();
+    let name = format!("wminspect{}.sock", display.replace('/', "_"));
+    match env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => PathBuf::from(dir).join(name),
+        None => env::temp_dir().join(name),
+    }
+}
+
+/// answer `query` from the window cache of `ctx`
+pub fn answer(ctx: &Context, query: &Query) -> Reply {
+    match *query {
+        Query::List => Reply::ok(ctx.shown(ctx.windows())),
+        Query::Filter { ref rule } => match Filter::parse(rule) {
+            Ok(filter) => {
+                let windows: Vec<_> = ctx.all_windows().into_iter().filter(|w| filter.apply_to(w)).collect();
+                Reply::ok(ctx.shown(windows))
+            },
+            Err(e) => Reply::error(e.to_string()),
+        },
+        Query::Window { id } => match ctx.window(id) {
+            Some(w) => Reply::ok(ctx.shown(vec![w]).pop()),
+            None => Reply::error(format!("unknown window {}", id)),
+        },
+        Query::Snapshot => Reply::ok(ctx.snapshot()),
+        Query::Titles { id } => Reply::ok(ctx.titles(id)),
+        Query::Geometries { id } => Reply::ok(ctx.geometries(id)),
+    }
+}
+
+fn handle_client(stream: UnixStream, ctx: &Context) -> io::Result<()> {
+    let mut out = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let reply = match serde_json::from_str(&line) {
+            Ok(query) => answer(ctx, &query),
+            Err(e) => Reply::error(format!("bad query: {}", e)),
+        };
+        serde_json::to_writer(&mut out, &reply)?;
+        out.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+/// Answer queries at unix socket `path` from a background thread, a stale
+/// socket left there is replaced. The cache of `ctx` is kept up to date by
+/// a monitor running meanwhile, see `spawn_monitor`.
+pub fn serve<P: AsRef<Path>>(path: P, ctx: Arc<Context>) -> io::Result<()> {
+    let path = path.as_ref();
+    if UnixStream::connect(path).is_ok() {
+        return Err(io::Error::new(io::ErrorKind::AddrInUse,
+                                  format!("a daemon is listening at {}", path.display())));
+    }
+    let _ = fs::remove_file(path);
+    let listener = UnixListener::bind(path)?;
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let ctx = ctx.clone();
+                    thread::spawn(move || {
+                        if let Err(e) = handle_client(stream, &ctx) {
+                            wm_debug!("daemon client: {}", e);
+                        }
+                    });
+                },
+                Err(e) => wm_debug!("daemon accept: {}", e),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Ask the daemon listening at `path`, fails if there is none.
+pub fn request<P: AsRef<Path>>(path: P, query: &Query) -> io::Result<Reply> {
+    let mut stream = UnixStream::connect(path)?;
+    serde_json::to_writer(&mut stream, query)?;
+    stream.write_all(b"\n")?;
+
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    Ok(serde_json::from_str(&line)?)
+}
+
+/// the value at dotted `path` of `v` like `attrs.map_state`, window ids
+/// formatted like `0x1c00003` and strings unquoted
+pub fn field(v: &Value, path: &str) -> Option<String> {
+    let value = v.pointer(&format!("/{}", path.replace('.', "/")))?;
+    Some(match *value {
+        Value::Number(ref n) if path == "id" => format!("0x{:x}", n.as_u64()?),
+        Value::String(ref s) => s.clone(),
+        ref other => other.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::mock::MockBackend;
+
+    fn query(stream: &mut BufReader<UnixStream>, line: &str) -> Reply {
+        stream.get_mut().write_all(line.as_bytes()).unwrap();
+        let mut reply = String::new();
+        stream.read_line(&mut reply).unwrap();
+        serde_json::from_str(&reply).unwrap()
+    }
+
+    #[test]
+    fn test_serve() {
+        let mock = MockBackend::new(1920, 1080);
+        let mut w = Window::new(WindowId(0x100));
+        w.name = "xterm".to_string();
+        mock.add_window(w).add_window(Window::new(WindowId(0x200)));
+        let ctx = Arc::new(Context::new(mock, Filter::parse("id = 0x100").unwrap()));
+        ctx.refresh_windows().unwrap();
+
+        let path = env::temp_dir().join(format!("wminspect-daemon-{}.sock", ::std::process::id()));
+        serve(&path, ctx.clone()).unwrap();
+        assert!(serve(&path, ctx).is_err());
+        let mut stream = BufReader::new(UnixStream::connect(&path).unwrap());
+
+        let list = query(&mut stream, "{\"cmd\": \"list\"}\n");
+        assert!(list.ok);
+        assert_eq!(list.result.unwrap()[0]["name"], "xterm");
+
+        let filtered = query(&mut stream, "{\"cmd\": \"filter\", \"rule\": \"id = 0x200\"}\n");
+        assert_eq!(filtered.result.unwrap()[0]["id"], 0x200);
+
+        let window = query(&mut stream, "{\"cmd\": \"window\", \"id\": 512}\n");
+        assert_eq!(window.result.unwrap()["id"], 0x200);
+        assert!(!query(&mut stream, "{\"cmd\": \"window\", \"id\": 1}\n").ok);
+
+        let snapshot = query(&mut stream, "{\"cmd\": \"snapshot\"}\n");
+        assert_eq!(snapshot.result.unwrap()["filtered"][0], 0x100);
+
+        let titles = query(&mut stream, "{\"cmd\": \"titles\", \"id\": 256}\n");
+        assert_eq!(titles.result.unwrap()[0]["value"], "xterm");
+        let geometries = query(&mut stream, "{\"cmd\": \"geometries\", \"id\": 256}\n");
+        assert_eq!(geometries.result.unwrap()[0]["value"]["width"], 0);
+
+        let bad = query(&mut stream, "{\"cmd\": \"dump\"}\n");
+        assert!(bad.error.unwrap().starts_with("bad query"));
+
+        let reply = request(&path, &Query::Filter { rule: "name = xterm".to_string() }).unwrap();
+        let windows = reply.result.unwrap();
+        assert_eq!(field(&windows[0], "id"), Some("0x100".to_string()));
+        assert_eq!(field(&windows[0], "name"), Some("xterm".to_string()));
+        assert_eq!(field(&windows[0], "attrs.map_state"), Some("Unmapped".to_string()));
+        assert_eq!(field(&windows[0], "geom.width"), Some("0".to_string()));
+        assert_eq!(field(&windows[0], "geom.depth"), None);
+        let _ = fs::remove_file(&path);
+        assert!(request(&path, &Query::List).is_err());
+    }
+}
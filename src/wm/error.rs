@@ -0,0 +1,119 @@
+use std::error;
+use std::fmt;
+use std::io;
+use std::time::Duration;
+
+use super::backend::ConnectError;
+
+/// Errors surfaced by the library, deciding to exit is up to the caller.
+#[derive(Debug)]
+pub enum WmError {
+    Connect(ConnectError),
+    /// filter rule that can not be evaluated
+    Parse(String),
+    /// syntax error in a filter rule or .rule sheet
+    Dsl(DslError),
+    /// sheet can not be loaded or compiled
+    Sheet(String),
+    /// snapshot can not be loaded or saved
+    Snapshot(String),
+    /// config file can not be loaded
+    Config(String),
+    /// event database can not be opened, written or queried
+    Database(String),
+    /// a request to the server failed
+    Request(&'static str),
+    /// the server did not answer for that long, the connection is given up
+    Timeout(Duration),
+    Io(io::Error),
+}
+
+pub type WmResult<T> = Result<T, WmError>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DslErrorKind {
+    /// `found` is where `expected` should be
+    Unexpected { expected: String, found: String },
+    UnknownPredicate(String),
+    BadValue(String),
+}
+
+/// Syntax error in a filter rule, `line` and `column` are 1-based and
+/// `snippet` is the offending line of the rule text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DslError {
+    pub kind: DslErrorKind,
+    pub line: usize,
+    pub column: usize,
+    pub snippet: String,
+}
+
+impl fmt::Display for DslErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DslErrorKind::Unexpected { ref expected, ref found } => {
+                write!(f, "unexpected {}, expecting {}", found, expected)
+            },
+            DslErrorKind::UnknownPredicate(ref s) => write!(f, "unknown predicate {}", s),
+            DslErrorKind::BadValue(ref s) => write!(f, "bad value {}", s),
+        }
+    }
+}
+
+impl fmt::Display for DslError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at line {}, col {}", self.kind, self.line, self.column)?;
+        if !self.snippet.is_empty() {
+            write!(f, "\n    {}\n    {:>width$}", self.snippet, "^", width = self.column)?;
+        }
+        Ok(())
+    }
+}
+
+impl error::Error for DslError {}
+
+impl fmt::Display for WmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            WmError::Connect(ref e) => write!(f, "{}", e),
+            WmError::Parse(ref s) => write!(f, "bad filter rule: {}", s),
+            WmError::Dsl(ref e) => write!(f, "bad filter rule: {}", e),
+            WmError::Sheet(ref s) => write!(f, "bad sheet: {}", s),
+            WmError::Snapshot(ref s) => write!(f, "bad snapshot: {}", s),
+            WmError::Config(ref s) => write!(f, "bad config: {}", s),
+            WmError::Database(ref s) => write!(f, "event database: {}", s),
+            WmError::Request(req) => write!(f, "{} request failed", req),
+            WmError::Timeout(t) => write!(f, "X server did not answer within {:.1}s, giving up", t.as_secs_f64()),
+            WmError::Io(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for WmError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            WmError::Connect(ref e) => Some(e),
+            WmError::Dsl(ref e) => Some(e),
+            WmError::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<ConnectError> for WmError {
+    fn from(e: ConnectError) -> WmError {
+        WmError::Connect(e)
+    }
+}
+
+impl From<DslError> for WmError {
+    fn from(e: DslError) -> WmError {
+        WmError::Dsl(e)
+    }
+}
+
+impl From<io::Error> for WmError {
+    fn from(e: io::Error) -> WmError {
+        WmError::Io(e)
+    }
+}
@@ -0,0 +1,109 @@
+use super::wm::Geometry;
+use super::backend::WindowId;
+
+/// properties whose changes are interesting to the monitor
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PropertyKind {
+    Name,
+    ClientListStacking,
+    Desktop,
+    ClientMachine,
+    TransientFor,
+    Class,
+    Pid,
+    WindowType,
+    State,
+    /// ICCCM WM_STATE, set by the window manager
+    WmState,
+    Opacity,
+    /// of root
+    CurrentDesktop,
+    /// any other property, e.g. for the TUI property inspector
+    Other,
+}
+
+impl PropertyKind {
+    /// name of the X property, "other" for `Other`
+    pub fn name(self) -> &'static str {
+        match self {
+            PropertyKind::Name => "_NET_WM_NAME",
+            PropertyKind::ClientListStacking => "_NET_CLIENT_LIST_STACKING",
+            PropertyKind::Desktop => "_NET_WM_DESKTOP",
+            PropertyKind::ClientMachine => "WM_CLIENT_MACHINE",
+            PropertyKind::TransientFor => "WM_TRANSIENT_FOR",
+            PropertyKind::Class => "WM_CLASS",
+            PropertyKind::Pid => "_NET_WM_PID",
+            PropertyKind::WindowType => "_NET_WM_WINDOW_TYPE",
+            PropertyKind::State => "_NET_WM_STATE",
+            PropertyKind::WmState => "WM_STATE",
+            PropertyKind::Opacity => "_NET_WM_WINDOW_OPACITY",
+            PropertyKind::CurrentDesktop => "_NET_CURRENT_DESKTOP",
+            PropertyKind::Other => "other",
+        }
+    }
+
+    /// true if it is cached in `Window`, see `Backend::update_property`
+    pub fn is_cached(self) -> bool {
+        !matches!(self, PropertyKind::ClientListStacking | PropertyKind::CurrentDesktop | PropertyKind::Other)
+    }
+}
+
+/// What happened to a window, stripped of protocol details.
+///
+/// A `Backend` translates server notifications into it, then `Context`
+/// completes it from the cached windows (e.g. the `old` geometry) before it
+/// reaches the monitor output, sinks and streams.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(tag = "type")]
+pub enum WindowEvent {
+    #[serde(rename = "create")]
+    Created { window: WindowId, parent: WindowId },
+    #[serde(rename = "destroy")]
+    Destroyed { window: WindowId },
+    #[serde(rename = "reparent")]
+    Reparented { window: WindowId, parent: WindowId },
+    /// `old` is unknown if the window is not cached
+    #[serde(rename = "configure")]
+    Configured {
+        window: WindowId,
+        above_sibling: WindowId,
+        old: Option<Geometry>,
+        new: Geometry,
+        override_redirect: bool,
+    },
+    #[serde(rename = "map")]
+    Mapped { window: WindowId },
+    #[serde(rename = "unmap")]
+    Unmapped { window: WindowId },
+    #[serde(rename = "property")]
+    PropertyChanged { window: WindowId, kind: PropertyKind },
+}
+
+impl WindowEvent {
+    /// short name, the same as the serialized `type`
+    pub fn name(&self) -> &'static str {
+        match *self {
+            WindowEvent::Created { .. } => "create",
+            WindowEvent::Destroyed { .. } => "destroy",
+            WindowEvent::Reparented { .. } => "reparent",
+            WindowEvent::Configured { .. } => "configure",
+            WindowEvent::Mapped { .. } => "map",
+            WindowEvent::Unmapped { .. } => "unmap",
+            WindowEvent::PropertyChanged { .. } => "property",
+        }
+    }
+
+    /// the window the event is about
+    pub fn window(&self) -> WindowId {
+        match *self {
+            WindowEvent::Created { window, .. } |
+            WindowEvent::Destroyed { window } |
+            WindowEvent::Reparented { window, .. } |
+            WindowEvent::Configured { window, .. } |
+            WindowEvent::Mapped { window } |
+            WindowEvent::Unmapped { window } |
+            WindowEvent::PropertyChanged { window, .. } => window,
+        }
+    }
+}
@@ -0,0 +1,146 @@
+extern crate serde_json;
+
+use std::io::{self, Write};
+
+use super::wm::*;
+
+/// columns of csv output
+const CSV_HEADER: &str = "id,name,x,y,width,height,map_state,override_redirect,desktop,client_machine,\
+                          xwayland,transient_for,border_width,depth,parent";
+
+/// Write `windows` in `format`, the way `Context::dump_windows` lists them
+/// without colors. `Wmctrl` lines take positions from the geometry as is.
+pub fn write_windows<W: Write>(out: &mut W, windows: &[Window], format: OutputFormat) -> io::Result<()> {
+    match format {
+        OutputFormat::Default => {
+            for (i, w) in windows.iter().enumerate() {
+                writeln!(out, "{}: {}", i, win2str(w, None))?;
+            }
+        },
+        OutputFormat::Wmctrl => {
+            let host_width = windows.iter()
+                .map(|w| w.client_machine.as_ref().map(|h| h.chars().count()).unwrap_or(3))
+                .max().unwrap_or(0);
+            for w in windows {
+                writeln!(out, "{}", wmctrl_line(w, (w.geom.x, w.geom.y), host_width))?;
+            }
+        },
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut *out, windows)?;
+            writeln!(out)?;
+        },
+        OutputFormat::Csv => {
+            writeln!(out, "{}", CSV_HEADER)?;
+            for w in windows {
+                writeln!(out, "{}", csv_line(w))?;
+            }
+        },
+        OutputFormat::Ndjson => {
+            for w in windows {
+                serde_json::to_writer(&mut *out, w)?;
+                writeln!(out)?;
+            }
+        },
+    }
+    Ok(())
+}
+
+/// Write every field of `w`, as a json object, a csv row or one
+/// `field value` line each for text.
+pub fn write_window<W: Write>(out: &mut W, w: &Window, format: OutputFormat) -> io::Result<()> {
+    match format {
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut *out, w)?;
+            writeln!(out)
+        },
+        OutputFormat::Csv | OutputFormat::Wmctrl | OutputFormat::Ndjson => write_windows(out, ::std::slice::from_ref(w), format),
+        OutputFormat::Default => {
+            let opt = |v: Option<String>| v.unwrap_or_else(|| "-".to_string());
+            writeln!(out, "id                 {}", w.id)?;
+            writeln!(out, "name               {}", w.name)?;
+            writeln!(out, "geometry           {}", w.geom)?;
+            writeln!(out, "border_width       {}", w.border_width)?;
+            writeln!(out, "depth              {}", w.depth)?;
+            writeln!(out, "parent             {}", opt(w.parent.map(|id| id.to_string())))?;
+            writeln!(out, "opacity            {}", opt(w.opacity.map(|_| format!("{}%", w.opacity_percent()))))?;
+            writeln!(out, "visible            {}", opt(w.visible_area.map(|_| format!("{}%", w.visible_percent()))))?;
+            writeln!(out, "map_state          {}", w.attrs.map_state)?;
+            writeln!(out, "override_redirect  {}", w.attrs.override_redirect)?;
+            writeln!(out, "desktop            {}", opt(w.desktop.map(|d| d.to_string())))?;
+            writeln!(out, "client_machine     {}", opt(w.client_machine.clone()))?;
+            writeln!(out, "xwayland           {}", w.xwayland)?;
+            writeln!(out, "transient_for      {}", opt(w.transient_for.map(|id| id.to_string())))
+        },
+    }
+}
+
+fn csv_line(w: &Window) -> String {
+    let opt = |v: Option<String>| v.unwrap_or_default();
+    [
+        w.id.to_string(),
+        csv_field(&w.name),
+        w.geom.x.to_string(),
+        w.geom.y.to_string(),
+        w.geom.width.to_string(),
+        w.geom.height.to_string(),
+        w.attrs.map_state.to_string(),
+        w.attrs.override_redirect.to_string(),
+        opt(w.desktop.map(|d| d.to_string())),
+        csv_field(&opt(w.client_machine.clone())),
+        w.xwayland.to_string(),
+        opt(w.transient_for.map(|id| id.to_string())),
+        w.border_width.to_string(),
+        w.depth.to_string(),
+        opt(w.parent.map(|id| id.to_string())),
+    ].join(",")
+}
+
+/// quote `s` if it has a separator, quote or line break in it
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::backend::WindowId;
+    use super::super::mock::window;
+
+    fn export(windows: &[Window], format: OutputFormat) -> String {
+        let mut out = Vec::new();
+        write_windows(&mut out, windows, format).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_write_windows() {
+        let windows = [window(WindowId(0x10), "xterm").at(1, 2).sized(30, 40),
+                       window(WindowId(0x20), "say \"hi\", there").at(1, 2).sized(30, 40)];
+
+        assert_eq!(export(&windows, OutputFormat::Default),
+                   "0: 0x10(xterm) 30x40+1+2 Unmapped\n1: 0x20(say \"hi\", there) 30x40+1+2 Unmapped\n");
+        let csv = export(&windows, OutputFormat::Csv);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], CSV_HEADER);
+        assert_eq!(lines[2], "0x20,\"say \"\"hi\"\", there\",1,2,30,40,Unmapped,false,,,false,,0,0,");
+
+        let json: Vec<Window> = serde_json::from_str(&export(&windows, OutputFormat::Json)).unwrap();
+        assert_eq!(json[1].name, windows[1].name);
+
+        let ndjson = export(&windows, OutputFormat::Ndjson);
+        let lines: Vec<Window> = ndjson.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1].name, windows[1].name);
+
+        let mut out = Vec::new();
+        write_window(&mut out, &windows[0], OutputFormat::Default).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.starts_with("id                 0x10\nname               xterm\n"), "{}", text);
+        assert!(text.contains("\nborder_width       0\ndepth              0\nparent             -\n"), "{}", text);
+        assert!(text.ends_with("transient_for      -\n"), "{}", text);
+    }
+}
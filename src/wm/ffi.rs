@@ -0,0 +1,249 @@
+//! C ABI for reusing the filter engine and window collection from C/C++,
+//! see `include/wminspect.h` (regenerated by running cbindgen after changing it).
+//!
+//! Every object returned by a `wmi_*_new`/`wmi_*_parse` call is owned by the
+//! caller and must be released with the matching `wmi_*_free`.
+
+extern crate libc;
+
+use std::ffi::{CStr, CString};
+use std::ptr;
+use std::time::Duration;
+use self::libc::c_char;
+
+use super::wm::*;
+use super::filter::*;
+use super::error::*;
+use super::backend::WindowId;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Opaque connection to the X server along with the cached windows.
+pub struct WmiContext {
+    ctx: Context,
+    windows: Vec<Window>,
+    names: Vec<CString>,
+}
+
+/// Opaque compiled filter rule.
+pub struct WmiFilter {
+    filter: Filter,
+}
+
+/// Plain copy of a window record, `name` is NUL terminated.
+#[repr(C)]
+pub struct WmiWindow {
+    pub id: u32,
+    pub x: i16,
+    pub y: i16,
+    pub width: u16,
+    pub height: u16,
+    pub mapped: bool,
+    pub override_redirect: bool,
+    pub xwayland: bool,
+    /// when returned by `wmi_context_window`, valid until next refresh
+    pub name: *const c_char,
+}
+
+impl WmiContext {
+    fn new(ctx: Context) -> WmiContext {
+        WmiContext { ctx, windows: Vec::new(), names: Vec::new() }
+    }
+
+    fn refresh(&mut self) -> WmResult<()> {
+        self.ctx.refresh_windows()?;
+        self.windows = self.ctx.windows();
+        self.names = self.windows.iter()
+            .map(|w| CString::new(w.name.replace('\0', "")).unwrap_or_default())
+            .collect();
+        Ok(())
+    }
+}
+
+unsafe fn opt_str<'a>(s: *const c_char) -> Option<&'a str> {
+    if s.is_null() {
+        None
+    } else {
+        CStr::from_ptr(s).to_str().ok()
+    }
+}
+
+/// Parse `rule` (see `--show-grammar`), return NULL if `rule` is malformed.
+///
+/// # Safety
+/// `rule` must be NULL or a NUL terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn wmi_filter_parse(rule: *const c_char) -> *mut WmiFilter {
+    match opt_str(rule).map(Filter::parse) {
+        Some(Ok(filter)) => Box::into_raw(Box::new(WmiFilter { filter })),
+        Some(Err(e)) => {
+            wm_debug!("wmi_filter_parse: {}", e);
+            ptr::null_mut()
+        },
+        None => ptr::null_mut(),
+    }
+}
+
+/// Test `w` against the filter, true means `w` is kept.
+///
+/// # Safety
+/// `filter` must come from `wmi_filter_parse`, `w.name` must be NULL or
+/// a NUL terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn wmi_filter_apply(filter: *const WmiFilter, w: *const WmiWindow) -> bool {
+    if filter.is_null() || w.is_null() {
+        return false;
+    }
+
+    let w = &*w;
+    let mut win = Window::new(WindowId(w.id));
+    win.name = opt_str(w.name).unwrap_or_default().to_string();
+    win.geom = Geometry { x: w.x, y: w.y, width: w.width, height: w.height };
+    win.attrs.map_state = if w.mapped { MapState::Viewable } else { MapState::Unmapped };
+    win.attrs.override_redirect = w.override_redirect;
+    win.xwayland = w.xwayland;
+
+    (*filter).filter.apply_to(&win)
+}
+
+/// # Safety
+/// `filter` must be NULL or come from `wmi_filter_parse`.
+#[no_mangle]
+pub unsafe extern "C" fn wmi_filter_free(filter: *mut WmiFilter) {
+    if !filter.is_null() {
+        drop(Box::from_raw(filter));
+    }
+}
+
+/// Connect to `display` (NULL for $DISPLAY), windows are filtered by `filter`
+/// which is consumed (NULL for no filtering). Return NULL on failure.
+///
+/// # Safety
+/// `display` must be NULL or a NUL terminated string, `filter` must be NULL
+/// or come from `wmi_filter_parse`.
+#[no_mangle]
+pub unsafe extern "C" fn wmi_context_new(display: *const c_char, filter: *mut WmiFilter) -> *mut WmiContext {
+    let f = if filter.is_null() { Filter::new() } else { Box::from_raw(filter).filter };
+
+    let mut builder = Context::builder().filter(f).timeout(CONNECT_TIMEOUT);
+    if let Some(display) = opt_str(display) {
+        builder = builder.display(display);
+    }
+
+    match builder.connect() {
+        Ok(ctx) => Box::into_raw(Box::new(WmiContext::new(ctx))),
+        Err(e) => {
+            wm_debug!("wmi_context_new: {}", e);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Re-collect windows from the server, return the number of filtered windows
+/// or -1 on failure.
+///
+/// # Safety
+/// `ctx` must come from `wmi_context_new`.
+#[no_mangle]
+pub unsafe extern "C" fn wmi_context_refresh(ctx: *mut WmiContext) -> isize {
+    if ctx.is_null() {
+        return -1;
+    }
+
+    let ctx = &mut *ctx;
+    match ctx.refresh() {
+        Ok(()) => ctx.windows.len() as isize,
+        Err(e) => {
+            wm_debug!("wmi_context_refresh: {}", e);
+            -1
+        }
+    }
+}
+
+/// Fill `out` with the `idx`th filtered window in stacking order (bottom -> top)
+/// as of the last refresh, return false if out of range.
+///
+/// # Safety
+/// `ctx` must come from `wmi_context_new`, `out` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn wmi_context_window(ctx: *const WmiContext, idx: usize, out: *mut WmiWindow) -> bool {
+    if ctx.is_null() || out.is_null() {
+        return false;
+    }
+
+    let ctx = &*ctx;
+    match ctx.windows.get(idx) {
+        Some(w) => {
+            *out = WmiWindow {
+                id: w.id.raw(),
+                x: w.geom.x,
+                y: w.geom.y,
+                width: w.geom.width,
+                height: w.geom.height,
+                mapped: w.attrs.map_state == MapState::Viewable,
+                override_redirect: w.attrs.override_redirect,
+                xwayland: w.xwayland,
+                name: ctx.names[idx].as_ptr(),
+            };
+            true
+        },
+        None => false,
+    }
+}
+
+/// # Safety
+/// `ctx` must be NULL or come from `wmi_context_new`.
+#[no_mangle]
+pub unsafe extern "C" fn wmi_context_free(ctx: *mut WmiContext) {
+    if !ctx.is_null() {
+        drop(Box::from_raw(ctx));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::mock::*;
+
+    #[test]
+    fn test_context_windows() {
+        let backend = MockBackend::new(1920, 1080);
+        backend.add_window(window(WindowId(0x10), "dde-dock").mapped()).add_window(window(WindowId(0x20), "deepin-terminal").mapped());
+
+        unsafe {
+            let f = wmi_filter_parse(b"name = dde*\0".as_ptr() as *const c_char);
+            let ctx = Box::into_raw(Box::new(WmiContext::new(Context::new(backend, Box::from_raw(f).filter))));
+
+            assert_eq!(wmi_context_refresh(ctx), 1);
+
+            let mut out: WmiWindow = std::mem::zeroed();
+            assert!(wmi_context_window(ctx, 0, &mut out));
+            assert_eq!(out.id, 0x10);
+            assert!(out.mapped);
+            assert_eq!(CStr::from_ptr(out.name).to_str().unwrap(), "dde-dock");
+            assert!(!wmi_context_window(ctx, 1, &mut out));
+
+            wmi_context_free(ctx);
+        }
+    }
+
+    #[test]
+    fn test_filter_apply() {
+        unsafe {
+            let f = wmi_filter_parse(b"name = dde*\0".as_ptr() as *const c_char);
+            assert!(!f.is_null());
+
+            let mut w = WmiWindow {
+                id: 0x10, x: 0, y: 0, width: 100, height: 100,
+                mapped: true, override_redirect: false, xwayland: false,
+                name: b"dde-osd\0".as_ptr() as *const c_char,
+            };
+            assert!(wmi_filter_apply(f, &w));
+
+            w.name = b"xterm\0".as_ptr() as *const c_char;
+            assert!(!wmi_filter_apply(f, &w));
+
+            wmi_filter_free(f);
+        }
+    }
+}
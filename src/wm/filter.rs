@@ -1,53 +1,230 @@
 extern crate serde;
 extern crate serde_json;
 extern crate bincode as bc;
+extern crate regex;
 
 use super::wm::*;
+use super::error::*;
+use super::backend::WindowId;
 use std::collections::HashSet;
 use std::convert::AsRef;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
 
-type FilterFunction = Box<dyn Fn(&Window) -> bool + Send>;
+/// identifies a rule within its `Filter`, never reused
+pub type RuleId = u32;
 
-pub struct ActionFuncPair {
+/// Returned by `Filter::add_rule` to remove the rule later.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RuleHandle(RuleId);
+
+impl RuleHandle {
+    pub fn id(&self) -> RuleId {
+        self.0
+    }
+}
+
+/// where a rule comes from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RuleOrigin {
+    /// parsed from a string, e.g. command line
+    Inline,
+    /// loaded by `Filter::load_sheet`
+    Sheet(PathBuf),
+    /// read by `Filter::load_sheet_from`, e.g. piped to `--filter -`
+    Stdin,
+    /// added by `Context` for its options
+    Builtin,
+}
+
+/// Description of a loaded rule, see `Filter::rules`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleInfo {
+    pub id: RuleId,
+    pub action: Action,
+    /// rule in the grammar of `filter_grammar`, normalized
+    pub source: String,
+    pub origin: RuleOrigin,
+    pub stats: RuleStats,
+}
+
+impl RuleInfo {
+    pub fn handle(&self) -> RuleHandle {
+        RuleHandle(self.id)
+    }
+}
+
+/// How a rule did so far, windows rejected by a `FilterOut` rule are
+/// `evaluated - matched`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RuleStats {
+    /// windows tested against the rule
+    pub evaluated: u64,
+    /// windows the rule held for
+    pub matched: u64,
+    /// total time spent evaluating
+    pub time: Duration,
+}
+
+#[derive(Default)]
+struct RuleCounters {
+    evaluated: AtomicU64,
+    matched: AtomicU64,
+    nanos: AtomicU64,
+}
+
+/// a clone starts from the counts so far
+impl Clone for RuleCounters {
+    fn clone(&self) -> RuleCounters {
+        let load = |c: &AtomicU64| AtomicU64::new(c.load(Ordering::Relaxed));
+        RuleCounters { evaluated: load(&self.evaluated), matched: load(&self.matched), nanos: load(&self.nanos) }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct ActionFuncPair {
+    pub(crate) id: RuleId,
     pub action: Action,
     pub(crate) rule: FilterRule,
-    pub func: FilterFunction
+    pub(crate) matcher: RuleMatcher,
+    pub(crate) origin: RuleOrigin,
+    counters: RuleCounters,
 }
 
-pub struct Filter {
-    pub rules: Vec<ActionFuncPair>
+impl ActionFuncPair {
+    /// test `w` against the rule and account for it
+    pub(crate) fn eval(&self, w: &Window) -> bool {
+        let start = Instant::now();
+        let matched = self.matcher.matches(w);
+        let nanos = start.elapsed().as_nanos() as u64;
+
+        self.counters.evaluated.fetch_add(1, Ordering::Relaxed);
+        if matched {
+            self.counters.matched.fetch_add(1, Ordering::Relaxed);
+        }
+        self.counters.nanos.fetch_add(nanos, Ordering::Relaxed);
+        matched
+    }
+
+    fn stats(&self) -> RuleStats {
+        RuleStats {
+            evaluated: self.counters.evaluated.load(Ordering::Relaxed),
+            matched: self.counters.matched.load(Ordering::Relaxed),
+            time: Duration::from_nanos(self.counters.nanos.load(Ordering::Relaxed)),
+        }
+    }
+
+    fn info(&self) -> RuleInfo {
+        let source = match self.action {
+            Action::FilterOut => self.rule.to_string(),
+            Action::Pin => format!("{}: pin", self.rule),
+        };
+        RuleInfo { id: self.id, action: self.action, source, origin: self.origin.clone(), stats: self.stats() }
+    }
 }
 
-unsafe impl Sync for Filter {}
+#[derive(Clone)]
+pub struct Filter {
+    pub(crate) rules: Vec<ActionFuncPair>,
+    next_id: RuleId,
+}
 
 impl Filter {
 
     /// constructors
     pub fn new() -> Filter {
-        Filter { rules: Vec::new(), }
+        Filter { rules: Vec::new(), next_id: 1 }
+    }
+
+    pub fn parse<S: AsRef<str>>(rule: S) -> WmResult<Filter> {
+        let mut filter = Filter::new();
+        filter.add_rules(rule)?;
+        Ok(filter)
     }
 
-    pub fn parse<S: AsRef<str>>(rule: S) -> Filter {
-        let mut filter = Filter { rules: Vec::new(), };
+    /// append all rules of `text`, like `parse` does on an empty filter
+    pub fn add_rules<S: AsRef<str>>(&mut self, text: S) -> WmResult<()> {
+        let mut tokens = scan_tokens(text);
+        self.extend_items(parse_rule(&mut tokens)?, RuleOrigin::Inline)
+    }
 
-        let mut tokens = scan_tokens(rule);
-        if let Some(top) = parse_rule(&mut tokens) {
-            for item in top.into_iter() {
-                wm_debug!("item: {:?}", item);
-                let f = item.rule.gen_closure();
-                filter.rules.push(ActionFuncPair { action: item.action, rule: item.rule, func: f});
-            }
+    /// compile and append `items`, nothing is added if any of them fails
+    pub(crate) fn extend_items(&mut self, items: Vec<FilterItem>, origin: RuleOrigin) -> WmResult<()> {
+        let mut compiled = Vec::new();
+        for item in items {
+            wm_debug!("item: {:?}", item);
+            let matcher = item.rule.compile_for_windows()?;
+            compiled.push((item, matcher));
         }
 
-        filter
+        for (item, matcher) in compiled {
+            self.push_rule(item.action, item.rule, matcher, origin.clone());
+        }
+        Ok(())
     }
 
-    pub fn apply_to(&self, w: &Window) -> bool {
-        !self.rules.iter().filter(|r| r.action == Action::FilterOut).any(|r| !(r.func)(w))
+    pub(crate) fn push_rule(&mut self, action: Action, rule: FilterRule, matcher: RuleMatcher, origin: RuleOrigin) -> RuleId {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.rules.push(ActionFuncPair { id, action, rule, matcher, origin, counters: RuleCounters::default() });
+        id
+    }
+
+    /// loaded rules in order of evaluation
+    pub fn rules(&self) -> Vec<RuleInfo> {
+        self.rules.iter().map(|r| r.info()).collect()
+    }
+
+    pub fn rule(&self, id: RuleId) -> Option<RuleInfo> {
+        self.rules.iter().find(|r| r.id == id).map(|r| r.info())
+    }
+
+    /// zero statistics of all rules
+    pub fn reset_stats(&self) {
+        for r in &self.rules {
+            r.counters.evaluated.store(0, Ordering::Relaxed);
+            r.counters.matched.store(0, Ordering::Relaxed);
+            r.counters.nanos.store(0, Ordering::Relaxed);
+        }
+    }
+
+    /// append a single rule item parsed from `text`, e.g. `name = xterm: pin`
+    pub fn add_rule<S: AsRef<str>>(&mut self, text: S) -> WmResult<RuleHandle> {
+        let mut tokens = scan_tokens(text);
+        let mut items = parse_rule(&mut tokens)?;
+        if items.len() != 1 {
+            return Err(parse_error(format!("expecting exactly one rule but {}", items.len())));
+        }
+
+        let item = items.remove(0);
+        let matcher = item.rule.compile_for_windows()?;
+        Ok(RuleHandle(self.push_rule(item.action, item.rule, matcher, RuleOrigin::Inline)))
+    }
+
+    /// return false if there is no such rule
+    pub fn remove_rule(&mut self, handle: RuleHandle) -> bool {
+        let len = self.rules.len();
+        self.rules.retain(|r| r.id != handle.0);
+        self.rules.len() != len
+    }
+
+    /// whether each rule holds for `w`, in order of evaluation and without
+    /// counting in their stats
+    pub fn results(&self, w: &Window) -> Vec<(RuleInfo, bool)> {
+        self.rules.iter().map(|r| (r.info(), r.matcher.matches(w))).collect()
+    }
+
+    /// true if any rule, pin ones included, looks at window names
+    pub fn uses_names(&self) -> bool {
+        self.rules.iter().any(|r| r.matcher.uses_names())
     }
 
-    pub fn add_live_rule(&mut self, item: ActionFuncPair) {
-        self.rules.push(item);
+    /// true if `w` passes all filter rules or any pin rule, pinned windows
+    /// are shown whatever the others say
+    pub fn apply_to(&self, w: &Window) -> bool {
+        !self.rules.iter().filter(|r| r.action == Action::FilterOut).any(|r| !r.eval(w)) || w.is_window_pinned(self)
     }
 }
 
@@ -63,7 +240,18 @@ pub(crate) enum Predicate {
     Id,
     Name,
     Attr(String), // String contains attr name (map_state or override_redirect)
-    Geom(String), // String contains attr name (x,y,width,height)
+    Geom(String), // String contains attr name (x,y,width,height,border_width,depth)
+    Parent,
+    Opacity,
+    Desktop,
+    Class,
+    Type,
+    /// type of a recorded event, see `EventQuery`
+    Event,
+    VisibleArea,
+    Occluded,
+    /// _NET_WM_STATE, named like `fullscreen`
+    State,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
@@ -72,6 +260,8 @@ pub(crate) enum Matcher {
     BoolValue(bool),
     MapStateValue(MapState),
     Wildcard(String), // all string values are considered wildcard matcher
+    /// value of `~`, kept as written
+    Regex(String),
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
@@ -82,9 +272,13 @@ pub(crate) enum Op {
     LT,
     GE,
     LE,
+    /// `state has fullscreen`, one of the states
+    Has,
+    /// `name ~ "^dde-(osd|dock)$"`, a regular expression
+    Match,
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub(crate) enum FilterRule {
     Adhoc,
     ClientsOnly,
@@ -102,205 +296,705 @@ pub(crate) struct FilterItem {
 
 type BoxedRule = Box<FilterRule>;
 
-fn wild_match(pat: &str, s: &str) -> bool {
-    // non recursive algorithm
-    fn mat2(pat: &[char], s: &[char]) -> bool {
-        let (mut i, mut j) = (0, 0);
-        let mut star = usize::max_value();
-        let mut k = 0;
-        while j < s.len() {
-            if pat.get(i).unwrap_or(&'\0') == &'?' || pat.get(i).unwrap_or(&'\0') == &s[j] {
-                i += 1; j += 1; 
-            } else if pat.get(i).unwrap_or(&'\0') == &'*' {
-                star = i; k = j; i += 1; 
-            } else if pat.get(star).is_some() {
-                k += 1; j = k; i = star + 1;
-            } else {
-                return false;
-            } 
+impl fmt::Display for Predicate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Predicate::Id => write!(f, "id"),
+            Predicate::Name => write!(f, "name"),
+            Predicate::Attr(ref a) => write!(f, "attrs.{}", a),
+            Predicate::Geom(ref g) => write!(f, "geom.{}", g),
+            Predicate::Parent => write!(f, "parent"),
+            Predicate::Opacity => write!(f, "opacity"),
+            Predicate::Desktop => write!(f, "desktop"),
+            Predicate::Class => write!(f, "class"),
+            Predicate::Type => write!(f, "type"),
+            Predicate::Event => write!(f, "event"),
+            Predicate::VisibleArea => write!(f, "visible_area"),
+            Predicate::Occluded => write!(f, "occluded"),
+            Predicate::State => write!(f, "state"),
+        }
+    }
+}
+
+/// ops comparing numbers, `has` and `~` have no meaning there
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub(crate) enum CmpOp {
+    Eq,
+    Neq,
+    GT,
+    LT,
+    GE,
+    LE,
+}
+
+impl CmpOp {
+    fn holds<T: Ord>(self, left: T, right: T) -> bool {
+        match self {
+            CmpOp::Eq => left == right,
+            CmpOp::Neq => left != right,
+            CmpOp::GT => left > right,
+            CmpOp::LT => left < right,
+            CmpOp::GE => left >= right,
+            CmpOp::LE => left <= right,
         }
+    }
+}
+
+impl Op {
+    /// `self` comparing numbers, an error for `has` and `~`
+    fn comparison(&self, pred: &Predicate) -> WmResult<CmpOp> {
+        match *self {
+            Op::Eq => Ok(CmpOp::Eq),
+            Op::Neq => Ok(CmpOp::Neq),
+            Op::GT => Ok(CmpOp::GT),
+            Op::LT => Ok(CmpOp::LT),
+            Op::GE => Ok(CmpOp::GE),
+            Op::LE => Ok(CmpOp::LE),
+            Op::Has | Op::Match => Err(parse_error(format!("{} can not use {} as op", pred, self))),
+        }
+    }
+}
 
-        while pat.get(i).unwrap_or(&'\0') == &'*' {
-            i += 1; 
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match *self {
+            Op::Eq => "=",
+            Op::Neq => "<>",
+            Op::GT => ">",
+            Op::LT => "<",
+            Op::GE => ">=",
+            Op::LE => "<=",
+            Op::Has => "has",
+            Op::Match => "~",
+        };
+        f.write_str(s)
+    }
+}
+
+impl fmt::Display for Matcher {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Matcher::IntegralValue(i) => write!(f, "{}", i),
+            Matcher::BoolValue(b) => write!(f, "{}", b),
+            Matcher::MapStateValue(st) => write!(f, "{}", format!("{}", st).to_lowercase()),
+            Matcher::Wildcard(ref s) | Matcher::Regex(ref s) => {
+                if s.is_empty() || s.chars().any(|c| c.is_whitespace() || ".,;:()<>=~".contains(c)) {
+                    write!(f, "\"{}\"", s)
+                } else {
+                    f.write_str(s)
+                }
+            },
         }
-        i == pat.len()
     }
+}
 
-    #[allow(dead_code)]
-    fn mat_star(pat: &[char], i: usize, s: &[char], mut j: usize) -> bool {
-        while j <= s.len() {
-            if mat(pat, i+1, s, j) {
-                return true;
+impl fmt::Display for FilterRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn join(f: &mut fmt::Formatter, name: &str, rules: &[BoxedRule]) -> fmt::Result {
+            write!(f, "{}(", name)?;
+            for (i, r) in rules.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{}", r)?;
             }
-            j += 1;
+            write!(f, ")")
         }
 
-        false
+        match *self {
+            FilterRule::Adhoc => write!(f, "<adhoc>"),
+            FilterRule::ClientsOnly => write!(f, "clients"),
+            FilterRule::Single { ref pred, ref op, ref matcher } => write!(f, "{} {} {}", pred, op, matcher),
+            FilterRule::All(ref rules) => join(f, "all", rules),
+            FilterRule::Any(ref rules) => join(f, "any", rules),
+            FilterRule::Not(ref rule) => write!(f, "not({})", rule),
+        }
     }
+}
 
-    #[allow(dead_code)]
-    fn mat(pat: &[char], i: usize, s: &[char], j: usize) -> bool {
-        if pat.len() == i || s.len() == j {
-            return pat.len() == i && s.len() == j;
+impl FilterRule {
+    /// how `matcher` is taken, for `explain`
+    fn leaf_kind(pred: &Predicate, op: &Op, matcher: &Matcher) -> &'static str {
+        match (pred, matcher) {
+            (Predicate::State, Matcher::Wildcard(_)) if *op == Op::Has => "one of the states",
+            (Predicate::Name, Matcher::Wildcard(s)) if is_wild_string(s) => "glob on the whole name",
+            (Predicate::Name, Matcher::Wildcard(_)) => "substring of the name",
+            (Predicate::Class, Matcher::Wildcard(s)) if is_wild_string(s) => "glob on the whole class",
+            (Predicate::Class, Matcher::Wildcard(_)) => "substring of the class",
+            (Predicate::Type, Matcher::Wildcard(s)) if is_wild_string(s) => "glob on a whole window type",
+            (Predicate::Type, Matcher::Wildcard(_)) => "substring of a window type",
+            (Predicate::State, Matcher::Wildcard(s)) if is_wild_string(s) => "glob on a whole state",
+            (Predicate::State, Matcher::Wildcard(_)) => "substring of a state",
+            (Predicate::Event, _) => "type of a recorded event",
+            (Predicate::Class, Matcher::Regex(_)) => "regex on the lowercased class",
+            (_, Matcher::Regex(_)) => "regex",
+            (_, Matcher::IntegralValue(_)) => "number",
+            (_, Matcher::BoolValue(_)) => "bool",
+            (_, Matcher::MapStateValue(_)) => "map state",
+            (_, Matcher::Wildcard(_)) => "value",
         }
+    }
 
-        if pat[i] == '?' || pat[i] == s[j] {
-            mat(pat, i+1, s, j+1)
-        } else if pat[i] == '*' {
-            mat_star(pat, i, s, j) 
+    /// one line per node, children indented by two spaces more
+    fn write_tree(&self, out: &mut String, depth: usize) {
+        let indent = "  ".repeat(depth);
+        let children: &[BoxedRule] = match *self {
+            FilterRule::Single { ref pred, ref op, ref matcher } => {
+                out.push_str(&format!("{}{} {} {}  [{}]\n", indent, pred, op, matcher, FilterRule::leaf_kind(pred, op, matcher)));
+                return;
+            },
+            FilterRule::Not(ref rule) => {
+                out.push_str(&format!("{}not\n", indent));
+                rule.write_tree(out, depth + 1);
+                return;
+            },
+            FilterRule::All(ref rules) => { out.push_str(&format!("{}all\n", indent)); rules },
+            FilterRule::Any(ref rules) => { out.push_str(&format!("{}any\n", indent)); rules },
+            FilterRule::Adhoc | FilterRule::ClientsOnly => {
+                out.push_str(&format!("{}{}\n", indent, self));
+                return;
+            },
+        };
+        for rule in children {
+            rule.write_tree(out, depth + 1);
+        }
+    }
+
+    /// add a node for this rule and its children to graph `out`, edges
+    /// from `parent`; node ids are taken from `next`
+    fn write_dot(&self, out: &mut String, parent: usize, next: &mut usize) {
+        let id = *next;
+        *next += 1;
+        let (label, shape, children): (String, _, &[BoxedRule]) = match *self {
+            FilterRule::Single { ref pred, ref op, ref matcher } =>
+                (format!("{} {} {}\n[{}]", pred, op, matcher, FilterRule::leaf_kind(pred, op, matcher)), "box", &[]),
+            FilterRule::Not(ref rule) => (String::from("not"), "ellipse", ::std::slice::from_ref(rule)),
+            FilterRule::All(ref rules) => (String::from("all"), "ellipse", rules),
+            FilterRule::Any(ref rules) => (String::from("any"), "ellipse", rules),
+            FilterRule::Adhoc | FilterRule::ClientsOnly => (self.to_string(), "box", &[]),
+        };
+        out.push_str(&format!("  n{} [label=\"{}\", shape={}];\n  n{} -> n{};\n", id, dot_escape(&label), shape, parent, id));
+        for rule in children {
+            rule.write_dot(out, id, next);
+        }
+    }
+}
+
+/// `action` as written in rules
+fn action_keyword(action: Action) -> &'static str {
+    match action {
+        Action::FilterOut => "filter",
+        Action::Pin => "pin",
+    }
+}
+
+/// `s` quoted for a graphviz label, newlines kept as line breaks
+fn dot_escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// `rule` as a graphviz digraph: a node per item with its action, any, all
+/// and not nodes above the predicates they combine, e.g. for
+/// `wminspect explain --dot RULE | dot -Tsvg`
+pub fn explain_dot<S: AsRef<str>>(rule: S) -> WmResult<String> {
+    let items = parse_rule(&mut scan_tokens(rule))?;
+    for item in &items {
+        item.rule.compile()?;
+    }
+
+    let mut out = String::from("digraph rule {\n  node [fontname=\"monospace\"];\n  n0 [label=\"rules\", shape=plaintext];\n");
+    let mut next = 1;
+    for (i, item) in items.iter().enumerate() {
+        let id = next;
+        next += 1;
+        out.push_str(&format!("  n{} [label=\"item {}: {}\", shape=doubleoctagon];\n  n0 -> n{};\n", id, i + 1, action_keyword(item.action), id));
+        item.rule.write_dot(&mut out, id, &mut next);
+    }
+    out.push_str("}\n");
+    Ok(out)
+}
+
+/// What `rule` is made of: its tokens with their line:column, the tree of
+/// each item parsed from them and the rules in normalized form, the way
+/// `Filter::rules` shows them.
+pub fn explain<S: AsRef<str>>(rule: S) -> WmResult<String> {
+    let mut tokens = scan_tokens(rule);
+    let mut out = String::from("tokens:\n");
+    for &(ref tk, pos) in &tokens.tokens {
+        out.push_str(&format!("  {}:{:<4} {}\n", pos.line, pos.column, tk));
+    }
+
+    let items = parse_rule(&mut tokens)?;
+    out.push_str("tree:\n");
+    for (i, item) in items.iter().enumerate() {
+        out.push_str(&format!("  item {}: {}\n", i + 1, action_keyword(item.action)));
+        item.rule.write_tree(&mut out, 2);
+    }
+
+    out.push_str("normalized:\n");
+    let mut filter = Filter::new();
+    filter.extend_items(items, RuleOrigin::Inline)?;
+    for info in filter.rules() {
+        out.push_str(&format!("  {}\n", info.source));
+    }
+    Ok(out)
+}
+
+/// a pattern compiled once when its rule is built; `*` and `?` glob the
+/// whole string, anything else matches a substring
+#[derive(Debug, Clone)]
+pub(crate) enum Wildcard {
+    Glob(Vec<char>),
+    Substring(String),
+    /// of `~`, matching anywhere unless anchored
+    Regex(regex::Regex),
+}
+
+impl PartialEq for Wildcard {
+    fn eq(&self, other: &Wildcard) -> bool {
+        match (self, other) {
+            (Wildcard::Glob(a), Wildcard::Glob(b)) => a == b,
+            (Wildcard::Substring(a), Wildcard::Substring(b)) => a == b,
+            (Wildcard::Regex(a), Wildcard::Regex(b)) => a.as_str() == b.as_str(),
+            _ => false,
+        }
+    }
+}
+
+impl Wildcard {
+    pub(crate) fn new(pat: &str) -> Wildcard {
+        if is_wild_string(pat) {
+            Wildcard::Glob(pat.chars().collect())
         } else {
-            return false;
+            Wildcard::Substring(pat.to_string())
+        }
+    }
+
+    /// `*` and `?` glob as with `new`, anything else matches the whole string
+    pub(crate) fn exact(pat: &str) -> Wildcard {
+        Wildcard::Glob(pat.chars().collect())
+    }
+
+    pub(crate) fn regex(pat: &str) -> WmResult<Wildcard> {
+        regex::Regex::new(pat).map(Wildcard::Regex).map_err(|e| parse_error(format!("bad regex {}: {}", pat, e)))
+    }
+
+    pub(crate) fn matches(&self, s: &str) -> bool {
+        match *self {
+            Wildcard::Glob(ref pat) => glob_match(pat, &s.chars().collect::<Vec<_>>()),
+            Wildcard::Substring(ref pat) => s.contains(pat.as_str()),
+            Wildcard::Regex(ref re) => re.is_match(s),
         }
     }
+}
+
+// non recursive algorithm
+fn glob_match(pat: &[char], s: &[char]) -> bool {
+    let (mut i, mut j) = (0, 0);
+    let mut star = usize::MAX;
+    let mut k = 0;
+    while j < s.len() {
+        if pat.get(i).unwrap_or(&'\0') == &'?' || pat.get(i).unwrap_or(&'\0') == &s[j] {
+            i += 1; j += 1; 
+        } else if pat.get(i).unwrap_or(&'\0') == &'*' {
+            star = i; k = j; i += 1; 
+        } else if pat.get(star).is_some() {
+            k += 1; j = k; i = star + 1;
+        } else {
+            return false;
+        } 
+    }
 
-    let res;
-    if is_wild_string(pat) {
-        //res = mat(&pat.chars().collect::<Vec<_>>(), 0, &s.chars().collect::<Vec<_>>(), 0);
-        res = mat2(&pat.chars().collect::<Vec<_>>(), &s.chars().collect::<Vec<_>>());
-    } else {
-        res = s.contains(pat);
+    while pat.get(i).unwrap_or(&'\0') == &'*' {
+        i += 1; 
     }
+    i == pat.len()
+}
 
-    res
+/// ad-hoc match compiling `pat` on every call, rules keep a `Wildcard`
+#[cfg(test)]
+fn wild_match(pat: &str, s: &str) -> bool {
+    Wildcard::new(pat).matches(s)
 }
 
 fn is_wild_string(pattern: &str) -> bool {
     pattern.chars().any(|c| c == '?' || c == '*')
 }
 
-fn parse_id(id_str: &str) -> u32 {
-    let id_str = id_str.to_lowercase();
-    if id_str.starts_with("0x") {
-        u32::from_str_radix(&id_str[2..], 16).unwrap_or(0)
-    } else {
-        id_str.parse::<u32>().unwrap_or(0)
-    }
-} 
-
-macro_rules! _match_geometry {
-    ($elem:tt, $op:tt, $i:tt) => (
-        match *$op {
-            Op::Eq => Box::new(move |ref w| w.geom.$elem == $i),
-            Op::Neq => Box::new(move |ref w| w.geom.$elem != $i),
-            Op::GT => Box::new(move |ref w| w.geom.$elem > $i),
-            Op::LT => Box::new(move |ref w| w.geom.$elem < $i),
-            Op::GE => Box::new(move |ref w| w.geom.$elem >= $i),
-            Op::LE => Box::new(move |ref w| w.geom.$elem <= $i),
-        })
+/// Selects recorded events by the window they concern, as it was after
+/// the event, and by their type with `event` rules, like
+/// `all(name = xterm*, event = configure)`. Rules separated by `;` all have
+/// to hold.
+#[derive(Debug, Clone)]
+pub struct EventQuery {
+    matcher: RuleMatcher,
 }
 
-impl FilterRule {
-    pub(crate) fn gen_closure(&self) -> FilterFunction {
-        use self::FilterRule::*;
-        match self {
-            &Adhoc => {Box::new(|_w| true)},
-            &ClientsOnly => FilterRule::clients_only_gen_closure(),
-            &Single {ref pred, ref op, ref matcher} => FilterRule::single_gen_closure(pred, op, matcher),
-            &All (ref rules) => FilterRule::all_gen_closure(rules),
-            &Any (ref rules) => FilterRule::any_gen_closure(rules),
-            &Not (ref rule) => FilterRule::not_gen_closure(rule),
+impl EventQuery {
+    pub fn parse<S: AsRef<str>>(text: S) -> WmResult<EventQuery> {
+        let mut tokens = scan_tokens(text);
+        let mut matchers = Vec::new();
+        for item in parse_rule(&mut tokens)? {
+            if item.action != Action::FilterOut {
+                return Err(parse_error("queries select events, they take no action"));
+            }
+            matchers.push(item.rule.compile()?);
         }
+        Ok(EventQuery { matcher: RuleMatcher::All(matchers) })
     }
 
-    /// TODO: clients info can only be retreived from wm context
-    fn clients_only_gen_closure() -> FilterFunction {
-        Box::new(|_w|{true})
+    /// true if event `kind`, like `map`, of window `w` is selected
+    pub fn matches(&self, w: &Window, kind: &str) -> bool {
+        self.matcher.matches_event(w, Some(kind))
     }
+}
 
-    fn any_gen_closure(rules: &Vec<BoxedRule>) -> FilterFunction {
-        let mut closures = Vec::new();
-        for r in rules {
-            closures.push(r.gen_closure())
-        }
+/// `_NET_WM_WINDOW_TYPE_DOCK` as `dock`, the way `type` rules name it
+pub fn window_type_name(atom: &str) -> String {
+    atom.trim_start_matches("_NET_WM_WINDOW_TYPE_").to_lowercase()
+}
 
-        Box::new(move |ref w| {
-            for f in &closures {
-                if f(w) {
-                    return true;
-                }
+/// `fullscreen` for `_NET_WM_STATE_FULLSCREEN`
+pub fn state_name(atom: &str) -> String {
+    atom.trim_start_matches("_NET_WM_STATE_").to_lowercase()
+}
+
+/// A rule passing windows like `w`: of its class and window type when it
+/// has them, of its name otherwise with the parts likely to change globbed,
+/// like the title of a document before ` - ` or numbers. Names are matched
+/// as they are while values of rules are lowercased, so capitals of the name
+/// are globbed too.
+pub fn suggest_rule(w: &Window) -> String {
+    let value = |s: &str| Matcher::Wildcard(s.to_string()).to_string();
+    let mut conds = Vec::new();
+    if let Some(class) = w.class.as_ref().filter(|c| !c.is_empty()) {
+        conds.push(format!("class = {}", value(&class.to_lowercase())));
+    }
+    if let Some(t) = w.window_type.iter().map(|t| window_type_name(t)).find(|t| t != "normal") {
+        conds.push(format!("type = {}", value(&t)));
+    }
+    if w.class.is_none() && !w.name.is_empty() {
+        conds.push(format!("name = {}", value(&name_pattern(&w.name))));
+    }
+
+    match conds.len() {
+        0 => format!("id = {}", w.id),
+        1 => conds.remove(0),
+        _ => format!("all({})", conds.join(", ")),
+    }
+}
+
+/// `name` with what comes before its last ` - ` or runs of digits globbed,
+/// and capitals as `?`
+fn name_pattern(name: &str) -> String {
+    let kept = [" - ", " \u{2014} ", " | "].iter()
+        .filter_map(|sep| name.rfind(sep))
+        .max()
+        .map(|i| format!("*{}", &name[i..]))
+        .unwrap_or_else(|| name.to_string());
+
+    let mut pat = String::new();
+    for c in kept.chars() {
+        if c.is_ascii_digit() {
+            if !pat.ends_with('*') {
+                pat.push('*');
             }
+        } else if c.is_uppercase() {
+            pat.push('?');
+        } else {
+            pat.push(c);
+        }
+    }
+    pat
+}
 
-            false
-        })
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum GeomField {
+    X,
+    Y,
+    Width,
+    Height,
+    BorderWidth,
+    Depth,
+}
+
+impl GeomField {
+    fn value_of(self, w: &Window) -> i32 {
+        match self {
+            GeomField::X => w.geom.x as i32,
+            GeomField::Y => w.geom.y as i32,
+            GeomField::Width => w.geom.width as i32,
+            GeomField::Height => w.geom.height as i32,
+            GeomField::BorderWidth => w.border_width as i32,
+            GeomField::Depth => w.depth as i32,
+        }
     }
+}
 
-    fn not_gen_closure(rule: &BoxedRule) -> FilterFunction {
-        let f = rule.gen_closure();
-        Box::new(move |ref w| !f(w))
+/// compiled form of a `FilterRule`, plain data so `Filter` is `Send + Sync`
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum RuleMatcher {
+    Always,
+    Name { pat: Wildcard, negate: bool },
+    Class { pat: Wildcard, negate: bool },
+    /// any of the window types, named as by `window_type_name`
+    Type { pat: Wildcard, negate: bool },
+    /// any of the states, named as by `state_name`
+    State { pat: Wildcard, negate: bool },
+    IdPattern(Wildcard),
+    Id(WindowId),
+    ParentPattern(Wildcard),
+    Parent(WindowId),
+    MapState { state: MapState, negate: bool },
+    OverrideRedirect { value: bool, negate: bool },
+    Iconified { value: bool, negate: bool },
+    /// window is on `desktop` or on all of them
+    Desktop { desktop: u32, negate: bool },
+    Geom { field: GeomField, op: CmpOp, value: i32 },
+    /// `value` is a percentage times 0xFFFFFFFF, compared with the opacity
+    /// times 100 to keep it exact
+    Opacity { op: CmpOp, value: u64 },
+    /// window is one of the managed clients
+    Clients(HashSet<WindowId>),
+    /// window intersects the screen of size (width, height)
+    OnScreen { width: u16, height: u16 },
+    /// window intersects the rectangle, e.g. of a monitor
+    Intersects(Geometry),
+    /// window name matches none of these
+    NotNamed(Vec<Wildcard>),
+    /// type of the event the window is recorded with, like `configure`
+    Event { pat: Wildcard, negate: bool },
+    /// visible area compared as a percentage of the whole, kept exact like
+    /// `Opacity`
+    VisibleArea { op: CmpOp, percent: u64 },
+    Occluded { value: bool, negate: bool },
+    All(Vec<RuleMatcher>),
+    Any(Vec<RuleMatcher>),
+    Not(Box<RuleMatcher>),
+}
+
+impl RuleMatcher {
+    pub(crate) fn matches(&self, w: &Window) -> bool {
+        self.matches_event(w, None)
     }
 
-    fn all_gen_closure(rules: &Vec<BoxedRule>) -> FilterFunction {
-        let mut closures = Vec::new();
-        for r in rules {
-            closures.push(r.gen_closure())
+    /// `event` is the type of the recorded event `w` comes with, event
+    /// rules never match without one
+    pub(crate) fn matches_event(&self, w: &Window, event: Option<&str>) -> bool {
+        use self::RuleMatcher::*;
+        match *self {
+            Always => true,
+            Name { ref pat, negate } => pat.matches(&w.name) != negate,
+            // values of rules are lowercased when scanned
+            Class { ref pat, negate } => w.class.as_ref().is_some_and(|c| pat.matches(&c.to_lowercase())) != negate,
+            Type { ref pat, negate } => w.window_type.iter().any(|t| pat.matches(&window_type_name(t))) != negate,
+            State { ref pat, negate } => w.state.iter().any(|st| pat.matches(&state_name(st))) != negate,
+            IdPattern(ref pat) => pat.matches(&w.id.to_string()),
+            Id(id) => w.id == id,
+            ParentPattern(ref pat) => w.parent.is_some_and(|p| pat.matches(&p.to_string())),
+            Parent(id) => w.parent == Some(id),
+            MapState { state, negate } => (w.attrs.map_state == state) != negate,
+            OverrideRedirect { value, negate } => (w.attrs.override_redirect == value) != negate,
+            Iconified { value, negate } => (w.attrs.iconified == value) != negate,
+            Desktop { desktop, negate } => (w.desktop == Some(desktop) || w.desktop == Some(0xFFFFFFFF)) != negate,
+            Geom { field, op, value } => op.holds(field.value_of(w), value),
+            Opacity { op, value } => op.holds(w.opacity.unwrap_or(u32::MAX) as u64 * 100, value),
+            Clients(ref clients) => clients.contains(&w.id),
+            OnScreen { width, height } => {
+                w.geom.x < width as i16 &&
+                    w.geom.y < height as i16 &&
+                    (w.geom.width as i16) + w.geom.x > 0 && (w.geom.height as i16) + w.geom.y > 0
+            },
+            Intersects(r) => {
+                let (x, y) = (w.geom.x as i32, w.geom.y as i32);
+                x < r.x as i32 + r.width as i32 && y < r.y as i32 + r.height as i32 &&
+                    x + w.geom.width as i32 > r.x as i32 && y + w.geom.height as i32 > r.y as i32
+            },
+            NotNamed(ref names) => !names.iter().any(|pat| pat.matches(&w.name)),
+            Event { ref pat, negate } => event.is_some_and(|e| pat.matches(e)) != negate,
+            VisibleArea { op, percent } => op.holds(w.visible_area.unwrap_or_else(|| w.area()) * 100, percent * w.area()),
+            Occluded { value, negate } => (w.is_occluded() == value) != negate,
+            All(ref ms) => ms.iter().all(|m| m.matches_event(w, event)),
+            Any(ref ms) => ms.iter().any(|m| m.matches_event(w, event)),
+            Not(ref m) => !m.matches_event(w, event),
         }
+    }
 
-        Box::new(move |ref w| {
-            for f in &closures {
-                if !f(w) {
-                    return false;
-                }
-            }
+    /// true if it tells events apart, which only recordings have
+    fn uses_events(&self) -> bool {
+        use self::RuleMatcher::*;
+        match *self {
+            Event { .. } => true,
+            All(ref ms) | Any(ref ms) => ms.iter().any(|m| m.uses_events()),
+            Not(ref m) => m.uses_events(),
+            _ => false,
+        }
+    }
 
-            true
-        })
+    /// true if window names are needed to tell a match
+    pub(crate) fn uses_names(&self) -> bool {
+        use self::RuleMatcher::*;
+        match *self {
+            Name { .. } | NotNamed(_) => true,
+            All(ref ms) | Any(ref ms) => ms.iter().any(|m| m.uses_names()),
+            Not(ref m) => m.uses_names(),
+            _ => false,
+        }
+    }
+}
+
+impl FilterRule {
+    /// fails if the rule can not be evaluated, e.g. a `name > x` from a sheet
+    pub(crate) fn compile(&self) -> WmResult<RuleMatcher> {
+        use self::FilterRule::*;
+        match self {
+            Adhoc => Ok(RuleMatcher::Always),
+            // TODO: clients info can only be retreived from wm context
+            ClientsOnly => Ok(RuleMatcher::Always),
+            Single {pred, op, matcher} => FilterRule::compile_single(pred, op, matcher),
+            All (rules) => Ok(RuleMatcher::All(FilterRule::compile_all(rules)?)),
+            Any (rules) => Ok(RuleMatcher::Any(FilterRule::compile_all(rules)?)),
+            Not (rule) => Ok(RuleMatcher::Not(Box::new(rule.compile()?))),
+        }
+    }
+
+    /// `compile` for rules of a `Filter`, which never sees events
+    fn compile_for_windows(&self) -> WmResult<RuleMatcher> {
+        let matcher = self.compile()?;
+        if matcher.uses_events() {
+            return Err(parse_error("event rules only select recorded events, see replay --query"));
+        }
+        Ok(matcher)
+    }
+
+    fn compile_all(rules: &[BoxedRule]) -> WmResult<Vec<RuleMatcher>> {
+        rules.iter().map(|r| r.compile()).collect()
     }
 
-    fn single_gen_closure(pred: &Predicate, op: &Op, matcher: &Matcher) -> FilterFunction {
-        match (pred, op, matcher) {
-            (&Predicate::Name, op, &Matcher::Wildcard(ref pat)) => {
-                let pat = pat.clone();
+    fn compile_single(pred: &Predicate, op: &Op, matcher: &Matcher) -> WmResult<RuleMatcher> {
+        if *op == Op::Has && *pred != Predicate::State {
+            return Err(parse_error(format!("{} can not use has as op", pred)));
+        }
+        if (*op == Op::Match) != matches!(*matcher, Matcher::Regex(_)) {
+            return Err(parse_error(format!("{} {} {} mixes up ~ and regex", pred, op, matcher)));
+        }
+        let m = match (pred, op, matcher) {
+            (Predicate::Name, Op::Match, Matcher::Regex(re)) => RuleMatcher::Name { pat: Wildcard::regex(re)?, negate: false },
+            (Predicate::Class, Op::Match, Matcher::Regex(re)) => RuleMatcher::Class { pat: Wildcard::regex(re)?, negate: false },
+            (Predicate::Type, Op::Match, Matcher::Regex(re)) => RuleMatcher::Type { pat: Wildcard::regex(re)?, negate: false },
+            (Predicate::State, Op::Match, Matcher::Regex(re)) => RuleMatcher::State { pat: Wildcard::regex(re)?, negate: false },
+            (Predicate::Id, Op::Match, Matcher::Regex(re)) => RuleMatcher::IdPattern(Wildcard::regex(re)?),
+            (Predicate::Parent, Op::Match, Matcher::Regex(re)) => RuleMatcher::ParentPattern(Wildcard::regex(re)?),
+            (_, Op::Match, _) => return Err(parse_error(format!("{} can not use ~ as op", pred))),
+            (Predicate::Name, op, Matcher::Wildcard(pat)) => {
+                match *op {
+                    Op::Eq | Op::Neq => RuleMatcher::Name { pat: Wildcard::new(pat), negate: *op == Op::Neq },
+                    _ => return Err(parse_error("name can only use Eq|Neq as op"))
+                }
+            },
+            (Predicate::Class, op, Matcher::Wildcard(pat)) => {
+                match *op {
+                    Op::Eq | Op::Neq => RuleMatcher::Class { pat: Wildcard::new(pat), negate: *op == Op::Neq },
+                    _ => return Err(parse_error("class can only use Eq|Neq as op"))
+                }
+            },
+            (Predicate::Type, op, Matcher::Wildcard(pat)) => {
+                match *op {
+                    Op::Eq | Op::Neq => RuleMatcher::Type { pat: Wildcard::new(pat), negate: *op == Op::Neq },
+                    _ => return Err(parse_error("type can only use Eq|Neq as op"))
+                }
+            },
+            (Predicate::State, op, Matcher::Wildcard(pat)) => {
+                match *op {
+                    // has takes the states as a set, one of them being `pat` as a whole
+                    Op::Has => RuleMatcher::State { pat: Wildcard::exact(pat), negate: false },
+                    Op::Eq | Op::Neq => RuleMatcher::State { pat: Wildcard::new(pat), negate: *op == Op::Neq },
+                    _ => return Err(parse_error("state can only use Has|Eq|Neq as op"))
+                }
+            },
+            // a plain name is the whole type, `map` is no part of `unmap`
+            (Predicate::Event, op, Matcher::Wildcard(pat)) => {
                 match *op {
-                    Op::Eq => Box::new(move |ref w| wild_match(&pat, &w.name)),
-                    Op::Neq => Box::new(move |ref w| !wild_match(&pat, &w.name)),
-                    _ => {panic!("name can only use Eq|Neq as op")}
+                    Op::Eq | Op::Neq => RuleMatcher::Event { pat: Wildcard::exact(pat), negate: *op == Op::Neq },
+                    _ => return Err(parse_error("event can only use Eq|Neq as op"))
+                }
+            },
+            (Predicate::Id, Op::Eq, Matcher::Wildcard(id)) => {
+                if is_wild_string(id) {
+                    RuleMatcher::IdPattern(Wildcard::new(id))
+                } else {
+                    RuleMatcher::Id(id.parse().map_err(|_| parse_error(format!("bad window id {}", id)))?)
                 }
-                
             },
-            (&Predicate::Id, &Op::Eq, &Matcher::Wildcard(ref id)) => {
-                let id = id.clone();
-                if is_wild_string(&id) {
-                    Box::new(move |ref w| wild_match(&id, &format!("0x{:x}", w.id)))
+            (Predicate::Parent, Op::Eq, Matcher::Wildcard(id)) => {
+                if is_wild_string(id) {
+                    RuleMatcher::ParentPattern(Wildcard::new(id))
                 } else {
-                    let i = parse_id(&id);
-                    Box::new(move |ref w| w.id == i)
+                    RuleMatcher::Parent(id.parse().map_err(|_| parse_error(format!("bad window id {}", id)))?)
+                }
+            },
+            (Predicate::Attr(attr), op, Matcher::MapStateValue(st)) if attr == "map_state" => {
+                match *op {
+                    Op::Eq | Op::Neq => RuleMatcher::MapState { state: *st, negate: *op == Op::Neq },
+                    _ => return Err(parse_error("map_state can only use Eq|Neq as op"))
+                }
+            },
+            (Predicate::Attr(attr), op, Matcher::BoolValue(b)) if attr == "override_redirect" => {
+                match *op {
+                    Op::Eq | Op::Neq => RuleMatcher::OverrideRedirect { value: *b, negate: *op == Op::Neq },
+                    _ => return Err(parse_error("override_redirect can only use Eq|Neq as op"))
                 }
             },
-            (&Predicate::Attr(ref attr), op, &Matcher::MapStateValue(ref st)) if attr == "map_state" => {
-                let state = *st;
+            (Predicate::Attr(attr), op, Matcher::BoolValue(b)) if attr == "iconified" => {
                 match *op {
-                    Op::Eq => Box::new(move |ref w| w.attrs.map_state == state),
-                    Op::Neq => Box::new(move |ref w| w.attrs.map_state != state),
-                    _ => {panic!("map_state can only use Eq|Neq as op")}
+                    Op::Eq | Op::Neq => RuleMatcher::Iconified { value: *b, negate: *op == Op::Neq },
+                    _ => return Err(parse_error("iconified can only use Eq|Neq as op"))
                 }
-                
             },
-            (&Predicate::Attr(ref attr), op, &Matcher::BoolValue(ref b)) if attr == "override_redirect" => {
-                let or = *b;
+            (Predicate::Geom(g), op, Matcher::IntegralValue(i)) => {
+                let (field, value) = match g.as_str() {
+                    "x" => (GeomField::X, *i as i32),
+                    "y" => (GeomField::Y, *i as i32),
+                    // keep the wrapping of the former `i as u16` comparison
+                    "width" => (GeomField::Width, *i as u16 as i32),
+                    "height" => (GeomField::Height, *i as u16 as i32),
+                    "border_width" => (GeomField::BorderWidth, *i as u16 as i32),
+                    "depth" => (GeomField::Depth, *i as i32),
+                    wrong => return Err(parse_error(format!("wrong geometry attribute {}", wrong)))
+                };
+                RuleMatcher::Geom { field, op: op.comparison(pred)?, value }
+            },
+            (Predicate::Desktop, op, Matcher::IntegralValue(i)) => {
                 match *op {
-                    Op::Eq => Box::new(move |ref w| w.attrs.override_redirect == or),
-                    Op::Neq => Box::new(move |ref w| w.attrs.override_redirect != or),
-                    _ => {panic!("override_redirect can only use Eq|Neq as op")}
+                    Op::Eq | Op::Neq => RuleMatcher::Desktop { desktop: *i as u32, negate: *op == Op::Neq },
+                    _ => return Err(parse_error("desktop can only use Eq|Neq as op"))
                 }
-                
             },
-            (&Predicate::Geom(ref g), op, &Matcher::IntegralValue(i)) => {
-                match g.as_str() {
-                    "x" => _match_geometry!(x, op, i),
-                    "y" => _match_geometry!(y, op, i),
-                    "width" => _match_geometry!(width, op, (i as u16)),
-                    "height" => _match_geometry!(height, op, (i as u16)),
-                    wrong @ _ => panic!("wrong geometry attribute {}", wrong)
+            (Predicate::Opacity, op, Matcher::IntegralValue(i)) if (0..=100).contains(i) => {
+                RuleMatcher::Opacity { op: op.comparison(pred)?, value: *i as u64 * 0xFFFFFFFF }
+            },
+            (Predicate::VisibleArea, op, Matcher::IntegralValue(i)) if (0..=100).contains(i) => {
+                RuleMatcher::VisibleArea { op: op.comparison(pred)?, percent: *i as u64 }
+            },
+            (Predicate::Occluded, op, Matcher::BoolValue(b)) => {
+                match *op {
+                    Op::Eq | Op::Neq => RuleMatcher::Occluded { value: *b, negate: *op == Op::Neq },
+                    _ => return Err(parse_error("occluded can only use Eq|Neq as op"))
                 }
             },
 
             _ => {
-                panic!("not implement"); 
+                return Err(parse_error(format!("unsupported rule {:?} {:?} {:?}", pred, op, matcher)));
             }
-        }
+        };
+
+        Ok(m)
     }
 }
 
+fn parse_error<S: Into<String>>(msg: S) -> WmError {
+    WmError::Parse(msg.into())
+}
 
 
 
@@ -321,190 +1015,327 @@ pub(crate) enum Token {
     EOT, // special
 }
 
+/// how a token is shown in error messages
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Token::*;
+        match *self {
+            OP(ref op) => write!(f, "'{}'", op),
+            StrLit(ref s) => write!(f, "'{}'", s),
+            ACTION(Action::Pin) => write!(f, "'pin'"),
+            ACTION(Action::FilterOut) => write!(f, "'filter'"),
+            ANY => write!(f, "'any'"),
+            ALL => write!(f, "'all'"),
+            NOT => write!(f, "'not'"),
+            LBRACE => write!(f, "'('"),
+            RBRACE => write!(f, "')'"),
+            COMMA => write!(f, "','"),
+            COLON => write!(f, "':'"),
+            SEMICOLON => write!(f, "';'"),
+            DOT => write!(f, "'.'"),
+            EOT => write!(f, "end of rule"),
+        }
+    }
+}
+
+/// 1-based position of a token in the rule text
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) struct Pos {
+    line: usize,
+    column: usize,
+}
+
 use std::collections::VecDeque;
-pub(crate) type Tokens = VecDeque<Token>;
+
+/// scanned tokens with their positions, always ends with EOT
+#[derive(Debug, Default)]
+pub(crate) struct Tokens {
+    tokens: VecDeque<(Token, Pos)>,
+    source: String,
+    /// position of the last token taken
+    last: Pos,
+}
+
+impl Tokens {
+    pub(crate) fn new() -> Tokens {
+        Tokens::default()
+    }
+
+    #[cfg(test)]
+    pub(crate) fn push_back(&mut self, tk: Token) {
+        self.push_at(tk, Pos::default());
+    }
+
+    fn push_at(&mut self, tk: Token, pos: Pos) {
+        self.tokens.push_back((tk, pos));
+    }
+
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.tokens.len()
+    }
+
+    fn peek(&self) -> &Token {
+        self.tokens.front().map(|(tk, _)| tk).unwrap_or(&Token::EOT)
+    }
+
+    /// pop next token, or EOT when run out
+    fn next(&mut self) -> Token {
+        match self.tokens.pop_front() {
+            Some((tk, pos)) => {
+                self.last = pos;
+                tk
+            },
+            None => Token::EOT
+        }
+    }
+
+    /// error at the last token taken
+    fn error(&self, kind: DslErrorKind) -> WmError {
+        let snippet = match self.last.line {
+            0 => String::new(),
+            l => self.source.lines().nth(l - 1).unwrap_or("").to_string(),
+        };
+        WmError::Dsl(DslError { kind, line: self.last.line, column: self.last.column, snippet })
+    }
+
+    fn unexpected<S: Into<String>>(&self, expected: S, found: &Token) -> WmError {
+        self.error(DslErrorKind::Unexpected { expected: expected.into(), found: found.to_string() })
+    }
+}
 
 /// parse `Tokens` into FilterItem list
-pub(crate) fn parse_rule(tokens: &mut Tokens) -> Option<Vec<FilterItem>> {
+pub(crate) fn parse_rule(tokens: &mut Tokens) -> WmResult<Vec<FilterItem>> {
     use self::Token::*;
 
     let mut items = Vec::new();
-    while let Some(item) = parse_item(tokens) {
-        items.push(item);
-        let tk = tokens.pop_front().unwrap();
-        if tk == EOT {
-            break;
+    while *tokens.peek() != EOT {
+        items.push(parse_item(tokens)?);
+        match tokens.next() {
+            EOT => break,
+            // ',' has been accepted as separator as well
+            SEMICOLON | COMMA => {},
+            tk => return Err(tokens.unexpected("';'", &tk)),
         }
     }
 
-    Some(items)
+    Ok(items)
 }
 
-fn parse_item(tokens: &mut Tokens) -> Option<FilterItem> {
+fn parse_item(tokens: &mut Tokens) -> WmResult<FilterItem> {
     use self::Token::*;
 
     let mut action = Action::FilterOut;
 
-    if tokens[0] == EOT { 
-        return None;
-    }
-
-    match parse_cond(tokens) {
-        Some(cond) => {
-            if tokens[0] == COLON {
-                tokens.pop_front();
-                match tokens.pop_front().unwrap() {
-                    ACTION(act) => action = act,
-                    _ => {wm_debug!("ignore wrong action")}
-                }
-            }
-
-            Some(FilterItem {action: action, rule: cond })
-        }, 
-        _ => {
-            return None
+    let cond = parse_cond(tokens)?;
+    if *tokens.peek() == COLON {
+        tokens.next();
+        match tokens.next() {
+            ACTION(act) => action = act,
+            tk => return Err(tokens.unexpected("action", &tk)),
         }
     }
+
+    Ok(FilterItem {action, rule: cond })
 }
 
 macro_rules! match_tok {
     ($tokens:tt, $kd:expr) => (
         {
-            if $tokens[0] == $kd {
-                $tokens.pop_front().unwrap();
-            } else {
-                panic!("expecting {:?} but {:?}", $kd, $tokens[0]);
+            let tk = $tokens.next();
+            if tk != $kd {
+                return Err($tokens.unexpected($kd.to_string(), &tk));
             }
         }
     )
 }
 
-fn parse_cond(tokens: &mut Tokens) -> Option<FilterRule> {
+fn parse_cond(tokens: &mut Tokens) -> WmResult<FilterRule> {
     use self::Token::*;
 
-    let tk = tokens.pop_front().unwrap();
+    let tk = tokens.next();
     match tk {
         StrLit(ref s) => {
-            let mut pred = Predicate::Id;
-
-            match s.as_str() {
+            let pred = match s.as_str() {
                 "attrs" => { 
                     match_tok!(tokens, DOT);
-                    let tk = tokens.pop_front().unwrap();
-                    if let StrLit(name) = tk {
-                        assert!(name == "map_state" || name == "override_redirect");
-                        pred = Predicate::Attr(name);
-                    } else {
-                        wm_debug!("wrong token");
+                    match tokens.next() {
+                        StrLit(ref name) if ["map_state", "override_redirect", "iconified"].contains(&name.as_str()) => {
+                            Predicate::Attr(name.clone())
+                        },
+                        StrLit(ref name) => {
+                            return Err(tokens.error(DslErrorKind::UnknownPredicate(format!("attrs.{}", name))))
+                        },
+                        tk => return Err(tokens.unexpected("attribute name", &tk)),
                     }
                 },
                 "geom" => {
                     match_tok!(tokens, DOT);
-                    let tk = tokens.pop_front().unwrap();
-                    if let StrLit(name) = tk {
-                        assert!(name == "x" || name == "y" || name == "width" || name == "height");
-                        pred = Predicate::Geom(name);
-                    } else {
-                        wm_debug!("wrong token");
+                    match tokens.next() {
+                        StrLit(ref name) if ["x", "y", "width", "height", "border_width", "depth"].contains(&name.as_str()) => {
+                            Predicate::Geom(name.clone())
+                        },
+                        StrLit(ref name) => {
+                            return Err(tokens.error(DslErrorKind::UnknownPredicate(format!("geom.{}", name))))
+                        },
+                        tk => return Err(tokens.unexpected("geometry name", &tk)),
                     }
                 },
 
-                "id" | "name" => {
-                    pred = if s == "id" { Predicate::Id } else { Predicate::Name };
-                },
+                "id" => Predicate::Id,
+                "name" => Predicate::Name,
+                "parent" => Predicate::Parent,
+                "opacity" => Predicate::Opacity,
+                "desktop" => Predicate::Desktop,
+                "class" => Predicate::Class,
+                "type" => Predicate::Type,
+                "state" => Predicate::State,
+                "event" => Predicate::Event,
+                "visible_area" => Predicate::VisibleArea,
+                "occluded" => Predicate::Occluded,
 
                 "clients" => {
-                    return Some(FilterRule::ClientsOnly);
+                    return Ok(FilterRule::ClientsOnly);
                 },
 
-                _ => { wm_debug!("wrong token"); }
-            }
+                _ => return Err(tokens.error(DslErrorKind::UnknownPredicate(s.clone()))),
+            };
+
+            let op = match tokens.next() {
+                OP(op) => op,
+                tk => return Err(tokens.unexpected("op", &tk)),
+            };
+
+            let matcher = match tokens.next() {
+                StrLit(ref s) if op == Op::Match => Matcher::Regex(s.clone()),
+                StrLit(ref s) => match pred {
+                    Predicate::Id | Predicate::Parent => Matcher::Wildcard(s.clone()),
+                    Predicate::Name | Predicate::Class | Predicate::Type | Predicate::State | Predicate::Event => Matcher::Wildcard(s.clone()),
+                    Predicate::Occluded => Matcher::BoolValue(!["0", "false"].contains(&s.to_lowercase().as_str())),
+                    Predicate::Attr(ref a) if a == "override_redirect" || a == "iconified" => {
+                        Matcher::BoolValue(match s.to_lowercase().as_str() {
+                            "0" | "false" => false,
+                            _ => true
+                        })
+                    },
+                    Predicate::Attr(_) => {
+                        Matcher::MapStateValue(match s.to_lowercase().as_str() {
+                            "viewable" => MapState::Viewable,
+                            "unmapped" => MapState::Unmapped,
+                            "unviewable" => MapState::Unviewable,
+                            _ => return Err(tokens.error(DslErrorKind::BadValue(format!("map state {}", s))))
+                        })
+                    },
+                    Predicate::Geom(_) => match s.parse::<i16>() {
+                        Ok(i) => Matcher::IntegralValue(i),
+                        Err(_) => return Err(tokens.error(DslErrorKind::BadValue(format!("geometry {}", s))))
+                    },
+                    Predicate::Opacity => match s.trim_end_matches('%').parse::<i16>() {
+                        Ok(i) if (0..=100).contains(&i) => Matcher::IntegralValue(i),
+                        _ => return Err(tokens.error(DslErrorKind::BadValue(format!("opacity {}", s))))
+                    },
+                    Predicate::VisibleArea => match s.trim_end_matches('%').parse::<i16>() {
+                        Ok(i) if (0..=100).contains(&i) => Matcher::IntegralValue(i),
+                        _ => return Err(tokens.error(DslErrorKind::BadValue(format!("visible_area {}", s))))
+                    },
+                    Predicate::Desktop => match s.parse::<i16>() {
+                        Ok(i) if i >= 0 => Matcher::IntegralValue(i),
+                        _ => return Err(tokens.error(DslErrorKind::BadValue(format!("desktop {}", s))))
+                    },
+                },
+                tk => return Err(tokens.unexpected("value", &tk)),
+            };
 
-            assert!(tokens.len() >= 2);
-            match (tokens.pop_front().unwrap(), tokens.pop_front().unwrap()) {
-                (OP(ref op), StrLit(ref s)) => {
-                    let matcher = match pred {
-                        Predicate::Id => Matcher::Wildcard(s.clone()),
-                        Predicate::Name => Matcher::Wildcard(s.clone()),
-                        Predicate::Attr(ref a) if a == "override_redirect" => {
-                            Matcher::BoolValue(match s.to_lowercase().as_str() {
-                                "0" | "false" => false,
-                                _ => true
-                            })
-                        },
-                        Predicate::Attr(ref a) if a == "map_state" => {
-                            Matcher::MapStateValue(match s.to_lowercase().as_str() {
-                                "viewable" => MapState::Viewable,
-                                "unmapped" => MapState::Unmapped,
-                                "unviewable" => MapState::Unviewable,
-                                _ => panic!("bad map state value")
-                            })
-                        },
-                        Predicate::Attr(_) => panic!("bad attr name"),
-                        Predicate::Geom(_) => Matcher::IntegralValue(s.parse::<i16>().unwrap_or(0))
-                    };
-
-                    Some(FilterRule::Single {
-                        pred: pred,
-                        op: op.clone(),
-                        matcher: matcher
-                    })
-                }, 
-
-                _ => {
-                    wm_debug!("wrong rule");
-                    None
-                } 
-            }
+            Ok(FilterRule::Single { pred, op, matcher })
         },
         
         ANY | ALL => {
             match_tok!(tokens, LBRACE);
             let mut rules = Vec::new();
-            while let Some(cond) = parse_cond(tokens) {
-                rules.push(Box::new(cond));
-                // pop ',' or ')' anyway
-                let tk = tokens.pop_front().unwrap();
-                if tk == RBRACE {
-                    break
+            loop {
+                rules.push(Box::new(parse_cond(tokens)?));
+                match tokens.next() {
+                    COMMA => {},
+                    RBRACE => break,
+                    tk => return Err(tokens.unexpected("',' or ')'", &tk)),
                 }
             }
 
             if tk == ANY {
-                Some(FilterRule::Any(rules))
+                Ok(FilterRule::Any(rules))
             } else {
-                Some(FilterRule::All(rules))
+                Ok(FilterRule::All(rules))
             }
         },
 
         NOT => {
             match_tok!(tokens, LBRACE);
-            if let Some(cond) = parse_cond(tokens) {
-                match_tok!(tokens, RBRACE);
-                Some(FilterRule::Not(Box::new(cond))) //FIXME: assert only one rule included 
-            } else {
-                None
-            }
+            let cond = parse_cond(tokens)?;
+            match_tok!(tokens, RBRACE);
+            Ok(FilterRule::Not(Box::new(cond)))
         },
-        _ => { wm_debug!("wrong match: [{:?}]", tk); None } 
+        _ => Err(tokens.unexpected("condition", &tk))
+    }
+}
+
+/// chars of the rule text along with the position of the next one
+struct PosChars<'a> {
+    chars: ::std::iter::Peekable<::std::str::Chars<'a>>,
+    pos: Pos,
+}
+
+impl<'a> PosChars<'a> {
+    fn peek(&mut self) -> Option<&char> {
+        self.chars.peek()
+    }
+
+    /// true if the word `has` comes next, after some whitespace
+    fn has_ahead(&self) -> bool {
+        let mut rest = self.chars.clone();
+        if !rest.peek().is_some_and(|c| c.is_whitespace()) {
+            return false;
+        }
+        let word: String = rest.skip_while(|c| c.is_whitespace()).take(4).collect();
+        let mut word = word.chars();
+        word.by_ref().take(3).collect::<String>().eq_ignore_ascii_case("has")
+            && word.next().is_some_and(|c| c.is_whitespace() || c == '\'' || c == '"')
+    }
+}
+
+impl<'a> Iterator for PosChars<'a> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        let ch = self.chars.next()?;
+        if ch == '\n' {
+            self.pos.line += 1;
+            self.pos.column = 1;
+        } else {
+            self.pos.column += 1;
+        }
+        Some(ch)
     }
 }
 
 pub(crate) fn scan_tokens<S: AsRef<str>>(rule: S) -> Tokens {
     use self::Token::*;
+    // where the token being scanned starts
+    let mut start;
     macro_rules! append_tok {
         ($tokens:tt, $tk:expr) => ({
-            $tokens.push_back($tk); 
+            $tokens.push_at($tk, start); 
             //println!("collect [{:?}]", $tk);
         })
     }
 
     let mut tokens = Tokens::new();
-    let mut chars = rule.as_ref().chars().peekable();
-    let metas: HashSet<_> = ['.', ',', ';', ':', '(', ')', '<', '>', '='].iter().cloned().collect();
+    tokens.source = rule.as_ref().to_string();
+    let mut chars = PosChars { chars: rule.as_ref().chars().peekable(), pos: Pos { line: 1, column: 1 } };
+    let metas: HashSet<_> = ['.', ',', ';', ':', '(', ')', '<', '>', '=', '~'].iter().cloned().collect();
     let mut need_act = false;
 
     loop {
+        start = chars.pos;
         let ch = match chars.next() {
             Some(c) => c,
             None => break,
@@ -514,6 +1345,9 @@ pub(crate) fn scan_tokens<S: AsRef<str>>(rule: S) -> Tokens {
             '=' => {
                 append_tok!(tokens, OP(Op::Eq));
             },
+            '~' => {
+                append_tok!(tokens, OP(Op::Match));
+            },
             
             '>' => {
                 let mut do_consume = false;
@@ -567,6 +1401,9 @@ pub(crate) fn scan_tokens<S: AsRef<str>>(rule: S) -> Tokens {
                     _ => false
                 };
 
+                // `has` is an op only where one is expected, `name = a has b` is a name
+                let after_op = matches!(tokens.tokens.back(), Some(&(OP(_), _)));
+                let mut has_next = false;
                 let mut s = String::new();
                 if !compound_str { s.push(ch); }
                 loop {
@@ -582,6 +1419,10 @@ pub(crate) fn scan_tokens<S: AsRef<str>>(rule: S) -> Tokens {
                             Some(val) if !metas.contains(val) => {},
                             _ => break,
                         }
+                        if !after_op && chars.has_ahead() {
+                            has_next = true;
+                            break;
+                        }
                     }
 
                     s.push(chars.next().unwrap());
@@ -594,6 +1435,12 @@ pub(crate) fn scan_tokens<S: AsRef<str>>(rule: S) -> Tokens {
                 s = s.trim().to_string();
                 //wm_debug!("s = {}", s);
 
+                // a regex is case sensitive, `\d` is no `\D`
+                if let Some(&(OP(Op::Match), _)) = tokens.tokens.back() {
+                    append_tok!(tokens, StrLit(s));
+                    continue;
+                }
+
                 match s.to_lowercase().as_str() {
                     "all" => append_tok!(tokens, ALL),
                     "any" => append_tok!(tokens, ANY),
@@ -602,10 +1449,18 @@ pub(crate) fn scan_tokens<S: AsRef<str>>(rule: S) -> Tokens {
                     "filter" if need_act => append_tok!(tokens, ACTION(Action::FilterOut)),
                     lowered @ _ => append_tok!(tokens, StrLit(lowered.to_string()))
                 }
+
+                if has_next {
+                    while chars.peek().is_some_and(|c| c.is_whitespace()) { chars.next(); }
+                    start = chars.pos;
+                    chars.by_ref().take(3).for_each(drop);
+                    append_tok!(tokens, OP(Op::Has));
+                }
             }
         } 
     }
 
+    start = chars.pos;
     append_tok!(tokens, EOT);
     tokens
 }
@@ -621,16 +1476,29 @@ pub fn filter_grammar() ->&'static str {
         | NOT '(' cond ')'
         | 'clients'
     pred -> ID ('.' ID)*
-    op -> '=' | '>' | '<' | '>=' | '<=' | '<>'
+    op -> '=' | '>' | '<' | '>=' | '<=' | '<>' | 'has' | '~'
     action -> 'filter' | 'pin'
     ID -> STRING_LIT
     VAL -> STRING_LIT
     
 pred could be:
-    attrs.(map_state|override_redirect)
-    geom.(x|y|width|height)
+    attrs.(map_state|override_redirect|iconified)
+    geom.(x|y|width|height|border_width|depth)
     id
     name
+    class (of WM_CLASS)
+    type (of _NET_WM_WINDOW_TYPE, like dock or dialog)
+    state (of _NET_WM_STATE, has for one state like fullscreen, = for a substring of one)
+    parent
+    opacity (a percentage like 80%)
+    visible_area (percentage of the window no sibling above covers, like 50%)
+    occluded (true if mapped but covered all over)
+    desktop (windows on all desktops are on each)
+    event (of recorded events, like configure, for replay --query only)
+
+id, parent, name, class, type and state take a regex with ~, like
+    name ~ \"^dde-(osd|dock)$\"
+matched anywhere unless anchored, class, type and state lowercased
 ";
 }
 
@@ -653,7 +1521,7 @@ mod tests {
 
         let rule = parse_rule(&mut tokens);
         println!("{:?}", rule);
-        assert!(rule.is_some());
+        assert!(rule.is_ok());
     }
 
     #[test]
@@ -674,7 +1542,7 @@ mod tests {
 
         let rule = parse_rule(&mut tokens);
         println!("{:?}", rule);
-        assert!(rule.is_some());
+        assert!(rule.is_ok());
         assert_eq!(rule.unwrap().len(), 2);
     }
 
@@ -698,7 +1566,7 @@ mod tests {
 
         let rule = parse_rule(&mut tokens);
         println!("{:?}", rule);
-        assert!(rule.is_some());
+        assert!(rule.is_ok());
         assert_eq!(rule.unwrap().len(), 2);
     }
 
@@ -731,7 +1599,7 @@ mod tests {
 
         let rule = parse_rule(&mut tokens);
         println!("{:?}", rule);
-        assert!(rule.is_some());
+        assert!(rule.is_ok());
         assert_eq!(rule.unwrap().len(), 2);
     }
 
@@ -779,7 +1647,7 @@ mod tests {
 
         let rule = parse_rule(&mut tokens);
         println!("rule: {:?}", rule);
-        assert!(rule.is_some());
+        assert!(rule.is_ok());
         assert_eq!(rule.unwrap().len(), 1);
     }
 
@@ -811,16 +1679,39 @@ mod tests {
         assert_eq!(wild_match("??*-wm-*", "deepin-wm-switcher"), true);
 
         assert_eq!(wild_match("*dde*", "ClutterActor: Clutter Reference Manual"), false);
+
+        // compiled once by the rule
+        let filter = Filter::parse("name = dde*").unwrap();
+        match filter.rules[0].matcher {
+            RuleMatcher::Name { ref pat, .. } => assert_eq!(*pat, Wildcard::Glob("dde*".chars().collect())),
+            ref m => panic!("unexpected {:?}", m),
+        }
+        assert_eq!(Wildcard::new("osd"), Wildcard::Substring("osd".to_string()));
     }
 
     #[test]
     fn test_whole() {
         use super::super::sheets::SheetFormat;
         let mut filter = Filter::new();
-        filter.extend_with("name = dde*;".to_string(), SheetFormat::Plain);
+        filter.extend_with("name = dde*;".to_string(), SheetFormat::Plain).unwrap();
         assert_eq!(filter.rules.len(), 1);
     }
 
+    #[test]
+    fn test_parse_error() {
+        assert!(Filter::parse("any(name = dde*, geom.x > 100); not(attrs.map_state = unmapped): pin").is_ok());
+        assert!(Filter::parse("").is_ok());
+
+        for rule in &["attr.map_state = unmapped", "attrs.map_state == unmapped", "name = a: bogus",
+                      "any(name = a", "not(name = a, name = b)", "geom.z > 1", "geom.x > abc",
+                      "attrs.map_state = hidden", "name > a", "name = a name = b"] {
+            match Filter::parse(rule) {
+                Err(WmError::Parse(_)) | Err(WmError::Dsl(_)) => {},
+                _ => panic!("{} should fail", rule),
+            }
+        }
+    }
+
     #[test]
     fn test_store1() {
         let act = Action::FilterOut; 
@@ -837,7 +1728,7 @@ mod tests {
         assert_eq!(act, act2);
 
         let mut tokens = scan_tokens("all(name = dde*, geom.x > 100);".to_string());
-        if let Some(top) = parse_rule(&mut tokens) {
+        if let Ok(top) = parse_rule(&mut tokens) {
             let serialized = serde_json::to_string(&top).unwrap();
             println!("serialized = {}", serialized);
         }
@@ -846,7 +1737,7 @@ mod tests {
     #[test]
     fn test_store2() {
         let mut tokens = scan_tokens("name = dde*;".to_string());
-        if let Some(top) = parse_rule(&mut tokens) {
+        if let Ok(top) = parse_rule(&mut tokens) {
             let serialized = serde_json::to_string(&top).unwrap();
             println!("serialized = {}", serialized);
 
@@ -865,10 +1756,10 @@ mod tests {
         let r = r#"
         any(all(geom.x > 0, geom.y > 0), 
             all(name = dde*, geom.x > 100)): filter;
-        not(attr.map_state == unmapped)
+        not(attrs.map_state = unmapped)
         "#;
         let mut tokens = scan_tokens(r.to_string());
-        if let Some(top) = parse_rule(&mut tokens) {
+        if let Ok(top) = parse_rule(&mut tokens) {
             let serialized = serde_json::to_string(&top).unwrap();
             println!("serialized = {}", serialized);
 
@@ -878,4 +1769,353 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_filter_send_sync() {
+        fn assert_send_sync<T: Send + Sync>(_: &T) {}
+
+        let filter = Filter::parse("any(name = dde*, geom.width >= 100); not(attrs.override_redirect = 1)").unwrap();
+        assert_send_sync(&filter);
+
+        let mut w = Window::new(WindowId(0x10));
+        w.name = "xterm".to_string();
+        w.geom.width = 200;
+        assert!(std::thread::spawn(move || filter.apply_to(&w)).join().unwrap());
+    }
+
+    #[test]
+    fn test_border_depth_parent() {
+        let mut w = Window::new(WindowId(0x10));
+        w.border_width = 2;
+        w.depth = 32;
+        w.parent = Some(WindowId(0x400001));
+
+        for rule in &["geom.border_width > 1", "geom.depth = 32", "parent = 0x400001", "parent = 0x4*"] {
+            assert!(Filter::parse(rule).unwrap().apply_to(&w), "{}", rule);
+        }
+        for rule in &["geom.border_width = 0", "geom.depth < 24", "parent = 0x1", "parent = 0x5*"] {
+            assert!(!Filter::parse(rule).unwrap().apply_to(&w), "{}", rule);
+        }
+        w.parent = None;
+        assert!(!Filter::parse("parent = *").unwrap().apply_to(&w));
+        assert!(Filter::parse("parent = xterm").is_err());
+    }
+
+    #[test]
+    fn test_opacity() {
+        let mut w = Window::new(WindowId(0x10));
+        assert!(Filter::parse("opacity = 100%").unwrap().apply_to(&w));
+        assert!(!Filter::parse("opacity < 100%").unwrap().apply_to(&w));
+
+        w.opacity = Some(0xCCCCCCCC);
+        assert_eq!(w.opacity_percent(), 80);
+        for rule in &["opacity < 100%", "opacity = 80%", "opacity >= 80", "not(opacity > 80%)"] {
+            assert!(Filter::parse(rule).unwrap().apply_to(&w), "{}", rule);
+        }
+        w.opacity = Some(0xFFFFFFFE);
+        assert!(Filter::parse("opacity < 100%").unwrap().apply_to(&w));
+        assert!(Filter::parse("opacity < 101%").is_err());
+        assert!(Filter::parse("opacity < half").is_err());
+        // no equality in disguise for ops not comparing numbers
+        assert!(Op::Has.comparison(&Predicate::Opacity).is_err());
+        assert!(Op::Match.comparison(&Predicate::VisibleArea).is_err());
+        assert_eq!(Op::GE.comparison(&Predicate::Opacity).unwrap(), CmpOp::GE);
+    }
+
+    #[test]
+    fn test_visible_area() {
+        let mut w = Window::new(WindowId(0x100));
+        w.geom = Geometry { x: 0, y: 0, width: 30, height: 10 };
+        w.attrs.map_state = MapState::Viewable;
+        // not known yet, taken as all of it
+        assert!(Filter::parse("visible_area = 100%").unwrap().apply_to(&w));
+        w.visible_area = Some(100);
+        assert_eq!(w.visible_percent(), 33);
+        for rule in &["visible_area < 34%", "visible_area > 33", "not(occluded = true)"] {
+            assert!(Filter::parse(rule).unwrap().apply_to(&w), "{}", rule);
+        }
+        assert!(w.to_string().ends_with("Viewable 33% visible"));
+
+        w.visible_area = Some(0);
+        assert!(w.is_occluded());
+        assert!(Filter::parse("all(occluded = true, visible_area = 0%)").unwrap().apply_to(&w));
+        w.attrs.map_state = MapState::Unmapped;
+        assert!(Filter::parse("occluded = false").unwrap().apply_to(&w));
+        assert!(Filter::parse("visible_area < 101%").is_err());
+        assert!(Filter::parse("occluded > 0").is_err());
+    }
+
+    #[test]
+    fn test_iconified() {
+        let mut w = Window::new(WindowId(0x10));
+        assert!(Filter::parse("attrs.iconified = false").unwrap().apply_to(&w));
+        w.attrs.iconified = true;
+        for rule in &["attrs.iconified = true", "attrs.iconified = 1", "attrs.iconified <> false"] {
+            assert!(Filter::parse(rule).unwrap().apply_to(&w), "{}", rule);
+        }
+        assert!(Filter::parse("attrs.iconified > 0").is_err());
+        assert!(w.attrs.to_string().ends_with(" Iconified"));
+    }
+
+    #[test]
+    fn test_event_query() {
+        let mut w = Window::new(WindowId(0x10));
+        w.name = "xterm".to_string();
+        let q = EventQuery::parse("all(name = xterm*, event = configure)").unwrap();
+        assert!(q.matches(&w, "configure"));
+        assert!(!q.matches(&w, "map"));
+
+        let q = EventQuery::parse("event <> map; name = x*").unwrap();
+        assert!(q.matches(&w, "unmap") && !q.matches(&w, "map"));
+        assert!(EventQuery::parse("any(event = map*, attrs.iconified = true)").unwrap().matches(&w, "map"));
+        assert!(EventQuery::parse("event = map: pin").is_err());
+        assert!(EventQuery::parse("event > map").is_err());
+
+        // live filters have no events to tell
+        assert!(Filter::parse("not(event = map)").is_err());
+        let mut filter = Filter::new();
+        assert_eq!(filter.add_rule("all(name = xterm*, event = map)").unwrap_err().to_string(),
+                   Filter::parse("all(name = xterm*, event = map)").err().unwrap().to_string());
+        assert!(filter.rules().is_empty());
+    }
+
+    #[test]
+    fn test_class_type() {
+        let mut w = Window::new(WindowId(0x10));
+        assert!(!Filter::parse("class = Firefox").unwrap().apply_to(&w));
+        assert!(Filter::parse("class <> Firefox").unwrap().apply_to(&w));
+        w.class = Some("Firefox".to_string());
+        w.window_type = vec!["_NET_WM_WINDOW_TYPE_DIALOG".to_string()];
+        for rule in &["class = Firefox", "class = Fire", "class = F*x", "type = dialog", "type <> dock", "type = dia*"] {
+            assert!(Filter::parse(rule).unwrap().apply_to(&w), "{}", rule);
+        }
+        assert!(!Filter::parse("type = normal").unwrap().apply_to(&w));
+        assert!(Filter::parse("class > a").is_err());
+        assert_eq!(window_type_name("_NET_WM_WINDOW_TYPE_DROPDOWN_MENU"), "dropdown_menu");
+    }
+
+    #[test]
+    fn test_state() {
+        let mut w = Window::new(WindowId(0x10));
+        w.name = "a has b".to_string();
+        assert!(!Filter::parse("state has fullscreen").unwrap().apply_to(&w));
+        w.state = vec!["_NET_WM_STATE_FULLSCREEN".to_string(), "_NET_WM_STATE_ABOVE".to_string()];
+        for rule in &["state has fullscreen", "state HAS 'above'", "state = full", "state = above", "state <> sticky",
+                      "all(state has above, name = a has b)", "not(state has hidden)", "state has full*"] {
+            assert!(Filter::parse(rule).unwrap().apply_to(&w), "{}", rule);
+        }
+        // has is set membership, = a substring of any state
+        assert!(!Filter::parse("state has full").unwrap().apply_to(&w));
+        assert!(!Filter::parse("state has maximized*").unwrap().apply_to(&w));
+        assert!(Filter::parse("name = a has b").unwrap().apply_to(&w));
+
+        let mut tokens = scan_tokens("state has fullscreen");
+        assert_eq!(tokens.next(), StrLit("state".to_string()));
+        assert_eq!(tokens.next(), OP(Op::Has));
+        assert_eq!(tokens.next(), StrLit("fullscreen".to_string()));
+        assert!(Filter::parse("name has xterm").is_err());
+        assert!(Filter::parse("state > above").is_err());
+        let text = explain("state  has above").unwrap();
+        assert!(text.contains("  1:8    'has'\n  1:12   'above'\n"), "{}", text);
+        assert_eq!(text.lines().last().unwrap(), "  state has above");
+        assert_eq!(state_name("_NET_WM_STATE_MAXIMIZED_VERT"), "maximized_vert");
+    }
+
+    #[test]
+    fn test_regex() {
+        let mut w = Window::new(WindowId(0x1a00003));
+        w.name = "dde-dock".to_string();
+        w.class = Some("Dde-Dock".to_string());
+        w.state = vec!["_NET_WM_STATE_ABOVE".to_string()];
+        for rule in &["name ~ \"^dde-(osd|dock)$\"", "name~dock", "id ~ '^0x1a0+3$'", "class ~ ^dde", "state ~ 'ab.ve'",
+                      "not(name ~ '^DDE')", "all(name ~ \"-\\w+$\", name = dde*)"] {
+            assert!(Filter::parse(rule).unwrap().apply_to(&w), "{}", rule);
+        }
+        for rule in &["name ~ \"^dde-osd$\"", "name ~ '^DDE'", "class ~ ^Dde"] {
+            assert!(!Filter::parse(rule).unwrap().apply_to(&w), "{}", rule);
+        }
+
+        let mut tokens = scan_tokens("name ~ \"^Dde-\\D+\"");
+        assert_eq!(tokens.next(), StrLit("name".to_string()));
+        assert_eq!(tokens.next(), OP(Op::Match));
+        assert_eq!(tokens.next(), StrLit("^Dde-\\D+".to_string()));
+        assert_eq!(Filter::parse("name ~ \"^dde-(osd|dock)$\"").unwrap().rules()[0].source, "name ~ \"^dde-(osd|dock)$\"");
+        assert!(Filter::parse("name ~ '('").is_err());
+        assert!(Filter::parse("geom.x ~ 1").is_err());
+        assert!(explain("name ~ ^dde").unwrap().contains("[regex]"));
+    }
+
+    #[test]
+    fn test_suggest_rule() {
+        let mut w = Window::new(WindowId(0x10));
+        assert_eq!(suggest_rule(&w), "id = 0x10");
+
+        w.name = "report.odt - LibreOffice Writer".to_string();
+        assert_eq!(suggest_rule(&w), "name = \"* - ?ibre?ffice ?riter\"");
+        w.name = "Terminal 12".to_string();
+        assert_eq!(suggest_rule(&w), "name = \"?erminal *\"");
+        w.name = "dde-osd".to_string();
+        assert_eq!(suggest_rule(&w), "name = dde-osd");
+
+        w.class = Some("Dde-osd".to_string());
+        w.window_type = vec!["_NET_WM_WINDOW_TYPE_NORMAL".to_string(), "_NET_WM_WINDOW_TYPE_NOTIFICATION".to_string()];
+        assert_eq!(suggest_rule(&w), "all(class = dde-osd, type = notification)");
+
+        let mut other = w.clone();
+        other.class = Some("xterm".to_string());
+        for name in &["report.odt - LibreOffice Writer", "Terminal 12"] {
+            w.class = None;
+            w.window_type.clear();
+            w.name = name.to_string();
+            let filter = Filter::parse(suggest_rule(&w)).unwrap();
+            assert!(filter.apply_to(&w), "{}", suggest_rule(&w));
+            other.name = "xterm".to_string();
+            assert!(!filter.apply_to(&other));
+        }
+    }
+
+    #[test]
+    fn test_desktop_rule() {
+        let mut w = Window::new(WindowId(0x10));
+        assert!(!Filter::parse("desktop = 2").unwrap().apply_to(&w));
+        w.desktop = Some(2);
+        assert!(Filter::parse("desktop = 2").unwrap().apply_to(&w));
+        assert!(Filter::parse("desktop <> 1").unwrap().apply_to(&w));
+        w.desktop = Some(0xFFFFFFFF);
+        assert!(Filter::parse("desktop = 1").unwrap().apply_to(&w));
+        assert!(Filter::parse("desktop > 1").is_err());
+        assert!(Filter::parse("desktop = -1").is_err());
+    }
+
+    #[test]
+    fn test_filter_clone() {
+        let filter = Filter::parse("name = dde*; not(geom.width > 100)").unwrap();
+        let mut w = Window::new(WindowId(0x10));
+        w.name = "dde-dock".to_string();
+        assert!(filter.apply_to(&w));
+
+        let mut copy = filter.clone();
+        assert_eq!(copy.rules(), filter.rules());
+        copy.add_rule("name = xterm").unwrap();
+        w.geom.width = 200;
+        assert!(!copy.apply_to(&w));
+        assert_eq!(filter.rules().len(), 2);
+        assert_eq!(filter.rules()[0].stats.evaluated, 1, "counts are not shared");
+    }
+
+    #[test]
+    fn test_rules_introspection() {
+        let mut filter = Filter::parse("any(name = 'dde osd', geom.x >= -10); not(attrs.map_state = viewable): pin; clients").unwrap();
+        let rules = filter.rules();
+        assert_eq!(rules.iter().map(|r| r.id).collect::<Vec<_>>(), vec![1, 2, 3]);
+        assert_eq!(rules[0].source, "any(name = \"dde osd\", geom.x >= -10)");
+        assert_eq!(rules[1].source, "not(attrs.map_state = viewable): pin");
+        assert_eq!(rules[1].action, Action::Pin);
+        assert_eq!(rules[2].origin, RuleOrigin::Inline);
+
+        // normalized source parses back to the same rule
+        for r in &rules {
+            assert_eq!(Filter::parse(&r.source).unwrap().rules()[0].source, r.source);
+        }
+
+        assert!(filter.remove_rule(rules[1].handle()));
+        assert!(!filter.remove_rule(rules[1].handle()));
+        assert!(filter.rule(2).is_none());
+        filter.extend_with("name = xterm", super::super::sheets::SheetFormat::Plain).unwrap();
+        assert_eq!(filter.rules().iter().map(|r| r.id).collect::<Vec<_>>(), vec![1, 3, 4]);
+    }
+
+    #[test]
+    fn test_rule_stats() {
+        let filter = Filter::parse("name = dde*; geom.width > 100; name = dde-osd: pin").unwrap();
+        for &(name, width) in &[("dde-dock", 200), ("dde-osd", 50), ("xterm", 300)] {
+            let mut w = Window::new(WindowId(0x10));
+            w.name = name.to_string();
+            w.geom.width = width;
+            filter.apply_to(&w);
+        }
+
+        let stats: Vec<_> = filter.rules().iter().map(|r| (r.stats.evaluated, r.stats.matched)).collect();
+        // xterm is rejected by the first rule, pin rules are only asked
+        // about windows the others reject
+        assert_eq!(stats, vec![(3, 2), (2, 1), (2, 1)]);
+
+        filter.reset_stats();
+        assert_eq!(filter.rules()[0].stats, RuleStats::default());
+    }
+
+    #[test]
+    fn test_add_rule() {
+        let mut filter = Filter::new();
+        let h = filter.add_rule("name = dde*").unwrap();
+        assert_eq!(filter.rule(h.id()).unwrap().source, "name = dde*");
+        assert!(filter.add_rule("name = a; name = b").is_err());
+        assert!(filter.add_rule("geom.x = wide").is_err());
+        assert_eq!(filter.rules().len(), 1);
+
+        assert!(filter.remove_rule(h));
+        assert!(filter.rules().is_empty());
+    }
+
+    #[test]
+    fn test_error_position() {
+        let err = match Filter::parse("name = a;\nany(name = b,\n    geom.x > 1));") {
+            Err(WmError::Dsl(e)) => e,
+            r => panic!("unexpected {:?}", r.map(|_| ())),
+        };
+        assert_eq!((err.line, err.column), (3, 16));
+        assert_eq!(err.snippet, "    geom.x > 1));");
+        assert_eq!(err.kind, DslErrorKind::Unexpected { expected: "';'".to_string(), found: "')'".to_string() });
+        assert!(err.to_string().starts_with("unexpected ')', expecting ';' at line 3, col 16"));
+
+        match Filter::parse("any(name = a") {
+            Err(WmError::Dsl(e)) => assert_eq!((e.line, e.column, e.kind.to_string().as_str()),
+                                               (1, 13, "unexpected end of rule, expecting ',' or ')'")),
+            r => panic!("unexpected {:?}", r.map(|_| ())),
+        }
+
+        match Filter::parse("geom.z > 1") {
+            Err(WmError::Dsl(e)) => assert_eq!((e.column, e.kind), (6, DslErrorKind::UnknownPredicate("geom.z".to_string()))),
+            r => panic!("unexpected {:?}", r.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_explain() {
+        let text = explain("any(name = xterm*, not(attrs.map_state = viewable)); geom.x >1: pin").unwrap();
+        assert!(text.starts_with("tokens:\n  1:1    'any'\n  1:4    '('\n  1:5    'name'\n"), "{}", text);
+        assert!(text.contains("\
+tree:
+  item 1: filter
+    any
+      name = xterm*  [glob on the whole name]
+      not
+        attrs.map_state = viewable  [map state]
+  item 2: pin
+    geom.x > 1  [number]
+normalized:
+  any(name = xterm*, not(attrs.map_state = viewable))
+  geom.x > 1: pin
+"), "{}", text);
+        assert!(explain("any(name = a").is_err());
+    }
+
+    #[test]
+    fn test_explain_dot() {
+        let dot = explain_dot("any(name = \"a b\", not(id = 0x100)): pin; clients").unwrap();
+        assert!(dot.starts_with("digraph rule {\n"));
+        assert!(dot.ends_with("}\n"));
+        for line in &[
+            "  n1 [label=\"item 1: pin\", shape=doubleoctagon];",
+            "  n2 [label=\"any\", shape=ellipse];",
+            "  n1 -> n2;",
+            "  n3 [label=\"name = \\\"a b\\\"\\n[substring of the name]\", shape=box];",
+            "  n2 -> n4;",
+            "  n4 -> n5;",
+            "  n6 [label=\"item 2: filter\", shape=doubleoctagon];",
+            "  n7 [label=\"clients\", shape=box];",
+        ] {
+            assert!(dot.lines().any(|l| l == *line), "{} not in\n{}", line, dot);
+        }
+        assert!(explain_dot("geom.z > 1").is_err());
+    }
 }
@@ -0,0 +1,151 @@
+use std::collections::{HashMap, VecDeque};
+use std::time;
+
+use super::backend::WindowId;
+use super::wm::Geometry;
+
+/// values kept per window by default
+pub const HISTORY_LEN: usize = 16;
+/// geometries kept per window, an animated placement takes a few dozens
+pub const GEOMETRY_HISTORY_LEN: usize = 64;
+/// windows with a history at most, the ones changed least recently are
+/// forgotten first, destroyed windows included
+pub const HISTORY_WINDOWS: usize = 4096;
+
+/// `value` taken by a window at `at`, milliseconds since the epoch
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Entry<T> {
+    pub at: u64,
+    pub value: T,
+}
+
+pub(crate) fn now() -> u64 {
+    time::SystemTime::now().duration_since(time::UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or_default()
+}
+
+/// `HH:MM:SS` in UTC of `at`, milliseconds since the epoch
+pub fn clock(at: u64) -> String {
+    let secs = at / 1000;
+    format!("{:02}:{:02}:{:02}", secs / 3600 % 24, secs / 60 % 60, secs % 60)
+}
+
+/// `HH:MM:SS.mmm` in UTC of `at`, milliseconds since the epoch
+pub fn clock_ms(at: u64) -> String {
+    format!("{}.{:03}", clock(at), at % 1000)
+}
+
+/// one line per geometry of `trail`, oldest first, telling how the window
+/// moved and resized since the previous one
+pub fn describe_trail(trail: &[Entry<Geometry>]) -> Vec<String> {
+    let mut prev: Option<&Entry<Geometry>> = None;
+    trail.iter().map(|e| {
+        let g = e.value;
+        let what = match prev {
+            None => "initial".to_string(),
+            Some(p) => {
+                let (dx, dy) = (g.x as i32 - p.value.x as i32, g.y as i32 - p.value.y as i32);
+                let (dw, dh) = (g.width as i32 - p.value.width as i32, g.height as i32 - p.value.height as i32);
+                let mut what = Vec::new();
+                if (dx, dy) != (0, 0) {
+                    what.push(format!("moved {:+},{:+}", dx, dy));
+                }
+                if (dw, dh) != (0, 0) {
+                    what.push(format!("resized {:+}x{:+}", dw, dh));
+                }
+                what.push(format!("after {}ms", e.at.saturating_sub(p.at)));
+                what.join(", ")
+            },
+        };
+        prev = Some(e);
+        format!("{}  {:<20} {}", clock_ms(e.at), g.to_string(), what)
+    }).collect()
+}
+
+/// Last values of something of windows, oldest first, kept after windows
+/// are gone so one can still tell what a window was.
+#[derive(Debug, Clone)]
+pub struct History<T> {
+    len: usize,
+    windows: HashMap<WindowId, VecDeque<Entry<T>>>,
+}
+
+impl<T: Clone + PartialEq> History<T> {
+    pub fn new(len: usize) -> History<T> {
+        History { len, windows: HashMap::new() }
+    }
+
+    /// remember `value` of window `id` if it differs from the last one,
+    /// true if it did
+    pub fn record(&mut self, id: WindowId, value: &T, at: u64) -> bool {
+        if self.len == 0 || self.last(id) == Some(value) {
+            return false;
+        }
+        if !self.windows.contains_key(&id) && self.windows.len() >= HISTORY_WINDOWS {
+            self.forget_oldest();
+        }
+
+        let entries = self.windows.entry(id).or_default();
+        if entries.len() == self.len {
+            entries.pop_front();
+        }
+        entries.push_back(Entry { at, value: value.clone() });
+        true
+    }
+
+    pub fn last(&self, id: WindowId) -> Option<&T> {
+        self.windows.get(&id).and_then(|entries| entries.back()).map(|e| &e.value)
+    }
+
+    /// values of window `id`, oldest first
+    pub fn get(&self, id: WindowId) -> Vec<Entry<T>> {
+        self.windows.get(&id).map(|entries| entries.iter().cloned().collect()).unwrap_or_default()
+    }
+
+    fn forget_oldest(&mut self) {
+        let oldest = self.windows.iter()
+            .min_by_key(|&(_, entries)| entries.back().map(|e| e.at))
+            .map(|(&id, _)| id);
+        if let Some(id) = oldest {
+            self.windows.remove(&id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_history() {
+        let mut history = History::new(2);
+        let id = WindowId(0x100);
+        assert!(history.record(id, &"a".to_string(), 1));
+        assert!(!history.record(id, &"a".to_string(), 2));
+        assert!(history.record(id, &"b".to_string(), 3));
+        assert!(history.record(id, &"c".to_string(), 4));
+
+        let values: Vec<_> = history.get(id).into_iter().map(|e| (e.at, e.value)).collect();
+        assert_eq!(values, vec![(3, "b".to_string()), (4, "c".to_string())]);
+        assert_eq!(history.last(id).map(String::as_str), Some("c"));
+        assert!(history.get(WindowId(0x200)).is_empty());
+        assert_eq!(clock(((25 * 60 + 2) * 60 + 3) * 1000 + 999), "01:02:03");
+        assert_eq!(clock_ms(3_007), "00:00:03.007");
+
+        for i in 0..HISTORY_WINDOWS as u32 {
+            history.record(WindowId(0x1000 + i), &"x".to_string(), 10 + i as u64);
+        }
+        assert!(history.get(id).is_empty());
+        assert_eq!(history.last(WindowId(0x1000)).map(String::as_str), Some("x"));
+    }
+
+    #[test]
+    fn test_describe_trail() {
+        let entry = |at, x, y, width, height| Entry { at, value: Geometry { x, y, width, height } };
+        let lines = describe_trail(&[entry(1000, 0, 0, 100, 50), entry(1016, 10, -5, 100, 50), entry(1100, 10, -5, 120, 40)]);
+        assert_eq!(lines, vec![
+            "00:00:01.000  100x50+0+0           initial",
+            "00:00:01.016  100x50+10+-5         moved +10,-5, after 16ms",
+            "00:00:01.100  120x40+10+-5         resized +20x-10, after 84ms",
+        ]);
+    }
+}
@@ -0,0 +1,126 @@
+//! Everything known of a single window at once, for `wminspect window`:
+//! its fields, the windows it is nested in, its ICCCM hints and how each
+//! filter rule took it.
+
+extern crate serde_json;
+
+use std::io::{self, Write};
+
+use super::export::write_window;
+use super::filter::{Action, RuleInfo, state_name, window_type_name};
+use super::props::Property;
+use super::wm::{OutputFormat, Window};
+
+/// How a rule took the window.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct RuleResult {
+    pub rule: String,
+    pub action: Action,
+    pub matched: bool,
+}
+
+impl RuleResult {
+    pub fn new(info: &RuleInfo, matched: bool) -> RuleResult {
+        RuleResult { rule: info.source.clone(), action: info.action, matched }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct WindowReport {
+    pub window: Window,
+    /// parent first, up to the root
+    pub parents: Vec<Window>,
+    /// WM_* properties, like WM_HINTS and WM_NORMAL_HINTS
+    pub hints: Vec<Property>,
+    pub rules: Vec<RuleResult>,
+}
+
+impl WindowReport {
+    /// true if the window passes all filter rules
+    pub fn passes(&self) -> bool {
+        self.rules.iter().filter(|r| r.action == Action::FilterOut).all(|r| r.matched)
+    }
+
+    /// true if a pin rule holds for it
+    pub fn pinned(&self) -> bool {
+        self.rules.iter().any(|r| r.action == Action::Pin && r.matched)
+    }
+
+    /// `field value` lines of the window, then its parents, hints and one
+    /// line per rule, `+` for the ones holding and `-` for the others
+    pub fn write_text<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let w = &self.window;
+        let list = |v: Vec<String>| if v.is_empty() { "-".to_string() } else { v.join(", ") };
+        write_window(out, w, OutputFormat::Default)?;
+        writeln!(out, "class              {}", w.class.as_deref().unwrap_or("-"))?;
+        writeln!(out, "pid                {}", w.pid.map(|p| p.to_string()).unwrap_or_else(|| "-".to_string()))?;
+        writeln!(out, "type               {}", list(w.window_type.iter().map(|t| window_type_name(t)).collect()))?;
+        writeln!(out, "state              {}", list(w.state.iter().map(|st| state_name(st)).collect()))?;
+        writeln!(out, "parents            {}", list(self.parents.iter().map(|p| match p.name.as_str() {
+            "" => p.id.to_string(),
+            name => format!("{} \"{}\"", p.id, name),
+        }).collect()))?;
+
+        if !self.hints.is_empty() {
+            writeln!(out, "hints:")?;
+            for p in &self.hints {
+                writeln!(out, "  {}", p)?;
+            }
+        }
+        if !self.rules.is_empty() {
+            writeln!(out, "rules:")?;
+            for r in &self.rules {
+                writeln!(out, "  {} {}", if r.matched { '+' } else { '-' }, r.rule)?;
+            }
+        }
+        let verdict = match (self.passes(), self.pinned()) {
+            (_, true) => "listed, pinned",
+            (true, false) => "listed",
+            (false, false) => "filtered out",
+        };
+        writeln!(out, "verdict            {}", verdict)
+    }
+
+    pub fn write_json<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        serde_json::to_writer_pretty(&mut *out, self)?;
+        writeln!(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::backend::WindowId;
+    use super::super::filter::Filter;
+    use super::super::mock::{window, MockBackend, MOCK_ROOT};
+    use super::super::wm::Context;
+
+    #[test]
+    fn test_window_report() {
+        let mock = MockBackend::new(1920, 1080);
+        let mut client = window(WindowId(0x201), "xterm");
+        client.class = Some("XTerm".to_string());
+        client.state = vec!["_NET_WM_STATE_ABOVE".to_string()];
+        let mut frame = window(WindowId(0x100), "frame");
+        frame.parent = Some(MOCK_ROOT);
+        mock.add_window(frame).add_child(WindowId(0x100), client)
+            .set_property(WindowId(0x201), "WM_HINTS", "WM_HINTS", "Client accepts input or input focus: True")
+            .set_property(WindowId(0x201), "_NET_WM_PID", "CARDINAL", "42");
+        let ctx = Context::builder().filter(Filter::parse("name = x*; class = gimp: pin").unwrap()).build(mock);
+
+        let report = ctx.window_report(WindowId(0x201)).unwrap();
+        assert_eq!(report.parents.iter().map(|p| p.id).collect::<Vec<_>>(), vec![WindowId(0x100), MOCK_ROOT]);
+        assert_eq!(report.hints.len(), 1);
+        assert!(report.passes() && !report.pinned());
+        assert_eq!(ctx.state().rules()[0].stats.evaluated, 0);
+
+        let mut out = Vec::new();
+        report.write_text(&mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.contains("state              above\n"), "{}", text);
+        assert!(text.contains(&format!("parents            0x100 \"frame\", {}\n", MOCK_ROOT)), "{}", text);
+        assert!(text.contains("hints:\n  WM_HINTS(WM_HINTS) = Client accepts input or input focus: True\n"), "{}", text);
+        assert!(text.ends_with("rules:\n  + name = x*\n  - class = gimp: pin\nverdict            listed\n"), "{}", text);
+
+        assert!(ctx.window_report(WindowId(0x999)).is_err());
+    }
+}
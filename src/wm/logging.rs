@@ -0,0 +1,237 @@
+extern crate serde_json;
+
+use std::fmt::Arguments;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::time;
+
+use super::error::*;
+
+/// how large a log file grows before it is rotated
+pub const LOG_ROTATE_SIZE: u64 = 4 << 20;
+
+/// how many rotated log files are kept, as FILE.1 (newest) ... FILE.N
+pub const LOG_KEEP: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+/// names of levels, as taken by `--log-level`
+pub const LOG_LEVELS: &[&str] = &["off", "error", "warn", "info", "debug", "trace"];
+
+impl Level {
+    pub fn name(self) -> &'static str {
+        LOG_LEVELS[self as usize]
+    }
+
+    /// the level of `-v` given `n` times, info, debug and then trace
+    pub fn from_verbosity(n: u64) -> Level {
+        Level::from_u8((Level::Warn as u64 + n).min(Level::Trace as u64) as u8)
+    }
+
+    fn from_u8(n: u8) -> Level {
+        [Level::Off, Level::Error, Level::Warn, Level::Info, Level::Debug, Level::Trace]
+            .get(n as usize).cloned().unwrap_or(Level::Trace)
+    }
+}
+
+impl ::std::str::FromStr for Level {
+    type Err = WmError;
+
+    fn from_str(s: &str) -> WmResult<Level> {
+        LOG_LEVELS.iter().position(|&name| name == s).map(|i| Level::from_u8(i as u8))
+            .ok_or_else(|| WmError::Config(format!("unknown log level {}", s)))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// `1700000000.123 debug src/wm/wm.rs:42: message`
+    Compact,
+    /// a json object per line with `ts`, `level`, `file`, `line` and `msg`
+    Json,
+}
+
+/// names of log formats, as taken by `--log-format`
+pub const LOG_FORMATS: &[&str] = &["compact", "json"];
+
+impl ::std::str::FromStr for LogFormat {
+    type Err = WmError;
+
+    fn from_str(s: &str) -> WmResult<LogFormat> {
+        match s {
+            "compact" => Ok(LogFormat::Compact),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(WmError::Config(format!("unknown log format {}", s))),
+        }
+    }
+}
+
+/// A log file rotated once it grows past `LOG_ROTATE_SIZE`.
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+}
+
+impl RotatingFile {
+    fn open(path: &Path) -> io::Result<RotatingFile> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingFile { path: path.to_path_buf(), file, size })
+    }
+
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        for n in (1..LOG_KEEP).rev() {
+            let _ = fs::rename(self.rotated(n), self.rotated(n + 1));
+        }
+        fs::rename(&self.path, self.rotated(1))?;
+        *self = RotatingFile::open(&self.path)?;
+        Ok(())
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        if self.size > 0 && self.size + line.len() as u64 > LOG_ROTATE_SIZE {
+            self.rotate()?;
+        }
+        self.file.write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+}
+
+struct Logger {
+    format: LogFormat,
+    /// stderr if None
+    file: Option<RotatingFile>,
+}
+
+const DEFAULT_LEVEL: Level = if cfg!(debug_assertions) { Level::Debug } else { Level::Warn };
+
+static LEVEL: AtomicU8 = AtomicU8::new(DEFAULT_LEVEL as u8);
+static LOGGER: Mutex<Logger> = Mutex::new(Logger { format: LogFormat::Compact, file: None });
+
+/// Messages up to `level` are logged, `Debug` by default for debug builds
+/// and `Warn` otherwise.
+pub fn set_level(level: Level) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn level() -> Level {
+    Level::from_u8(LEVEL.load(Ordering::Relaxed))
+}
+
+pub fn enabled(level: Level) -> bool {
+    level != Level::Off && level <= self::level()
+}
+
+pub fn set_format(format: LogFormat) {
+    LOGGER.lock().unwrap().format = format;
+}
+
+/// Log to `path` instead of stderr, keeping the window output on stdout
+/// apart from diagnostics.
+pub fn set_file<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    let file = RotatingFile::open(path.as_ref())?;
+    LOGGER.lock().unwrap().file = Some(file);
+    Ok(())
+}
+
+fn format_line(format: LogFormat, level: Level, file: &str, line: u32, msg: &str) -> String {
+    let ts = time::SystemTime::now().duration_since(time::UNIX_EPOCH).unwrap_or_default();
+    let ts = ts.as_secs() as f64 + f64::from(ts.subsec_millis()) / 1000.0;
+    match format {
+        LogFormat::Compact => format!("{:.3} {} {}:{}: {}\n", ts, level.name(), file, line, msg),
+        LogFormat::Json => {
+            #[derive(Serialize)]
+            struct Entry<'a> { ts: f64, level: &'a str, file: &'a str, line: u32, msg: &'a str }
+            let entry = Entry { ts, level: level.name(), file, line, msg };
+            format!("{}\n", serde_json::to_string(&entry).unwrap_or_default())
+        },
+    }
+}
+
+/// write a message logged at `file:line`, used by `wm_log!` and its
+/// shorthands `wm_info!`, `wm_debug!` and `wm_trace!`
+pub fn log(level: Level, file: &str, line: u32, args: Arguments) {
+    if !enabled(level) {
+        return;
+    }
+
+    let mut logger = LOGGER.lock().unwrap();
+    let text = format_line(logger.format, level, file, line, &args.to_string());
+    let res = match logger.file {
+        Some(ref mut f) => f.write_line(&text),
+        None => {
+            eprint!("{}", text);
+            Ok(())
+        },
+    };
+    if let Err(e) = res {
+        // the log file is gone, fall back to stderr
+        logger.file = None;
+        eprintln!("wminspect: can not write log: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_level() {
+        assert_eq!("warn".parse::<Level>().unwrap(), Level::Warn);
+        assert!("loud".parse::<Level>().is_err());
+        assert_eq!(Level::Trace.name(), "trace");
+        assert!(Level::Error < Level::Debug);
+        assert_eq!(Level::from_verbosity(1), Level::Info);
+        assert_eq!(Level::from_verbosity(2), Level::Debug);
+        assert_eq!(Level::from_verbosity(5), Level::Trace);
+    }
+
+    #[test]
+    fn test_format_line() {
+        let line = format_line(LogFormat::Compact, Level::Info, "src/wm/wm.rs", 42, "hi");
+        assert!(line.ends_with(" info src/wm/wm.rs:42: hi\n"), "{}", line);
+
+        let line = format_line(LogFormat::Json, Level::Warn, "src/wm/wm.rs", 42, "say \"hi\"");
+        let v: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(v["level"], "warn");
+        assert_eq!(v["line"], 42);
+        assert_eq!(v["msg"], "say \"hi\"");
+    }
+
+    #[test]
+    fn test_rotate() {
+        let dir = ::std::env::temp_dir().join(format!("wminspect-log-{}", ::std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("wminspect.log");
+
+        let mut f = RotatingFile::open(&path).unwrap();
+        let line = format!("{}\n", "x".repeat(LOG_ROTATE_SIZE as usize / 2));
+        for _ in 0..(LOG_KEEP + 3) {
+            f.write_line(&line).unwrap();
+        }
+        assert!(path.exists());
+        assert!(f.rotated(LOG_KEEP).exists());
+        assert!(!f.rotated(LOG_KEEP + 1).exists());
+        assert!(fs::metadata(&path).unwrap().len() <= LOG_ROTATE_SIZE);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
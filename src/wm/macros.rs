@@ -10,13 +10,23 @@ macro_rules! hashset {
     ( $( $e:expr, )+ ) => ( hashset!( $($e),+ ) );
 }
 
+/// log at `Level::$level` to stderr or the log file, see `wm::logging`
+macro_rules! wm_log {
+    ( $level:ident, $($a:tt)* ) => (
+        $crate::wm::logging::log($crate::wm::logging::Level::$level, file!(), line!(), format_args!($($a)*))
+    )
+}
+
+macro_rules! wm_info {
+    ( $($a:tt)* ) => ( wm_log!(Info, $($a)*) )
+}
+
 macro_rules! wm_debug {
-    ( $($a:tt)* ) => (
-        if cfg!(debug_assertions) {
-            let (f, l) = (file!(), line!());
-            print!("{}:{}: ", f, l);
-            println!{$($a)*}; 
-        })
+    ( $($a:tt)* ) => ( wm_log!(Debug, $($a)*) )
+}
+
+macro_rules! wm_trace {
+    ( $($a:tt)* ) => ( wm_log!(Trace, $($a)*) )
 }
 
 #[cfg(feature = "core_intrinsics")]
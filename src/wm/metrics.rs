@@ -0,0 +1,240 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as FmtWrite;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[derive(Default)]
+struct MetricsData {
+    windows_by_state: BTreeMap<&'static str, u64>,
+    filtered_windows: u64,
+    pinned_windows: u64,
+    events: BTreeMap<&'static str, u64>,
+    refreshes: u64,
+    last_refresh: Duration,
+}
+
+/// Counters and gauges recorded by `Context`, rendered in prometheus text format.
+#[derive(Default)]
+pub struct Metrics {
+    data: Mutex<MetricsData>,
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics::default()
+    }
+
+    pub fn record_event(&self, kind: &'static str) {
+        *self.data.lock().unwrap().events.entry(kind).or_insert(0) += 1;
+    }
+
+    pub fn record_refresh(&self, elapsed: Duration) {
+        let mut data = self.data.lock().unwrap();
+        data.refreshes += 1;
+        data.last_refresh = elapsed;
+    }
+
+    /// `by_state` holds the number of cached windows per map state
+    pub fn set_window_counts(&self, by_state: BTreeMap<&'static str, u64>, filtered: u64, pinned: u64) {
+        let mut data = self.data.lock().unwrap();
+        data.windows_by_state = by_state;
+        data.filtered_windows = filtered;
+        data.pinned_windows = pinned;
+    }
+
+    pub fn render(&self) -> String {
+        let data = self.data.lock().unwrap();
+        let mut s = String::new();
+
+        macro_rules! metric {
+            ($name:expr, $kind:expr, $help:expr) => (
+                let _ = writeln!(s, "# HELP {} {}\n# TYPE {} {}", $name, $help, $name, $kind);
+            )
+        }
+
+        metric!("wminspect_windows", "gauge", "Number of cached windows by map state.");
+        for (state, n) in &data.windows_by_state {
+            let _ = writeln!(s, "wminspect_windows{{state=\"{}\"}} {}", state, n);
+        }
+
+        metric!("wminspect_filtered_windows", "gauge", "Number of windows passing the filter.");
+        let _ = writeln!(s, "wminspect_filtered_windows {}", data.filtered_windows);
+
+        metric!("wminspect_pinned_windows", "gauge", "Number of windows matched by pin rules.");
+        let _ = writeln!(s, "wminspect_pinned_windows {}", data.pinned_windows);
+
+        metric!("wminspect_events_total", "counter", "Number of handled events by type.");
+        for (kind, n) in &data.events {
+            let _ = writeln!(s, "wminspect_events_total{{type=\"{}\"}} {}", kind, n);
+        }
+
+        metric!("wminspect_refreshes_total", "counter", "Number of full window refreshes.");
+        let _ = writeln!(s, "wminspect_refreshes_total {}", data.refreshes);
+
+        metric!("wminspect_refresh_duration_seconds", "gauge", "Duration of the last full refresh.");
+        let _ = writeln!(s, "wminspect_refresh_duration_seconds {:.6}",
+                         data.last_refresh.as_secs() as f64 + data.last_refresh.subsec_nanos() as f64 / 1e9);
+
+        s
+    }
+}
+
+/// Time spent in each phase of getting windows out, summed over runs and
+/// kept in the order phases first ran, for `--timings`.
+#[derive(Default)]
+pub struct Timings {
+    phases: Mutex<Vec<(&'static str, Duration)>>,
+}
+
+impl Timings {
+    pub fn new() -> Timings {
+        Timings::default()
+    }
+
+    /// run `f`, adding the time it took to `phase`
+    pub fn time<T, F: FnOnce() -> T>(&self, phase: &'static str, f: F) -> T {
+        let start = Instant::now();
+        let res = f();
+        self.add(phase, start.elapsed());
+        res
+    }
+
+    pub fn add(&self, phase: &'static str, elapsed: Duration) {
+        let mut phases = self.phases.lock().unwrap();
+        match phases.iter_mut().find(|&&mut (name, _)| name == phase) {
+            Some(&mut (_, ref mut total)) => *total += elapsed,
+            None => phases.push((phase, elapsed)),
+        }
+    }
+
+    pub fn phases(&self) -> Vec<(&'static str, Duration)> {
+        self.phases.lock().unwrap().clone()
+    }
+
+    pub fn reset(&self) {
+        self.phases.lock().unwrap().clear();
+    }
+}
+
+/// like `query_tree 3ms, properties 180ms, filter 2ms, output 12ms`
+impl ::std::fmt::Display for Timings {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        for (i, (phase, elapsed)) in self.phases().into_iter().enumerate() {
+            let ms = elapsed.as_secs_f64() * 1e3;
+            let sep = if i > 0 { ", " } else { "" };
+            if ms < 10.0 {
+                write!(f, "{}{} {:.1}ms", sep, phase, ms)?;
+            } else {
+                write!(f, "{}{} {:.0}ms", sep, phase, ms)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn handle_client(mut stream: TcpStream, metrics: &Metrics) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+
+    // only the request line matters, read until end of headers
+    let mut req = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !req.windows(4).any(|w| w == b"\r\n\r\n") && req.len() < 8192 {
+        let n = stream.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        req.extend_from_slice(&buf[..n]);
+    }
+
+    let req = String::from_utf8_lossy(&req);
+    let mut parts = req.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", metrics.render()),
+        _ => ("404 Not Found", "not found\n".to_string()),
+    };
+
+    write!(stream, "HTTP/1.0 {}\r\nContent-Type: text/plain; version=0.0.4\r\n\
+           Content-Length: {}\r\nConnection: close\r\n\r\n{}", status, body.len(), body)
+}
+
+/// Serve `metrics` at http://`addr`/metrics from a background thread,
+/// return the address actually bound.
+pub fn serve<A: ToSocketAddrs>(addr: A, metrics: Arc<Metrics>) -> io::Result<SocketAddr> {
+    let listener = TcpListener::bind(addr)?;
+    let local = listener.local_addr()?;
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    if let Err(e) = handle_client(stream, &metrics) {
+                        wm_debug!("metrics client: {}", e);
+                    }
+                },
+                Err(e) => wm_debug!("metrics accept: {}", e),
+            }
+        }
+    });
+
+    Ok(local)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let m = Metrics::new();
+        m.record_event("create");
+        m.record_event("create");
+        m.record_event("map");
+        m.record_refresh(Duration::from_millis(1500));
+        m.set_window_counts(vec![("viewable", 3), ("unmapped", 1)].into_iter().collect(), 2, 1);
+
+        let text = m.render();
+        assert!(text.contains("wminspect_events_total{type=\"create\"} 2\n"));
+        assert!(text.contains("wminspect_events_total{type=\"map\"} 1\n"));
+        assert!(text.contains("wminspect_windows{state=\"viewable\"} 3\n"));
+        assert!(text.contains("wminspect_filtered_windows 2\n"));
+        assert!(text.contains("wminspect_refresh_duration_seconds 1.500000\n"));
+        assert!(text.contains("# TYPE wminspect_refreshes_total counter\n"));
+    }
+
+    #[test]
+    fn test_timings() {
+        let t = Timings::new();
+        t.add("query_tree", Duration::from_micros(2500));
+        t.add("properties", Duration::from_millis(120));
+        t.add("query_tree", Duration::from_micros(500));
+        assert_eq!(t.time("filter", || 42), 42);
+        assert_eq!(t.phases().iter().map(|&(p, _)| p).collect::<Vec<_>>(), vec!["query_tree", "properties", "filter"]);
+        assert!(t.to_string().starts_with("query_tree 3.0ms, properties 120ms, filter 0.0ms"), "{}", t);
+
+        t.reset();
+        assert_eq!(t.to_string(), "");
+    }
+
+    #[test]
+    fn test_serve() {
+        let m = Arc::new(Metrics::new());
+        m.record_event("destroy");
+        let addr = serve("127.0.0.1:0", m).unwrap();
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let mut resp = String::new();
+        stream.read_to_string(&mut resp).unwrap();
+        assert!(resp.starts_with("HTTP/1.0 200 OK\r\n"));
+        assert!(resp.contains("wminspect_events_total{type=\"destroy\"} 1\n"));
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        let mut resp = String::new();
+        stream.read_to_string(&mut resp).unwrap();
+        assert!(resp.starts_with("HTTP/1.0 404"));
+    }
+}
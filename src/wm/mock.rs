@@ -0,0 +1,396 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::os::unix::io::RawFd;
+use std::time::Duration;
+
+use super::wm::*;
+use super::backend::*;
+use super::event::*;
+use super::props::Property;
+
+pub const MOCK_ROOT: WindowId = WindowId(0x1);
+
+/// Window `id` named `name` to feed a `MockBackend` or a `GlobalState`,
+/// 100x100 at 0,0 and unmapped; chain the setters below for the rest.
+pub fn window(id: WindowId, name: &str) -> Window {
+    let mut w = Window::new(id);
+    w.name = name.to_string();
+    w.geom = Geometry { x: 0, y: 0, width: 100, height: 100 };
+    w
+}
+
+/// Setters of test windows, see `window`.
+impl Window {
+    pub fn at(mut self, x: i16, y: i16) -> Window {
+        self.geom.x = x;
+        self.geom.y = y;
+        self
+    }
+
+    pub fn sized(mut self, width: u16, height: u16) -> Window {
+        self.geom.width = width;
+        self.geom.height = height;
+        self
+    }
+
+    pub fn mapped(mut self) -> Window {
+        self.attrs.map_state = MapState::Viewable;
+        self
+    }
+
+    pub fn transient(mut self, of: WindowId) -> Window {
+        self.transient_for = Some(of);
+        self
+    }
+}
+
+struct MockState {
+    screen: (u16, u16),
+    xwayland: bool,
+    windows: HashMap<WindowId, Window>,
+    /// stacking order (bottom -> top)
+    stack: Vec<WindowId>,
+    /// children of windows below root, bottom -> top
+    children: HashMap<WindowId, Vec<WindowId>>,
+    clients: Vec<WindowId>,
+    current_desktop: Option<u32>,
+    /// window `pick_window` returns, as if clicked
+    picked: Option<WindowId>,
+    outputs: Vec<(String, Geometry)>,
+    events: VecDeque<WindowEvent>,
+    /// readable while events are queued, only in live mode
+    wake: Option<WakePipe>,
+    /// outlines drawn by `highlight`, and how many are still shown
+    highlights: Vec<Geometry>,
+    shown: usize,
+    properties: HashMap<WindowId, Vec<Property>>,
+    /// actions asked by `control`, in order
+    controls: Vec<(WindowId, WindowAction)>,
+    /// set once the fake server stopped answering, see `freeze`
+    frozen: Option<Duration>,
+}
+
+/// Scriptable in-memory backend, so `Context` and `monitor` can run without
+/// an X server.
+///
+/// The `create_window`/`configure_window`/... helpers update the fake server
+/// and queue the matching event; `monitor` returns once the script is drained,
+/// unless `set_live` is on.
+/// The handle is cheap to clone, keep one around to feed more events after
+/// giving a clone to `Context`.
+#[derive(Clone)]
+pub struct MockBackend {
+    state: Arc<Mutex<MockState>>,
+}
+
+impl MockBackend {
+    pub fn new(width: u16, height: u16) -> MockBackend {
+        MockBackend {
+            state: Arc::new(Mutex::new(MockState {
+                screen: (width, height),
+                xwayland: false,
+                windows: HashMap::new(),
+                stack: Vec::new(),
+                children: HashMap::new(),
+                clients: Vec::new(),
+                current_desktop: None,
+                picked: None,
+                outputs: Vec::new(),
+                events: VecDeque::new(),
+                wake: None,
+                highlights: Vec::new(),
+                shown: 0,
+                properties: HashMap::new(),
+                controls: Vec::new(),
+                frozen: None,
+            }))
+        }
+    }
+
+    /// put `w` on top of the stack without generating any event
+    pub fn add_window(&self, w: Window) -> &Self {
+        let mut st = self.state.lock().unwrap();
+        st.stack.retain(|&id| id != w.id);
+        st.stack.push(w.id);
+        st.windows.insert(w.id, w);
+        self
+    }
+
+    /// put `w` on top of the children of `parent`, a window below root,
+    /// without generating any event
+    pub fn add_child(&self, parent: WindowId, mut w: Window) -> &Self {
+        w.parent = Some(parent);
+        let mut st = self.state.lock().unwrap();
+        st.children.entry(parent).or_default().push(w.id);
+        st.windows.insert(w.id, w);
+        self
+    }
+
+    pub fn set_xwayland(&self, xwayland: bool) -> &Self {
+        self.state.lock().unwrap().xwayland = xwayland;
+        self
+    }
+
+    pub fn set_clients(&self, clients: &[WindowId]) -> &Self {
+        self.state.lock().unwrap().clients = clients.to_vec();
+        self
+    }
+
+    /// add a RandR output `name` showing `geom` of root
+    pub fn add_output(&self, name: &str, geom: Geometry) -> &Self {
+        self.state.lock().unwrap().outputs.push((name.to_string(), geom));
+        self
+    }
+
+    /// window the user will click for `pick_window`, None to cancel
+    pub fn set_picked(&self, id: Option<WindowId>) -> &Self {
+        self.state.lock().unwrap().picked = id;
+        self
+    }
+
+    /// switch to desktop `n` and queue the property change of root
+    pub fn set_current_desktop(&self, n: u32) -> &Self {
+        self.state.lock().unwrap().current_desktop = Some(n);
+        self.push_event(WindowEvent::PropertyChanged { window: MOCK_ROOT, kind: PropertyKind::CurrentDesktop })
+    }
+
+    /// in live mode the backend provides an event fd, so consumers keep
+    /// waiting for more events instead of stopping once the queue is empty
+    pub fn set_live(&self, live: bool) -> &Self {
+        let mut st = self.state.lock().unwrap();
+        st.wake = if live { Some(WakePipe::new().expect("mock event pipe")) } else { None };
+        if let Some(ref wake) = st.wake {
+            if !st.events.is_empty() {
+                wake.wake();
+            }
+        }
+        self
+    }
+
+    pub fn push_event(&self, ev: WindowEvent) -> &Self {
+        let mut st = self.state.lock().unwrap();
+        st.events.push_back(ev);
+        if let Some(ref wake) = st.wake {
+            wake.wake();
+        }
+        self
+    }
+
+    pub fn create_window(&self, w: Window) -> &Self {
+        let id = w.id;
+        self.add_window(w);
+        self.push_event(WindowEvent::Created { window: id, parent: MOCK_ROOT })
+    }
+
+    pub fn destroy_window(&self, id: WindowId) -> &Self {
+        {
+            let mut st = self.state.lock().unwrap();
+            st.windows.remove(&id);
+            st.stack.retain(|&w| w != id);
+        }
+        self.push_event(WindowEvent::Destroyed { window: id })
+    }
+
+    /// move and restack `id` right above `above` (or to the bottom if WINDOW_NONE)
+    pub fn configure_window(&self, id: WindowId, geom: Geometry, above: WindowId) -> &Self {
+        let override_redirect = {
+            let mut st = self.state.lock().unwrap();
+            st.stack.retain(|&w| w != id);
+            let idx = st.stack.iter().position(|&w| w == above).map(|i| i + 1).unwrap_or(0);
+            st.stack.insert(idx, id);
+            match st.windows.get_mut(&id) {
+                Some(w) => { w.geom = geom; w.attrs.override_redirect },
+                None => false
+            }
+        };
+
+        self.push_event(WindowEvent::Configured {
+            window: id, above_sibling: above, old: None, new: geom, override_redirect
+        })
+    }
+
+    pub fn map_window(&self, id: WindowId) -> &Self {
+        self.set_map_state(id, MapState::Viewable);
+        self.push_event(WindowEvent::Mapped { window: id })
+    }
+
+    pub fn unmap_window(&self, id: WindowId) -> &Self {
+        self.set_map_state(id, MapState::Unmapped);
+        self.push_event(WindowEvent::Unmapped { window: id })
+    }
+
+    pub fn rename_window<S: Into<String>>(&self, id: WindowId, name: S) -> &Self {
+        if let Some(w) = self.state.lock().unwrap().windows.get_mut(&id) {
+            w.name = name.into();
+        }
+        self.push_event(WindowEvent::PropertyChanged { window: id, kind: PropertyKind::Name })
+    }
+
+    /// change the cached property `kind` of `id` with `f`
+    pub fn update_window<F: FnOnce(&mut Window)>(&self, id: WindowId, kind: PropertyKind, f: F) -> &Self {
+        if let Some(w) = self.state.lock().unwrap().windows.get_mut(&id) {
+            f(w);
+        }
+        self.push_event(WindowEvent::PropertyChanged { window: id, kind })
+    }
+
+    /// set property `name` of `id`, replacing the one of the same name
+    pub fn set_property(&self, id: WindowId, name: &str, type_name: &str, value: &str) -> &Self {
+        {
+            let mut st = self.state.lock().unwrap();
+            let props = st.properties.entry(id).or_default();
+            props.retain(|p| p.name != name);
+            props.push(Property { name: name.to_string(), type_name: type_name.to_string(), value: value.to_string() });
+            props.sort_by(|a, b| a.name.cmp(&b.name));
+        }
+        self.push_event(WindowEvent::PropertyChanged { window: id, kind: PropertyKind::Other })
+    }
+
+    /// every geometry outlined so far, and how many outlines are still shown
+    pub fn highlights(&self) -> (Vec<Geometry>, usize) {
+        let st = self.state.lock().unwrap();
+        (st.highlights.clone(), st.shown)
+    }
+
+    /// actions carried out by `control` so far
+    pub fn controls(&self) -> Vec<(WindowId, WindowAction)> {
+        self.state.lock().unwrap().controls.clone()
+    }
+
+    /// stop answering like a frozen server whose connection was given up
+    /// after `timeout`
+    pub fn freeze(&self, timeout: Duration) -> &Self {
+        self.state.lock().unwrap().frozen = Some(timeout);
+        self
+    }
+
+    fn set_map_state(&self, id: WindowId, state: MapState) {
+        if let Some(w) = self.state.lock().unwrap().windows.get_mut(&id) {
+            w.attrs.map_state = state;
+        }
+    }
+}
+
+impl Backend for MockBackend {
+    fn root(&self) -> WindowId {
+        MOCK_ROOT
+    }
+
+    fn is_xwayland(&self) -> bool {
+        self.state.lock().unwrap().xwayland
+    }
+
+    fn screen_size(&self) -> (u16, u16) {
+        self.state.lock().unwrap().screen
+    }
+
+    fn outputs(&self) -> Vec<(String, Geometry)> {
+        self.state.lock().unwrap().outputs.clone()
+    }
+
+    fn query_tree(&self) -> Option<Vec<WindowId>> {
+        let st = self.state.lock().unwrap();
+        if st.frozen.is_some() {
+            return None;
+        }
+        Some(st.stack.clone())
+    }
+
+    fn query_children(&self, id: WindowId) -> Option<Vec<WindowId>> {
+        let st = self.state.lock().unwrap();
+        if !st.windows.contains_key(&id) {
+            return None;
+        }
+        Some(st.children.get(&id).cloned().unwrap_or_default())
+    }
+
+    fn query_windows_with(&self, ids: &[WindowId], names: bool) -> Vec<Window> {
+        let st = self.state.lock().unwrap();
+        ids.iter().map(|id| {
+            let mut w = st.windows.get(id).cloned().unwrap_or_else(|| {
+                let mut w = Window::new(*id);
+                w.valid = false;
+                w
+            });
+            if !names {
+                w.name.clear();
+            }
+            w
+        }).collect()
+    }
+
+    fn window_name(&self, id: WindowId) -> Option<(String, NameSource)> {
+        self.state.lock().unwrap().windows.get(&id)
+            .map(|w| (w.name.clone(), w.name_source.unwrap_or(NameSource::NetWmName)))
+    }
+
+    fn translate_coordinates(&self, id: WindowId, x: i16, y: i16) -> Option<(i16, i16)> {
+        self.state.lock().unwrap().windows.get(&id).map(|w| (w.geom.x + x, w.geom.y + y))
+    }
+
+    fn properties(&self, id: WindowId) -> Vec<Property> {
+        self.state.lock().unwrap().properties.get(&id).cloned().unwrap_or_default()
+    }
+
+    fn client_list(&self) -> Vec<WindowId> {
+        self.state.lock().unwrap().clients.clone()
+    }
+
+    fn current_desktop(&self) -> Option<u32> {
+        self.state.lock().unwrap().current_desktop
+    }
+
+    fn pick_window(&self) -> Option<WindowId> {
+        self.state.lock().unwrap().picked
+    }
+
+    fn control(&self, id: WindowId, action: WindowAction) -> bool {
+        let mut st = self.state.lock().unwrap();
+        if !st.windows.contains_key(&id) {
+            return false;
+        }
+        st.controls.push((id, action));
+        true
+    }
+
+    fn highlight(&self, geom: Geometry, _color: u32) -> Vec<WindowId> {
+        let mut st = self.state.lock().unwrap();
+        st.highlights.push(geom);
+        st.shown += 1;
+        vec![WindowId(0xf1a50000 + st.highlights.len() as u32)]
+    }
+
+    fn unhighlight(&self, overlay: &[WindowId]) {
+        let mut st = self.state.lock().unwrap();
+        st.shown -= overlay.len();
+    }
+
+    fn watch_root(&self) {}
+
+    fn wait_for_event(&self) -> Option<WindowEvent> {
+        self.state.lock().unwrap().events.pop_front()
+    }
+
+    fn poll_event(&self) -> Option<WindowEvent> {
+        let mut st = self.state.lock().unwrap();
+        let ev = st.events.pop_front();
+        if st.events.is_empty() {
+            if let Some(ref wake) = st.wake {
+                wake.drain();
+            }
+        }
+        ev
+    }
+
+    fn event_fd(&self) -> Option<RawFd> {
+        self.state.lock().unwrap().wake.as_ref().map(|wake| wake.read_fd())
+    }
+
+    fn connection_lost(&self) -> bool {
+        self.state.lock().unwrap().frozen.is_some()
+    }
+
+    fn timed_out(&self) -> Option<Duration> {
+        self.state.lock().unwrap().frozen
+    }
+}
@@ -1,9 +1,51 @@
 #[macro_use] pub mod macros;
 #[macro_use] pub mod wm;
+pub mod error;
+pub mod event;
+pub mod export;
 pub mod filter;
+pub mod history;
+pub mod inspect;
+pub mod logging;
+pub mod props;
+mod reload;
 pub mod sheets;
+pub mod theme;
+pub mod snapshot;
+pub mod state;
+mod stack;
+pub mod backend;
+pub mod metrics;
+pub mod ws;
+pub mod daemon;
+pub mod repl;
+pub mod systemd;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "async")]
+pub mod stream;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+#[cfg(feature = "sqlite")]
+pub mod sessions;
+#[cfg(feature = "sqlite")]
+pub mod trace;
+#[cfg(any(test, feature = "testing"))]
+pub mod mock;
 
-pub use self::wm::*;
-pub use self::filter::*;
-pub use self::macros::*;
-pub use self::sheets::*;
+pub use self::wm::{Geometry, MapState, Attributes, Window, NameSource, Options, Desktop, Bell, OutputFormat, OUTPUT_FORMATS, DEBOUNCE, MAX_DEPTH, EventSink,
+                   SPECIAL_PRESETS, DEFAULT_SPECIAL_PRESETS, special_preset,
+                   Context, ContextBuilder, MonitorHandle, spawn_monitor, monitor, watch};
+pub use self::error::{WmError, WmResult, DslError, DslErrorKind};
+pub use self::event::{PropertyKind, WindowEvent};
+pub use self::filter::{RuleId, RuleHandle, RuleOrigin, RuleInfo, RuleStats, Filter, Action, filter_grammar, explain, explain_dot, EventQuery, suggest_rule, window_type_name, state_name};
+pub use self::export::{write_windows, write_window};
+pub use self::inspect::{RuleResult, WindowReport};
+pub use self::props::Property;
+pub use self::sheets::{SheetFormat, append_rule};
+pub use self::theme::{Style, Theme};
+pub use self::snapshot::{Change, DiffReport, ServerInfo, Snapshot, SnapshotDiff};
+pub use self::state::{LayoutChange, GlobalState};
+pub use self::backend::{WindowId, WINDOW_NONE, Backend, XcbBackend, ConnectError, WindowAction, WINDOW_ACTIONS, REQUEST_TIMEOUT};
+#[cfg(any(test, feature = "testing"))]
+pub use self::mock::{MockBackend, MOCK_ROOT};
@@ -0,0 +1,200 @@
+use std::fmt;
+use std::borrow::Cow;
+use std::str;
+
+/// A property of a window decoded for display, like `xprop` shows it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Property {
+    pub name: String,
+    /// name of the property type, e.g. `UTF8_STRING`
+    #[serde(rename = "type")]
+    pub type_name: String,
+    pub value: String,
+}
+
+impl fmt::Display for Property {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}({}) = {}", self.name, self.type_name, self.value)
+    }
+}
+
+/// Items of a property as read from the server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RawValue {
+    /// format 8
+    Bytes(Vec<u8>),
+    /// format 16 or 32
+    Words(Vec<u32>),
+}
+
+/// Render `raw` of a property typed `type_name`, `atom_name` looks up the
+/// names of ATOM items.
+pub fn decode(type_name: &str, raw: &RawValue, atom_name: &dyn Fn(u32) -> String) -> String {
+    match (type_name, raw) {
+        ("STRING", RawValue::Bytes(b)) | ("UTF8_STRING", RawValue::Bytes(b)) |
+        ("COMPOUND_TEXT", RawValue::Bytes(b)) | ("C_STRING", RawValue::Bytes(b)) => {
+            // lists of strings are separated by NUL
+            let b = b.strip_suffix(&[0]).unwrap_or(b);
+            b.split(|&c| c == 0)
+                .map(|s| {
+                    let s = decode_text(type_name, s, None).map_or_else(|| String::from_utf8_lossy(s).into_owned(), |t| t.0);
+                    format!("{:?}", s)
+                })
+                .collect::<Vec<_>>()
+                .join(", ")
+        },
+        ("ATOM", RawValue::Words(w)) => join(w, |&a| atom_name(a)),
+        ("WINDOW", RawValue::Words(w)) | ("PIXMAP", RawValue::Words(w)) |
+        ("DRAWABLE", RawValue::Words(w)) => join(w, |id| format!("0x{:x}", id)),
+        ("CARDINAL", RawValue::Words(w)) => join(w, |n| n.to_string()),
+        ("INTEGER", RawValue::Words(w)) => join(w, |&n| (n as i32).to_string()),
+        (_, RawValue::Words(w)) => join(w, |n| format!("0x{:x}", n)),
+        (_, RawValue::Bytes(b)) => join(b, |n| format!("0x{:02x}", n)),
+    }
+}
+
+/// The first string of a text property typed `type_name`, and false if
+/// some of it could not be decoded and was replaced by U+FFFD; None if it
+/// is not text. `locale` is WM_LOCALE_NAME of the window: in UTF-8 locales
+/// old clients often put UTF-8 in STRING, which is taken as is if valid.
+pub fn decode_text(type_name: &str, bytes: &[u8], locale: Option<&str>) -> Option<(String, bool)> {
+    let b = bytes.split(|&c| c == 0).next().unwrap_or(bytes);
+    match type_name {
+        "UTF8_STRING" => Some(match str::from_utf8(b) {
+            Ok(s) => (s.to_string(), true),
+            Err(_) => (String::from_utf8_lossy(b).into_owned(), false),
+        }),
+        "STRING" => match str::from_utf8(b) {
+            Ok(s) if locale.is_some_and(is_utf8_locale) => Some((s.to_string(), true)),
+            _ => Some((latin1(b), true)),
+        },
+        "COMPOUND_TEXT" => Some(decode_compound_text(b)),
+        _ => None,
+    }
+}
+
+fn is_utf8_locale(locale: &str) -> bool {
+    let codeset = locale.split('.').nth(1).unwrap_or("").split('@').next().unwrap_or("");
+    codeset.eq_ignore_ascii_case("utf-8") || codeset.eq_ignore_ascii_case("utf8")
+}
+
+fn latin1(b: &[u8]) -> String {
+    b.iter().map(|&c| c as char).collect()
+}
+
+/// Charsets of COMPOUND_TEXT known without tables, others are shown as
+/// U+FFFD per char of `width` bytes.
+#[derive(Clone, Copy)]
+enum Charset {
+    Ascii,
+    Latin1,
+    Other { width: usize },
+}
+
+/// COMPOUND_TEXT (ISO 2022) as far as it uses ASCII, Latin-1 and UTF-8
+/// segments, what most clients write; false if anything else was replaced
+fn decode_compound_text(b: &[u8]) -> (String, bool) {
+    let (mut gl, mut gr) = (Charset::Ascii, Charset::Latin1);
+    let mut out = String::new();
+    let mut exact = true;
+    let mut i = 0;
+    while i < b.len() {
+        let c = b[i];
+        if c == 0x1b {
+            let rest = &b[i + 1..];
+            // ESC % G switches to UTF-8 until ESC % @
+            if rest.starts_with(b"%G") {
+                let start = i + 3;
+                let end = b[start..].windows(3).position(|w| w == b"\x1b%@").map_or(b.len(), |p| start + p);
+                let text = String::from_utf8_lossy(&b[start..end]);
+                exact &= matches!(text, Cow::Borrowed(_));
+                out.push_str(&text);
+                i = (end + 3).min(b.len());
+                continue;
+            }
+            let (len, set, left) = match *rest {
+                [b'(', b'B', ..] => (3, Charset::Ascii, true),
+                [b'-', b'A', ..] => (3, Charset::Latin1, false),
+                [b'(', _, ..] => (3, Charset::Other { width: 1 }, true),
+                [b')', _, ..] | [b'-', _, ..] => (3, Charset::Other { width: 1 }, false),
+                [b'$', b'(', _, ..] => (4, Charset::Other { width: 2 }, true),
+                [b'$', b')', _, ..] => (4, Charset::Other { width: 2 }, false),
+                _ => (1, gl, true),
+            };
+            if left { gl = set } else { gr = set }
+            i += len;
+            continue;
+        }
+
+        let set = match c {
+            b'\n' | b'\t' | b' ' => {
+                out.push(c as char);
+                i += 1;
+                continue;
+            },
+            0x21..=0x7e => gl,
+            0xa0..=0xff => gr,
+            // other controls, e.g. CSI of direction changes
+            _ => {
+                i += 1;
+                continue;
+            },
+        };
+        match set {
+            Charset::Ascii | Charset::Latin1 => out.push(c as char),
+            Charset::Other { width } => {
+                out.push('\u{fffd}');
+                exact = false;
+                i += width - 1;
+            },
+        }
+        i += 1;
+    }
+    (out, exact)
+}
+
+fn join<T, F: Fn(&T) -> String>(items: &[T], f: F) -> String {
+    items.iter().map(f).collect::<Vec<_>>().join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        let names = |a: u32| if a == 0x10 { "_NET_WM_STATE_ABOVE".to_string() } else { format!("{}", a) };
+        let bytes = |b: &[u8]| RawValue::Bytes(b.to_vec());
+        let words = |w: &[u32]| RawValue::Words(w.to_vec());
+
+        assert_eq!(decode("UTF8_STRING", &bytes(b"xterm"), &names), "\"xterm\"");
+        assert_eq!(decode("STRING", &bytes(b"xterm\0XTerm\0"), &names), "\"xterm\", \"XTerm\"");
+        assert_eq!(decode("ATOM", &words(&[0x10, 3]), &names), "_NET_WM_STATE_ABOVE, 3");
+        assert_eq!(decode("WINDOW", &words(&[0x3c00003]), &names), "0x3c00003");
+        assert_eq!(decode("CARDINAL", &words(&[0, 4294967295]), &names), "0, 4294967295");
+        assert_eq!(decode("INTEGER", &words(&[4294967295]), &names), "-1");
+        assert_eq!(decode("WM_HINTS", &words(&[1, 0x20]), &names), "0x1, 0x20");
+        assert_eq!(decode("_MOTIF_WM_HINTS", &bytes(&[1, 255]), &names), "0x01, 0xff");
+
+        let p = Property { name: "WM_NAME".to_string(), type_name: "STRING".to_string(), value: "\"x\"".to_string() };
+        assert_eq!(p.to_string(), "WM_NAME(STRING) = \"x\"");
+    }
+
+    #[test]
+    fn test_decode_text() {
+        let text = decode_text;
+        let exact = |s: &str| Some((s.to_string(), true));
+        assert_eq!(text("UTF8_STRING", "xterm ☺\0".as_bytes(), None), exact("xterm ☺"));
+        assert_eq!(text("UTF8_STRING", b"caf\xe9", None), Some(("caf\u{fffd}".to_string(), false)));
+        assert_eq!(text("STRING", b"caf\xe9", None), exact("café"));
+        assert_eq!(text("STRING", "café".as_bytes(), None), exact("cafÃ©"));
+        assert_eq!(text("STRING", "café".as_bytes(), Some("fr_FR.UTF-8")), exact("café"));
+        assert_eq!(text("CARDINAL", b"1", None), None);
+
+        // Latin-1 right half by default, UTF-8 and unknown segments
+        assert_eq!(text("COMPOUND_TEXT", b"caf\xe9", None), exact("café"));
+        let utf8 = [&b"emacs: \x1b%G"[..], "日本".as_bytes(), b"\x1b%@ ok"].concat();
+        assert_eq!(text("COMPOUND_TEXT", &utf8, None), exact("emacs: 日本 ok"));
+        assert_eq!(text("COMPOUND_TEXT", b"\x1b$(BF|K\\\x1b(B!", None), Some(("\u{fffd}\u{fffd}!".to_string(), false)));
+    }
+}
@@ -0,0 +1,131 @@
+//! Sheets the filter was loaded from are watched with inotify while
+//! monitoring, so rules can be edited without a restart.
+
+extern crate libc;
+
+use std::collections::HashMap;
+use std::ffi::{CString, OsStr};
+use std::io;
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::RawFd;
+use std::path::{Path, PathBuf};
+use std::ptr;
+
+/// Directories of the watched sheets, editors replace a file as often as
+/// they write it in place and a watch on the file itself would be lost.
+pub(crate) struct SheetWatch {
+    fd: RawFd,
+    dirs: HashMap<libc::c_int, PathBuf>,
+    sheets: Vec<PathBuf>,
+}
+
+/// `path` split into its directory and name
+fn split(path: &Path) -> Option<(&Path, &OsStr)> {
+    let dir = match path.parent() {
+        Some(dir) if dir.as_os_str().is_empty() => Path::new("."),
+        Some(dir) => dir,
+        None => return None,
+    };
+    path.file_name().map(|name| (dir, name))
+}
+
+impl SheetWatch {
+    pub(crate) fn new(sheets: Vec<PathBuf>) -> io::Result<SheetWatch> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut watch = SheetWatch { fd, dirs: HashMap::new(), sheets };
+
+        let mask = libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO;
+        for sheet in &watch.sheets {
+            let dir = match split(sheet) {
+                Some((dir, _)) => dir,
+                None => continue,
+            };
+            if watch.dirs.values().any(|d| d == dir) {
+                continue;
+            }
+            let cdir = CString::new(dir.as_os_str().as_bytes()).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+            let wd = unsafe { libc::inotify_add_watch(fd, cdir.as_ptr(), mask) };
+            if wd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            watch.dirs.insert(wd, dir.to_path_buf());
+        }
+        Ok(watch)
+    }
+
+    /// readable when a sheet may have changed
+    pub(crate) fn fd(&self) -> RawFd {
+        self.fd
+    }
+
+    /// sheets written or replaced since last call, in the order they were given
+    pub(crate) fn changed(&self) -> Vec<PathBuf> {
+        let mut touched = Vec::new();
+        let mut buf = [0u8; 4096];
+        loop {
+            let n = unsafe { libc::read(self.fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+            if n <= 0 {
+                break;
+            }
+
+            let header = mem::size_of::<libc::inotify_event>();
+            let mut at = 0;
+            while at + header <= n as usize {
+                let ev: libc::inotify_event = unsafe { ptr::read_unaligned(buf[at..].as_ptr() as *const libc::inotify_event) };
+                let name = &buf[at + header..at + header + ev.len as usize];
+                let name = OsStr::from_bytes(name.split(|&b| b == 0).next().unwrap_or(&[]));
+                if let Some(dir) = self.dirs.get(&ev.wd) {
+                    touched.push((dir.clone(), name.to_os_string()));
+                }
+                at += header + ev.len as usize;
+            }
+        }
+
+        self.sheets.iter()
+            .filter(|sheet| split(sheet).is_some_and(|(dir, name)| touched.iter().any(|(d, n)| d == dir && n == name)))
+            .cloned()
+            .collect()
+    }
+}
+
+impl Drop for SheetWatch {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn test_sheet_watch() {
+        let dir = env::temp_dir().join(format!("wminspect-watch-{}", ::std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (a, b) = (dir.join("a.rule"), dir.join("b.rule"));
+        fs::write(&a, "name = a").unwrap();
+        fs::write(&b, "name = b").unwrap();
+
+        let watch = SheetWatch::new(vec![a.clone(), b.clone()]).unwrap();
+        assert_eq!(watch.dirs.len(), 1);
+        assert!(watch.changed().is_empty());
+
+        fs::write(&b, "name = c").unwrap();
+        fs::write(dir.join("other"), "").unwrap();
+        assert_eq!(watch.changed(), vec![b.clone()]);
+
+        // replaced the way editors save
+        fs::write(dir.join("a.rule.tmp"), "name = d").unwrap();
+        fs::rename(dir.join("a.rule.tmp"), &a).unwrap();
+        assert_eq!(watch.changed(), vec![a.clone()]);
+        assert!(watch.changed().is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,169 @@
+//! `wminspect repl`: type rules, see right away which windows pass them
+//! and gather the good ones into a sheet.
+
+use std::fs;
+use std::io::{BufRead, Write};
+
+use super::wm::*;
+use super::error::{WmError, WmResult};
+use super::filter::Filter;
+
+const HELP: &str = "\
+RULE           list windows passing RULE, like name = xterm
+:add [RULE]    append RULE, or the last one tried, to the sheet
+:drop N        remove rule N from the sheet
+:rules         list rules of the sheet
+:sheet         list windows passing the whole sheet
+:write FILE    save the sheet as plain rules, like sheet.rule
+:help          this text
+:quit          leave, as end of input does";
+
+/// rules gathered so far and the last one tried
+#[derive(Debug, Default)]
+struct Session {
+    sheet: Vec<String>,
+    last: Option<String>,
+}
+
+/// Read lines of `input` until `:quit` or its end, answering each on `out`.
+/// Windows are fetched again for every rule tried, so they are up to date.
+pub fn run<R: BufRead, W: Write>(ctx: &Context, input: R, mut out: W) -> WmResult<()> {
+    let mut session = Session::default();
+    let mut lines = input.lines();
+    loop {
+        write!(out, "> ")?;
+        out.flush()?;
+        let line = match lines.next() {
+            Some(line) => line?,
+            None => break,
+        };
+        let line = line.trim();
+        if line == ":quit" || line == ":q" {
+            break;
+        }
+        if let Err(e) = session.eval(ctx, line, &mut out) {
+            writeln!(out, "error: {}", e)?;
+        }
+    }
+    writeln!(out)?;
+    Ok(())
+}
+
+impl Session {
+    fn eval<W: Write>(&mut self, ctx: &Context, line: &str, out: &mut W) -> WmResult<()> {
+        let (cmd, arg) = match line.find(char::is_whitespace) {
+            Some(i) => (&line[..i], line[i..].trim()),
+            None => (line, ""),
+        };
+        match cmd {
+            "" => {},
+            ":help" | ":h" => writeln!(out, "{}", HELP)?,
+            ":add" => {
+                let rule = if arg.is_empty() {
+                    match self.last.clone() {
+                        Some(rule) => rule,
+                        None => return complain(out, "no rule tried yet"),
+                    }
+                } else {
+                    Filter::parse(arg)?;
+                    arg.to_string()
+                };
+                self.sheet.push(rule);
+                writeln!(out, "rule {} added", self.sheet.len())?;
+            },
+            ":drop" => {
+                let n = match arg.parse::<usize>() {
+                    Ok(n) if n >= 1 && n <= self.sheet.len() => n,
+                    _ => return complain(out, format!("no rule {}", arg)),
+                };
+                writeln!(out, "rule {} dropped: {}", n, self.sheet.remove(n - 1))?;
+            },
+            ":rules" => {
+                for (i, rule) in self.sheet.iter().enumerate() {
+                    writeln!(out, "{}: {}", i + 1, rule)?;
+                }
+            },
+            ":sheet" => {
+                let filter = Filter::parse(self.sheet.join("; "))?;
+                list_matching(ctx, &filter, out)?;
+            },
+            ":write" | ":w" => {
+                if arg.is_empty() {
+                    return complain(out, "missing file to write");
+                }
+                fs::write(arg, self.text()).map_err(|e| WmError::Sheet(format!("{}: {}", arg, e)))?;
+                writeln!(out, "{} rules written to {}", self.sheet.len(), arg)?;
+            },
+            _ if cmd.starts_with(':') => {
+                return complain(out, format!("unknown command {}, try :help", cmd));
+            },
+            _ => {
+                let filter = Filter::parse(line)?;
+                self.last = Some(line.to_string());
+                list_matching(ctx, &filter, out)?;
+            },
+        }
+        Ok(())
+    }
+
+    /// the sheet as a .rule file
+    fn text(&self) -> String {
+        self.sheet.join(";\n") + "\n"
+    }
+}
+
+fn complain<W: Write, S: AsRef<str>>(out: &mut W, what: S) -> WmResult<()> {
+    writeln!(out, "error: {}", what.as_ref())?;
+    Ok(())
+}
+
+fn list_matching<W: Write>(ctx: &Context, filter: &Filter, out: &mut W) -> WmResult<()> {
+    ctx.refresh_windows()?;
+    let all = ctx.all_windows();
+    let matched = ctx.shown(all.iter().filter(|w| filter.apply_to(w)).cloned().collect());
+    for w in &matched {
+        writeln!(out, "{}", win2str(w, Some(ctx.theme())))?;
+    }
+    writeln!(out, "{} of {} windows match", matched.len(), all.len())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::io::Cursor;
+
+    use super::*;
+    use wm::backend::WindowId;
+    use wm::mock::MockBackend;
+
+    #[test]
+    fn test_repl() {
+        let mock = MockBackend::new(1920, 1080);
+        let mut w = Window::new(WindowId(0x100));
+        w.name = "xterm".to_string();
+        mock.add_window(w).add_window(Window::new(WindowId(0x200)));
+        let ctx = Context::new(mock, Filter::new());
+
+        let path = env::temp_dir().join(format!("wminspect-repl-{}.rule", ::std::process::id()));
+        let input = format!("name = xterm\n:add\nname = (\n:add id = 0x200: pin\n:frob\n:rules\n:drop 2\n:write {}\n:quit\nname = never\n", path.display());
+        let mut out = Vec::new();
+        run(&ctx, Cursor::new(input), &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        assert!(out.contains("0x100(xterm)"), "{}", out);
+        assert!(out.contains("1 of 2 windows match"));
+        assert!(out.contains("rule 1 added"));
+        assert!(out.contains("rule 2 added"));
+        assert!(out.contains("error: "));
+        assert!(out.contains("unknown command :frob"));
+        assert!(out.contains("2: id = 0x200: pin"));
+        assert!(out.contains("rule 2 dropped"));
+        assert!(!out.contains("never"));
+
+        let sheet = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(sheet, "name = xterm\n");
+        assert!(Filter::parse(sheet).is_ok());
+    }
+}
@@ -0,0 +1,224 @@
+//! Two recorded sessions compared by what happened to the windows of each
+//! app, e.g. the same app under two window managers: windows are told
+//! apart by the rule `suggest_rule` gives for them rather than by id, which
+//! differs from run to run.
+
+extern crate serde_json;
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{self, Write};
+use std::path::Path;
+
+use super::backend::WindowId;
+use super::error::WmResult;
+use super::filter::suggest_rule;
+use super::sqlite::{Database, RecordedEvent, last_session, recorded_events};
+use super::wm::Geometry;
+
+/// configures per window one session has to have more than the other for
+/// it to be told
+pub const CHURN_THRESHOLD: f64 = 2.0;
+
+/// What a session did with the windows of one app.
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct AppBehavior {
+    pub windows: usize,
+    pub maps: usize,
+    pub configures: usize,
+    /// where windows were when first mapped, without duplicates
+    pub mapped_at: Vec<Geometry>,
+}
+
+impl AppBehavior {
+    fn per_window(&self, n: usize) -> f64 {
+        n as f64 / self.windows.max(1) as f64
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Difference {
+    /// the rule telling the app
+    pub app: String,
+    pub what: &'static str,
+    pub a: String,
+    pub b: String,
+}
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct SessionReport {
+    /// apps seen in session A only
+    pub only_a: Vec<String>,
+    pub only_b: Vec<String>,
+    pub differences: Vec<Difference>,
+}
+
+impl SessionReport {
+    pub fn is_empty(&self) -> bool {
+        self.only_a.is_empty() && self.only_b.is_empty() && self.differences.is_empty()
+    }
+
+    /// one line each, `-` for apps of A only, `+` of B only and `~` for
+    /// what they did differently
+    pub fn write_text<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        for app in &self.only_a {
+            writeln!(out, "- {}", app)?;
+        }
+        for app in &self.only_b {
+            writeln!(out, "+ {}", app)?;
+        }
+        for d in &self.differences {
+            writeln!(out, "~ {} {} {} -> {}", d.app, d.what, d.a, d.b)?;
+        }
+        Ok(())
+    }
+
+    pub fn write_json<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        serde_json::to_writer_pretty(&mut *out, self)?;
+        writeln!(out)
+    }
+}
+
+/// behavior of the apps of `events` by their rule; a window goes by the
+/// rule of the first state recorded of it, ones never recorded are left out
+pub fn summarize(events: &[RecordedEvent]) -> BTreeMap<String, AppBehavior> {
+    let mut apps: BTreeMap<String, AppBehavior> = BTreeMap::new();
+    let mut keys: HashMap<(i64, WindowId), String> = HashMap::new();
+    let mut mapped: HashSet<(i64, WindowId)> = HashSet::new();
+
+    for ev in events {
+        let id = (ev.session, ev.window);
+        let key = match (keys.get(&id), ev.state.as_ref()) {
+            (Some(key), _) => key.clone(),
+            (None, Some(w)) => {
+                let key = suggest_rule(w);
+                keys.insert(id, key.clone());
+                apps.entry(key.clone()).or_default().windows += 1;
+                key
+            },
+            (None, None) => continue,
+        };
+
+        let app = apps.get_mut(&key).expect("app of a known window");
+        match ev.kind.as_str() {
+            "map" => {
+                app.maps += 1;
+                if mapped.insert(id) {
+                    if let Some(geom) = ev.state.as_ref().map(|w| w.geom) {
+                        if !app.mapped_at.contains(&geom) {
+                            app.mapped_at.push(geom);
+                        }
+                    }
+                }
+            },
+            "configure" => app.configures += 1,
+            _ => {},
+        }
+    }
+    apps
+}
+
+fn geometries(gs: &[Geometry]) -> String {
+    gs.iter().map(|g| g.to_string()).collect::<Vec<_>>().join(",")
+}
+
+fn same_geometries(a: &[Geometry], b: &[Geometry]) -> bool {
+    a.iter().all(|g| b.contains(g)) && b.iter().all(|g| a.contains(g))
+}
+
+/// what apps of `b` did differently from `a`
+pub fn compare_sessions(a: &[RecordedEvent], b: &[RecordedEvent]) -> SessionReport {
+    let (a, b) = (summarize(a), summarize(b));
+    let mut report = SessionReport {
+        only_a: a.keys().filter(|k| !b.contains_key(*k)).cloned().collect(),
+        only_b: b.keys().filter(|k| !a.contains_key(*k)).cloned().collect(),
+        differences: Vec::new(),
+    };
+
+    for (app, x) in &a {
+        let y = match b.get(app) {
+            Some(y) => y,
+            None => continue,
+        };
+        let mut differ = |what, a: String, b: String| report.differences.push(Difference { app: app.clone(), what, a, b });
+
+        if x.windows != y.windows {
+            differ("windows", x.windows.to_string(), y.windows.to_string());
+        }
+        if !x.mapped_at.is_empty() && !y.mapped_at.is_empty() && !same_geometries(&x.mapped_at, &y.mapped_at) {
+            differ("mapped at", geometries(&x.mapped_at), geometries(&y.mapped_at));
+        }
+        let (ma, mb) = (x.per_window(x.maps), y.per_window(y.maps));
+        if (ma - mb).abs() >= 1.0 {
+            differ("maps per window", format!("{:.1}", ma), format!("{:.1}", mb));
+        }
+        let (ca, cb) = (x.per_window(x.configures), y.per_window(y.configures));
+        if (ca - cb).abs() >= CHURN_THRESHOLD {
+            differ("configures per window", format!("{:.1}", ca), format!("{:.1}", cb));
+        }
+    }
+    report
+}
+
+/// events of `session` recorded in the database at `path`, of the last
+/// one if None
+pub fn load_session<P: AsRef<Path>>(path: P, session: Option<i64>) -> WmResult<Vec<RecordedEvent>> {
+    let db = Database::open_readonly(path)?;
+    match session {
+        Some(session) => recorded_events(&db, Some(session)),
+        None => match last_session(&db)? {
+            Some(session) => recorded_events(&db, Some(session)),
+            None => Ok(Vec::new()),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::wm::Window;
+
+    fn recorded(kind: &str, id: u32, class: &str, geom: (i16, i16)) -> RecordedEvent {
+        let mut w = Window::new(WindowId(id));
+        w.class = Some(class.to_string());
+        w.geom = Geometry { x: geom.0, y: geom.1, width: 800, height: 600 };
+        RecordedEvent { session: 1, time: 0.0, kind: kind.to_string(), window: WindowId(id), data: String::new(), state: Some(w) }
+    }
+
+    #[test]
+    fn test_compare_sessions() {
+        let a = vec![
+            recorded("create", 0x100, "XTerm", (0, 0)),
+            recorded("map", 0x100, "XTerm", (0, 0)),
+            recorded("configure", 0x100, "XTerm", (0, 0)),
+            recorded("map", 0x200, "Gimp", (10, 10)),
+            RecordedEvent { session: 1, time: 0.0, kind: "map".to_string(), window: WindowId(0x300),
+                            data: String::new(), state: None },
+        ];
+        // other ids, xterm placed elsewhere and configured over and over
+        let mut b = vec![
+            recorded("map", 0x500, "XTerm", (20, 30)),
+            recorded("map", 0x600, "Gimp", (10, 10)),
+            recorded("map", 0x700, "Firefox", (0, 0)),
+        ];
+        b.extend((0..4).map(|_| recorded("configure", 0x500, "XTerm", (20, 30))));
+
+        let apps = summarize(&a);
+        assert_eq!(apps.len(), 2);
+        assert_eq!(apps["class = xterm"], AppBehavior {
+            windows: 1, maps: 1, configures: 1, mapped_at: vec![Geometry { x: 0, y: 0, width: 800, height: 600 }],
+        });
+
+        let report = compare_sessions(&a, &b);
+        assert!(report.only_a.is_empty());
+        assert_eq!(report.only_b, vec!["class = firefox".to_string()]);
+        let whats: Vec<(&str, &str)> = report.differences.iter().map(|d| (d.app.as_str(), d.what)).collect();
+        assert_eq!(whats, vec![("class = xterm", "mapped at"), ("class = xterm", "configures per window")]);
+
+        let mut out = Vec::new();
+        report.write_text(&mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(),
+                   "+ class = firefox\n~ class = xterm mapped at 800x600+0+0 -> 800x600+20+30\n\
+                    ~ class = xterm configures per window 1.0 -> 4.0\n");
+        assert!(compare_sessions(&a, &a).is_empty());
+    }
+}
@@ -1,10 +1,11 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::convert::AsRef;
 use std::ffi::OsString;
 use std::os::unix::ffi::OsStrExt;
-use std::fs::{File, create_dir_all};
+use std::fs::{self, File, create_dir_all, read_dir};
 use std::io::Read;
-use super::filter::{scan_tokens, parse_rule, Filter, ActionFuncPair, FilterItem};
+use super::filter::{scan_tokens, parse_rule, Filter, FilterItem, RuleOrigin};
+use super::error::*;
 extern crate serde_json;
 extern crate bincode as bc;
 
@@ -21,136 +22,243 @@ pub enum SheetFormat {
 
 impl Filter {
     /// Extend filter with rules from `data` which can belong to any kind of `SheetFormat`
-    pub fn extend_with<S: AsRef<str>>(&mut self, data: S, format: SheetFormat) -> &mut Self {
+    pub fn extend_with<S: AsRef<str>>(&mut self, data: S, format: SheetFormat) -> WmResult<&mut Self> {
+        self.extend_from(data.as_ref(), format, RuleOrigin::Inline)
+    }
+
+    fn extend_from(&mut self, data: &str, format: SheetFormat, origin: RuleOrigin) -> WmResult<&mut Self> {
         #[inline]
-        fn load_action_pairs<S: AsRef<str>>(rule: S) -> Option<Vec<FilterItem>> {
+        fn load_action_pairs<S: AsRef<str>>(rule: S) -> WmResult<Vec<FilterItem>> {
             let mut tokens = scan_tokens(rule);
             parse_rule(&mut tokens)
         }
 
         #[inline]
-        fn load_bin_form(data: &str) -> Option<Vec<FilterItem>> {
+        fn load_bin_form(data: &str) -> WmResult<Vec<FilterItem>> {
             wm_debug!("load_bin_form");
-            bc::deserialize_from(&mut data.as_bytes()).ok()
+            bc::deserialize_from(&mut data.as_bytes()).map_err(|e| WmError::Sheet(format!("bin: {}", e)))
         }
 
         #[inline]
-        fn load_json_form(data: &str) -> Option<Vec<FilterItem>> {
+        fn load_json_form(data: &str) -> WmResult<Vec<FilterItem>> {
             wm_debug!("load_json_form");
-            serde_json::from_str::<Vec<FilterItem>>(data).ok()
-        }
-        if let Some(items) = match format {
-            SheetFormat::Json => load_json_form(data.as_ref()),
-            SheetFormat::Binary => load_bin_form(data.as_ref()),
-            SheetFormat::Plain => load_action_pairs(data.as_ref()),
-            _ => None
-        } {
-            wm_debug!("extend_with {:?}", items);
-            let mut items = items.into_iter()
-                .map(|item| {
-                     let f = item.rule.gen_closure();
-                     ActionFuncPair { action: item.action, rule: item.rule, func: f }
-                })
-                .collect();
-            self.rules.append(&mut items);
+            serde_json::from_str::<Vec<FilterItem>>(data).map_err(|e| WmError::Sheet(format!("json: {}", e)))
         }
-        self
+
+        let items = match format {
+            SheetFormat::Json => load_json_form(data)?,
+            SheetFormat::Binary => load_bin_form(data)?,
+            SheetFormat::Plain => load_action_pairs(data)?,
+            SheetFormat::Invalid => return Err(WmError::Sheet("invalid sheet format".to_string()))
+        };
+
+        wm_debug!("extend_with {:?}", items);
+        self.extend_items(items, origin)?;
+        Ok(self)
     }
 
     /// Load sheets from disk at `path`
     /// sheet may be in any of three forms: unparsed form with ext .rule,
     /// two serialized forms: .json and .bin
     ///
-    pub fn load_sheet<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
-        if !path.as_ref().exists() {
-            wm_debug!("{:?} does not exists", path.as_ref());
-            return self;
-        }
-
-        let ext: OsString = match path.as_ref().extension() {
-            Some(ext) => OsString::from(ext),
-            None => return self
+    pub fn load_sheet<P: AsRef<Path>>(&mut self, path: P) -> WmResult<&mut Self> {
+        let format = match path.as_ref().extension().map(|ext| ext.as_bytes()) {
+            Some(b"json") => SheetFormat::Json,
+            Some(b"bin") => SheetFormat::Binary,
+            Some(b"rule") => SheetFormat::Plain,
+            _ => return Err(WmError::Sheet(format!("{:?} is not a .json, .bin or .rule file", path.as_ref())))
         };
 
+        let data = read_sheet(path.as_ref())?;
+        self.extend_from(&data, format, RuleOrigin::Sheet(path.as_ref().to_path_buf()))
+    }
 
-        if let Ok(mut f) = File::open(path.as_ref()) {
-            let mut data = String::new();
-            if let Err(_) = f.read_to_string(&mut data) {
-                return self;
-            }
+    /// Load sheet `path` again, its rules taking the place of the ones it
+    /// gave before. Rules are kept as they were if it fails to load.
+    pub fn reload_sheet<P: AsRef<Path>>(&mut self, path: P) -> WmResult<&mut Self> {
+        let origin = RuleOrigin::Sheet(path.as_ref().to_path_buf());
+        let mut fresh = Filter::new();
+        fresh.load_sheet(path.as_ref())?;
 
-            let format = match ext.as_bytes() {
-                b"json" => SheetFormat::Json,
-                b"bin" => SheetFormat::Binary,
-                b"rule" => SheetFormat::Plain,
-                _ => SheetFormat::Invalid
-            };
-            self.extend_with(&data, format);
-        } else {
-            wm_debug!("load sheet from {:?} failed", path.as_ref());
+        let at = self.rules.iter().position(|r| r.origin == origin).unwrap_or(self.rules.len());
+        self.rules.retain(|r| r.origin != origin);
+        // pushed for new ids, then moved where the old ones were
+        let len = self.rules.len();
+        for r in fresh.rules {
+            self.push_rule(r.action, r.rule, r.matcher, r.origin);
         }
+        let rules = self.rules.split_off(len);
+        self.rules.splice(at..at, rules);
+        Ok(self)
+    }
 
-        self
+    /// Load a sheet read from `reader` until its end, e.g. stdin. It is
+    /// taken as json if it starts with `[`, as plain rules otherwise.
+    pub fn load_sheet_from<R: Read>(&mut self, mut reader: R) -> WmResult<&mut Self> {
+        let mut data = String::new();
+        reader.read_to_string(&mut data).map_err(|e| WmError::Sheet(format!("stdin: {}", e)))?;
+        let format = if data.trim_start().starts_with('[') { SheetFormat::Json } else { SheetFormat::Plain };
+        self.extend_from(&data, format, RuleOrigin::Stdin)
     }
 
-    /// Compile rule from disk file into json or bincode format
-    pub fn compile<S: AsRef<Path>, P: AsRef<Path>>(rule: S, out: P) {
-        wm_debug!("compile {:?} to {:?}", rule.as_ref(), out.as_ref());
+    /// Load every .rule, .json and .bin sheet in directory `dir` in order of
+    /// their names, other files are skipped. A broken sheet does not stop the
+    /// others from loading, its error is returned along with its path.
+    pub fn load_sheet_dir<P: AsRef<Path>>(&mut self, dir: P) -> WmResult<Vec<(PathBuf, WmError)>> {
+        let dir = dir.as_ref();
+        let mut paths: Vec<PathBuf> = read_dir(dir)
+            .map_err(|e| WmError::Sheet(format!("{}: {}", dir.display(), e)))?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.is_file())
+            .filter(|path| matches!(path.extension().map(|ext| ext.as_bytes()), Some(b"rule" | b"json" | b"bin")))
+            .collect();
+        paths.sort();
 
-        if !rule.as_ref().exists() {
-            wm_debug!("{:?} does not exists", rule.as_ref());
-            return ;
-        }
+        Ok(paths.into_iter().filter_map(|path| self.load_sheet(&path).err().map(|e| (path, e))).collect())
+    }
 
-        if let Some(d) = out.as_ref().parent() {
-            if !d.exists() && create_dir_all(d).is_err() {
-                return;
-            }
-        }
+    /// Compile rule from disk file into json or bincode format
+    pub fn compile<S: AsRef<Path>, P: AsRef<Path>>(rule: S, out: P) -> WmResult<()> {
+        wm_debug!("compile {:?} to {:?}", rule.as_ref(), out.as_ref());
 
         let ext: OsString = match out.as_ref().extension() {
             Some(ext) => OsString::from(ext),
-            None => return
+            None => return Err(WmError::Sheet("invalid extension".to_string()))
+        };
+
+        let data = read_sheet(rule.as_ref())?;
+
+        let rule = {
+            let mut tokens = scan_tokens(&data);
+            parse_rule(&mut tokens)?
         };
 
-        if let Ok(mut f) = File::open(rule.as_ref()) {
-            let mut data = String::new();
-            if let Err(_) = f.read_to_string(&mut data) {
-                return;
+        if let Some(d) = out.as_ref().parent() {
+            if !d.as_os_str().is_empty() && !d.exists() {
+                create_dir_all(d)?;
             }
+        }
 
-            let rule = {
-                let mut tokens = scan_tokens(&data);
-                parse_rule(&mut tokens)
-            };
-
-            let mut dest = match File::create(out.as_ref()) {
-                Err(e) => {
-                    wm_debug!("create {:?} failed: {}", out.as_ref(), e);
-                    return;
-                }, 
-                Ok(v) => v
-            };
-
-            let result = match (rule, ext.as_bytes()) {
-                (Some(rule), b"json") => {
-                    serde_json::to_writer(&mut dest, &rule)
-                        .map_err(|e| format!("json: {}", e))
-                },
-                (Some(rule), b"bin") => {
-                    bc::serialize_into(&mut dest, &rule)
-                        .map_err(|e| format!("bin: {}", e))
-                },
-                _ => { Err("invalid extension".to_string()) }
-            };
-
-            wm_debug!("compile {}", if result.is_err() {
-                format!("failed: {}", result.err().unwrap())
-            } else {
-                "done".to_string()
-            });
+        match ext.as_bytes() {
+            b"json" => {
+                let mut dest = File::create(out.as_ref())?;
+                serde_json::to_writer(&mut dest, &rule)
+                    .map_err(|e| WmError::Sheet(format!("json: {}", e)))
+            },
+            b"bin" => {
+                let mut dest = File::create(out.as_ref())?;
+                bc::serialize_into(&mut dest, &rule)
+                    .map_err(|e| WmError::Sheet(format!("bin: {}", e)))
+            },
+            _ => Err(WmError::Sheet("invalid extension".to_string()))
         }
     }
 }
 
+/// Add `rule` at the end of .rule sheet `path`, creating it if need be.
+/// The rule is checked first, nothing is written if it does not parse.
+pub fn append_rule<P: AsRef<Path>>(path: P, rule: &str) -> WmResult<()> {
+    let path = path.as_ref();
+    if path.extension().map(|ext| ext.as_bytes()) != Some(b"rule") {
+        return Err(WmError::Sheet(format!("{:?} is not a .rule file", path)));
+    }
+    Filter::parse(rule)?;
+
+    let mut data = if path.exists() { read_sheet(path)? } else { String::new() };
+    data.truncate(data.trim_end().len());
+    if !data.is_empty() && !data.ends_with(';') {
+        data.push(';');
+    }
+    if !data.is_empty() {
+        data.push('\n');
+    }
+    data.push_str(rule);
+    data.push('\n');
+    fs::write(path, data).map_err(|e| WmError::Sheet(format!("{}: {}", path.display(), e)))
+}
+
+fn read_sheet(path: &Path) -> WmResult<String> {
+    let mut data = String::new();
+    File::open(path).and_then(|mut f| f.read_to_string(&mut data))
+        .map_err(|e| WmError::Sheet(format!("{}: {}", path.display(), e)))?;
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn test_load_sheet_dir() {
+        let dir = env::temp_dir().join(format!("wminspect-sheets-{}", ::std::process::id()));
+        fs::create_dir_all(dir.join("nested.rule")).unwrap();
+        fs::write(dir.join("20-osd.rule"), "name = dde-osd: pin").unwrap();
+        fs::write(dir.join("10-dock.rule"), "name = dde-dock").unwrap();
+        fs::write(dir.join("30-broken.json"), "{").unwrap();
+        fs::write(dir.join("README"), "not a sheet").unwrap();
+
+        let mut filter = Filter::new();
+        let errors = filter.load_sheet_dir(&dir).unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, dir.join("30-broken.json"));
+        let sources: Vec<String> = filter.rules().into_iter().map(|r| r.source).collect();
+        assert_eq!(sources.len(), 2);
+        assert!(sources[0].contains("dde-dock"), "{:?}", sources);
 
+        assert!(filter.load_sheet_dir(dir.join("missing")).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_load_sheet_from() {
+        let mut filter = Filter::new();
+        filter.load_sheet_from("name = dde-osd: pin;\nname = dde-dock".as_bytes()).unwrap();
+        let json = serde_json::to_string(&parse_rule(&mut scan_tokens("id = 0x100")).unwrap()).unwrap();
+        filter.load_sheet_from(format!("  {}", json).as_bytes()).unwrap();
+
+        let rules = filter.rules();
+        assert_eq!(rules.len(), 3);
+        assert!(rules.iter().all(|r| r.origin == RuleOrigin::Stdin));
+        assert!(rules[2].source.contains("0x100"), "{:?}", rules[2]);
+        assert!(filter.load_sheet_from("name = ".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_reload_sheet() {
+        let path = env::temp_dir().join(format!("wminspect-reload-{}.rule", ::std::process::id()));
+        fs::write(&path, "name = a; name = b").unwrap();
+        let mut filter = Filter::parse("id = 0x100").unwrap();
+        filter.load_sheet(&path).unwrap();
+        filter.add_rules("id = 0x200").unwrap();
+
+        fs::write(&path, "name = c: pin").unwrap();
+        filter.reload_sheet(&path).unwrap();
+        let sources: Vec<String> = filter.rules().into_iter().map(|r| r.source).collect();
+        assert_eq!(sources, vec!["id = 0x100", "name = c: pin", "id = 0x200"]);
+        let ids: Vec<_> = filter.rules().into_iter().map(|r| r.id).collect();
+        assert!(ids[1] > ids[2], "{:?}", ids);
+
+        fs::write(&path, "name = ").unwrap();
+        assert!(filter.reload_sheet(&path).is_err());
+        assert_eq!(filter.rules().len(), 3);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_append_rule() {
+        let path = env::temp_dir().join(format!("wminspect-append-{}.rule", ::std::process::id()));
+        let _ = fs::remove_file(&path);
+        append_rule(&path, "class = xterm").unwrap();
+        append_rule(&path, "type = dock: pin").unwrap();
+        assert!(append_rule(&path, "class > xterm").is_err());
+        assert!(append_rule(path.with_extension("json"), "class = xterm").is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "class = xterm;\ntype = dock: pin\n");
+
+        let mut filter = Filter::new();
+        filter.load_sheet(&path).unwrap();
+        assert_eq!(filter.rules().len(), 2);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,318 @@
+extern crate serde_json;
+
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use super::wm::*;
+use super::backend::*;
+use super::error::*;
+
+/// Cached windows captured at a point in time, by `Context::snapshot`
+/// or loaded from a json file written by `save`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Snapshot {
+    /// all windows in stacking order (bottom -> top)
+    pub windows: Vec<Window>,
+    /// ids passing the filter in stacking order
+    pub filtered: Vec<WindowId>,
+    /// ids matched by pin rules
+    pub pinned: Vec<WindowId>,
+    #[serde(default)]
+    pub options: Options,
+    /// seconds since the unix epoch, None if not taken by `Context::snapshot`
+    #[serde(default)]
+    pub taken_at: Option<u64>,
+    #[serde(default)]
+    pub server: Option<ServerInfo>,
+}
+
+/// What a snapshot was taken from.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct ServerInfo {
+    /// e.g. `:0`, as given to connect
+    pub display: Option<String>,
+    pub vendor: Option<String>,
+    pub release: Option<u32>,
+    pub screen_size: (u16, u16),
+    pub xwayland: bool,
+}
+
+/// Changes from one `Snapshot` to a later one, ids are in stacking order
+/// of the snapshot they belong to.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SnapshotDiff {
+    pub added: Vec<WindowId>,
+    pub removed: Vec<WindowId>,
+    /// windows kept but restacked relative to the others
+    pub moved: Vec<WindowId>,
+    pub geometry_changed: Vec<WindowId>,
+}
+
+impl SnapshotDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() &&
+            self.moved.is_empty() && self.geometry_changed.is_empty()
+    }
+}
+
+/// An attribute of a window from one snapshot to a later one.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Change<T> {
+    pub id: WindowId,
+    pub old: T,
+    pub new: T,
+}
+
+/// Everything that changed between two snapshots, with the windows and
+/// values involved, for `compare` to report.
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct DiffReport {
+    pub created: Vec<Window>,
+    pub destroyed: Vec<Window>,
+    /// windows kept but restacked relative to the others
+    pub restacked: Vec<WindowId>,
+    pub geometry: Vec<Change<Geometry>>,
+    pub map_state: Vec<Change<MapState>>,
+    pub name: Vec<Change<String>>,
+}
+
+impl DiffReport {
+    pub fn is_empty(&self) -> bool {
+        self.created.is_empty() && self.destroyed.is_empty() && self.restacked.is_empty() &&
+            self.geometry.is_empty() && self.map_state.is_empty() && self.name.is_empty()
+    }
+
+    /// one line per change, `+` for created, `-` for destroyed, `~` for
+    /// changed and `^` for restacked windows
+    pub fn write_text<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        for w in &self.created {
+            writeln!(out, "+ {}", win2str(w, None))?;
+        }
+        for w in &self.destroyed {
+            writeln!(out, "- {}", win2str(w, None))?;
+        }
+        for c in &self.geometry {
+            writeln!(out, "~ {} geometry {} -> {}", c.id, c.old, c.new)?;
+        }
+        for c in &self.map_state {
+            writeln!(out, "~ {} map_state {} -> {}", c.id, c.old, c.new)?;
+        }
+        for c in &self.name {
+            writeln!(out, "~ {} name {:?} -> {:?}", c.id, c.old, c.new)?;
+        }
+        for id in &self.restacked {
+            writeln!(out, "^ {} restacked", id)?;
+        }
+        Ok(())
+    }
+
+    pub fn write_json<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        serde_json::to_writer_pretty(&mut *out, self)?;
+        writeln!(out)
+    }
+}
+
+impl Snapshot {
+    pub fn load<P: AsRef<Path>>(path: P) -> WmResult<Snapshot> {
+        let path = path.as_ref();
+        let f = File::open(path).map_err(|e| WmError::Snapshot(format!("{}: {}", path.display(), e)))?;
+        serde_json::from_reader(BufReader::new(f))
+            .map_err(|e| WmError::Snapshot(format!("{}: {}", path.display(), e)))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> WmResult<()> {
+        let f = File::create(path)?;
+        self.write_to(BufWriter::new(f))
+    }
+
+    /// write it as json, the same as `save`
+    pub fn write_to<W: Write>(&self, mut out: W) -> WmResult<()> {
+        serde_json::to_writer_pretty(&mut out, self).map_err(|e| WmError::Snapshot(e.to_string()))?;
+        writeln!(out)?;
+        Ok(())
+    }
+
+    /// only the windows passing the filter
+    pub fn filtered_only(mut self) -> Snapshot {
+        let filtered: HashSet<WindowId> = self.filtered.iter().cloned().collect();
+        self.windows.retain(|w| filtered.contains(&w.id));
+        self.pinned.retain(|id| filtered.contains(id));
+        self
+    }
+
+    pub fn window(&self, id: WindowId) -> Option<&Window> {
+        self.windows.iter().find(|w| w.id == id)
+    }
+
+    /// what changed from `self` to `later`, in detail
+    pub fn compare(&self, later: &Snapshot) -> DiffReport {
+        let diff = self.diff(later);
+        let mut report = DiffReport {
+            created: diff.added.iter().filter_map(|&id| later.window(id)).cloned().collect(),
+            destroyed: diff.removed.iter().filter_map(|&id| self.window(id)).cloned().collect(),
+            restacked: diff.moved,
+            ..DiffReport::default()
+        };
+
+        for new in &later.windows {
+            let old = match self.window(new.id) {
+                Some(old) => old,
+                None => continue,
+            };
+            let id = new.id;
+            if old.geom != new.geom {
+                report.geometry.push(Change { id, old: old.geom, new: new.geom });
+            }
+            if old.attrs.map_state != new.attrs.map_state {
+                report.map_state.push(Change { id, old: old.attrs.map_state, new: new.attrs.map_state });
+            }
+            if old.name != new.name {
+                report.name.push(Change { id, old: old.name.clone(), new: new.name.clone() });
+            }
+        }
+        report
+    }
+
+    /// what changed from `self` to `later`
+    pub fn diff(&self, later: &Snapshot) -> SnapshotDiff {
+        let before: HashMap<WindowId, &Window> = self.windows.iter().map(|w| (w.id, w)).collect();
+        let after: HashMap<WindowId, &Window> = later.windows.iter().map(|w| (w.id, w)).collect();
+
+        let added = later.windows.iter().map(|w| w.id).filter(|id| !before.contains_key(id)).collect();
+        let removed = self.windows.iter().map(|w| w.id).filter(|id| !after.contains_key(id)).collect();
+
+        let kept_before: Vec<WindowId> = self.windows.iter().map(|w| w.id)
+            .filter(|id| after.contains_key(id)).collect();
+        let kept_after: Vec<WindowId> = later.windows.iter().map(|w| w.id)
+            .filter(|id| before.contains_key(id)).collect();
+
+        // windows out of the longest common stacking order are the ones restacked
+        let unmoved = longest_common_subsequence(&kept_before, &kept_after);
+        let moved = kept_after.iter().cloned().filter(|id| !unmoved.contains(id)).collect();
+
+        let geometry_changed = kept_after.iter().cloned()
+            .filter(|id| before[id].geom != after[id].geom)
+            .collect();
+
+        SnapshotDiff { added, removed, moved, geometry_changed }
+    }
+}
+
+fn longest_common_subsequence(a: &[WindowId], b: &[WindowId]) -> HashSet<WindowId> {
+    // lens[i][j] is the lcs length of a[i..] and b[j..]
+    let mut lens = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lens[i][j] = if a[i] == b[j] {
+                lens[i + 1][j + 1] + 1
+            } else {
+                lens[i + 1][j].max(lens[i][j + 1])
+            };
+        }
+    }
+
+    let mut common = HashSet::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if a[i] == b[j] {
+            common.insert(a[i]);
+            i += 1;
+            j += 1;
+        } else if lens[i + 1][j] >= lens[i][j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    common
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::filter::Filter;
+    use super::super::mock::{window, MockBackend};
+
+    #[test]
+    fn test_diff() {
+        let before = Snapshot {
+            windows: vec![window(WindowId(0x1), ""), window(WindowId(0x2), ""), window(WindowId(0x3), ""), window(WindowId(0x4), "")],
+            ..Snapshot::default()
+        };
+        // 0x1 raised to top, 0x3 moved, 0x4 gone and 0x5 created
+        let after = Snapshot {
+            windows: vec![window(WindowId(0x2), ""), window(WindowId(0x3), "").at(10, 0), window(WindowId(0x5), ""), window(WindowId(0x1), "")],
+            ..Snapshot::default()
+        };
+
+        let diff = before.diff(&after);
+        assert_eq!(diff.added, vec![WindowId(0x5)]);
+        assert_eq!(diff.removed, vec![WindowId(0x4)]);
+        assert_eq!(diff.moved, vec![WindowId(0x1)]);
+        assert_eq!(diff.geometry_changed, vec![WindowId(0x3)]);
+        assert!(before.diff(&before).is_empty());
+    }
+
+    #[test]
+    fn test_compare() {
+        let renamed = window(WindowId(0x2), "after").mapped();
+        let before = Snapshot {
+            windows: vec![window(WindowId(0x1), ""), window(WindowId(0x2), ""), window(WindowId(0x3), "")],
+            ..Snapshot::default()
+        };
+        let after = Snapshot {
+            windows: vec![renamed, window(WindowId(0x1), "").at(5, 0), window(WindowId(0x4), "")],
+            ..Snapshot::default()
+        };
+
+        let report = before.compare(&after);
+        let mut text = Vec::new();
+        report.write_text(&mut text).unwrap();
+        assert_eq!(String::from_utf8(text).unwrap(), "\
+            + 0x4() 100x100+0+0 Unmapped\n\
+            - 0x3() 100x100+0+0 Unmapped\n\
+            ~ 0x1 geometry 100x100+0+0 -> 100x100+5+0\n\
+            ~ 0x2 map_state Unmapped -> Viewable\n\
+            ~ 0x2 name \"\" -> \"after\"\n\
+            ^ 0x1 restacked\n");
+
+        let mut json = Vec::new();
+        report.write_json(&mut json).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(json["geometry"][0]["new"]["x"], 5);
+        assert_eq!(json["created"][0]["id"], before.compare(&after).created[0].id.0);
+        assert!(before.compare(&before).is_empty());
+    }
+
+    #[test]
+    fn test_context_snapshot() {
+        let mock = MockBackend::new(1920, 1080);
+        mock.add_window(window(WindowId(0x100), "")).add_window(window(WindowId(0x200), ""));
+        let ctx = Context::new(mock.clone(), Filter::parse("id = 0x200").unwrap());
+        ctx.refresh_windows().unwrap();
+
+        let snap = ctx.snapshot();
+        assert_eq!(snap.windows.iter().map(|w| w.id).collect::<Vec<_>>(), vec![WindowId(0x100), WindowId(0x200)]);
+        assert_eq!(snap.filtered, vec![WindowId(0x200)]);
+
+        let path = ::std::env::temp_dir().join(format!("wminspect-snapshot-{}.json", ::std::process::id()));
+        snap.save(&path).unwrap();
+        let loaded = Snapshot::load(&path).unwrap();
+        let _ = ::std::fs::remove_file(&path);
+        assert!(snap.diff(&loaded).is_empty());
+        assert_eq!(loaded.filtered, vec![WindowId(0x200)]);
+        assert!(loaded.taken_at.is_some());
+        assert_eq!(loaded.server.as_ref().map(|s| s.screen_size), Some((1920, 1080)));
+
+        let filtered = snap.clone().filtered_only();
+        assert_eq!(filtered.windows.iter().map(|w| w.id).collect::<Vec<_>>(), vec![WindowId(0x200)]);
+
+        mock.configure_window(WindowId(0x100), Geometry { x: 5, y: 5, width: 10, height: 10 }, WindowId(0x200));
+        ctx.refresh_windows().unwrap();
+        let diff = snap.diff(&ctx.snapshot());
+        assert_eq!(diff.moved, vec![WindowId(0x100)]);
+        assert_eq!(diff.geometry_changed, vec![WindowId(0x100)]);
+    }
+}
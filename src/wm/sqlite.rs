@@ -0,0 +1,615 @@
+//! `--log-db`: monitor events and snapshots of windows recorded into a
+//! SQLite database, for long sessions to be looked into with SQL afterwards.
+//!
+//! Every run is a row of `sessions`; `events` has one row per handled event,
+//! `snapshots` and `windows` the filtered windows after each refresh and
+//! every `SNAPSHOT_INTERVAL`, and `geometries` where windows were, from
+//! configure events and snapshots.
+
+extern crate serde_json;
+
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::io::Write;
+use std::os::raw::{c_char, c_int, c_void};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::ptr;
+use std::slice;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use super::wm::*;
+use super::event::*;
+use super::backend::WindowId;
+use super::filter::EventQuery;
+use super::history::clock_ms;
+use super::error::{WmError, WmResult};
+
+#[allow(non_camel_case_types)]
+enum sqlite3 {}
+#[allow(non_camel_case_types)]
+enum sqlite3_stmt {}
+
+const SQLITE_OK: c_int = 0;
+const SQLITE_ROW: c_int = 100;
+const SQLITE_DONE: c_int = 101;
+const SQLITE_INTEGER: c_int = 1;
+const SQLITE_FLOAT: c_int = 2;
+const SQLITE_NULL: c_int = 5;
+const SQLITE_OPEN_READONLY: c_int = 0x1;
+const SQLITE_OPEN_READWRITE: c_int = 0x2;
+const SQLITE_OPEN_CREATE: c_int = 0x4;
+const SQLITE_OPEN_FULLMUTEX: c_int = 0x10000;
+/// bound text is copied by sqlite
+const SQLITE_TRANSIENT: isize = -1;
+
+#[link(name = "sqlite3")]
+extern "C" {
+    fn sqlite3_open_v2(filename: *const c_char, db: *mut *mut sqlite3, flags: c_int, vfs: *const c_char) -> c_int;
+    fn sqlite3_close(db: *mut sqlite3) -> c_int;
+    fn sqlite3_errmsg(db: *mut sqlite3) -> *const c_char;
+    fn sqlite3_exec(db: *mut sqlite3, sql: *const c_char, callback: *const c_void, arg: *mut c_void,
+                    errmsg: *mut *mut c_char) -> c_int;
+    fn sqlite3_prepare_v2(db: *mut sqlite3, sql: *const c_char, n: c_int, stmt: *mut *mut sqlite3_stmt,
+                          tail: *mut *const c_char) -> c_int;
+    fn sqlite3_bind_int64(stmt: *mut sqlite3_stmt, i: c_int, value: i64) -> c_int;
+    fn sqlite3_bind_double(stmt: *mut sqlite3_stmt, i: c_int, value: f64) -> c_int;
+    fn sqlite3_bind_text(stmt: *mut sqlite3_stmt, i: c_int, value: *const c_char, n: c_int, destructor: isize) -> c_int;
+    fn sqlite3_bind_null(stmt: *mut sqlite3_stmt, i: c_int) -> c_int;
+    fn sqlite3_step(stmt: *mut sqlite3_stmt) -> c_int;
+    fn sqlite3_finalize(stmt: *mut sqlite3_stmt) -> c_int;
+    fn sqlite3_column_count(stmt: *mut sqlite3_stmt) -> c_int;
+    fn sqlite3_column_type(stmt: *mut sqlite3_stmt, i: c_int) -> c_int;
+    fn sqlite3_column_int64(stmt: *mut sqlite3_stmt, i: c_int) -> i64;
+    fn sqlite3_column_double(stmt: *mut sqlite3_stmt, i: c_int) -> f64;
+    fn sqlite3_column_text(stmt: *mut sqlite3_stmt, i: c_int) -> *const u8;
+    fn sqlite3_column_bytes(stmt: *mut sqlite3_stmt, i: c_int) -> c_int;
+    fn sqlite3_free(p: *mut c_void);
+    fn sqlite3_last_insert_rowid(db: *mut sqlite3) -> i64;
+}
+
+const SCHEMA: &str = "
+PRAGMA journal_mode = WAL;
+PRAGMA synchronous = NORMAL;
+CREATE TABLE IF NOT EXISTS sessions (
+    id INTEGER PRIMARY KEY,
+    started REAL NOT NULL,
+    display TEXT
+);
+CREATE TABLE IF NOT EXISTS events (
+    id INTEGER PRIMARY KEY,
+    session INTEGER NOT NULL REFERENCES sessions(id),
+    time REAL NOT NULL,
+    type TEXT NOT NULL,
+    window INTEGER NOT NULL,
+    -- the event as --output ndjson prints it
+    data TEXT NOT NULL,
+    -- the window after the event as json, if it passes the filter
+    window_data TEXT
+);
+CREATE TABLE IF NOT EXISTS snapshots (
+    id INTEGER PRIMARY KEY,
+    session INTEGER NOT NULL REFERENCES sessions(id),
+    time REAL NOT NULL,
+    windows INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS windows (
+    snapshot INTEGER NOT NULL REFERENCES snapshots(id),
+    -- bottom to top
+    stack INTEGER NOT NULL,
+    id INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    class TEXT,
+    map_state TEXT NOT NULL,
+    override_redirect INTEGER NOT NULL,
+    iconified INTEGER NOT NULL,
+    desktop INTEGER,
+    data TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS geometries (
+    session INTEGER NOT NULL REFERENCES sessions(id),
+    time REAL NOT NULL,
+    window INTEGER NOT NULL,
+    x INTEGER NOT NULL,
+    y INTEGER NOT NULL,
+    width INTEGER NOT NULL,
+    height INTEGER NOT NULL,
+    -- the configure event, NULL if taken from a snapshot
+    event INTEGER REFERENCES events(id)
+);
+CREATE INDEX IF NOT EXISTS events_window ON events(window);
+CREATE INDEX IF NOT EXISTS geometries_window ON geometries(window);
+";
+
+/// how often the filtered windows are recorded while events come in
+pub const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// a value bound to or read from a statement
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+}
+
+impl Value {
+    pub fn as_i64(&self) -> Option<i64> {
+        match *self {
+            Value::Integer(i) => Some(i),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match *self {
+            Value::Text(ref s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+impl<'a> From<&'a str> for Value {
+    fn from(s: &'a str) -> Value {
+        Value::Text(s.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Value {
+        Value::Text(s)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(i: i64) -> Value {
+        Value::Integer(i)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(f: f64) -> Value {
+        Value::Real(f)
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(v: Option<T>) -> Value {
+        v.map_or(Value::Null, Into::into)
+    }
+}
+
+/// Connection to a SQLite database, serialized by sqlite itself.
+pub struct Database {
+    raw: *mut sqlite3,
+}
+
+// opened with SQLITE_OPEN_FULLMUTEX
+unsafe impl Send for Database {}
+
+/// a prepared statement, finalized when dropped
+struct Statement<'a> {
+    db: &'a Database,
+    raw: *mut sqlite3_stmt,
+}
+
+impl Database {
+    /// open the database at `path`, created with the schema if missing
+    pub fn open<P: AsRef<Path>>(path: P) -> WmResult<Database> {
+        let db = Database::open_with(path.as_ref(), SQLITE_OPEN_READWRITE | SQLITE_OPEN_CREATE)?;
+        db.execute(SCHEMA)?;
+        Ok(db)
+    }
+
+    /// open an existing database without writing to it
+    pub fn open_readonly<P: AsRef<Path>>(path: P) -> WmResult<Database> {
+        Database::open_with(path.as_ref(), SQLITE_OPEN_READONLY)
+    }
+
+    fn open_with(path: &Path, flags: c_int) -> WmResult<Database> {
+        let cpath = CString::new(path.as_os_str().as_bytes())
+            .map_err(|_| WmError::Database(format!("{}: bad path", path.display())))?;
+        let mut raw = ptr::null_mut();
+        let rc = unsafe { sqlite3_open_v2(cpath.as_ptr(), &mut raw, flags | SQLITE_OPEN_FULLMUTEX, ptr::null()) };
+        let db = Database { raw };
+        if rc != SQLITE_OK {
+            return Err(WmError::Database(format!("{}: {}", path.display(), db.errmsg())));
+        }
+        Ok(db)
+    }
+
+    fn errmsg(&self) -> String {
+        if self.raw.is_null() {
+            return "out of memory".to_string();
+        }
+        unsafe { CStr::from_ptr(sqlite3_errmsg(self.raw)) }.to_string_lossy().into_owned()
+    }
+
+    fn error(&self) -> WmError {
+        WmError::Database(self.errmsg())
+    }
+
+    /// run `sql`, which may be several statements without parameters
+    pub fn execute(&self, sql: &str) -> WmResult<()> {
+        let csql = CString::new(sql).map_err(|_| WmError::Database("nul in statement".to_string()))?;
+        let mut errmsg = ptr::null_mut();
+        let rc = unsafe { sqlite3_exec(self.raw, csql.as_ptr(), ptr::null(), ptr::null_mut(), &mut errmsg) };
+        if rc == SQLITE_OK {
+            return Ok(());
+        }
+        if errmsg.is_null() {
+            return Err(self.error());
+        }
+        let msg = unsafe { CStr::from_ptr(errmsg) }.to_string_lossy().into_owned();
+        unsafe { sqlite3_free(errmsg as *mut c_void) };
+        Err(WmError::Database(msg))
+    }
+
+    fn prepare(&self, sql: &str, params: &[Value]) -> WmResult<Statement<'_>> {
+        let csql = CString::new(sql).map_err(|_| WmError::Database("nul in statement".to_string()))?;
+        let mut raw = ptr::null_mut();
+        if unsafe { sqlite3_prepare_v2(self.raw, csql.as_ptr(), -1, &mut raw, ptr::null_mut()) } != SQLITE_OK {
+            return Err(self.error());
+        }
+        let stmt = Statement { db: self, raw };
+        for (i, param) in params.iter().enumerate() {
+            let i = i as c_int + 1;
+            let rc = unsafe {
+                match *param {
+                    Value::Null => sqlite3_bind_null(raw, i),
+                    Value::Integer(v) => sqlite3_bind_int64(raw, i, v),
+                    Value::Real(v) => sqlite3_bind_double(raw, i, v),
+                    Value::Text(ref s) => sqlite3_bind_text(raw, i, s.as_ptr() as *const c_char, s.len() as c_int, SQLITE_TRANSIENT),
+                }
+            };
+            if rc != SQLITE_OK {
+                return Err(self.error());
+            }
+        }
+        Ok(stmt)
+    }
+
+    /// run one statement with `params` bound to `?`s, the rowid it inserted
+    pub fn insert(&self, sql: &str, params: &[Value]) -> WmResult<i64> {
+        let stmt = self.prepare(sql, params)?;
+        while stmt.step()? {}
+        Ok(unsafe { sqlite3_last_insert_rowid(self.raw) })
+    }
+
+    /// rows of a query with `params` bound to `?`s
+    pub fn query(&self, sql: &str, params: &[Value]) -> WmResult<Vec<Vec<Value>>> {
+        let stmt = self.prepare(sql, params)?;
+        let mut rows = Vec::new();
+        while stmt.step()? {
+            rows.push(stmt.row());
+        }
+        Ok(rows)
+    }
+}
+
+impl Drop for Database {
+    fn drop(&mut self) {
+        unsafe { sqlite3_close(self.raw) };
+    }
+}
+
+impl<'a> Statement<'a> {
+    /// true if a row is ready
+    fn step(&self) -> WmResult<bool> {
+        match unsafe { sqlite3_step(self.raw) } {
+            SQLITE_ROW => Ok(true),
+            SQLITE_DONE => Ok(false),
+            _ => Err(self.db.error()),
+        }
+    }
+
+    fn row(&self) -> Vec<Value> {
+        let n = unsafe { sqlite3_column_count(self.raw) };
+        (0..n).map(|i| unsafe {
+            match sqlite3_column_type(self.raw, i) {
+                SQLITE_NULL => Value::Null,
+                SQLITE_INTEGER => Value::Integer(sqlite3_column_int64(self.raw, i)),
+                SQLITE_FLOAT => Value::Real(sqlite3_column_double(self.raw, i)),
+                // text, and blobs read as text
+                _ => {
+                    let text = sqlite3_column_text(self.raw, i);
+                    let len = sqlite3_column_bytes(self.raw, i) as usize;
+                    if text.is_null() {
+                        Value::Text(String::new())
+                    } else {
+                        Value::Text(String::from_utf8_lossy(slice::from_raw_parts(text, len)).into_owned())
+                    }
+                },
+            }
+        }).collect()
+    }
+}
+
+impl<'a> Drop for Statement<'a> {
+    fn drop(&mut self) {
+        unsafe { sqlite3_finalize(self.raw) };
+    }
+}
+
+fn unix_time() -> f64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs_f64()).unwrap_or(0.0)
+}
+
+fn geometry_params(session: i64, time: f64, w: WindowId, geom: &Geometry, event: Option<i64>) -> Vec<Value> {
+    vec![session.into(), time.into(), (w.0 as i64).into(), (geom.x as i64).into(), (geom.y as i64).into(),
+         (geom.width as i64).into(), (geom.height as i64).into(), event.into()]
+}
+
+const INSERT_GEOMETRY: &str = "INSERT INTO geometries (session, time, window, x, y, width, height, event) \
+                               VALUES (?, ?, ?, ?, ?, ?, ?, ?)";
+
+/// Records monitor events into a `Database`, one session per sink.
+pub struct SqliteSink {
+    db: Mutex<Database>,
+    session: i64,
+    interval: Duration,
+    last_snapshot: Mutex<Option<Instant>>,
+}
+
+impl SqliteSink {
+    /// record into the database at `path`, created if missing, as a new
+    /// session of `display`
+    pub fn open<P: AsRef<Path>>(path: P, display: Option<&str>) -> WmResult<SqliteSink> {
+        let db = Database::open(path)?;
+        let session = db.insert("INSERT INTO sessions (started, display) VALUES (?, ?)",
+                                &[unix_time().into(), display.into()])?;
+        Ok(SqliteSink { db: Mutex::new(db), session, interval: SNAPSHOT_INTERVAL, last_snapshot: Mutex::new(None) })
+    }
+
+    /// snapshot windows that often instead of `SNAPSHOT_INTERVAL`
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// id of the session recorded, the rowid in `sessions`
+    pub fn session(&self) -> i64 {
+        self.session
+    }
+
+    fn record_event(&self, db: &Database, time: f64, ev: &WindowEvent, windows: &[Window]) -> WmResult<()> {
+        let window_data = windows.iter().find(|w| w.id == ev.window())
+            .and_then(|w| serde_json::to_string(w).ok());
+        let data = serde_json::to_string(ev).unwrap_or_default();
+        let id = db.insert("INSERT INTO events (session, time, type, window, data, window_data) VALUES (?, ?, ?, ?, ?, ?)",
+                           &[self.session.into(), time.into(), ev.name().into(), (ev.window().0 as i64).into(),
+                             data.into(), window_data.into()])?;
+        if let WindowEvent::Configured { window, ref new, .. } = *ev {
+            db.insert(INSERT_GEOMETRY, &geometry_params(self.session, time, window, new, Some(id)))?;
+        }
+        Ok(())
+    }
+
+    fn record_snapshot(&self, db: &Database, time: f64, windows: &[Window]) -> WmResult<()> {
+        db.execute("BEGIN")?;
+        let res = self.insert_snapshot(db, time, windows);
+        db.execute(if res.is_ok() { "COMMIT" } else { "ROLLBACK" })?;
+        res
+    }
+
+    fn insert_snapshot(&self, db: &Database, time: f64, windows: &[Window]) -> WmResult<()> {
+        let snapshot = db.insert("INSERT INTO snapshots (session, time, windows) VALUES (?, ?, ?)",
+                                 &[self.session.into(), time.into(), (windows.len() as i64).into()])?;
+        for (stack, w) in windows.iter().enumerate() {
+            db.insert("INSERT INTO windows (snapshot, stack, id, name, class, map_state, override_redirect, \
+                       iconified, desktop, data) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+                      &[snapshot.into(), (stack as i64).into(), (w.id.0 as i64).into(), w.name.as_str().into(),
+                        w.class.clone().into(), format!("{:?}", w.attrs.map_state).into(),
+                        (w.attrs.override_redirect as i64).into(), (w.attrs.iconified as i64).into(),
+                        w.desktop.map(|d| d as i64).into(), serde_json::to_string(w).unwrap_or_default().into()])?;
+            db.insert(INSERT_GEOMETRY, &geometry_params(self.session, time, w.id, &w.geom, None))?;
+        }
+        Ok(())
+    }
+}
+
+impl EventSink for SqliteSink {
+    fn publish(&self, ev: Option<&WindowEvent>, windows: &[Window]) {
+        let db = self.db.lock().unwrap();
+        let time = unix_time();
+        if let Some(ev) = ev {
+            if let Err(e) = self.record_event(&db, time, ev, windows) {
+                wm_debug!("log db: {}", e);
+            }
+        }
+
+        let mut last = self.last_snapshot.lock().unwrap();
+        if ev.is_none() || last.is_none_or(|at| at.elapsed() >= self.interval) {
+            match self.record_snapshot(&db, time, windows) {
+                Ok(()) => *last = Some(Instant::now()),
+                Err(e) => wm_debug!("log db: {}", e),
+            }
+        }
+    }
+}
+
+/// An event read back from a recording.
+#[derive(Debug, Clone)]
+pub struct RecordedEvent {
+    pub session: i64,
+    /// seconds since the epoch
+    pub time: f64,
+    /// like `configure`, as `WindowEvent::name`
+    pub kind: String,
+    pub window: WindowId,
+    /// the event as json
+    pub data: String,
+    /// the window after the event as last recorded, by the event itself or
+    /// an earlier event or snapshot; None if it never passed the filter
+    pub state: Option<Window>,
+}
+
+/// events of `session`, or of all sessions, in the order they were recorded
+pub fn recorded_events(db: &Database, session: Option<i64>) -> WmResult<Vec<RecordedEvent>> {
+    let session = Value::from(session);
+    let events = db.query("SELECT session, time, type, window, data, window_data FROM events \
+                           WHERE ?1 IS NULL OR session = ?1 ORDER BY id", slice::from_ref(&session))?;
+    let snapshots = db.query("SELECT s.session, s.time, w.data FROM windows w JOIN snapshots s ON w.snapshot = s.id \
+                              WHERE ?1 IS NULL OR s.session = ?1 ORDER BY s.id, w.stack", &[session])?;
+
+    let real = |v: &Value| match *v {
+        Value::Real(f) => f,
+        Value::Integer(i) => i as f64,
+        _ => 0.0,
+    };
+    let window = |v: &Value| v.as_str().and_then(|s| serde_json::from_str::<Window>(s).ok());
+
+    // last known windows by session and id, snapshots taken up to each event
+    // applied before it
+    let mut known: HashMap<(i64, WindowId), Window> = HashMap::new();
+    let mut snapshots = snapshots.iter().peekable();
+    let mut recorded = Vec::with_capacity(events.len());
+    for row in &events {
+        let (session, time) = (row[0].as_i64().unwrap_or(0), real(&row[1]));
+        while let Some(snap) = snapshots.next_if(|snap| real(&snap[1]) <= time) {
+            if let Some(w) = window(&snap[2]) {
+                known.insert((snap[0].as_i64().unwrap_or(0), w.id), w);
+            }
+        }
+
+        let id = WindowId(row[3].as_i64().unwrap_or(0) as u32);
+        if let Some(w) = window(&row[5]) {
+            known.insert((session, id), w);
+        }
+        recorded.push(RecordedEvent {
+            session,
+            time,
+            kind: row[2].as_str().unwrap_or_default().to_string(),
+            window: id,
+            data: row[4].as_str().unwrap_or_default().to_string(),
+            state: known.get(&(session, id)).cloned(),
+        });
+    }
+    Ok(recorded)
+}
+
+/// events recorded in the database at `path` of `session`, or of all,
+/// selected by `query`; windows never seen are matched with only their id
+pub fn select_events<P: AsRef<Path>>(path: P, query: Option<&EventQuery>, session: Option<i64>) -> WmResult<Vec<RecordedEvent>> {
+    let db = Database::open_readonly(path)?;
+    let mut events = recorded_events(&db, session)?;
+    if let Some(query) = query {
+        events.retain(|ev| match ev.state {
+            Some(ref w) => query.matches(w, &ev.kind),
+            None => query.matches(&Window::new(ev.window), &ev.kind),
+        });
+    }
+    Ok(events)
+}
+
+/// id of the session recorded last in `db`, None if there is none
+pub fn last_session(db: &Database) -> WmResult<Option<i64>> {
+    let rows = db.query("SELECT max(id) FROM sessions", &[])?;
+    Ok(rows.first().and_then(|row| row[0].as_i64()))
+}
+
+/// print recorded events selected by `query`, all of them if None, one
+/// line each like `12:00:01.234 map 0x3c00003(xterm) ...`; a line telling
+/// the session comes before its events unless `session` is given. Returns
+/// how many are printed.
+pub fn replay<P: AsRef<Path>, W: Write>(path: P, query: Option<&EventQuery>, session: Option<i64>, out: &mut W) -> WmResult<usize> {
+    let mut current = None;
+    let mut printed = 0;
+    for ev in select_events(path, query, session)? {
+        if session.is_none() && current != Some(ev.session) {
+            writeln!(out, "session {}", ev.session)?;
+            current = Some(ev.session);
+        }
+        let line = match ev.state {
+            Some(ref w) => win2str(w, None),
+            None => ev.window.to_string(),
+        };
+        writeln!(out, "{} {} {}", clock_ms((ev.time * 1000.0) as u64), ev.kind, line)?;
+        printed += 1;
+    }
+    Ok(printed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn test_database() {
+        let path = env::temp_dir().join(format!("wminspect-db-{}.sqlite", ::std::process::id()));
+        let _ = fs::remove_file(&path);
+        let db = Database::open(&path).unwrap();
+        db.execute("CREATE TABLE t (a INTEGER, b TEXT, c REAL)").unwrap();
+        assert_eq!(db.insert("INSERT INTO t VALUES (?, ?, ?)", &[1i64.into(), "it's".into(), 0.5.into()]).unwrap(), 1);
+        assert_eq!(db.insert("INSERT INTO t VALUES (?, ?, ?)", &[2i64.into(), Value::Null, Value::Null]).unwrap(), 2);
+        let rows = db.query("SELECT a, b, c FROM t WHERE a >= ? ORDER BY a", &[1i64.into()]).unwrap();
+        assert_eq!(rows, vec![vec![Value::Integer(1), Value::Text("it's".to_string()), Value::Real(0.5)],
+                              vec![Value::Integer(2), Value::Null, Value::Null]]);
+        assert!(db.query("SELECT nope FROM t", &[]).is_err());
+        drop(db);
+
+        assert!(Database::open_readonly(&path).unwrap().execute("DELETE FROM t").is_err());
+        assert!(Database::open_readonly(path.with_extension("missing")).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_sqlite_sink() {
+        let path = env::temp_dir().join(format!("wminspect-sink-{}.sqlite", ::std::process::id()));
+        let _ = fs::remove_file(&path);
+        let sink = SqliteSink::open(&path, Some(":0")).unwrap().with_interval(Duration::from_secs(3600));
+        let mut w = Window::new(WindowId(0x100));
+        w.name = "xterm".to_string();
+        w.geom = Geometry { x: 1, y: 2, width: 30, height: 40 };
+
+        sink.publish(None, &[w.clone()]);
+        sink.publish(Some(&WindowEvent::Mapped { window: WindowId(0x100) }), &[w.clone()]);
+        let new = Geometry { x: 5, y: 6, width: 30, height: 40 };
+        sink.publish(Some(&WindowEvent::Configured {
+            window: WindowId(0x100), above_sibling: WindowId(0), old: Some(w.geom), new, override_redirect: false,
+        }), &[w.clone()]);
+        sink.publish(Some(&WindowEvent::Destroyed { window: WindowId(0x100) }), &[]);
+        drop(sink);
+
+        let db = Database::open_readonly(&path).unwrap();
+        let events = db.query("SELECT type, window, window_data IS NOT NULL FROM events ORDER BY id", &[]).unwrap();
+        assert_eq!(events.iter().map(|r| r[0].as_str().unwrap()).collect::<Vec<_>>(), vec!["map", "configure", "destroy"]);
+        assert_eq!(events[0][1], Value::Integer(0x100));
+        assert_eq!(events[0][2], Value::Integer(1));
+        assert_eq!(events[2][2], Value::Integer(0));
+
+        // one snapshot, the interval is not over yet
+        let windows = db.query("SELECT name, map_state FROM windows", &[]).unwrap();
+        assert_eq!(windows, vec![vec![Value::from("xterm"), Value::from("Unmapped")]]);
+        let geoms = db.query("SELECT x, event IS NULL FROM geometries ORDER BY time, x", &[]).unwrap();
+        assert_eq!(geoms, vec![vec![Value::Integer(1), Value::Integer(1)], vec![Value::Integer(5), Value::Integer(0)]]);
+        let sessions = db.query("SELECT display FROM sessions", &[]).unwrap();
+        assert_eq!(sessions, vec![vec![Value::from(":0")]]);
+
+        // the destroyed window is known from before
+        let events = recorded_events(&db, Some(1)).unwrap();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[2].state.as_ref().map(|w| w.name.as_str()), Some("xterm"));
+        assert!(recorded_events(&db, Some(2)).unwrap().is_empty());
+        drop(db);
+
+        let query = EventQuery::parse("all(name = xterm, not(event = map))").unwrap();
+        let mut out = Vec::new();
+        assert_eq!(replay(&path, Some(&query), None, &mut out).unwrap(), 2);
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 3, "{}", out);
+        assert_eq!(lines[0], "session 1");
+        assert!(lines[1].contains(" configure 0x100(xterm)"), "{}", out);
+        assert!(lines[2].contains(" destroy 0x100(xterm)"), "{}", out);
+        let mut out = Vec::new();
+        assert_eq!(replay(&path, None, Some(1), &mut out).unwrap(), 3);
+        assert!(!String::from_utf8(out).unwrap().contains("session"));
+        for ext in &["sqlite", "sqlite-wal", "sqlite-shm"] {
+            let _ = fs::remove_file(path.with_extension(ext));
+        }
+    }
+}
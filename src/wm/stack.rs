@@ -0,0 +1,230 @@
+use std::collections::{btree_map, BTreeMap, HashMap};
+use std::fmt;
+use std::iter::FromIterator;
+
+use super::backend::WindowId;
+
+/// room left between labels of neighbours, so that most inserts find a free
+/// label in between
+const LABEL_GAP: u64 = 1 << 20;
+
+/// label of the first window after relabeling, leaving room below for
+/// windows lowered to the bottom
+const LABEL_BASE: u64 = 1 << 62;
+
+/// Window ids in stacking order (bottom -> top) that can be looked up,
+/// removed and restacked without scanning the whole stack.
+///
+/// Each id holds an order label, spaced by `LABEL_GAP`, and an id moved in
+/// between two others takes the label halfway; only when there is no label
+/// left in between are all of them spaced out again.
+#[derive(Clone, Default)]
+pub(crate) struct StackView {
+    order: BTreeMap<u64, WindowId>,
+    labels: HashMap<WindowId, u64>,
+    /// bumped whenever the order changes
+    generation: u64,
+}
+
+impl StackView {
+    pub(crate) fn new() -> StackView {
+        StackView::default()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    pub(crate) fn contains(&self, id: WindowId) -> bool {
+        self.labels.contains_key(&id)
+    }
+
+    /// ids from bottom to top
+    pub(crate) fn iter(&self) -> btree_map::Values<'_, u64, WindowId> {
+        self.order.values()
+    }
+
+    pub(crate) fn to_vec(&self) -> Vec<WindowId> {
+        self.iter().cloned().collect()
+    }
+
+    /// changes whenever the order does, to tell if anything moved without
+    /// comparing whole stacks
+    pub(crate) fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// ids below `id` from the nearest one down, none if `id` is unknown
+    pub(crate) fn below(&self, id: WindowId) -> impl Iterator<Item = WindowId> + '_ {
+        let label = self.labels.get(&id).cloned().unwrap_or(0);
+        self.order.range(..label).rev().map(|(_, &id)| id)
+    }
+
+    /// true if `id` is below `other`, false if either is unknown
+    pub(crate) fn is_below(&self, id: WindowId, other: WindowId) -> bool {
+        match (self.labels.get(&id), self.labels.get(&other)) {
+            (Some(a), Some(b)) => a < b,
+            _ => false,
+        }
+    }
+
+    pub(crate) fn remove(&mut self, id: WindowId) -> bool {
+        match self.labels.remove(&id) {
+            Some(label) => {
+                self.order.remove(&label);
+                self.generation += 1;
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// put `id` on top, it is moved if already there
+    pub(crate) fn push(&mut self, id: WindowId) {
+        let top = self.order.iter().next_back().map(|(_, &top)| top);
+        if top == Some(id) {
+            return;
+        }
+        match top {
+            Some(top) => { self.place(id, Some(top)); },
+            None => { self.place(id, None); },
+        }
+    }
+
+    /// put `id` right above `below`, or at the bottom if None; false and
+    /// nothing changed if `below` is unknown
+    pub(crate) fn place(&mut self, id: WindowId, below: Option<WindowId>) -> bool {
+        if below == Some(id) {
+            return false;
+        }
+        let floor = match below {
+            Some(below) => match self.labels.get(&below) {
+                Some(&label) => Some(label),
+                None => return false,
+            },
+            None => None,
+        };
+        if self.below(id).next() == below && self.contains(id) {
+            return true;
+        }
+
+        self.remove(id);
+        let label = match self.free_label(floor) {
+            Some(label) => label,
+            None => {
+                self.relabel();
+                let floor = below.map(|below| self.labels[&below]);
+                self.free_label(floor).expect("room after relabeling")
+            },
+        };
+        self.order.insert(label, id);
+        self.labels.insert(id, label);
+        self.generation += 1;
+        true
+    }
+
+    /// an unused label right above `floor`, or at the bottom if None
+    fn free_label(&self, floor: Option<u64>) -> Option<u64> {
+        let ceil = match floor {
+            Some(floor) => self.order.range(floor + 1..).next().map(|(&l, _)| l),
+            None => self.order.keys().next().cloned(),
+        };
+        match (floor, ceil) {
+            (Some(floor), Some(ceil)) if ceil - floor >= 2 => Some(floor + (ceil - floor) / 2),
+            (Some(floor), None) => floor.checked_add(LABEL_GAP),
+            (None, Some(ceil)) => ceil.checked_sub(LABEL_GAP),
+            (None, None) => Some(LABEL_BASE),
+            _ => None,
+        }
+    }
+
+    fn relabel(&mut self) {
+        let ids = self.to_vec();
+        self.order.clear();
+        for (i, id) in ids.into_iter().enumerate() {
+            let label = LABEL_BASE + i as u64 * LABEL_GAP;
+            self.order.insert(label, id);
+            self.labels.insert(id, label);
+        }
+    }
+}
+
+impl FromIterator<WindowId> for StackView {
+    fn from_iter<I: IntoIterator<Item = WindowId>>(ids: I) -> StackView {
+        let mut view = StackView::new();
+        for id in ids {
+            view.push(id);
+        }
+        view
+    }
+}
+
+/// same ids in the same order
+impl PartialEq for StackView {
+    fn eq(&self, other: &StackView) -> bool {
+        self.iter().eq(other.iter())
+    }
+}
+
+impl fmt::Debug for StackView {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(view: &StackView) -> Vec<u32> {
+        view.iter().map(|id| id.0).collect()
+    }
+
+    #[test]
+    fn test_place() {
+        let mut view: StackView = (1..5).map(WindowId).collect();
+        assert_eq!(ids(&view), vec![1, 2, 3, 4]);
+        assert!(view.contains(WindowId(3)));
+
+        assert!(view.place(WindowId(1), Some(WindowId(3))));
+        assert_eq!(ids(&view), vec![2, 3, 1, 4]);
+        assert!(view.place(WindowId(4), None));
+        assert_eq!(ids(&view), vec![4, 2, 3, 1]);
+        assert!(!view.place(WindowId(2), Some(WindowId(9))));
+        view.push(WindowId(2));
+        assert_eq!(ids(&view), vec![4, 3, 1, 2]);
+        assert_eq!(view.below(WindowId(1)).collect::<Vec<_>>(), vec![WindowId(3), WindowId(4)]);
+
+        assert!(view.remove(WindowId(3)));
+        assert!(!view.remove(WindowId(3)));
+        assert_eq!(ids(&view), vec![4, 1, 2]);
+        assert_eq!(view.len(), 3);
+    }
+
+    #[test]
+    fn test_generation() {
+        let mut view: StackView = (1..4).map(WindowId).collect();
+        let gen = view.generation();
+        assert!(view.place(WindowId(2), Some(WindowId(1))));
+        view.push(WindowId(3));
+        assert_eq!(view.generation(), gen, "nothing moved");
+
+        view.push(WindowId(1));
+        assert_ne!(view.generation(), gen);
+    }
+
+    #[test]
+    fn test_relabel() {
+        // keep squeezing windows right above the bottom one until labels
+        // run out in between
+        let mut view: StackView = vec![WindowId(0), WindowId(1)].into_iter().collect();
+        for i in 2..100 {
+            assert!(view.place(WindowId(i), Some(WindowId(0))));
+        }
+        let mut expected = vec![0];
+        expected.extend((2..100).rev());
+        expected.push(1);
+        assert_eq!(ids(&view), expected);
+        assert_eq!(view, expected.into_iter().map(WindowId).collect());
+    }
+}
@@ -0,0 +1,860 @@
+use std::collections::{HashMap, HashSet};
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::path::Path;
+
+use super::wm::*;
+use super::filter::*;
+use super::backend::*;
+use super::snapshot::Snapshot;
+use super::error::*;
+use super::stack::StackView;
+use super::history::{self, Entry, History, HISTORY_LEN, GEOMETRY_HISTORY_LEN};
+
+pub(crate) type WindowStackView = StackView;
+pub(crate) type WindowListView = HashSet<WindowId>;
+
+/// contains cached windows data, which should keep in sync with server
+#[derive(Clone)]
+pub(crate) struct WindowsLayout {
+    /// collected window infos
+    pub(crate) windows: HashMap<WindowId, Window>,
+    /// a view maintained by stacking order (bottom -> top)
+    pub(crate) stack_view: WindowStackView,
+
+    pub(crate) filtered_view: WindowStackView,
+    pub(crate) pinned_windows: WindowListView,
+}
+
+impl WindowsLayout {
+    pub(crate) fn filtered_windows(&self) -> Vec<Window> {
+        self.filtered_view.iter()
+            .filter_map(|wid| self.windows.get(wid).cloned())
+            .collect()
+    }
+
+    pub(crate) fn all_windows(&self) -> Vec<Window> {
+        self.stack_view.iter()
+            .filter_map(|wid| self.windows.get(wid).cloned())
+            .collect()
+    }
+
+    /// filtered windows each followed by those below it in the hierarchy,
+    /// siblings in stacking order, with how many filtered ancestors they
+    /// have; windows of a filtered out parent go below the nearest filtered
+    /// ancestor
+    pub(crate) fn filtered_tree(&self) -> Vec<(WindowId, usize)> {
+        let parent_of = |id: WindowId| self.windows.get(&id).and_then(|w| w.parent);
+        let mut children: HashMap<Option<WindowId>, Vec<WindowId>> = HashMap::new();
+        for &wid in self.filtered_view.iter() {
+            let mut parent = parent_of(wid);
+            // bounded in case parents of cached windows went stale
+            for _ in 0..self.windows.len() {
+                match parent {
+                    Some(p) if !self.filtered_view.contains(p) => parent = parent_of(p),
+                    _ => break,
+                }
+            }
+            children.entry(parent.filter(|&p| p != wid)).or_default().push(wid);
+        }
+
+        let mut tree = Vec::with_capacity(self.filtered_view.len());
+        let mut pending: Vec<(WindowId, usize)> = children.get(&None).into_iter().flatten()
+            .rev().map(|&wid| (wid, 0)).collect();
+        while let Some((wid, depth)) = pending.pop() {
+            tree.push((wid, depth));
+            if tree.len() > self.filtered_view.len() {
+                break;
+            }
+            pending.extend(children.get(&Some(wid)).into_iter().flatten().rev().map(|&child| (child, depth + 1)));
+        }
+        tree
+    }
+
+    /// put filtered `wid` right above the nearest filtered window below it
+    /// in the stack
+    fn place_filtered(&mut self, wid: WindowId) {
+        let below = self.stack_view.below(wid).find(|&id| id != wid && self.filtered_view.contains(id));
+        self.filtered_view.place(wid, below);
+    }
+
+    /// re-apply `filter` to `wid` alone after it changed, instead of to all
+    /// windows, keeping the filtered view in stacking order
+    fn refilter_window(&mut self, wid: WindowId, filter: &Filter) {
+        let (passes, pinned) = match self.windows.get(&wid) {
+            Some(w) => (filter.apply_to(w), w.is_window_pinned(filter)),
+            None => return,
+        };
+
+        if pinned {
+            self.pinned_windows.insert(wid);
+        } else {
+            self.pinned_windows.remove(&wid);
+        }
+
+        if passes && !self.filtered_view.contains(wid) {
+            self.place_filtered(wid);
+        } else if !passes {
+            self.filtered_view.remove(wid);
+        }
+    }
+
+    /// recompute the visible area of windows from the stack, windows
+    /// covered only by mapped, opaque siblings above them: of all of them
+    /// if `from` is None, else of `from` and its siblings below it; return
+    /// those whose area changed
+    fn update_visibility(&mut self, from: Option<WindowId>) -> Vec<WindowId> {
+        let parent = from.map(|wid| self.windows.get(&wid).and_then(|w| w.parent));
+        let mut covering: HashMap<Option<WindowId>, Vec<Rect>> = HashMap::new();
+        let mut changed = Vec::new();
+        let mut reached = from.is_none();
+        let top_down: Vec<WindowId> = self.stack_view.iter().rev().cloned().collect();
+        for wid in top_down {
+            reached = reached || Some(wid) == from;
+            let w = match self.windows.get_mut(&wid) {
+                Some(w) if parent.is_none_or(|p| w.parent == p) => w,
+                _ => continue,
+            };
+            let rect = Rect::of(w);
+            let above = covering.entry(w.parent).or_default();
+            if reached {
+                let visible = if w.attrs.map_state == MapState::Viewable { rect.uncovered_area(above) } else { 0 };
+                if w.visible_area != Some(visible) {
+                    w.visible_area = Some(visible);
+                    changed.push(wid);
+                }
+            }
+            if covers(w) {
+                above.push(rect);
+            }
+        }
+        changed
+    }
+
+    /// `update_visibility` from `from` and re-apply `filter` to the windows
+    /// it changed
+    fn revisit(&mut self, from: Option<WindowId>, filter: &Filter) {
+        for wid in self.update_visibility(from) {
+            self.refilter_window(wid, filter);
+        }
+    }
+
+    /// nearest window below `wid` in the stack of the same parent
+    fn sibling_below(&self, wid: WindowId) -> Option<WindowId> {
+        let parent = self.windows.get(&wid)?.parent;
+        self.stack_view.below(wid).find(|id| self.windows.get(id).is_some_and(|w| w.parent == parent))
+    }
+
+    /// where to revisit from after `wid` moved in the stack from right above
+    /// `old_below`: windows it left are below the higher of the two
+    fn highest_moved(&self, wid: WindowId, old_below: Option<WindowId>) -> WindowId {
+        match old_below {
+            Some(below) if self.stack_view.is_below(wid, below) => below,
+            _ => wid,
+        }
+    }
+
+    fn update_stack(&mut self, wid: WindowId, above: WindowId) {
+        //wm_debug!("update_stack {} {}", wid, above);
+        if !self.windows.contains_key(&wid) {
+            return;
+        }
+
+        let below = if above == WINDOW_NONE { None } else { Some(above) };
+        if !self.stack_view.place(wid, below) {
+            wm_debug!("update_stack: unknown sibling {}", above);
+            self.stack_view.push(wid);
+        }
+
+        if self.filtered_view.contains(wid) {
+            wm_trace!("update_stack {} {}", wid, above);
+            self.place_filtered(wid);
+        }
+    }
+}
+
+/// true if `w` hides what is below it
+fn covers(w: &Window) -> bool {
+    w.attrs.map_state == MapState::Viewable && !w.attrs.input_only && w.opacity.is_none_or(|o| o == u32::MAX)
+}
+
+/// all of `w` its visible area and that of its siblings depend on
+fn footprint(w: &Window) -> (Rect, MapState, bool, Option<WindowId>) {
+    (Rect::of(w), w.attrs.map_state, covers(w), w.parent)
+}
+
+/// Half-open box `[x0, x1) x [y0, y1)` a window covers, border included.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Rect {
+    x0: i32,
+    y0: i32,
+    x1: i32,
+    y1: i32,
+}
+
+impl Rect {
+    fn of(w: &Window) -> Rect {
+        let border = 2 * w.border_width as i32;
+        let (x0, y0) = (w.geom.x as i32, w.geom.y as i32);
+        Rect { x0, y0, x1: x0 + w.geom.width as i32 + border, y1: y0 + w.geom.height as i32 + border }
+    }
+
+    fn area(&self) -> u64 {
+        (self.x1 - self.x0).max(0) as u64 * (self.y1 - self.y0).max(0) as u64
+    }
+
+    fn intersects(&self, r: &Rect) -> bool {
+        self.x0 < r.x1 && r.x0 < self.x1 && self.y0 < r.y1 && r.y0 < self.y1
+    }
+
+    /// what is left of it with `r` cut out, up to four boxes
+    fn subtract(&self, r: &Rect, out: &mut Vec<Rect>) {
+        if !self.intersects(r) {
+            return out.push(*self);
+        }
+        let (y0, y1) = (self.y0.max(r.y0), self.y1.min(r.y1));
+        let pieces = [
+            Rect { y1: r.y0, ..*self },
+            Rect { y0: r.y1, ..*self },
+            Rect { y0, y1, x1: r.x0, ..*self },
+            Rect { y0, y1, x0: r.x1, ..*self },
+        ];
+        out.extend(pieces.iter().filter(|p| p.area() > 0));
+    }
+
+    /// area of it none of `covering` is over
+    fn uncovered_area(&self, covering: &[Rect]) -> u64 {
+        let mut left = vec![*self];
+        for r in covering.iter().filter(|r| self.intersects(r)) {
+            let mut next = Vec::with_capacity(left.len());
+            for piece in &left {
+                piece.subtract(r, &mut next);
+            }
+            left = next;
+            if left.is_empty() {
+                break;
+            }
+        }
+        left.iter().map(Rect::area).sum()
+    }
+}
+
+/// What changed in `GlobalState`, see `GlobalState::subscribe`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LayoutChange {
+    /// all windows were re-collected
+    Reset,
+    Added(WindowId),
+    Removed(WindowId),
+    /// properties or geometry of the window changed
+    Updated(WindowId),
+    /// the window moved in the stack
+    Restacked(WindowId),
+    /// windows passing the filter or their order changed
+    FilteredView,
+}
+
+/// write access to the current layout, copied first if shared with readers
+struct LayoutWriter<'a>(MutexGuard<'a, Arc<WindowsLayout>>);
+
+impl<'a> Deref for LayoutWriter<'a> {
+    type Target = WindowsLayout;
+
+    fn deref(&self) -> &WindowsLayout {
+        &self.0
+    }
+}
+
+impl<'a> DerefMut for LayoutWriter<'a> {
+    fn deref_mut(&mut self) -> &mut WindowsLayout {
+        Arc::make_mut(&mut self.0)
+    }
+}
+
+/// The window cache engine: cached windows kept in stacking order, the filter
+/// and the views it derives, updated incrementally event by event.
+///
+/// It knows nothing about the display server, `Context` feeds it.
+pub struct GlobalState {
+    filter: Mutex<Filter>,
+    options: Mutex<Options>,
+    clients_pending_update: Mutex<bool>,
+
+    //TODO: move into inner struct as one, and save two extra locks
+    /// readers clone the Arc and let go of the lock at once, writers copy
+    /// the layout only if a reader still holds the previous one
+    inner: Mutex<Arc<WindowsLayout>>,
+
+    subscribers: Mutex<Vec<Sender<LayoutChange>>>,
+    /// last names of windows, recorded as they change
+    titles: Mutex<History<String>>,
+    /// last geometries of windows, moves and resizes included
+    geometries: Mutex<History<Geometry>>,
+}
+
+impl GlobalState {
+    pub fn new(f: Filter) -> GlobalState {
+        GlobalState {
+            filter: Mutex::new(f),
+            options: Mutex::new(Options::empty()),
+            clients_pending_update: Mutex::new(false),
+            inner: Mutex::new(Arc::new(
+                WindowsLayout {
+                    windows:  HashMap::new(),
+                    stack_view: WindowStackView::new(),
+
+                    filtered_view: WindowStackView::new(),
+                    pinned_windows: WindowListView::new(),
+                })),
+            subscribers: Mutex::new(Vec::new()),
+            titles: Mutex::new(History::new(HISTORY_LEN)),
+            geometries: Mutex::new(History::new(GEOMETRY_HISTORY_LEN)),
+        }
+    }
+
+    pub fn options(&self) -> Options {
+        *self.options.lock().unwrap()
+    }
+
+    /// the owner (e.g. `Context`) is in charge of making them effective
+    pub fn set_options(&self, options: Options) {
+        *self.options.lock().unwrap() = options;
+    }
+
+    /// rules of the filter, see `Filter::rules`
+    pub fn rules(&self) -> Vec<RuleInfo> {
+        self.filter.lock().unwrap().rules()
+    }
+
+    /// add a rule to the filter and re-apply it, see `Filter::add_rule`
+    pub fn add_rule<S: AsRef<str>>(&self, text: S) -> WmResult<RuleHandle> {
+        let handle = self.filter.lock().unwrap().add_rule(text)?;
+        self.refilter();
+        Ok(handle)
+    }
+
+    /// remove a rule from the filter and re-apply it, return false if there
+    /// is no such rule
+    pub fn remove_rule(&self, handle: RuleHandle) -> bool {
+        let removed = self.filter.lock().unwrap().remove_rule(handle);
+        if removed {
+            self.refilter();
+        }
+        removed
+    }
+
+    /// receive every change from now on, drop the receiver to unsubscribe
+    pub fn subscribe(&self) -> Receiver<LayoutChange> {
+        let (tx, rx) = channel();
+        self.subscribers.lock().unwrap().push(tx);
+        rx
+    }
+
+    fn notify(&self, changes: &[LayoutChange]) {
+        if changes.is_empty() {
+            return;
+        }
+
+        let mut subscribers = self.subscribers.lock().unwrap();
+        subscribers.retain(|tx| changes.iter().all(|&c| tx.send(c).is_ok()));
+    }
+
+    /// `change` and `FilteredView` if the filtered view changed since it was
+    /// at generation `old`
+    fn notify_with_view(&self, change: LayoutChange, old: u64, layout: &WindowsLayout) {
+        if layout.filtered_view.generation() != old {
+            self.notify(&[change, LayoutChange::FilteredView]);
+        } else {
+            self.notify(&[change]);
+        }
+    }
+
+    /// snapshot of cached windows, never in the way of updates however long
+    /// it is kept
+    pub(crate) fn read_layout(&self) -> Arc<WindowsLayout> {
+        self.inner.lock().unwrap().clone()
+    }
+
+    /// lock cached windows for update, do not call any locking operations
+    /// meanwhile
+    fn write_layout(&self) -> LayoutWriter<'_> {
+        LayoutWriter(self.inner.lock().unwrap())
+    }
+
+    /// lock filter, must not be held when calling other methods
+    pub(crate) fn lock_filter(&self) -> MutexGuard<'_, Filter> {
+        self.filter.lock().unwrap()
+    }
+
+    /// replace all cached windows with `windows` in stacking order
+    pub fn reset(&self, windows: Vec<Window>) {
+        let mut layout = self.write_layout();
+        let filter = self.filter.lock().unwrap();
+
+        layout.stack_view = windows.iter().map(|w| w.id).collect();
+        self.record(&windows);
+        layout.windows = windows.into_iter().map(|w| (w.id, w)).collect();
+        layout.update_visibility(None);
+        let windows = layout.all_windows();
+        layout.pinned_windows = windows.iter().filter(|w| w.is_window_pinned(&filter)).map(|w| w.id).collect();
+        layout.filtered_view = windows.iter().filter(|w| filter.apply_to(w)).map(|w| w.id).collect();
+        self.notify(&[LayoutChange::Reset]);
+
+        //wm_debug!("stack_view: {:?}, \nfiltered_view: {:?}", layout.stack_view, layout.filtered_view);
+    }
+
+    /// re-apply the filter and pin rules to cached windows, e.g. after rules changed
+    pub fn refilter(&self) {
+        let mut layout = self.write_layout();
+        let filter = self.filter.lock().unwrap();
+
+        let filtered: WindowStackView = layout.stack_view.iter()
+            .filter(|wid| layout.windows.get(wid).is_some_and(|w| filter.apply_to(w)))
+            .cloned().collect();
+        layout.pinned_windows = layout.windows.values()
+            .filter(|w| w.is_window_pinned(&filter)).map(|w| w.id).collect();
+        if filtered != layout.filtered_view {
+            layout.filtered_view = filtered;
+            self.notify(&[LayoutChange::FilteredView]);
+        }
+    }
+
+    /// true once if windows were added or removed since last call
+    pub(crate) fn take_clients_pending_update(&self) -> bool {
+        let mut pending = self.clients_pending_update.lock().unwrap();
+        let update = *pending;
+        *pending = false;
+        update
+    }
+
+    /// Tell if window is contained in current filter rule set.
+    pub fn is_window_concerned(&self, w: WindowId) -> bool {
+        let layout = self.read_layout();
+        layout.filtered_view.contains(w)
+    }
+
+    /// add Window to the stack
+    pub fn update_with(&self, w: Window) {
+        let wid = w.id;
+
+        let mut layout = self.write_layout();
+        let filter = self.filter.lock().unwrap();
+        let old = layout.filtered_view.generation();
+        let old_below = layout.sibling_below(wid);
+
+        // may be known already if created in between of refresh and the event
+        layout.stack_view.push(wid);
+        if filter.apply_to(&w) {
+            layout.filtered_view.push(wid);
+            wm_debug!("filtered_view {:?}", layout.filtered_view);
+        } else {
+            layout.filtered_view.remove(wid);
+        }
+        if w.is_window_pinned(&filter) {
+            layout.pinned_windows.insert(wid);
+        } else {
+            layout.pinned_windows.remove(&wid);
+        }
+        self.record(::std::slice::from_ref(&w));
+        layout.windows.insert(w.id, w);
+        let from = layout.highest_moved(wid, old_below);
+        layout.revisit(Some(from), &filter);
+        *self.clients_pending_update.lock().unwrap() = true;
+        self.notify_with_view(LayoutChange::Added(wid), old, &layout);
+    }
+
+    pub fn remove(&self, wid: WindowId) {
+        let mut layout = self.write_layout();
+        let old = layout.filtered_view.generation();
+        // only what it covered is seen anew
+        let uncovered = layout.windows.get(&wid).filter(|w| covers(w)).and_then(|_| layout.sibling_below(wid));
+        layout.windows.remove(&wid);
+        layout.stack_view.remove(wid);
+        layout.filtered_view.remove(wid);
+        layout.pinned_windows.retain(|&w| w != wid);
+        if uncovered.is_some() {
+            layout.revisit(uncovered, &self.filter.lock().unwrap());
+        }
+        *self.clients_pending_update.lock().unwrap() = true;
+        self.notify_with_view(LayoutChange::Removed(wid), old, &layout);
+    }
+
+    /// lock and call `f`, do not call any locking operations in `f`; the
+    /// filter is re-applied to the window afterwards. false if `wid` is not
+    /// cached
+    pub fn with_window_mut<F>(&self, wid: WindowId, mut f: F) -> bool where F: FnMut(&mut Window) {
+        let mut layout = self.write_layout();
+        let old = layout.filtered_view.generation();
+        let before;
+        match layout.windows.get_mut(&wid) {
+            Some(win) => {
+                before = footprint(win);
+                f(win);
+                self.record(::std::slice::from_ref(win));
+            },
+            None => {
+                wm_debug!("with_window_mut: bad wid {}", wid);
+                return false;
+            },
+        }
+
+        let filter = self.filter.lock().unwrap();
+        layout.refilter_window(wid, &filter);
+        // a title change leaves what is seen of windows as it is
+        match layout.windows.get(&wid).map(footprint) {
+            Some(after) if after.3 != before.3 => layout.revisit(None, &filter),
+            Some(after) if after != before => layout.revisit(Some(wid), &filter),
+            _ => {},
+        }
+        self.notify_with_view(LayoutChange::Updated(wid), old, &layout);
+        true
+    }
+
+    /// sync stack from configure notify
+    pub fn update_stack(&self, wid: WindowId, above: WindowId) {
+        let mut layout = self.write_layout();
+        let old = layout.filtered_view.generation();
+        let (order, old_below) = (layout.stack_view.generation(), layout.sibling_below(wid));
+        layout.update_stack(wid, above);
+        if layout.stack_view.generation() != order {
+            let from = layout.highest_moved(wid, old_below);
+            layout.revisit(Some(from), &self.filter.lock().unwrap());
+        }
+        self.notify_with_view(LayoutChange::Restacked(wid), old, &layout);
+    }
+
+    /// update inner window layout from configure event, return the previous
+    /// geometry of `window` if cached
+    pub fn update_window(&self, window: WindowId, above: WindowId, geom: Geometry) -> Option<Geometry> {
+        //wm_debug!("update_window {} ", window);
+        let mut layout = self.write_layout();
+        let old = layout.filtered_view.generation();
+        let (order, old_below) = (layout.stack_view.generation(), layout.sibling_below(window));
+
+        layout.update_stack(window, above);
+        let prev = layout.windows.get_mut(&window).map(|win| ::std::mem::replace(&mut win.geom, geom));
+        if prev.is_some() {
+            self.geometries.lock().unwrap().record(window, &geom, history::now());
+        }
+        let filter = self.filter.lock().unwrap();
+        if prev.is_some_and(|prev| prev != geom) {
+            layout.refilter_window(window, &filter);
+        }
+        if prev.is_some_and(|prev| prev != geom) || layout.stack_view.generation() != order {
+            let from = layout.highest_moved(window, old_below);
+            layout.revisit(Some(from), &filter);
+        }
+        if prev.is_some() {
+            self.notify_with_view(LayoutChange::Updated(window), old, &layout);
+        }
+        prev
+    }
+
+    /// copy of windows passing the filter in stacking order (bottom -> top)
+    pub fn filtered_windows(&self) -> Vec<Window> {
+        self.read_layout().filtered_windows()
+    }
+
+    /// copy of all cached windows in stacking order (bottom -> top)
+    pub fn all_windows(&self) -> Vec<Window> {
+        self.read_layout().all_windows()
+    }
+
+    /// copy of cached window `id`, filtered or not
+    pub fn window(&self, id: WindowId) -> Option<Window> {
+        self.read_layout().windows.get(&id).cloned()
+    }
+
+    /// names window `id` had, oldest first, recorded as long as it is
+    /// cached and kept a while after it is gone
+    pub fn titles(&self, id: WindowId) -> Vec<Entry<String>> {
+        self.titles.lock().unwrap().get(id)
+    }
+
+    /// geometries window `id` had, oldest first, kept like `titles`
+    pub fn geometries(&self, id: WindowId) -> Vec<Entry<Geometry>> {
+        self.geometries.lock().unwrap().get(id)
+    }
+
+    /// remember names and geometries of `windows` which changed
+    fn record(&self, windows: &[Window]) {
+        let at = history::now();
+        let mut titles = self.titles.lock().unwrap();
+        for w in windows.iter().filter(|w| !w.name.is_empty()) {
+            titles.record(w.id, &w.name, at);
+        }
+        let mut geometries = self.geometries.lock().unwrap();
+        for w in windows {
+            geometries.record(w.id, &w.geom, at);
+        }
+    }
+
+    /// capture current cached windows, see `Snapshot`
+    pub fn snapshot(&self) -> Snapshot {
+        let layout = self.read_layout();
+        let mut pinned: Vec<WindowId> = layout.pinned_windows.iter().cloned().collect();
+        pinned.sort();
+
+        Snapshot {
+            windows: layout.all_windows(),
+            filtered: layout.filtered_view.to_vec(),
+            pinned,
+            options: self.options(),
+            taken_at: None,
+            server: None,
+        }
+    }
+
+    /// replace cached windows and options with the ones of `snapshot`, the
+    /// pinned set and filtered view are recomputed with current filter as
+    /// `reset` does
+    pub fn restore(&self, snapshot: Snapshot) {
+        *self.options.lock().unwrap() = snapshot.options;
+
+        let mut layout = self.write_layout();
+        let filter = self.filter.lock().unwrap();
+
+        layout.stack_view = snapshot.windows.iter().map(|w| w.id).collect();
+        self.record(&snapshot.windows);
+        layout.windows = snapshot.windows.into_iter().map(|w| (w.id, w)).collect();
+        let windows = layout.all_windows();
+        layout.pinned_windows = windows.iter().filter(|w| w.is_window_pinned(&filter)).map(|w| w.id).collect();
+        layout.filtered_view = windows.iter().filter(|w| filter.apply_to(w)).map(|w| w.id).collect();
+        self.notify(&[LayoutChange::Reset]);
+    }
+
+    /// save the whole state as json, see `load`
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> WmResult<()> {
+        self.snapshot().save(path)
+    }
+
+    /// restore the state saved by `save`, the owner (e.g. `Context`) should
+    /// make the restored options effective
+    pub fn load<P: AsRef<Path>>(&self, path: P) -> WmResult<()> {
+        self.restore(Snapshot::load(path)?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::mock::window;
+
+    fn ids(ws: Vec<Window>) -> Vec<WindowId> {
+        ws.iter().map(|w| w.id).collect()
+    }
+
+    #[test]
+    fn test_read_layout() {
+        let state = GlobalState::new(Filter::new());
+        state.reset(vec![window(WindowId(0x1), "xterm"), window(WindowId(0x2), "dde-dock")]);
+
+        // updates go on while a reader holds its snapshot, which stays as is
+        let layout = state.read_layout();
+        state.remove(WindowId(0x1));
+        state.with_window_mut(WindowId(0x2), |w| w.name = "dde-osd".to_string());
+        assert_eq!(ids(layout.all_windows()), vec![WindowId(0x1), WindowId(0x2)]);
+        assert_eq!(layout.windows[&WindowId(0x2)].name, "dde-dock");
+        assert_eq!(state.window(WindowId(0x2)).unwrap().name, "dde-osd");
+        assert_eq!(ids(state.all_windows()), vec![WindowId(0x2)]);
+    }
+
+    #[test]
+    fn test_titles() {
+        let state = GlobalState::new(Filter::new());
+        state.reset(vec![window(WindowId(0x1), "vim"), window(WindowId(0x2), "")]);
+        state.with_window_mut(WindowId(0x1), |w| w.name = "vim - main.rs".to_string());
+        state.with_window_mut(WindowId(0x1), |w| w.geom.width = 200);
+        state.with_window_mut(WindowId(0x2), |w| w.name = "xterm".to_string());
+        state.remove(WindowId(0x1));
+
+        let titles: Vec<_> = state.titles(WindowId(0x1)).into_iter().map(|e| e.value).collect();
+        assert_eq!(titles, vec!["vim", "vim - main.rs"]);
+        assert_eq!(state.titles(WindowId(0x2)).len(), 1);
+        assert!(state.titles(WindowId(0x3)).is_empty());
+
+        let geoms: Vec<_> = state.geometries(WindowId(0x1)).into_iter().map(|e| e.value.width).collect();
+        assert_eq!(geoms, vec![100, 200]);
+        let geom = Geometry { x: 5, y: 5, width: 200, height: 100 };
+        assert_eq!(state.update_window(WindowId(0x2), WINDOW_NONE, geom).map(|g| g.width), Some(100));
+        assert_eq!(state.geometries(WindowId(0x2)).last().map(|e| e.value), Some(geom));
+    }
+
+    #[test]
+    fn test_incremental_updates() {
+        let state = GlobalState::new(Filter::parse("name = dde*; name = dde-osd: pin").unwrap());
+        state.reset(vec![window(WindowId(0x1), "dde-dock"), window(WindowId(0x2), "xterm"), window(WindowId(0x3), "dde-osd")]);
+        assert_eq!(ids(state.filtered_windows()), vec![WindowId(0x1), WindowId(0x3)]);
+        assert_eq!(state.snapshot().pinned, vec![WindowId(0x3)]);
+
+        // raise dock to top
+        state.update_stack(WindowId(0x1), WindowId(0x3));
+        assert_eq!(ids(state.all_windows()), vec![WindowId(0x2), WindowId(0x3), WindowId(0x1)]);
+        assert_eq!(ids(state.filtered_windows()), vec![WindowId(0x3), WindowId(0x1)]);
+
+        state.update_with(window(WindowId(0x4), "dde-launcher"));
+        // known already, no longer pinned once renamed
+        state.update_with(window(WindowId(0x3), "dde-panel"));
+        assert!(state.snapshot().pinned.is_empty());
+        state.remove(WindowId(0x3));
+        assert_eq!(ids(state.filtered_windows()), vec![WindowId(0x1), WindowId(0x4)]);
+        assert!(state.snapshot().pinned.is_empty());
+        assert!(state.take_clients_pending_update());
+        assert!(!state.take_clients_pending_update());
+
+        state.with_window_mut(WindowId(0x2), |w| w.name = "dde-xterm".to_string());
+        state.refilter();
+        assert_eq!(ids(state.filtered_windows()), vec![WindowId(0x2), WindowId(0x1), WindowId(0x4)]);
+    }
+
+    #[test]
+    fn test_subscribe() {
+        let state = GlobalState::new(Filter::parse("name = dde*").unwrap());
+        let rx = state.subscribe();
+
+        state.reset(vec![window(WindowId(0x1), "dde-dock"), window(WindowId(0x2), "xterm")]);
+        state.update_with(window(WindowId(0x3), "xclock"));
+        state.update_with(window(WindowId(0x4), "dde-osd"));
+        state.update_stack(WindowId(0x2), WindowId(0x3));
+        state.with_window_mut(WindowId(0x2), |w| w.name = "dde-xterm".to_string());
+        state.refilter();
+        state.remove(WindowId(0x1));
+
+        use super::LayoutChange::*;
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec![
+            Reset, Added(WindowId(0x3)), Added(WindowId(0x4)), FilteredView, Restacked(WindowId(0x2)),
+            Updated(WindowId(0x2)), FilteredView, Removed(WindowId(0x1)), FilteredView,
+        ]);
+
+        drop(rx);
+        state.remove(WindowId(0x2));
+        assert!(state.subscribers.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_save_load() {
+        let state = GlobalState::new(Filter::parse("name = dde*; name = dde-osd: pin").unwrap());
+        state.set_options(Options::MAPPED_ONLY | Options::SHOW_DIFF);
+        state.reset(vec![window(WindowId(0x1), "dde-osd"), window(WindowId(0x2), "xterm"), window(WindowId(0x3), "dde-dock")]);
+        state.update_stack(WindowId(0x1), WindowId(0x3));
+
+        let path = ::std::env::temp_dir().join(format!("wminspect-state-{}.json", ::std::process::id()));
+        state.save(&path).unwrap();
+
+        let restored = GlobalState::new(Filter::parse("name = dde*").unwrap());
+        let rx = restored.subscribe();
+        restored.load(&path).unwrap();
+        let _ = ::std::fs::remove_file(&path);
+
+        assert_eq!(restored.options(), Options::MAPPED_ONLY | Options::SHOW_DIFF);
+        assert_eq!(ids(restored.all_windows()), vec![WindowId(0x2), WindowId(0x3), WindowId(0x1)]);
+        assert_eq!(ids(restored.filtered_windows()), vec![WindowId(0x3), WindowId(0x1)]);
+        assert!(restored.snapshot().pinned.is_empty());
+        assert_eq!(rx.try_recv(), Ok(LayoutChange::Reset));
+
+        assert!(restored.load("/nonexistent/wminspect.json").is_err());
+    }
+
+    #[test]
+    fn test_restore_other_rules() {
+        let state = GlobalState::new(Filter::parse("name = dde*; name = xterm: pin").unwrap());
+        state.reset(vec![window(WindowId(0x1), "dde-osd"), window(WindowId(0x2), "xterm"), window(WindowId(0x3), "dde-dock")]);
+        assert_eq!(state.snapshot().pinned, vec![WindowId(0x2)]);
+
+        // pins are the ones of the rules restored into, not of the snapshot
+        let restored = GlobalState::new(Filter::parse("name = xterm; name = dde-osd: pin").unwrap());
+        restored.restore(state.snapshot());
+        assert_eq!(restored.snapshot().pinned, vec![WindowId(0x1)]);
+        assert_eq!(ids(restored.filtered_windows()), vec![WindowId(0x1), WindowId(0x2)]);
+    }
+
+    #[test]
+    fn test_live_rules() {
+        let state = GlobalState::new(Filter::new());
+        state.reset(vec![window(WindowId(0x1), "dde-dock"), window(WindowId(0x2), "xterm")]);
+        let rx = state.subscribe();
+
+        let h = state.add_rule("name = dde*").unwrap();
+        assert_eq!(ids(state.filtered_windows()), vec![0x1]);
+        let pin = state.add_rule("name = xterm: pin").unwrap();
+        assert_eq!(ids(state.filtered_windows()), vec![0x1, 0x2]);
+        assert_eq!(state.snapshot().pinned, vec![0x2]);
+        assert!(state.add_rule("name >").is_err());
+
+        assert!(state.remove_rule(h));
+        assert!(state.remove_rule(pin));
+        assert!(!state.remove_rule(h));
+        assert_eq!(ids(state.filtered_windows()), vec![0x1, 0x2]);
+        assert!(state.snapshot().pinned.is_empty());
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec![LayoutChange::FilteredView, LayoutChange::FilteredView]);
+    }
+
+    #[test]
+    fn test_visibility() {
+        let mapped = |id: u32, x: i16, y: i16, width: u16, height: u16| {
+            let mut w = window(WindowId(id), "");
+            w.geom = Geometry { x, y, width, height };
+            w.attrs.map_state = MapState::Viewable;
+            w
+        };
+        let mut input_only = mapped(0x4, 0, 0, 100, 100);
+        input_only.attrs.input_only = true;
+        let state = GlobalState::new(Filter::parse("occluded = false").unwrap());
+        // bottom to top: a desktop, a window half covered by the one above
+        // it and an input-only one over all of them
+        state.reset(vec![mapped(0x1, 0, 0, 100, 100), mapped(0x2, 0, 0, 40, 40), mapped(0x3, 20, 0, 40, 40), input_only]);
+        let visible = |id: u32| state.window(WindowId(id)).unwrap().visible_area;
+        assert_eq!(visible(0x1), Some(10000 - 60 * 40));
+        assert_eq!(visible(0x2), Some(20 * 40));
+        assert_eq!(visible(0x3), Some(1600));
+        assert_eq!(visible(0x4), Some(10000));
+        assert_eq!(state.window(WindowId(0x2)).unwrap().visible_percent(), 50);
+
+        // covered all over once 0x3 moves onto it, seen again once unmapped
+        state.update_window(WindowId(0x3), WindowId(0x2), Geometry { x: 0, y: 0, width: 40, height: 40 });
+        assert_eq!(visible(0x2), Some(0));
+        assert_eq!(ids(state.filtered_windows()), vec![WindowId(0x1), WindowId(0x3), WindowId(0x4)]);
+        state.with_window_mut(WindowId(0x3), |w| w.attrs.map_state = MapState::Unmapped);
+        assert_eq!((visible(0x2), visible(0x3)), (Some(1600), Some(0)));
+        assert_eq!(ids(state.filtered_windows()).len(), 4);
+
+        // a new top window over all of them
+        state.update_with(mapped(0x5, -10, -10, 200, 200));
+        assert_eq!(ids(state.filtered_windows()), vec![WindowId(0x3), WindowId(0x5)]);
+        state.remove(WindowId(0x5));
+        assert_eq!(ids(state.filtered_windows()).len(), 4);
+    }
+
+    #[test]
+    fn test_visibility_incremental() {
+        let mapped = |id: u32, x: i16| {
+            let mut w = window(WindowId(id), "");
+            w.geom = Geometry { x, y: 0, width: 40, height: 40 };
+            w.attrs.map_state = MapState::Viewable;
+            w
+        };
+        let state = GlobalState::new(Filter::new());
+        state.reset(vec![mapped(0x1, 0), mapped(0x2, 20), mapped(0x3, 100)]);
+        let visible = |id: u32| state.window(WindowId(id)).unwrap().visible_area;
+        assert_eq!(visible(0x1), Some(800));
+
+        // stale areas show which windows were looked at again
+        for id in 1..4 {
+            state.with_window_mut(WindowId(id), |w| w.visible_area = Some(1));
+        }
+        state.with_window_mut(WindowId(0x2), |w| w.name = "renamed".to_string());
+        assert_eq!((visible(0x1), visible(0x2), visible(0x3)), (Some(1), Some(1), Some(1)));
+
+        // a move reaches the window itself and those below it only
+        state.update_window(WindowId(0x2), WindowId(0x1), Geometry { x: 0, y: 0, width: 40, height: 40 });
+        assert_eq!((visible(0x1), visible(0x2), visible(0x3)), (Some(0), Some(1600), Some(1)));
+
+        // lowered below 0x1, which it covered
+        state.update_stack(WindowId(0x2), WINDOW_NONE);
+        assert_eq!((visible(0x1), visible(0x2)), (Some(1600), Some(0)));
+        state.remove(WindowId(0x1));
+        assert_eq!(visible(0x2), Some(1600));
+    }
+}
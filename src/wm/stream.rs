@@ -0,0 +1,130 @@
+extern crate futures_core;
+extern crate tokio;
+
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::pin::Pin;
+use std::task::{self, Poll};
+
+use self::futures_core::Stream;
+use self::tokio::io::unix::AsyncFd;
+
+use super::wm::*;
+use super::event::*;
+use super::error::*;
+
+struct EventFd(RawFd);
+
+impl AsRawFd for EventFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0
+    }
+}
+
+/// Window events, applied to `Context` before being yielded.
+///
+/// Created by `Context::event_stream`, the stream ends once the backend
+/// has no more events to come.
+pub struct EventStream<'a> {
+    ctx: &'a Context,
+    fd: Option<AsyncFd<EventFd>>,
+}
+
+impl Context {
+    /// watch root and refresh windows, then stream upcoming events.
+    /// Must be called within a tokio runtime with IO enabled.
+    pub fn event_stream(&self) -> WmResult<EventStream<'_>> {
+        self.backend().watch_root();
+        self.refresh_windows()?;
+
+        let fd = match self.backend().event_fd() {
+            Some(fd) => Some(AsyncFd::with_interest(EventFd(fd), tokio::io::Interest::READABLE)?),
+            None => None,
+        };
+
+        Ok(EventStream { ctx: self, fd })
+    }
+}
+
+impl<'a> EventStream<'a> {
+    fn next_event(&self) -> Option<WindowEvent> {
+        let mut ev = self.ctx.backend().poll_event()?;
+        self.ctx.apply_event(&mut ev);
+        self.ctx.record_event(&ev);
+        Some(ev)
+    }
+}
+
+impl<'a> Stream for EventStream<'a> {
+    type Item = WindowEvent;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut task::Context) -> Poll<Option<WindowEvent>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(ev) = this.next_event() {
+                return Poll::Ready(Some(ev));
+            }
+
+            let fd = match this.fd {
+                Some(ref fd) if !this.ctx.backend().connection_lost() => fd,
+                _ => return Poll::Ready(None),
+            };
+
+            match fd.poll_read_ready(cx) {
+                Poll::Ready(Ok(mut guard)) => guard.clear_ready(),
+                Poll::Ready(Err(e)) => {
+                    wm_debug!("event stream: {}", e);
+                    return Poll::Ready(None);
+                },
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::future::Future;
+    use super::super::filter::Filter;
+    use super::super::mock::MockBackend;
+    use super::super::backend::WindowId;
+
+    /// collect names of all events (no async blocks in edition 2015)
+    struct Collect<'a>(EventStream<'a>, Vec<&'static str>);
+
+    impl<'a> Future for Collect<'a> {
+        type Output = Vec<&'static str>;
+
+        fn poll(self: Pin<&mut Self>, cx: &mut task::Context) -> Poll<Self::Output> {
+            let this = self.get_mut();
+            loop {
+                match Pin::new(&mut this.0).poll_next(cx) {
+                    Poll::Ready(Some(ev)) => this.1.push(ev.name()),
+                    Poll::Ready(None) => return Poll::Ready(this.1.split_off(0)),
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_event_stream() {
+        let mock = MockBackend::new(1920, 1080);
+        let mut w = Window::new(WindowId(0x100));
+        w.name = "first".to_string();
+        mock.add_window(w);
+
+        let ctx = Context::new(mock.clone(), Filter::new());
+        let mut w = Window::new(WindowId(0x200));
+        w.name = "second".to_string();
+        mock.create_window(w).map_window(WindowId(0x200)).destroy_window(WindowId(0x100));
+
+        let rt = tokio::runtime::Builder::new_current_thread().enable_io().build().unwrap();
+        let _guard = rt.enter();
+        let events = rt.block_on(Collect(ctx.event_stream().unwrap(), Vec::new()));
+
+        assert_eq!(events, vec!["create", "map", "destroy"]);
+        assert_eq!(ctx.snapshot().filtered, vec![WindowId(0x200)]);
+        assert!(ctx.metrics().render().contains("wminspect_events_total{type=\"map\"} 1\n"));
+    }
+}
@@ -0,0 +1,130 @@
+use std::env;
+use std::io::{self, Write};
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::path::Path;
+
+use super::wm::*;
+use super::event::*;
+
+/// where journald takes native protocol entries
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// `LOG_INFO` of syslog
+const PRIORITY_INFO: u8 = 6;
+
+/// Tell the service manager about `state` like `READY=1`, the way
+/// `sd_notify(3)` does. Ok(false) if not started by one.
+pub fn notify(state: &str) -> io::Result<bool> {
+    let path = match env::var_os("NOTIFY_SOCKET") {
+        Some(path) => path,
+        None => return Ok(false),
+    };
+    let path = path.to_string_lossy();
+    let addr = match path.strip_prefix('@') {
+        Some(name) => SocketAddr::from_abstract_name(name)?,
+        None => SocketAddr::from_pathname(&*path)?,
+    };
+
+    let sock = UnixDatagram::unbound()?;
+    sock.send_to_addr(state.as_bytes(), &addr)?;
+    Ok(true)
+}
+
+/// true if stderr goes to the journal, e.g. when run by a unit
+pub fn connected_to_journal() -> bool {
+    env::var_os("JOURNAL_STREAM").is_some()
+}
+
+/// one journal entry of `fields` in the native protocol, values spanning
+/// lines are sized explicitly
+fn journal_entry(fields: &[(&str, String)]) -> Vec<u8> {
+    let mut entry = Vec::new();
+    for &(key, ref value) in fields {
+        if value.contains('\n') {
+            entry.extend_from_slice(key.as_bytes());
+            entry.push(b'\n');
+            entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+            entry.extend_from_slice(value.as_bytes());
+        } else {
+            let _ = write!(entry, "{}={}", key, value);
+        }
+        entry.push(b'\n');
+    }
+    entry
+}
+
+/// Logs monitor events as structured journal entries, with the event type
+/// in `WM_EVENT`, the window in `WM_WINDOW` and the number of filtered
+/// windows after it in `WM_FILTERED`.
+pub struct JournalSink {
+    sock: UnixDatagram,
+}
+
+impl JournalSink {
+    pub fn new() -> io::Result<JournalSink> {
+        JournalSink::with_socket(JOURNAL_SOCKET)
+    }
+
+    /// log to the journal listening at `path` instead
+    pub fn with_socket<P: AsRef<Path>>(path: P) -> io::Result<JournalSink> {
+        let sock = UnixDatagram::unbound()?;
+        sock.connect(path)?;
+        Ok(JournalSink { sock })
+    }
+
+    fn fields(ev: Option<&WindowEvent>, windows: &[Window]) -> Vec<(&'static str, String)> {
+        let mut fields = vec![
+            ("PRIORITY", PRIORITY_INFO.to_string()),
+            ("SYSLOG_IDENTIFIER", "wminspect".to_string()),
+            ("WM_FILTERED", windows.len().to_string()),
+        ];
+        match ev {
+            Some(ev) => {
+                fields.push(("MESSAGE", format!("{} {}", ev.name(), ev.window())));
+                fields.push(("WM_EVENT", ev.name().to_string()));
+                fields.push(("WM_WINDOW", ev.window().to_string()));
+            },
+            None => fields.push(("MESSAGE", format!("refresh, {} windows", windows.len()))),
+        }
+        fields
+    }
+}
+
+impl EventSink for JournalSink {
+    fn publish(&self, ev: Option<&WindowEvent>, windows: &[Window]) {
+        if let Err(e) = self.sock.send(&journal_entry(&JournalSink::fields(ev, windows))) {
+            wm_debug!("journal: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::backend::WindowId;
+
+    #[test]
+    fn test_journal_entry() {
+        let entry = journal_entry(&[("MESSAGE", "map 0x100".to_string()), ("WM_NOTE", "a\nb".to_string())]);
+        assert_eq!(entry, b"MESSAGE=map 0x100\nWM_NOTE\n\x03\0\0\0\0\0\0\0a\nb\n".to_vec());
+    }
+
+    #[test]
+    fn test_journal_sink() {
+        let path = env::temp_dir().join(format!("wminspect-journal-{}.sock", ::std::process::id()));
+        let _ = ::std::fs::remove_file(&path);
+        let journal = UnixDatagram::bind(&path).unwrap();
+
+        let sink = JournalSink::with_socket(&path).unwrap();
+        sink.publish(Some(&WindowEvent::Mapped { window: WindowId(0x100) }), &[Window::new(WindowId(0x100))]);
+        let mut buf = [0u8; 512];
+        let n = journal.recv(&mut buf).unwrap();
+        let _ = ::std::fs::remove_file(&path);
+
+        let entry = String::from_utf8_lossy(&buf[..n]);
+        assert!(entry.contains("MESSAGE=map 0x100\n"), "{}", entry);
+        assert!(entry.contains("WM_EVENT=map\n"), "{}", entry);
+        assert!(entry.contains("WM_FILTERED=1\n"), "{}", entry);
+    }
+}
@@ -0,0 +1,189 @@
+use std::fmt;
+use std::str::FromStr;
+
+use super::error::*;
+
+/// Color and attributes of some text, written like `bold cyan on black`.
+///
+/// Colors are the 8 ANSI ones, `bright-` ones and `#rrggbb`, attributes are
+/// `bold`, `dim`, `italic`, `underline`, `blink` and `reverse`. `none` or
+/// an empty style leaves text as is.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Style {
+    /// SGR parameters, e.g. `1;36;40`
+    sgr: String,
+}
+
+const COLORS: &[&str] = &["black", "red", "green", "yellow", "blue", "magenta", "cyan", "white"];
+
+const ATTRS: &[(&str, u8)] = &[
+    ("bold", 1), ("dim", 2), ("italic", 3), ("underline", 4), ("blink", 5), ("reverse", 7),
+];
+
+/// SGR parameters of a color, `base` is 30 for foreground and 40 for background
+fn color_sgr(name: &str, base: u8) -> Option<String> {
+    if let Some(hex) = name.strip_prefix('#') {
+        let rgb = u32::from_str_radix(hex, 16).ok().filter(|_| hex.len() == 6)?;
+        return Some(format!("{};2;{};{};{}", base + 8, rgb >> 16, (rgb >> 8) & 0xff, rgb & 0xff));
+    }
+    let (name, base) = match name.strip_prefix("bright-") {
+        Some(name) => (name, base + 60),
+        None => (name, base),
+    };
+    COLORS.iter().position(|&c| c == name).map(|i| (base + i as u8).to_string())
+}
+
+impl FromStr for Style {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Style, String> {
+        let mut params = Vec::new();
+        let mut words = s.split_whitespace();
+        while let Some(word) = words.next() {
+            let word = word.to_lowercase();
+            let param = match word.as_str() {
+                "none" => continue,
+                "on" => {
+                    let color = words.next().ok_or_else(|| format!("missing color after on in {:?}", s))?;
+                    color_sgr(&color.to_lowercase(), 40)
+                },
+                _ => ATTRS.iter().find(|&&(a, _)| a == word).map(|&(_, p)| p.to_string())
+                    .or_else(|| color_sgr(&word, 30)),
+            };
+            params.push(param.ok_or_else(|| format!("bad style {:?}", s))?);
+        }
+        Ok(Style { sgr: params.join(";") })
+    }
+}
+
+impl Style {
+    /// the escape sequence starting the style, empty for no style
+    pub fn start(&self) -> String {
+        if self.sgr.is_empty() { String::new() } else { format!("\x1b[{}m", self.sgr) }
+    }
+
+    pub fn paint(&self, s: &str) -> String {
+        if self.sgr.is_empty() { s.to_string() } else { format!("{}{}\x1b[0m", self.start(), s) }
+    }
+
+    pub fn is_plain(&self) -> bool {
+        self.sgr.is_empty()
+    }
+}
+
+impl fmt::Display for Style {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.sgr)
+    }
+}
+
+/// Styles of colored listings and of the TUI.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Theme {
+    pub id: Style,
+    pub name: Style,
+    pub geometry: Style,
+    pub attrs: Style,
+    /// the XWL tag
+    pub xwayland: Style,
+    /// the @host tag of windows of remote clients
+    pub remote: Style,
+    /// windows changed by the last event with --diff
+    pub changed: Style,
+    /// windows matched by pin rules
+    pub pinned: Style,
+    /// the selected row of the TUI
+    pub selected: Style,
+    /// pane titles of the TUI
+    pub title: Style,
+}
+
+/// names of themes for `Theme::by_name`
+pub const THEMES: &[&str] = &["dark", "light"];
+
+macro_rules! theme {
+    ($($field:ident: $style:expr),* $(,)*) => (
+        Theme { $($field: $style.parse().expect("bad builtin style")),* }
+    )
+}
+
+impl Default for Theme {
+    fn default() -> Theme {
+        Theme::dark()
+    }
+}
+
+impl Theme {
+    /// for dark terminals, the default
+    pub fn dark() -> Theme {
+        theme! {
+            id: "blue", name: "cyan", geometry: "red", attrs: "green", xwayland: "magenta", remote: "bright-yellow",
+            changed: "on white", pinned: "yellow", selected: "reverse", title: "none",
+        }
+    }
+
+    pub fn light() -> Theme {
+        theme! {
+            id: "blue", name: "magenta", geometry: "red", attrs: "green", xwayland: "bright-black", remote: "bold yellow",
+            changed: "on bright-yellow", pinned: "bold red", selected: "reverse", title: "bold",
+        }
+    }
+
+    pub fn by_name(name: &str) -> Option<Theme> {
+        match name {
+            "dark" => Some(Theme::dark()),
+            "light" => Some(Theme::light()),
+            _ => None,
+        }
+    }
+
+    /// change style of `part`, named as the fields
+    pub fn set(&mut self, part: &str, style: &str) -> WmResult<()> {
+        let slot = match part {
+            "id" => &mut self.id,
+            "name" => &mut self.name,
+            "geometry" => &mut self.geometry,
+            "attrs" => &mut self.attrs,
+            "xwayland" => &mut self.xwayland,
+            "remote" => &mut self.remote,
+            "changed" => &mut self.changed,
+            "pinned" => &mut self.pinned,
+            "selected" => &mut self.selected,
+            "title" => &mut self.title,
+            _ => return Err(WmError::Config(format!("unknown theme color {}", part))),
+        };
+        *slot = style.parse().map_err(|e| WmError::Config(format!("{}: {}", part, e)))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_style() {
+        let parse = |s: &str| s.parse::<Style>().map(|s| s.to_string());
+        assert_eq!(parse("bold cyan on black"), Ok("1;36;40".to_string()));
+        assert_eq!(parse("bright-red on bright-white"), Ok("91;107".to_string()));
+        assert_eq!(parse("#ff8000 on #000010"), Ok("38;2;255;128;0;48;2;0;0;16".to_string()));
+        assert_eq!(parse("none"), Ok("".to_string()));
+        assert!(parse("purple").is_err());
+        assert!(parse("red on").is_err());
+        assert!(parse("#fff").is_err());
+
+        let style: Style = "reverse".parse().unwrap();
+        assert_eq!(style.paint("x"), "\x1b[7mx\x1b[0m");
+        assert_eq!(Style::default().paint("x"), "x");
+    }
+
+    #[test]
+    fn test_theme() {
+        let mut theme = Theme::by_name("light").unwrap();
+        theme.set("pinned", "underline").unwrap();
+        assert_eq!(theme.pinned.to_string(), "4");
+        assert!(theme.set("pinned", "nope").is_err());
+        assert!(theme.set("border", "red").is_err());
+        assert!(Theme::by_name("solarized").is_none());
+    }
+}
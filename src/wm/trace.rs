@@ -0,0 +1,188 @@
+//! Recorded events as Chrome trace-event JSON, for timeline viewers like
+//! Perfetto or chrome://tracing: a process per session, a thread per
+//! window, slices for the periods windows are mapped and instant events
+//! for everything else.
+
+extern crate serde_json;
+
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Write};
+
+use super::backend::WindowId;
+use super::error::WmResult;
+use super::sqlite::RecordedEvent;
+use super::wm::MapState;
+
+#[derive(Serialize, Debug)]
+struct TraceEvent {
+    name: String,
+    ph: &'static str,
+    pid: i64,
+    tid: u32,
+    /// microseconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ts: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dur: Option<f64>,
+    /// scope of instant events, the thread
+    #[serde(skip_serializing_if = "Option::is_none")]
+    s: Option<&'static str>,
+    args: serde_json::Value,
+}
+
+impl TraceEvent {
+    fn metadata(name: &str, pid: i64, tid: u32, value: String) -> TraceEvent {
+        TraceEvent { name: name.to_string(), ph: "M", pid, tid, ts: None, dur: None, s: None,
+                     args: serde_json::json!({ "name": value }) }
+    }
+
+    fn slice(pid: i64, w: WindowId, from: f64, to: f64) -> TraceEvent {
+        TraceEvent { name: "mapped".to_string(), ph: "X", pid, tid: w.0, ts: Some(micros(from)),
+                     dur: Some(micros(to) - micros(from)), s: None, args: serde_json::Value::Null }
+    }
+
+    fn instant(ev: &RecordedEvent) -> TraceEvent {
+        let args: serde_json::Value = serde_json::from_str(&ev.data).unwrap_or(serde_json::Value::Null);
+        // property changes are told apart by the property
+        let name = match args.get("kind").and_then(|k| k.as_str()) {
+            Some(kind) => format!("{} {}", ev.kind, kind),
+            None => ev.kind.clone(),
+        };
+        TraceEvent { name, ph: "i", pid: ev.session, tid: ev.window.0, ts: Some(micros(ev.time)),
+                     dur: None, s: Some("t"), args }
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Trace {
+    trace_events: Vec<TraceEvent>,
+    display_time_unit: &'static str,
+}
+
+fn micros(secs: f64) -> f64 {
+    (secs * 1e6).round()
+}
+
+/// the timeline of `events`, recorded in order; windows mapped before a
+/// session or still mapped after it are taken as mapped from its first
+/// event or until its last one
+pub fn write_trace<W: Write>(out: &mut W, events: &[RecordedEvent]) -> WmResult<()> {
+    let mut trace = Vec::new();
+    let mut bounds: HashMap<i64, (f64, f64)> = HashMap::new();
+    let mut names: HashMap<(i64, WindowId), String> = HashMap::new();
+    let mut mapped: HashMap<(i64, WindowId), f64> = HashMap::new();
+    // windows mapped or unmapped since their session began
+    let mut toggled: HashSet<(i64, WindowId)> = HashSet::new();
+
+    for ev in events {
+        let key = (ev.session, ev.window);
+        let span = bounds.entry(ev.session).or_insert((ev.time, ev.time));
+        span.1 = ev.time;
+        let start = span.0;
+        if let Some(ref w) = ev.state {
+            names.insert(key, w.name.clone());
+        }
+
+        match ev.kind.as_str() {
+            "map" => {
+                toggled.insert(key);
+                mapped.entry(key).or_insert(ev.time);
+            },
+            "unmap" | "destroy" => {
+                let since = match mapped.remove(&key) {
+                    Some(since) => Some(since),
+                    // mapped before anything was recorded of it
+                    None if !toggled.contains(&key) => match ev.kind.as_str() {
+                        "unmap" => Some(start),
+                        _ => ev.state.as_ref().filter(|w| w.attrs.map_state == MapState::Viewable).map(|_| start),
+                    },
+                    None => None,
+                };
+                toggled.insert(key);
+                if let Some(since) = since {
+                    trace.push(TraceEvent::slice(ev.session, ev.window, since, ev.time));
+                }
+                if ev.kind == "destroy" {
+                    trace.push(TraceEvent::instant(ev));
+                }
+            },
+            _ => trace.push(TraceEvent::instant(ev)),
+        }
+    }
+
+    let mut open: Vec<_> = mapped.into_iter().collect();
+    open.sort_by_key(|&((session, w), _)| (session, w));
+    for ((session, w), since) in open {
+        trace.push(TraceEvent::slice(session, w, since, bounds[&session].1));
+    }
+
+    let mut sessions: Vec<_> = bounds.keys().cloned().collect();
+    sessions.sort();
+    let mut windows: Vec<_> = events.iter().map(|ev| (ev.session, ev.window)).collect::<HashSet<_>>().into_iter().collect();
+    windows.sort();
+    let mut meta: Vec<TraceEvent> = sessions.into_iter()
+        .map(|session| TraceEvent::metadata("process_name", session, 0, format!("session {}", session)))
+        .collect();
+    meta.extend(windows.into_iter().map(|(session, w)| {
+        let name = match names.get(&(session, w)) {
+            Some(name) if !name.is_empty() => format!("{} {}", w, name),
+            _ => w.to_string(),
+        };
+        TraceEvent::metadata("thread_name", session, w.0, name)
+    }));
+    meta.extend(trace);
+
+    serde_json::to_writer(&mut *out, &Trace { trace_events: meta, display_time_unit: "ms" })
+        .map_err(io::Error::from)?;
+    writeln!(out)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::wm::Window;
+
+    fn recorded(time: f64, kind: &str, window: u32, state: Option<Window>) -> RecordedEvent {
+        let data = match kind {
+            "property" => format!("{{\"type\":\"property\",\"window\":{},\"kind\":\"name\"}}", window),
+            _ => format!("{{\"type\":\"{}\",\"window\":{}}}", kind, window),
+        };
+        RecordedEvent { session: 1, time, kind: kind.to_string(), window: WindowId(window), data, state }
+    }
+
+    #[test]
+    fn test_write_trace() {
+        let mut xterm = Window::new(WindowId(0x100));
+        xterm.name = "xterm".to_string();
+        let events = vec![
+            recorded(9.0, "create", 0x300, None),
+            recorded(10.0, "unmap", 0x200, None),
+            recorded(11.0, "map", 0x100, Some(xterm.clone())),
+            recorded(11.5, "configure", 0x100, Some(xterm.clone())),
+            recorded(12.0, "property", 0x100, Some(xterm.clone())),
+            recorded(13.0, "unmap", 0x100, Some(xterm.clone())),
+            recorded(14.0, "map", 0x100, Some(xterm)),
+            recorded(16.0, "configure", 0x300, None),
+        ];
+        let mut out = Vec::new();
+        write_trace(&mut out, &events).unwrap();
+        let trace: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        let evs = trace["traceEvents"].as_array().unwrap();
+
+        let names: Vec<&str> = evs.iter().filter(|e| e["name"] == "thread_name")
+            .map(|e| e["args"]["name"].as_str().unwrap()).collect();
+        assert_eq!(names, vec!["0x100 xterm", "0x200", "0x300"]);
+        assert!(evs.iter().any(|e| e["name"] == "process_name" && e["args"]["name"] == "session 1"));
+
+        // mapped before the recording, mapped in between and still mapped
+        let slices: Vec<(u64, f64, f64)> = evs.iter().filter(|e| e["ph"] == "X")
+            .map(|e| (e["tid"].as_u64().unwrap(), e["ts"].as_f64().unwrap() / 1e6, e["dur"].as_f64().unwrap() / 1e6))
+            .collect();
+        assert_eq!(slices, vec![(0x200, 9.0, 1.0), (0x100, 11.0, 2.0), (0x100, 14.0, 2.0)]);
+
+        let instants: Vec<&str> = evs.iter().filter(|e| e["ph"] == "i").map(|e| e["name"].as_str().unwrap()).collect();
+        assert_eq!(instants, vec!["create", "configure", "property name", "configure"]);
+    }
+}
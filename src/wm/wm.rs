@@ -1,25 +1,43 @@
-extern crate xcb;
-extern crate xcb_util;
+#[cfg(feature = "term")]
 extern crate colored;
 extern crate timer;
 extern crate crossbeam;
 extern crate libc;
+extern crate serde_json;
 
 use std;
-use self::colored::*;
+#[cfg(feature = "term")]
+use self::colored::control::SHOULD_COLORIZE;
 use std::fmt::*;
 use std::time;
-use xcb::xproto;
-use xcb_util::ewmh;
 use std::sync::*;
 use std::sync::atomic::{AtomicBool, self};
-use std::collections::{HashMap, HashSet};
+use std::collections::HashSet;
 use std::cmp::Ordering;
+use std::io;
+use std::os::unix::io::RawFd;
+use std::path::PathBuf;
+use std::panic;
+use std::thread;
 
 use super::filter::*;
+use super::backend::*;
+use super::event::*;
+use super::metrics::{Metrics, Timings};
+use super::error::*;
+use super::snapshot::{DiffReport, ServerInfo, Snapshot};
+use super::export;
+use super::theme::{Style, Theme};
+use super::state::{GlobalState, WindowsLayout};
+use super::history::Entry;
+use super::inspect::{RuleResult, WindowReport};
+use super::reload::SheetWatch;
+
+/// border color of `Context::flash`
+const HIGHLIGHT_COLOR: u32 = 0xff0000;
 
 /// helper type to format vec of window
-struct HexedVec<'a, T: 'a>(&'a Vec<T>);
+pub(crate) struct HexedVec<'a, T: 'a>(pub(crate) &'a Vec<T>);
 
 impl<'a, T: Debug + LowerHex> Debug for HexedVec<'a, T> {
     fn fmt(&self, f: &mut Formatter) -> Result {
@@ -37,7 +55,7 @@ impl<'a, T: Debug + LowerHex> Debug for HexedVec<'a, T> {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Geometry {
     pub x: i16,
     pub y: i16,
@@ -52,15 +70,6 @@ impl Display for Geometry {
     }
 }
 
-impl Geometry {
-    pub fn update_with_configure(&mut self, cne: &xcb::ConfigureNotifyEvent) {
-        self.x = cne.x();
-        self.y = cne.y();
-        self.width = cne.width();
-        self.height = cne.height();
-    }
-}
-
 #[derive(Serialize, Deserialize, Debug, Copy, Clone, Eq)]
 pub enum MapState {
     Unmapped,
@@ -84,26 +93,91 @@ impl Display for MapState {
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
 pub struct Attributes {
     pub override_redirect: bool,
     pub map_state: MapState,
+    /// of class InputOnly, never drawn
+    #[serde(default)]
+    pub input_only: bool,
+    /// minimized: WM_STATE is IconicState or _NET_WM_STATE has
+    /// _NET_WM_STATE_HIDDEN
+    #[serde(default)]
+    pub iconified: bool,
 }
 
 impl Display for Attributes {
     fn fmt(&self, f: &mut Formatter) -> Result {
-        write!(f, "{}{}", if self.override_redirect { "OR " } else {""}, 
-               self.map_state)
+        write!(f, "{}{}{}", if self.override_redirect { "OR " } else {""}, 
+               self.map_state, if self.iconified { " Iconified" } else { "" })
+    }
+}
+
+/// Property a window name was read from.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NameSource {
+    #[serde(rename = "_NET_WM_NAME")]
+    NetWmName,
+    #[serde(rename = "WM_NAME")]
+    WmName,
+}
+
+impl Display for NameSource {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        f.write_str(match *self {
+            NameSource::NetWmName => "_NET_WM_NAME",
+            NameSource::WmName => "WM_NAME",
+        })
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Window {
-    pub id: xcb::Window,
+    pub id: WindowId,
     pub name: String,
+    /// None if the window has no name
+    #[serde(default)]
+    pub name_source: Option<NameSource>,
     pub attrs: Attributes,
     pub geom: Geometry,
-    valid: bool,
+    /// width of the border around `geom`
+    #[serde(default)]
+    pub border_width: u16,
+    /// bits per pixel, 0 for input-only windows
+    #[serde(default)]
+    pub depth: u8,
+    /// None for the root window
+    #[serde(default)]
+    pub parent: Option<WindowId>,
+    /// backed by a wl_surface of an XWayland compositor
+    pub xwayland: bool,
+    /// _NET_WM_DESKTOP, 0xFFFFFFFF means all desktops
+    pub desktop: Option<u32>,
+    /// WM_CLIENT_MACHINE
+    pub client_machine: Option<String>,
+    /// WM_TRANSIENT_FOR, the window this dialog or popup belongs to
+    pub transient_for: Option<WindowId>,
+    /// class part of WM_CLASS
+    #[serde(default)]
+    pub class: Option<String>,
+    /// _NET_WM_PID
+    #[serde(default)]
+    pub pid: Option<u32>,
+    /// _NET_WM_WINDOW_TYPE atoms, like `_NET_WM_WINDOW_TYPE_DOCK`
+    #[serde(default)]
+    pub window_type: Vec<String>,
+    /// _NET_WM_STATE atoms, like `_NET_WM_STATE_HIDDEN`
+    #[serde(default)]
+    pub state: Vec<String>,
+    /// _NET_WM_WINDOW_OPACITY, 0xFFFFFFFF is opaque, None if not set
+    #[serde(default)]
+    pub opacity: Option<u32>,
+    /// pixels not covered by siblings above it, border included, 0 unless
+    /// mapped; None until the stack it is in is known
+    #[serde(default)]
+    pub visible_area: Option<u64>,
+    #[serde(skip, default = "default_valid")]
+    pub(crate) valid: bool,
 }
 
 impl Eq for Window {}
@@ -128,15 +202,130 @@ impl PartialEq for Window {
 
 impl Display for Window {
     fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
-        let id = format!("0x{:x}", self.id);
-        write!(f, "{}({}) {} {}", id, self.name, self.geom, self.attrs)
+        let id = self.id.to_string();
+        write!(f, "{}({}) {} {}", id, self.name, self.geom, self.attrs)?;
+        if let Some(p) = self.partly_visible() {
+            write!(f, " {}% visible", p)?;
+        }
+        if self.xwayland {
+            write!(f, " XWL")?;
+        }
+        match self.remote_host() {
+            Some(host) => write!(f, " @{}", host),
+            None => Ok(()),
+        }
     }
 }
 
+/// `kind-xxxxxxxx` from the FNV-1a hash of `s`
+fn anonymous(kind: &str, s: &str) -> String {
+    let hash = s.bytes().fold(0x811c9dc5u32, |h, b| (h ^ b as u32).wrapping_mul(0x01000193));
+    format!("{}-{:08x}", kind, hash)
+}
+
+/// name of this machine, empty if unknown
+pub fn local_host() -> &'static str {
+    static HOST: OnceLock<String> = OnceLock::new();
+    HOST.get_or_init(|| {
+        let mut buf = [0u8; 256];
+        if unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) } != 0 {
+            return String::new();
+        }
+        let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+        String::from_utf8_lossy(&buf[..len]).into_owned()
+    })
+}
+
+/// true if `host` names this machine, a short name matches the full one
+fn is_local_host(host: &str) -> bool {
+    let short = |h: &str| h.split('.').next().unwrap_or("").to_lowercase();
+    let local = local_host();
+    host.eq_ignore_ascii_case("localhost") || host.eq_ignore_ascii_case(local) ||
+        (!local.is_empty() && short(host) == short(local))
+}
+
+fn default_valid() -> bool {
+    true
+}
+
 impl Window {
-    fn is_window_pinned(&self, filter: &Filter) -> bool {
+    /// a blank window record, to be filled from server replies
+    pub fn new(id: WindowId) -> Window {
+        Window {
+            id,
+            name: "".to_string(),
+            name_source: None,
+            attrs: Attributes{override_redirect: false, map_state: MapState::Unmapped, input_only: false, iconified: false},
+            geom: Geometry{x:0,y:0,width:0,height:0},
+            border_width: 0,
+            depth: 0,
+            parent: None,
+            xwayland: false,
+            desktop: None,
+            client_machine: None,
+            transient_for: None,
+            class: None,
+            pid: None,
+            window_type: Vec::new(),
+            state: Vec::new(),
+            opacity: None,
+            visible_area: None,
+            valid: true,
+        }
+    }
+
+    /// WM_CLIENT_MACHINE if the client runs on another host, e.g. one
+    /// forwarded over ssh
+    pub fn remote_host(&self) -> Option<&str> {
+        self.client_machine.as_deref().filter(|h| !h.is_empty() && !is_local_host(h))
+    }
+
+    /// opacity in percent, windows without _NET_WM_WINDOW_OPACITY are opaque
+    pub fn opacity_percent(&self) -> u32 {
+        self.opacity.map_or(100, |o| (o as u64 * 100 / 0xFFFFFFFF) as u32)
+    }
+
+    /// pixels the window covers, border included
+    pub fn area(&self) -> u64 {
+        let border = 2 * self.border_width as u64;
+        (self.geom.width as u64 + border) * (self.geom.height as u64 + border)
+    }
+
+    /// visible area in percent, rounded down; 100 until it is known
+    pub fn visible_percent(&self) -> u32 {
+        match self.visible_area {
+            Some(visible) if self.area() > 0 => (visible * 100 / self.area()) as u32,
+            _ => 100,
+        }
+    }
+
+    /// mapped but covered all over by windows above it
+    pub fn is_occluded(&self) -> bool {
+        self.attrs.map_state == MapState::Viewable && self.visible_area == Some(0) && self.area() > 0
+    }
+
+    /// how much of it can be seen, for listings; None if all of it or
+    /// not mapped
+    fn partly_visible(&self) -> Option<u32> {
+        Some(self.visible_percent()).filter(|&p| p < 100 && self.attrs.map_state == MapState::Viewable)
+    }
+
+    /// copy telling nothing of what the user works on: name and class are
+    /// replaced with hashes, the same on every run, host and pid dropped
+    pub fn anonymized(&self) -> Window {
+        let mut w = self.clone();
+        if !w.name.is_empty() {
+            w.name = anonymous("name", &w.name);
+        }
+        w.class = w.class.map(|c| anonymous("class", &c));
+        w.client_machine = None;
+        w.pid = None;
+        w
+    }
+
+    pub(crate) fn is_window_pinned(&self, filter: &Filter) -> bool {
         for rule in &filter.rules {
-            if rule.action == Action::Pin && rule.func.as_ref()(self) {
+            if rule.action == Action::Pin && rule.eval(self) {
                 return true;
             }
         }
@@ -145,595 +334,1344 @@ impl Window {
     }
 }
 
-type WindowStackView = Vec<xcb::Window>;
-type WindowListView = HashSet<xcb::Window>;
+/// Set of flags tuning how windows are filtered and shown.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Options(u32);
+
+impl Options {
+    pub const COLORFUL: Options = Options(1);
+    pub const MAPPED_ONLY: Options = Options(1 << 1);
+    pub const OMIT_HIDDEN: Options = Options(1 << 2);
+    pub const NO_SPECIAL: Options = Options(1 << 3);
+    pub const SHOW_DIFF: Options = Options(1 << 4);
+    pub const CLIENTS_ONLY: Options = Options(1 << 5);
+    /// drop InputOnly windows while collecting, with their subtrees
+    pub const NO_INPUT_ONLY: Options = Options(1 << 6);
+    /// leave names out while collecting, only windows passing the filter
+    /// get theirs unless rules look at names
+    pub const NO_NAMES: Options = Options(1 << 7);
+    /// hash names and classes and drop hosts and pids of listed windows,
+    /// for dumps to be shared
+    pub const ANONYMIZE: Options = Options(1 << 8);
+    /// list windows indented below their parents
+    pub const TREE: Options = Options(1 << 9);
+
+    pub fn empty() -> Options {
+        Options(0)
+    }
 
-/// contains cached windows data, which should keep in sync with server
-struct WindowsLayout {
-    /// collected window infos
-    windows: HashMap<xcb::Window, Window>,
-    /// a view maintained by stacking order (bottom -> top)
-    stack_view: WindowStackView,
+    pub fn all() -> Options {
+        Options((1 << 10) - 1)
+    }
 
-    filtered_view: WindowStackView,
-    pinned_windows: WindowListView,
-}
+    pub fn bits(self) -> u32 {
+        self.0
+    }
 
-#[derive(Debug, Clone)]
-pub enum Condition {
-    Colorful,
-    MappedOnly,
-    OmitHidden,
-    NoSpecial,
-    ShowDiff,
-    ClientsOnly,
-}
+    /// unknown bits are dropped
+    pub fn from_bits_truncate(bits: u32) -> Options {
+        Options(bits & Options::all().0)
+    }
 
-pub struct Context<'a> {
-    pub c: &'a ewmh::Connection,
-    pub root: xcb::Window,
-    filter: Mutex<Filter>,
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
 
-    pub options: Vec<Condition>,
-    
-    clients_pending_update: Mutex<bool>,
+    /// true if all flags of `other` are set
+    pub fn contains(self, other: Options) -> bool {
+        self.0 & other.0 == other.0
+    }
 
-    //TODO: move into inner struct as one, and save two extra locks
-    inner: Mutex<WindowsLayout>,
-    
-}
+    pub fn set(&mut self, other: Options) {
+        self.0 |= other.0;
+    }
 
-//TODO: property changes over time
-pub enum XcbRequest<'a> {
-    GWA(xcb::GetWindowAttributesCookie<'a>),
-    GE(xcb::GetGeometryCookie<'a>),
-    GP(xcb::GetPropertyCookie<'a>),
-    GWN(ewmh::GetWmNameCookie<'a>),
-}
+    pub fn clear(&mut self, other: Options) {
+        self.0 &= !other.0;
+    }
 
-#[derive(Clone)]
-pub enum Message {
-    LastConfigureEvent(xcb::ffi::xcb_configure_notify_event_t),
-    Reset,
-    Quit,
+    pub fn toggle(&mut self, other: Options) {
+        self.0 ^= other.0;
+    }
 }
 
-impl Debug for Message {
-    fn fmt(&self, f: &mut Formatter) -> Result {
-        use self::Message::*;
-        match self {
-            &LastConfigureEvent(ref raw) => {
-                write!(f, "Message::LastConfigureEvent(ConfigureNotify{{\
-                    w: {:#x}, above: {:#x}, x: {:#x}, y: {:#x}, width: {:#x}, height: {:#x}}})",
-                    raw.window, raw.above_sibling, raw.x, raw.y, raw.width, raw.height)
-            },
-            &Reset => write!(f, "Message::Reset"),
-            &Quit => write!(f, "Message::Quit"),
-        }
+impl ::std::ops::BitOr for Options {
+    type Output = Options;
+
+    fn bitor(self, other: Options) -> Options {
+        Options(self.0 | other.0)
     }
 }
 
-fn as_event<'r, T>(e: &'r xcb::GenericEvent) -> &'r T {
-    return unsafe { xcb::cast_event::<T>(&e) };
+impl ::std::ops::BitOrAssign for Options {
+    fn bitor_assign(&mut self, other: Options) {
+        self.set(other);
+    }
 }
 
-macro_rules! build_fun {
-    ($getter:ident, $setter:ident, $cond:tt) => (
-        pub fn $getter(&self) -> bool {
-            self.options.as_slice().iter().any(|c| {
-                match *c {
-                    Condition::$cond => true,
-                    _ => false
-                }
-            })
-        }
-        
-        pub fn $setter(&mut self) {
-            self.options.push(Condition::$cond)
-        })
+impl ::std::iter::FromIterator<Options> for Options {
+    fn from_iter<I: IntoIterator<Item = Options>>(iter: I) -> Options {
+        iter.into_iter().fold(Options::empty(), |a, b| a | b)
+    }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Default,
+    /// the same layout as `wmctrl -lG`
+    Wmctrl,
+    /// a json array of windows
+    Json,
+    Csv,
+    /// a json object per line, for each window and each monitor event
+    Ndjson,
+}
 
-impl<'a> Context<'a> {
-    build_fun!(mapped_only, set_mapped_only, MappedOnly);
-    build_fun!(colorful, set_colorful, Colorful);
-    build_fun!(omit_hidden, set_omit_hidden, OmitHidden);
-    build_fun!(no_special, set_no_special, NoSpecial);
-    build_fun!(show_diff, set_show_diff, ShowDiff);
-    build_fun!(clients_only, set_clients_only, ClientsOnly);
+/// names of output formats, as taken by `--output`
+pub const OUTPUT_FORMATS: &[&str] = &["default", "wmctrl", "json", "csv", "ndjson"];
 
-    pub fn new(c: &'a ewmh::Connection, f: Filter) -> Context<'a> {
-        let screen = c.get_setup().roots().next().unwrap();
+impl ::std::str::FromStr for OutputFormat {
+    type Err = WmError;
 
-        Context {
-            c: c,
-            root: screen.root(),
-            filter: Mutex::new(f),
-            options: Vec::new(),
+    fn from_str(s: &str) -> WmResult<OutputFormat> {
+        match s {
+            "default" => Ok(OutputFormat::Default),
+            "wmctrl" => Ok(OutputFormat::Wmctrl),
+            "json" => Ok(OutputFormat::Json),
+            "csv" => Ok(OutputFormat::Csv),
+            "ndjson" => Ok(OutputFormat::Ndjson),
+            _ => Err(WmError::Parse(format!("unknown output format {}", s))),
+        }
+    }
+}
 
-            clients_pending_update: Mutex::new(false),
+/// Workspace windows are restricted to by `ContextBuilder::desktop`,
+/// `current` follows the window manager as it switches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Desktop {
+    Number(u32),
+    Current,
+}
 
-            inner: Mutex::new(
-                WindowsLayout {
-                    windows:  HashMap::new(),
-                    stack_view: WindowStackView::new(),
+impl ::std::str::FromStr for Desktop {
+    type Err = WmError;
 
-                    filtered_view: WindowStackView::new(),
-                    pinned_windows: WindowListView::new(),
-                })
+    fn from_str(s: &str) -> WmResult<Desktop> {
+        match s {
+            "current" => Ok(Desktop::Current),
+            _ => s.parse().map(Desktop::Number).map_err(|_| WmError::Parse(format!("invalid desktop {}", s))),
         }
     }
+}
 
-    /// `changes` is updated windows for current event
-    /// TODO: highlight pinned windows in different style
-    pub fn dump_windows(&self, changes: Option<WindowListView>) {
-        let layout = self.inner.lock().unwrap();
-
-        let colored = self.colorful();
-        for (i, wid) in layout.filtered_view.iter().enumerate() {
-            let w = layout.windows.get(wid).expect(&format!("{} does not exist!", wid));
+/// How the monitor tells a window of a pin rule was created, mapped or
+/// destroyed, see `ContextBuilder::bell`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Bell {
+    /// BEL on stderr
+    Terminal,
+    /// run by `sh -c` with the event in `WM_EVENT` and the window in
+    /// `WM_WINDOW`, like `paplay /usr/share/sounds/freedesktop/stereo/bell.oga`
+    Command(String),
+}
 
-            if self.show_diff() && changes.is_some() &&
-                changes.as_ref().unwrap().contains(&wid) {
-                println!("{}: {}", i, win2str(w, colored).on_white());
-            } else {
-                println!("{}: {}", i, win2str(w, colored));
-            }
+impl Bell {
+    fn ring(&self, ev: &WindowEvent) {
+        match *self {
+            Bell::Terminal => {
+                let _ = io::Write::write_all(&mut io::stderr(), b"\x07");
+            },
+            Bell::Command(ref cmd) => {
+                let child = std::process::Command::new("sh").arg("-c").arg(cmd)
+                    .env("WM_EVENT", ev.name())
+                    .env("WM_WINDOW", ev.window().to_string())
+                    .spawn();
+                match child {
+                    // reaped aside, a slow player should not hold events up
+                    Ok(mut child) => { thread::spawn(move || child.wait()); },
+                    Err(e) => wm_debug!("bell {}: {}", cmd, e),
+                }
+            },
         }
     }
+}
 
-    /// Tell if window is contained in current filter rule set.
-    pub fn is_window_concerned(&self, w: xcb::Window) -> bool {
-        let layout = self.inner.lock().unwrap();
-        layout.filtered_view.iter().any(|&id| id == w)
-    }
+/// how deep below root windows are collected by default, children of root
+/// only
+pub const MAX_DEPTH: usize = 1;
+
+/// fewest windows worth a thread of their own when querying with jobs
+const MIN_JOB_WINDOWS: usize = 128;
+
+/// most events the monitor applies before dumping windows once for all
+const MAX_BATCH: usize = 256;
+
+/// how long configure events have to settle before the monitor dumps
+/// windows again, by default
+pub const DEBOUNCE: time::Duration = time::Duration::from_millis(50);
+
+/// Names of the windows a window manager keeps for itself, which
+/// `--no-special` leaves out; the last ones are those checked by
+/// _NET_SUPPORTING_WM_CHECK.
+pub const SPECIAL_PRESETS: &[(&str, &[&str])] = &[
+    ("mutter", &["mutter guard window", "mutter topleft corner window"]),
+    ("deepin-metacity", &["deepin-metacity guard window", "deepin-metacity topleft corner window"]),
+    ("kwin", &["KWin"]),
+    ("xfwm", &["Xfwm4", "xfwm4-tabwin"]),
+    ("openbox", &["Openbox"]),
+];
+
+/// presets whose windows are special unless told otherwise
+pub const DEFAULT_SPECIAL_PRESETS: &[&str] = &["mutter", "deepin-metacity"];
+
+/// names of the windows of preset `wm`, like `kwin`
+pub fn special_preset(wm: &str) -> Option<&'static [&'static str]> {
+    SPECIAL_PRESETS.iter().find(|&&(name, _)| name == wm).map(|&(_, names)| names)
+}
+
+fn default_special_names() -> Vec<String> {
+    DEFAULT_SPECIAL_PRESETS.iter()
+        .flat_map(|wm| special_preset(wm).unwrap_or(&[]))
+        .map(|name| name.to_string())
+        .collect()
+}
 
-    /// add Window to the stack
-    pub fn update_with(&self, w: Window) {
-        let wid = w.id;
+/// Observer of the monitor, e.g. to publish events to the network.
+pub trait EventSink: Send + Sync {
+    /// called after `ev` is handled, or after a full refresh if `ev` is None,
+    /// `windows` are the filtered windows in stacking order
+    fn publish(&self, ev: Option<&WindowEvent>, windows: &[Window]);
+}
 
-        let mut layout = self.inner.lock().unwrap();
-        let filter = self.filter.lock().unwrap();
+/// Connects a `Backend` to the `GlobalState` it keeps in sync.
+pub struct Context {
+    backend: Box<dyn Backend>,
+    pub root: WindowId,
+
+    output: OutputFormat,
+    theme: Theme,
+    debounce: time::Duration,
+    max_depth: usize,
+    /// threads querying windows at once
+    jobs: usize,
+    /// display given to the builder
+    display: Option<String>,
+    /// only windows on this desktop pass the filter
+    desktop: Option<Desktop>,
+    /// only windows on this RandR output pass the filter
+    on_output: Option<String>,
+    /// globs of window names `NO_SPECIAL` leaves out
+    special_names: Vec<String>,
+    /// rung by the monitor for windows of pin rules
+    bell: Option<Bell>,
+    /// where listings and monitor output go
+    writer: Mutex<Box<dyn io::Write + Send>>,
+    metrics: Arc<Metrics>,
+    timings: Timings,
+    sinks: Vec<Arc<dyn EventSink>>,
+
+    state: Arc<GlobalState>,
+}
 
-        layout.stack_view.push(wid);
-        if filter.apply_to(&w) {
-            layout.filtered_view.push(wid);
-            wm_debug!("filtered_view {:?}", HexedVec(&layout.filtered_view));
-        }
-        if w.is_window_pinned(&filter) {
-            layout.pinned_windows.insert(wid);
+/// Configures and creates a `Context`, see `Context::builder`.
+///
+/// ```no_run
+/// # use wminspect::prelude::*;
+/// let ctx = Context::builder()
+///     .filter(Filter::parse("name = dde*").unwrap())
+///     .options(Options::MAPPED_ONLY | Options::OMIT_HIDDEN)
+///     .output(OutputFormat::Wmctrl)
+///     .display(":0")
+///     .connect()
+///     .unwrap();
+/// ```
+pub struct ContextBuilder {
+    filter: Filter,
+    options: Options,
+    output: OutputFormat,
+    writer: Option<Box<dyn io::Write + Send>>,
+    theme: Theme,
+    debounce: time::Duration,
+    max_depth: usize,
+    jobs: usize,
+    display: Option<String>,
+    desktop: Option<Desktop>,
+    on_output: Option<String>,
+    special_names: Vec<String>,
+    bell: Option<Bell>,
+    screen: Option<i32>,
+    timeout: time::Duration,
+}
+
+impl Default for ContextBuilder {
+    fn default() -> ContextBuilder {
+        ContextBuilder {
+            filter: Filter::new(),
+            options: Options::empty(),
+            output: OutputFormat::Default,
+            writer: None,
+            theme: Theme::default(),
+            debounce: DEBOUNCE,
+            max_depth: MAX_DEPTH,
+            jobs: 1,
+            display: None,
+            desktop: None,
+            on_output: None,
+            special_names: default_special_names(),
+            bell: None,
+            screen: None,
+            timeout: REQUEST_TIMEOUT,
         }
-        layout.windows.entry(w.id).or_insert(w);
-        *self.clients_pending_update.lock().unwrap() = true;
     }
+}
 
-    pub fn update_pin_state(&self, wid: xcb::Window) {
-        let mut layout = self.inner.lock().unwrap();
-        let filter = self.filter.lock().unwrap();
-
-        let pinned = if let Some(win) = layout.windows.get_mut(&wid) {
-            win.is_window_pinned(&filter)
-        } else {
-            return;
-        };
+impl ContextBuilder {
+    pub fn new() -> ContextBuilder {
+        ContextBuilder::default()
+    }
 
-        if pinned {
-            layout.pinned_windows.insert(wid);
-        } else {
-            layout.pinned_windows.remove(&wid);
-        }
+    pub fn filter(mut self, f: Filter) -> Self {
+        self.filter = f;
+        self
     }
 
-    pub fn remove(&self, wid: xcb::Window) {
-        let mut layout = self.inner.lock().unwrap();
-        layout.windows.remove(&wid);
-        layout.stack_view.retain(|&w| w != wid);
-        layout.filtered_view.retain(|&w| w != wid);
-        layout.pinned_windows.retain(|&w| w != wid);
-        *self.clients_pending_update.lock().unwrap() = true;
+    /// add `options` to those set already
+    pub fn options(mut self, options: Options) -> Self {
+        self.options.set(options);
+        self
     }
 
-    /// lock and call `f`, do not call any locking operations in `f`
-    pub fn with_window_mut<F>(&self, wid: xcb::Window, mut f: F) where F: FnMut(&mut Window) {
-        let mut layout = self.inner.lock().unwrap();
-        if let Some(win) = layout.windows.get_mut(&wid) {
-            f(win);
-        } else {
-            wm_debug!("with_window_mut: bad wid {}", wid);
-        }
+    pub fn output(mut self, output: OutputFormat) -> Self {
+        self.output = output;
+        self
     }
 
+    /// where to print listings and monitor output, stdout by default
+    pub fn writer<W: io::Write + Send + 'static>(mut self, writer: W) -> Self {
+        self.writer = Some(Box::new(writer));
+        self
+    }
 
-    /// refresh internal windows cache from xserver
-    /// this is a very heavy operation and may stop the world now
-    /// (may be moved into a thread or so)
-    pub fn refresh_windows(&self) {
+    /// colors of listings with `Options::COLORFUL`
+    pub fn theme(mut self, theme: Theme) -> Self {
+        self.theme = theme;
+        self
+    }
 
-        let mut layout = self.inner.lock().unwrap();
-        let windows = self.collect_windows();
+    /// how long configure events have to settle before the monitor dumps
+    /// windows again, `DEBOUNCE` by default
+    pub fn debounce(mut self, debounce: time::Duration) -> Self {
+        self.debounce = debounce;
+        self
+    }
 
-        layout.stack_view = windows.iter().map(|w| w.id).collect();
-        layout.pinned_windows = self.collect_pinned_windows(&windows);
-        layout.windows = windows.clone().into_iter().map(|w| (w.id, w)).collect();
+    /// how deep below root windows are collected, each followed by its
+    /// subtree, `MAX_DEPTH` by default and 0 for no limit
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
 
-        self.rebuild_filter();
-        layout.filtered_view = self.apply_filter(&windows);
+    /// query windows with up to `jobs` threads, for sessions of thousands
+    /// of windows; 1 by default
+    pub fn jobs(mut self, jobs: usize) -> Self {
+        self.jobs = jobs.max(1);
+        self
+    }
 
-        //wm_debug!("stack_view: {:?}, \nfiltered_view: {:?}",
-                  //HexedVec(&layout.stack_view), HexedVec(&layout.filtered_view));
+    /// X display to connect to, DISPLAY by default
+    pub fn display<S: Into<String>>(mut self, display: S) -> Self {
+        self.display = Some(display.into());
+        self
     }
 
-    fn update_stack_unlocked(&self, layout: &mut WindowsLayout, wid: xcb::Window, above: xcb::Window) {
-        //wm_debug!("update_stack_unlocked {:#x} {:#x}", wid, above);
-        if !layout.windows.contains_key(&wid) {
-            return;
-        }
+    /// only windows on `desktop` pass the filter, sticky ones included
+    pub fn desktop(mut self, desktop: Desktop) -> Self {
+        self.desktop = Some(desktop);
+        self
+    }
 
-        layout.stack_view.retain(|&w| w != wid);
-        if above == xcb::WINDOW_NONE {
-            layout.stack_view.insert(0, wid);
-        } else {
-            //TODO: check if operation needed
-            let idx = layout.stack_view.iter().position(|&x| x == above).unwrap();
-            layout.stack_view.insert(idx+1, wid);
-        }
+    /// only windows intersecting RandR output `name`, like `HDMI-1`, pass
+    /// the filter
+    pub fn on_output<S: Into<String>>(mut self, name: S) -> Self {
+        self.on_output = Some(name.into());
+        self
+    }
 
-        if layout.filtered_view.iter().any(|&id| id == wid) {
-            wm_debug!("update_stack_unlocked {:#x} {:#x}", wid, above);
-            //wm_debug!("PRE: filtered_view: {:?}", HexedVec(&layout.filtered_view));
-            layout.filtered_view.retain(|&w| w != wid);
-            if above == xcb::WINDOW_NONE || layout.filtered_view.len() == 0 {
-                layout.filtered_view.insert(0, wid);
-            } else {
-                if let Some(idx) = layout.filtered_view.iter().position(|&x| x == above) {
-                    layout.filtered_view.insert(idx+1, wid);
-                } else {
-                    // find neareast lower sibling as above_sibling
-                    let lower_id = *layout.stack_view.iter().rev().skip_while(|&&id| id == above)
-                        .find(|&&w| layout.filtered_view.iter().position(|&id| id == w).is_some())
-                        .unwrap();
-                    let upper_bound = layout.filtered_view.iter().position(|&w| w == lower_id).unwrap();
-                    layout.filtered_view.insert(upper_bound+1, wid);
-                }
-            }
-            //wm_debug!("POST: filtered_view: {:?}", HexedVec(&layout.filtered_view));
-        }
+    /// globs of the names of windows `Options::NO_SPECIAL` leaves out,
+    /// replacing those of `DEFAULT_SPECIAL_PRESETS`
+    pub fn special_names(mut self, names: Vec<String>) -> Self {
+        self.special_names = names;
+        self
     }
 
-    /// sync stack from configure notify
-    pub fn update_stack(&self, wid: xcb::Window, above: xcb::Window) {
-        let mut layout = self.inner.lock().unwrap();
-        self.update_stack_unlocked(&mut layout, wid, above);
+    /// alert with `bell` when the monitor sees a window of a pin rule
+    /// created, mapped or destroyed
+    pub fn bell(mut self, bell: Bell) -> Self {
+        self.bell = Some(bell);
+        self
     }
 
-    fn update_window_unlocked(&self, layout: &mut WindowsLayout, cne: &xcb::ConfigureNotifyEvent) {
-        let wid = cne.window();
+    /// screen to inspect, the one given by display name by default
+    pub fn screen(mut self, screen: i32) -> Self {
+        self.screen = Some(screen);
+        self
+    }
 
-        if !layout.windows.contains_key(&wid) {
-            return;
-        }
+    /// how long to wait for the X server, to connect as well as for each
+    /// reply, `REQUEST_TIMEOUT` by default
+    pub fn timeout(mut self, timeout: time::Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
 
-        if let Some(win) = layout.windows.get_mut(&wid) {
-            win.geom.update_with_configure(cne);
+    /// connect to the X server and create the context
+    pub fn connect(mut self) -> WmResult<Context> {
+        if self.display.is_none() {
+            self.display = std::env::var("DISPLAY").ok();
         }
+        let backend = XcbBackend::connect_screen(self.display.as_deref(), self.screen, self.timeout)?;
+        Ok(self.build(backend))
     }
 
-    /// update inner window layout from configure event
-    pub fn update_window(&self, cne: &xcb::ConfigureNotifyEvent) {
-        //wm_debug!("update_window {:#x} ", cne.window());
-        let mut layout = self.inner.lock().unwrap();
-        let wid = cne.window();
-
-        self.update_stack_unlocked(&mut layout, wid, cne.above_sibling());
-        self.update_window_unlocked(&mut layout, cne);
+    /// create the context upon an existing backend
+    pub fn build<B: Backend + 'static>(self, backend: B) -> Context {
+        let mut ctx = Context::new(backend, self.filter);
+        ctx.state.set_options(self.options);
+        ctx.output = self.output;
+        ctx.theme = self.theme;
+        ctx.debounce = self.debounce;
+        ctx.max_depth = self.max_depth;
+        ctx.jobs = self.jobs;
+        ctx.display = self.display;
+        ctx.desktop = self.desktop;
+        ctx.on_output = self.on_output;
+        ctx.special_names = self.special_names;
+        ctx.bell = self.bell;
+        if let Some(writer) = self.writer {
+            ctx.set_writer(writer);
+        }
+        ctx
     }
+}
 
-    fn collect_pinned_windows(&self, windows: &Vec<Window>) -> WindowListView {
-        let filter = self.filter.lock().unwrap();
-        let f = |w| {
-            for rule in &filter.rules {
-                if rule.action == Action::Pin && rule.func.as_ref()(w) {
-                    return Some(w.id.clone());
-                }
-            }
-            None
-        };
+#[derive(Clone)]
+pub(crate) enum Message {
+    LastConfigureEvent { window: WindowId, above_sibling: WindowId },
+    Reset,
+    Quit,
+}
 
-        windows.iter().filter_map(f).collect()
+impl Debug for Message {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        use self::Message::*;
+        match self {
+            &LastConfigureEvent { window, above_sibling } => {
+                write!(f, "Message::LastConfigureEvent(w: {}, above: {})", window, above_sibling)
+            },
+            &Reset => write!(f, "Message::Reset"),
+            &Quit => write!(f, "Message::Quit"),
+        }
     }
+}
 
+macro_rules! build_fun {
+    ($getter:ident, $flag:ident) => (
+        pub fn $getter(&self) -> bool {
+            self.options().contains(Options::$flag)
+        })
+}
 
-    fn collect_windows(&self) ->Vec<Window> {
-        let c = self.c;
-
-        let res = match xcb::query_tree(&c, self.root).get_reply() {
-            Ok(result) => result,
-            Err(_) => return Vec::new(),
-        };
 
-        let target_windows = self.query_windows(&res);
-        wm_debug!("initial total #{}", target_windows.len());
-        target_windows
+impl Context {
+    build_fun!(mapped_only, MAPPED_ONLY);
+    build_fun!(colorful, COLORFUL);
+    build_fun!(omit_hidden, OMIT_HIDDEN);
+    build_fun!(no_special, NO_SPECIAL);
+    build_fun!(show_diff, SHOW_DIFF);
+    build_fun!(clients_only, CLIENTS_ONLY);
+    build_fun!(no_input_only, NO_INPUT_ONLY);
+    build_fun!(no_names, NO_NAMES);
+    build_fun!(anonymize, ANONYMIZE);
+    build_fun!(tree, TREE);
+
+    pub fn options(&self) -> Options {
+        self.state.options()
     }
 
-    /// rebuild filter rule set
-    /// rebuild will clear all adhoc rules and readd them for now, since some 
-    /// conditions can be changed (e.g _NET_CLIENT_LIST_STACKING)
-    fn rebuild_filter(&self) {
-        macro_rules! adhoc {
-            ($filter:ident, $c:expr) => ({
-                let afp = ActionFuncPair {
-                    action: Action::FilterOut,
-                    rule: FilterRule::Adhoc,
-                    func: Box::new( $c )
-                };
-                $filter.add_live_rule(afp);
-            })
-        }
-
-        let mut filter = self.filter.lock().unwrap();
+    /// replace options, filtering related ones take effect on cached windows
+    /// immediately
+    pub fn set_options(&self, options: Options) {
+        self.state.set_options(options);
+        self.rebuild_filter();
+        self.state.refilter();
+    }
 
-        if self.mapped_only() || self.omit_hidden() {
-            // TODO: rewrite with _NET_WM_STATE of window
-            /*
-            let has_guard_window = target_windows.iter()
-                .any(|w| w.name.contains("guard window") && w.attrs.override_redirect);
+    pub fn set_option(&self, option: Options) {
+        let mut options = self.options();
+        options.set(option);
+        self.set_options(options);
+    }
 
-            if has_guard_window {
-                wm_debug!("has guard window, filter out not mapped or hidden");
-            }
+    pub fn clear_option(&self, option: Options) {
+        let mut options = self.options();
+        options.clear(option);
+        self.set_options(options);
+    }
 
-            do_filter!(target_windows, skip_while, |w| {
-                if has_guard_window {
-                    !w.name.contains("guard window") || !w.attrs.override_redirect
-                } else {
-                    false
-                }
-            });
-            */
+    pub fn toggle_option(&self, option: Options) {
+        let mut options = self.options();
+        options.toggle(option);
+        self.set_options(options);
+    }
 
-            if self.mapped_only() {
-                adhoc!(filter, |w| { w.attrs.map_state == MapState::Viewable });
-            }
+    pub fn builder() -> ContextBuilder {
+        ContextBuilder::new()
+    }
 
-            if self.omit_hidden() {
-                let (screen_width, screen_height) = {
-                    let screen = self.c.get_setup().roots().next().unwrap();
-                    (screen.width_in_pixels(), screen.height_in_pixels())
-                };
+    pub fn new<B: Backend + 'static>(backend: B, f: Filter) -> Context {
+        let root = backend.root();
 
-                adhoc!(filter, move |w| {
-                    w.geom.x < screen_width as i16 &&
-                        w.geom.y < screen_height as i16 &&
-                        (w.geom.width as i16) + w.geom.x > 0 && (w.geom.height as i16) + w.geom.y > 0
-                });
-            }
+        Context {
+            backend: Box::new(backend),
+            root,
+            output: OutputFormat::Default,
+            theme: Theme::default(),
+            debounce: DEBOUNCE,
+            max_depth: MAX_DEPTH,
+            jobs: 1,
+            display: None,
+            desktop: None,
+            on_output: None,
+            special_names: default_special_names(),
+            bell: None,
+            writer: Mutex::new(Box::new(io::stdout())),
+            metrics: Arc::new(Metrics::new()),
+            timings: Timings::new(),
+            sinks: Vec::new(),
+
+            state: Arc::new(GlobalState::new(f)),
         }
+    }
 
-        if self.no_special() {
-            let specials = hashset!(
-                ("mutter guard window"),
-                ("deepin-metacity guard window"),
-                ("mutter topleft corner window"),
-                ("deepin-metacity topleft corner window"),
-                );
-
-            adhoc!(filter, move |w| !specials.contains(w.name.as_str()));
-        }
+    /// the window cache, which can be shared with other threads
+    pub fn state(&self) -> &Arc<GlobalState> {
+        &self.state
+    }
 
-        if self.clients_only() {
-            self.update_clients_only_rule_locked(&mut filter);
-        }
+    /// `changes` is updated windows for current event
+    pub fn dump_windows(&self, changes: Option<HashSet<WindowId>>) {
+        self.timings.time("output", || self.write_listing(changes))
     }
 
-    fn update_clients_only_rule_locked(&self, filter: &mut Filter) {
-        //NOTE: clients is changing overtime
-        //dont figure out how to solve it, so we need to re-build this 
-        //rule on the air every time clients list gets updated.
-        //or make boxed closure's lifetime as long as filter instead of static
-        let clients = self.collect_window_manager_properties();
+    fn write_listing(&self, changes: Option<HashSet<WindowId>>) {
+        if let OutputFormat::Json | OutputFormat::Csv | OutputFormat::Ndjson = self.output {
+            let windows = self.shown(self.state.filtered_windows());
+            let mut writer = self.writer.lock().unwrap();
+            let _ = export::write_windows(&mut *writer, &windows, self.output);
+            return;
+        }
 
-        if let Some(i) = filter.rules.iter().position(|r| r.rule == FilterRule::ClientsOnly) {
-            let r = filter.rules.get_mut(i).unwrap();
-            r.func = Box::new(move |w| clients.contains(&w.id));
+        let layout = self.state.read_layout();
+
+        use std::io::Write as IoWrite;
+        let theme = if self.colorful() { Some(&self.theme) } else { None };
+        let (line_theme, name_cols) = listing_style(theme);
+        let mut writer = self.writer.lock().unwrap();
+        let mut out = io::BufWriter::new(&mut *writer);
+        // one line buffer for all windows
+        let mut line = String::new();
+        let windows: Vec<(WindowId, usize)> = if self.tree() {
+            layout.filtered_tree()
         } else {
-            let afp = ActionFuncPair {
-                action: Action::FilterOut,
-                rule: FilterRule::ClientsOnly,
-                func: Box::new(move |w| clients.contains(&w.id))
+            layout.filtered_view.iter().map(|&wid| (wid, 0)).collect()
+        };
+        for (i, &(wid, depth)) in windows.iter().enumerate() {
+            let w = match layout.windows.get(&wid) {
+                Some(w) => w,
+                None => {
+                    wm_debug!("{} does not exist!", wid);
+                    continue;
+                }
+            };
+
+            line.clear();
+            let _ = if self.anonymize() {
+                write_window(&mut line, &w.anonymized(), line_theme, name_cols)
+            } else {
+                write_window(&mut line, w, line_theme, name_cols)
+            };
+            // pinned windows are marked like in the tui
+            let pinned = layout.pinned_windows.contains(&wid);
+            let indent = "  ".repeat(depth);
+            let _ = match theme {
+                Some(theme) if pinned => write!(out, "{}: {}{}", Painted(&theme.pinned, i), indent, Painted(&theme.pinned, '*')),
+                _ => write!(out, "{}: {}{}", i, indent, if pinned { "*" } else { "" }),
+            };
+            let _ = if self.show_diff() && changes.as_ref().is_some_and(|c| c.contains(&wid)) {
+                writeln!(out, "{}", Painted(&self.theme.changed, &line))
+            } else {
+                writeln!(out, "{}", line)
             };
-            filter.rules.push(afp);
         }
+        let _ = out.flush();
     }
 
-    /// update clients related rule if necessary 
-    /// return true if updated or false when nothing to be done
-    pub fn update_clients(&self) -> bool {
-        let update = *self.clients_pending_update.lock().unwrap();
-
-        if update {
-            let mut layout = self.inner.lock().unwrap();
-            let mut filter = self.filter.lock().unwrap();
+    /// format windows and monitor events are printed in
+    pub fn output_format(&self) -> OutputFormat {
+        self.output
+    }
 
-            //TODO: check if clients really changed ?
-            //self.rebuild_filter();
-            self.update_clients_only_rule_locked(&mut filter);
+    /// print in `output` from now on, like `ContextBuilder::output`
+    pub fn set_output_format(&mut self, output: OutputFormat) {
+        self.output = output;
+    }
 
-            layout.filtered_view = layout.windows.iter()
-                .filter(|&(_, w)| filter.apply_to(w)).map(|(_, w)| w.id).collect();
+    pub fn theme(&self) -> &Theme {
+        &self.theme
+    }
 
-            *self.clients_pending_update.lock().unwrap() = false;
-        }
+    pub fn set_theme(&mut self, theme: Theme) {
+        self.theme = theme;
+    }
 
-        update
+    pub fn debounce(&self) -> time::Duration {
+        self.debounce
     }
 
-    /// filter windows by applying loaded rules
-    fn apply_filter(&self, windows: &Vec<Window>) -> WindowStackView {
-        let filter = self.filter.lock().unwrap();
-        windows.iter().filter(|w| filter.apply_to(w)).map(|w| w.id).collect()
+    pub fn max_depth(&self) -> usize {
+        self.max_depth
     }
 
-    fn collect_window_manager_properties(&self) -> WindowStackView {
-        let c = self.c;
+    pub fn jobs(&self) -> usize {
+        self.jobs
+    }
 
-        let cookie = ewmh::get_client_list_unchecked(&c, 0);
-        match cookie.get_reply() {
-            Ok(ref reply) => {
-                    let list = reply.windows().to_vec();
-                    wm_debug!("CLIENT_LIST: {:#?}", HexedVec(&list));
-                    list
-            },
-            _ => Vec::new()
-        }
+    pub fn set_writer<W: io::Write + Send + 'static>(&self, writer: W) {
+        *self.writer.lock().unwrap() = Box::new(writer);
     }
 
-    pub fn query_window(&self, id: xcb::Window) -> Window {
-        let c = self.c;
+    /// write a line of output, a broken writer is ignored
+    fn print(&self, line: Arguments) {
+        let mut writer = self.writer.lock().unwrap();
+        let _ = writeln!(writer, "{}", line);
+    }
 
-        let mut qs: Vec<XcbRequest> = Vec::new();
-        qs.push(XcbRequest::GWA(xcb::get_window_attributes(&c, id)));
-        qs.push(XcbRequest::GE(xcb::get_geometry(&c, id)));
-        qs.push(XcbRequest::GWN(ewmh::get_wm_name_unchecked(&c, id)));
+    /// ring the bell if `ev` brought a window of a pin rule, or took one
+    /// that `was_pinned` before it was applied
+    fn ring_bell(&self, ev: &WindowEvent, was_pinned: bool) {
+        let bell = match self.bell {
+            Some(ref bell) => bell,
+            None => return,
+        };
+        let rings = match *ev {
+            WindowEvent::Created { window, .. } | WindowEvent::Mapped { window } =>
+                self.state.read_layout().pinned_windows.contains(&window),
+            WindowEvent::Destroyed { .. } => was_pinned,
+            _ => false,
+        };
+        if rings {
+            bell.ring(ev);
+        }
+    }
 
-        macro_rules! apply_reply {
-            ($win:ident $cookie:ident $reply:ident $e:expr) => (
-                match $cookie.get_reply() {
-                    Ok($reply) => $e,
-                    Err(_) => $win.valid = false,
-                })
+    /// print the line of monitor event `ev`, or `ev` as json with
+    /// `OutputFormat::Ndjson`
+    fn print_event(&self, ev: &WindowEvent, line: Arguments) {
+        if self.output != OutputFormat::Ndjson {
+            return self.print(line);
         }
+        if let Ok(json) = serde_json::to_string(ev) {
+            self.print(format_args!("{}", json));
+        }
+    }
 
-        let mut win = Window {
-            id: id,
-            name: "".to_string(),
-            attrs: Attributes{override_redirect: false, map_state: MapState::Unmapped},
-            geom: Geometry{x:0,y:0,width:0,height:0},
-            valid: true,
-        };
+    /// list clients of window manager the way `wmctrl -lG` does, honoring
+    /// the filter rules, returns how many are listed
+    pub fn dump_wmctrl(&self) -> usize {
+        let clients = self.timings.time("query_tree", || self.backend.client_list());
+        let windows = self.timings.time("properties", || self.backend.query_windows(&clients));
+
+        let windows = self.timings.time("filter", || {
+            self.rebuild_filter();
+            let filter = self.state.lock_filter();
+            windows.into_iter().filter(|w| filter.apply_to(w)).collect()
+        });
+        let windows = self.shown(windows);
+
+        let start = time::Instant::now();
+        let host_width = windows.iter()
+            .map(|w| w.client_machine.as_ref().map(|h| h.chars().count()).unwrap_or(3))
+            .max().unwrap_or(0);
+        for w in &windows {
+            let pos = self.backend.translate_coordinates(w.id, w.geom.x, w.geom.y)
+                .unwrap_or((w.geom.x, w.geom.y));
+            self.print(format_args!("{}", wmctrl_line(w, pos, host_width)));
+        }
+        self.timings.add("output", start.elapsed());
+        windows.len()
+    }
 
-        for query in qs {
-            match query {
-                XcbRequest::GWA(cookie) => {
-                    apply_reply!(win cookie reply {
-                        win.attrs.override_redirect = reply.override_redirect();
-                        win.attrs.map_state = match reply.map_state() {
-                            0 => MapState::Unmapped,
-                            2 => MapState::Viewable,
-                            _ => MapState::Unviewable,
-                        };
-                    })
-                },
-                XcbRequest::GE(cookie) => {
-                    apply_reply!(win cookie reply {
-                        win.geom = Geometry {
-                            x: reply.x(), 
-                            y: reply.y(),
-                            width: reply.width(),
-                            height: reply.height(),
-                        };
-                    })
-                },
-                XcbRequest::GWN(cookie) => {
-                    apply_reply!(win cookie reply {
-                        win.name = reply.string().to_string();
-                    })
-                },
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.metrics.clone()
+    }
 
-                _ => {}
-            }
-        }
+    /// time spent querying the server, filtering and writing windows out
+    /// so far: `query_tree`, `properties`, `filter` and `output`
+    pub fn timings(&self) -> &Timings {
+        &self.timings
+    }
 
-        return win;
+    pub fn add_sink(&mut self, sink: Arc<dyn EventSink>) {
+        self.sinks.push(sink);
     }
 
-    fn query_windows(&self, res: &xcb::QueryTreeReply) -> Vec<Window> {
-        let c = self.c;
+    /// account a handled event and the layout it leaves behind
+    pub(crate) fn record_event(&self, ev: &WindowEvent) {
+        self.metrics.record_event(ev.name());
+        let layout = self.state.read_layout();
+        self.record_layout_metrics(&layout);
+        self.notify_sinks(&layout, Some(ev));
+    }
 
-        let mut qs: Vec<XcbRequest> = Vec::new();
-        for w in res.children() {
-            qs.push(XcbRequest::GWA(xcb::get_window_attributes(&c, *w)));
-            qs.push(XcbRequest::GE(xcb::get_geometry(&c, *w)));
-            qs.push(XcbRequest::GWN(ewmh::get_wm_name_unchecked(&c, *w)));
+    fn notify_sinks(&self, layout: &WindowsLayout, ev: Option<&WindowEvent>) {
+        if self.sinks.is_empty() {
+            return;
         }
 
-        macro_rules! apply_reply {
-            ($win:ident $cookie:ident $reply:ident $e:expr) => (
-                match $cookie.get_reply() {
-                    Ok($reply) => $e,
-                    Err(err) => {
-                        wm_debug!("---######### {:?}", err);
-                        $win.valid = false
-                    },
-                })
+        let windows = self.shown(layout.filtered_windows());
+        for sink in &self.sinks {
+            sink.publish(ev, &windows);
         }
+    }
 
-        let mut windows = Vec::with_capacity(res.children_len() as usize);
-        let window_ids = res.children();
-
-        let ev_mask: u32 = xproto::EVENT_MASK_STRUCTURE_NOTIFY | xproto::EVENT_MASK_PROPERTY_CHANGE |
-            xproto::EVENT_MASK_FOCUS_CHANGE;
-        for (i, query) in qs.into_iter().enumerate() {
-            let idx = i / 3;
-            if i % 3 == 0 {
-                windows.push(Window {
-                    id: window_ids[idx],
-                    name: "".to_string(),
-                    attrs: Attributes{override_redirect: false, map_state: MapState::Unmapped},
-                    geom: Geometry{x:0,y:0,width:0,height:0},
-                    valid: true,
-                });
-            }
-
-            xcb::xproto::change_window_attributes(&c, window_ids[idx],
-                                                  &[(xcb::xproto::CW_EVENT_MASK, ev_mask)]);
-
-            if let Some(win) = windows.last_mut() {
-                match query {
-                    XcbRequest::GWA(cookie) => {
-                        apply_reply!(win cookie reply {
-                            win.attrs.override_redirect = reply.override_redirect();
-                            win.attrs.map_state = match reply.map_state() {
-                                0 => MapState::Unmapped,
-                                2 => MapState::Viewable,
-                                _ => MapState::Unviewable,
-                            };
-                        })
-                    },
-                    XcbRequest::GE(cookie) => {
-                        apply_reply!(win cookie reply {
-                            win.geom = Geometry {
-                                x: reply.x(), 
-                                y: reply.y(),
-                                width: reply.width(),
-                                height: reply.height(),
-                            };
-                        })
-                    },
-                    XcbRequest::GWN(cookie) => {
-                        apply_reply!(win cookie reply {
-                            win.name = reply.string().to_string();
-                        })
-                    },
-
-                    _ => {}
-                }
+    /// capture current cached windows, see `Snapshot`
+    pub fn snapshot(&self) -> Snapshot {
+        let mut snapshot = self.state.snapshot();
+        snapshot.taken_at = time::SystemTime::now().duration_since(time::UNIX_EPOCH).ok().map(|d| d.as_secs());
+        snapshot.server = Some(self.server_info());
+        if self.anonymize() {
+            snapshot.windows = self.shown(snapshot.windows);
+            // the display names the host of forwarded ones
+            if let Some(ref mut server) = snapshot.server {
+                server.display = None;
             }
+        }
+        snapshot
+    }
 
+    /// `windows` the way they are listed, anonymized if asked
+    pub(crate) fn shown(&self, windows: Vec<Window>) -> Vec<Window> {
+        if self.anonymize() {
+            windows.iter().map(Window::anonymized).collect()
+        } else {
+            windows
         }
+    }
 
-        return windows;
+    /// what is known about the server, `display` is the one connected to
+    /// if given to the builder
+    pub fn server_info(&self) -> ServerInfo {
+        let vendor = self.backend.vendor();
+        ServerInfo {
+            display: self.display.clone(),
+            vendor: vendor.as_ref().map(|v| v.0.clone()),
+            release: vendor.map(|v| v.1),
+            screen_size: self.backend.screen_size(),
+            xwayland: self.backend.is_xwayland(),
+        }
     }
-}
 
+    /// update cached windows with `ev` and fill in what the backend does
+    /// not know (e.g. `old` geometry), return true if it concerns the cached
+    /// windows and they should be dumped again
+    pub fn apply_event(&self, ev: &mut WindowEvent) -> bool {
+        match *ev {
+            WindowEvent::Created { window, parent } => {
+                if parent != self.root {
+                    return false;
+                }
 
-pub fn monitor(ctx: &Context) {
-    let ev_mask: u32 = xproto::EVENT_MASK_SUBSTRUCTURE_NOTIFY | xproto::EVENT_MASK_PROPERTY_CHANGE;
-    xcb::xproto::change_window_attributes(&ctx.c, ctx.root,
-                                          &[(xcb::xproto::CW_EVENT_MASK, ev_mask)]);
-    ctx.c.flush();
+                // assumes that window will be at top when created
+                let new_win = self.query_window(window);
+                self.state.update_with(new_win);
+                true
+            },
+            WindowEvent::Destroyed { window } => {
+                let concerned = self.state.is_window_concerned(window);
+                if concerned {
+                    self.state.remove(window);
+                }
+                concerned
+            },
+            WindowEvent::Reparented { window, parent } => {
+                let concerned = self.state.is_window_concerned(window);
+                if concerned {
+                    if parent != self.root {
+                        self.state.remove(window);
+                    } else {
+                        let new_win = self.query_window(window);
+                        self.state.update_with(new_win);
+                    }
+                }
+                concerned
+            },
+            WindowEvent::Configured { window, above_sibling, ref mut old, new, .. } => {
+                // the new geometry may take it in or out of the filtered view
+                let was_concerned = self.state.is_window_concerned(window);
+                *old = self.state.update_window(window, above_sibling, new);
+                self.enter_view(window, was_concerned)
+            },
+            WindowEvent::Mapped { window } | WindowEvent::Unmapped { window } => {
+                let was_concerned = self.state.is_window_concerned(window);
+                let state = if let WindowEvent::Mapped { .. } = *ev { MapState::Viewable } else { MapState::Unmapped };
+                self.state.with_window_mut(window, |win| {
+                    win.attrs.map_state = state;
+                }) && self.enter_view(window, was_concerned)
+            },
+            WindowEvent::PropertyChanged { window, kind } => {
+                if window == self.root {
+                    match kind {
+                        PropertyKind::ClientListStacking => self.update_clients(),
+                        PropertyKind::CurrentDesktop => self.follow_desktop(),
+                        _ => false,
+                    }
+                } else if kind.is_cached() {
+                    // the cached value is refreshed, and dumped again only if
+                    // it takes the window in or out of the filtered view
+                    let was_concerned = self.state.is_window_concerned(window);
+                    self.state.with_window_mut(window, |w| {
+                        self.backend.update_property(w, kind);
+                        wm_debug!("{} updated of {}", kind.name(), window);
+                    }) && was_concerned != self.state.is_window_concerned(window)
+                } else {
+                    false
+                }
+            },
+        }
+    }
+
+    /// copy of windows passing the filter in stacking order (bottom -> top)
+    pub fn windows(&self) -> Vec<Window> {
+        self.state.filtered_windows()
+    }
+
+    /// copy of all cached windows in stacking order (bottom -> top)
+    pub fn all_windows(&self) -> Vec<Window> {
+        self.state.all_windows()
+    }
+
+    /// copy of cached window `id`, filtered or not
+    pub fn window(&self, id: WindowId) -> Option<Window> {
+        self.state.window(id)
+    }
+
+    /// names window `id` had, oldest first, see `GlobalState::titles`
+    pub fn titles(&self, id: WindowId) -> Vec<Entry<String>> {
+        self.state.titles(id)
+    }
+
+    /// geometries window `id` had, oldest first, see `GlobalState::geometries`
+    pub fn geometries(&self, id: WindowId) -> Vec<Entry<Geometry>> {
+        self.state.geometries(id)
+    }
+
+    fn record_layout_metrics(&self, layout: &WindowsLayout) {
+        let mut by_state = ::std::collections::BTreeMap::new();
+        for w in layout.windows.values() {
+            let state = match w.attrs.map_state {
+                MapState::Unmapped => "unmapped",
+                MapState::Viewable => "viewable",
+                MapState::Unviewable => "unviewable",
+            };
+            *by_state.entry(state).or_insert(0) += 1;
+        }
+        self.metrics.set_window_counts(by_state, layout.filtered_view.len() as u64,
+                                       layout.pinned_windows.len() as u64);
+    }
+
+    pub fn backend(&self) -> &dyn Backend {
+        self.backend.as_ref()
+    }
+
+    /// watch the sheets rules were loaded from, if any
+    fn watch_sheets(&self) -> Option<SheetWatch> {
+        let mut paths: Vec<PathBuf> = Vec::new();
+        for rule in self.state.lock_filter().rules() {
+            match rule.origin {
+                RuleOrigin::Sheet(path) if !paths.contains(&path) => paths.push(path),
+                _ => {},
+            }
+        }
+        if paths.is_empty() {
+            return None;
+        }
+        SheetWatch::new(paths).map_err(|e| wm_log!(Warn, "sheets will not be reloaded: {}", e)).ok()
+    }
+
+    /// load `sheets` again in place of the rules they gave, and list windows
+    /// passing the new rules; a sheet failing to load keeps its old rules
+    pub(crate) fn reload_sheets(&self, sheets: &[PathBuf]) {
+        let mut reloaded = Vec::new();
+        {
+            let mut filter = self.state.lock_filter();
+            for path in sheets {
+                match filter.reload_sheet(path) {
+                    Ok(_) => reloaded.push(path.display().to_string()),
+                    Err(e) => wm_log!(Warn, "keeping the rules of {}: {}", path.display(), e),
+                }
+            }
+        }
+        if reloaded.is_empty() {
+            return;
+        }
+
+        wm_info!("reloaded {}", reloaded.join(", "));
+        self.state.refilter();
+        if self.output != OutputFormat::Ndjson {
+            self.print(format_args!("reloaded {}", reloaded.join(", ")));
+        }
+        self.dump_windows(None);
+    }
+
+    /// refresh internal windows cache from xserver
+    /// this is a very heavy operation and may stop the world now
+    /// (may be moved into a thread or so)
+    pub fn refresh_windows(&self) -> WmResult<()> {
+        let start = time::Instant::now();
+
+        self.timings.time("filter", || self.rebuild_filter());
+        let names = !self.no_names() || self.state.lock_filter().uses_names();
+        let windows = self.collect_windows(names)?;
+        self.timings.time("filter", || self.state.reset(windows));
+        if !names {
+            let ids = self.state.read_layout().filtered_view.to_vec();
+            self.timings.time("properties", || self.fetch_names(&ids));
+        }
+
+        self.metrics.record_refresh(start.elapsed());
+        let layout = self.state.read_layout();
+        self.record_layout_metrics(&layout);
+        self.notify_sinks(&layout, None);
+        Ok(())
+    }
+
+    fn collect_windows(&self, names: bool) -> WmResult<Vec<Window>> {
+        let children = self.timings.time("query_tree", || self.backend.query_tree())
+            .ok_or_else(|| self.request_error("query_tree"))?;
+
+        let mut target_windows = Vec::with_capacity(children.len());
+        self.collect_subtrees(&children, 1, names, &mut target_windows);
+        if let Some(t) = self.backend.timed_out() {
+            return Err(WmError::Timeout(t));
+        }
+        wm_info!("initial total #{}", target_windows.len());
+        Ok(target_windows)
+    }
+
+    /// names of `ids` left out while collecting, see `Options::NO_NAMES`
+    fn fetch_names(&self, ids: &[WindowId]) {
+        let names = self.backend.window_names(ids);
+        for (&id, name) in ids.iter().zip(names) {
+            if let Some((name, source)) = name {
+                self.state.with_window_mut(id, |w| {
+                    w.name = name.clone();
+                    w.name_source = Some(source);
+                });
+            }
+        }
+    }
+
+    /// true if `window` is concerned now or `was_concerned`, and fetch its
+    /// name if it just passed the filter without one
+    fn enter_view(&self, window: WindowId, was_concerned: bool) -> bool {
+        let concerned = self.state.is_window_concerned(window);
+        if concerned && !was_concerned && self.no_names() {
+            self.fetch_names(&[window]);
+        }
+        was_concerned || concerned
+    }
+
+    /// `WmError::Timeout` if `req` failed for the server not answering
+    fn request_error(&self, req: &'static str) -> WmError {
+        match self.backend.timed_out() {
+            Some(t) => WmError::Timeout(t),
+            None => WmError::Request(req),
+        }
+    }
+
+    /// query `ids` split among up to `jobs` threads, keeping their order
+    fn fetch_windows(&self, ids: &[WindowId], names: bool) -> Vec<Window> {
+        let chunk = ids.len().div_ceil(self.jobs).max(MIN_JOB_WINDOWS);
+        if self.jobs <= 1 || ids.len() <= chunk {
+            return self.backend.query_windows_with(ids, names);
+        }
+
+        thread::scope(|s| {
+            let parts: Vec<_> = ids.chunks(chunk)
+                .map(|part| s.spawn(move || self.backend.query_windows_with(part, names)))
+                .collect();
+            parts.into_iter()
+                .flat_map(|part| part.join().unwrap_or_else(|e| panic::resume_unwind(e)))
+                .collect()
+        })
+    }
+
+    /// query `ids` at `depth` below root, each followed by its subtree
+    /// until `max_depth`
+    fn collect_subtrees(&self, ids: &[WindowId], depth: usize, names: bool, windows: &mut Vec<Window>) {
+        let no_input_only = self.no_input_only();
+        let level: Vec<Window> = self.timings.time("properties", || self.fetch_windows(ids, names)).into_iter()
+            .filter(|w| !no_input_only || !w.attrs.input_only)
+            .collect();
+        if self.max_depth != 0 && depth >= self.max_depth {
+            windows.extend(level);
+            return;
+        }
+
+        // children of the whole level in one go, saving a round trip per window
+        let ids: Vec<WindowId> = level.iter().map(|w| w.id).collect();
+        let children = self.timings.time("query_tree", || self.backend.query_children_of(&ids));
+        for (w, children) in level.into_iter().zip(children) {
+            windows.push(w);
+            match children {
+                Some(ref children) if !children.is_empty() => self.collect_subtrees(children, depth + 1, names, windows),
+                _ => {},
+            }
+        }
+    }
+
+    /// rebuild filter rule set
+    /// rebuild will clear all adhoc rules and readd them for now, since some 
+    /// conditions can be changed (e.g _NET_CLIENT_LIST_STACKING)
+    fn rebuild_filter(&self) {
+        let options = self.options();
+        macro_rules! adhoc {
+            ($filter:ident, $m:expr) => ({
+                $filter.push_rule(Action::FilterOut, FilterRule::Adhoc, $m, RuleOrigin::Builtin);
+            })
+        }
+
+        let mut filter = self.state.lock_filter();
+        let clients_only = options.contains(Options::CLIENTS_ONLY);
+        // rules of sheets or add_rule stay, `clients` ones included
+        filter.rules.retain(|r| r.origin != RuleOrigin::Builtin
+            || (r.rule != FilterRule::Adhoc && (clients_only || r.rule != FilterRule::ClientsOnly)));
+
+        if options.contains(Options::MAPPED_ONLY) {
+            adhoc!(filter, RuleMatcher::MapState { state: MapState::Viewable, negate: false });
+        }
+
+        // minimized as the window manager tells, or moved out of the screen
+        // by the ones which do not
+        if options.contains(Options::OMIT_HIDDEN) {
+            let (width, height) = self.backend.screen_size();
+            adhoc!(filter, RuleMatcher::Iconified { value: false, negate: false });
+            adhoc!(filter, RuleMatcher::OnScreen { width, height });
+        }
+
+        if options.contains(Options::NO_SPECIAL) {
+            let specials = self.special_names.iter().map(|name| Wildcard::exact(name)).collect();
+            adhoc!(filter, RuleMatcher::NotNamed(specials));
+        }
+
+        let desktop = match self.desktop {
+            Some(Desktop::Number(n)) => Some(n),
+            Some(Desktop::Current) => self.backend.current_desktop(),
+            None => None,
+        };
+        if let Some(desktop) = desktop {
+            adhoc!(filter, RuleMatcher::Desktop { desktop, negate: false });
+        }
+
+        if let Some(ref name) = self.on_output {
+            match self.backend.outputs().into_iter().find(|o| o.0 == *name) {
+                Some((_, rect)) => adhoc!(filter, RuleMatcher::Intersects(rect)),
+                None => {
+                    wm_info!("no output {}, no window is on it", name);
+                    adhoc!(filter, RuleMatcher::Not(Box::new(RuleMatcher::Always)));
+                },
+            }
+        }
+
+        if clients_only {
+            self.update_clients_only_rule_locked(&mut filter);
+        }
+    }
+
+    fn update_clients_only_rule_locked(&self, filter: &mut Filter) {
+        //NOTE: clients is changing overtime, so we need to re-build this
+        //rule on the air every time clients list gets updated.
+        let clients = self.collect_window_manager_properties().into_iter().collect();
+
+        if let Some(r) = filter.rules.iter_mut().find(|r| r.rule == FilterRule::ClientsOnly && r.origin == RuleOrigin::Builtin) {
+            r.matcher = RuleMatcher::Clients(clients);
+        } else {
+            filter.push_rule(Action::FilterOut, FilterRule::ClientsOnly, RuleMatcher::Clients(clients), RuleOrigin::Builtin);
+        }
+    }
+
+    /// filter by the desktop switched to when following the current one,
+    /// return true if so
+    fn follow_desktop(&self) -> bool {
+        if self.desktop != Some(Desktop::Current) {
+            return false;
+        }
+        self.rebuild_filter();
+        self.state.refilter();
+        true
+    }
+
+    /// update clients related rule if necessary 
+    /// return true if updated or false when nothing to be done
+    pub fn update_clients(&self) -> bool {
+        let update = self.state.take_clients_pending_update() && self.clients_only();
+
+        if update {
+            //TODO: check if clients really changed ?
+            self.update_clients_only_rule_locked(&mut self.state.lock_filter());
+            self.state.refilter();
+        }
+
+        update
+    }
+
+    fn collect_window_manager_properties(&self) -> Vec<WindowId> {
+        self.backend.client_list()
+    }
+
+    pub fn query_window(&self, id: WindowId) -> Window {
+        self.backend.query_window(id)
+    }
+
+    /// the window the user clicks, None if cancelled
+    pub fn pick(&self) -> Option<Window> {
+        self.backend.pick_window().map(|id| self.backend.query_window(id)).filter(|w| w.valid)
+    }
+
+    /// all there is to tell of window `id`, cached or not, see `WindowReport`
+    pub fn window_report(&self, id: WindowId) -> WmResult<WindowReport> {
+        let window = self.window(id).unwrap_or_else(|| self.backend.query_window(id));
+        if !window.valid {
+            return Err(self.request_error("get_geometry"));
+        }
+
+        let mut parents: Vec<Window> = Vec::new();
+        let mut next = window.parent;
+        while let Some(id) = next.filter(|id| !parents.iter().any(|p| p.id == *id)) {
+            let p = self.window(id).unwrap_or_else(|| self.backend.query_window(id));
+            next = if id == self.backend.root() { None } else { p.parent };
+            parents.push(p);
+        }
+
+        let hints = self.backend.properties(id).into_iter().filter(|p| p.name.starts_with("WM_")).collect();
+        let rules = self.state.lock_filter().results(&window).iter().map(|(info, m)| RuleResult::new(info, *m)).collect();
+        Ok(WindowReport { window, parents, hints, rules })
+    }
+
+    /// outline window `id` on screen for `duration`, blocks meanwhile
+    pub fn flash(&self, id: WindowId, duration: time::Duration) -> WmResult<()> {
+        let w = self.backend.query_window(id);
+        if !w.valid {
+            return Err(self.request_error("get_geometry"));
+        }
+        let (x, y) = self.backend.translate_coordinates(id, 0, 0).unwrap_or((w.geom.x, w.geom.y));
+
+        let overlay = self.backend.highlight(Geometry { x, y, ..w.geom }, HIGHLIGHT_COLOR);
+        thread::sleep(duration);
+        self.backend.unhighlight(&overlay);
+        Ok(())
+    }
+
+    /// re-collect windows and print what changed since `last`, which is
+    /// replaced by the filtered windows now; true if anything changed
+    pub fn poll_changes(&self, last: &mut Snapshot) -> WmResult<bool> {
+        self.refresh_windows()?;
+        let now = self.snapshot().filtered_only();
+        let report = last.compare(&now);
+        *last = now;
+
+        if report.is_empty() {
+            return Ok(false);
+        }
+        self.print_report(&report)?;
+        Ok(true)
+    }
+
+    /// print `report` as json with `OutputFormat::Json`, as text otherwise
+    pub fn print_report(&self, report: &DiffReport) -> WmResult<()> {
+        let mut writer = self.writer.lock().unwrap();
+        match self.output {
+            OutputFormat::Json => report.write_json(&mut *writer)?,
+            _ => report.write_text(&mut *writer)?,
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// raise, close, ... window `id`, see `WindowAction`
+    pub fn control(&self, id: WindowId, action: WindowAction) -> WmResult<()> {
+        if self.backend.control(id, action) {
+            Ok(())
+        } else {
+            Err(self.request_error("control"))
+        }
+    }
+}
+
+
+/// Self-pipe to wake up a thread blocked in `poll(2)`.
+pub(crate) struct WakePipe {
+    fds: [RawFd; 2],
+}
+
+impl WakePipe {
+    pub(crate) fn new() -> io::Result<WakePipe> {
+        let mut fds = [-1; 2];
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC | libc::O_NONBLOCK) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(WakePipe { fds })
+    }
+
+    /// turns readable after `wake` until `drain`
+    pub(crate) fn read_fd(&self) -> RawFd {
+        self.fds[0]
+    }
+
+    pub(crate) fn wake(&self) {
+        // a full pipe is readable already
+        unsafe { libc::write(self.fds[1], b"w".as_ptr() as *const libc::c_void, 1) };
+    }
+
+    pub(crate) fn drain(&self) {
+        let mut buf = [0u8; 64];
+        while unsafe { libc::read(self.fds[0], buf.as_mut_ptr() as *mut libc::c_void, buf.len()) } > 0 {}
+    }
+}
+
+impl Drop for WakePipe {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fds[0]);
+            libc::close(self.fds[1]);
+        }
+    }
+}
+
+struct Shutdown {
+    stopped: AtomicBool,
+    pipe: WakePipe,
+}
+
+impl Shutdown {
+    fn new() -> WmResult<Shutdown> {
+        Ok(Shutdown { stopped: AtomicBool::new(false), pipe: WakePipe::new()? })
+    }
+
+    fn stop(&self) {
+        self.stopped.store(true, atomic::Ordering::Release);
+        self.pipe.wake();
+    }
+
+    fn is_stopped(&self) -> bool {
+        self.stopped.load(atomic::Ordering::Acquire)
+    }
+}
+
+/// Controls a monitor running in its own thread, see `spawn_monitor`.
+pub struct MonitorHandle {
+    shutdown: Arc<Shutdown>,
+    thread: thread::JoinHandle<WmResult<()>>,
+}
+
+impl MonitorHandle {
+    /// ask the monitor to quit, it returns after the event being handled
+    pub fn stop(&self) {
+        self.shutdown.stop();
+    }
+
+    /// wait for the monitor to quit, by `stop` or when events run out
+    pub fn join(self) -> WmResult<()> {
+        match self.thread.join() {
+            Ok(res) => res,
+            Err(e) => panic::resume_unwind(e),
+        }
+    }
+}
+
+/// Run `monitor` in a new thread, the returned handle stops it.
+pub fn spawn_monitor(ctx: Arc<Context>) -> WmResult<MonitorHandle> {
+    let shutdown = Arc::new(Shutdown::new()?);
+    let thread = {
+        let shutdown = shutdown.clone();
+        thread::Builder::new().name("wminspect-monitor".to_string())
+            .spawn(move || monitor_until(&ctx, &shutdown))?
+    };
+
+    Ok(MonitorHandle { shutdown, thread })
+}
+
+/// Block until next event, reloading the `sheets` changed in the meantime;
+/// None if events run out, the connection is lost or `shutdown` is stopped.
+fn next_event(ctx: &Context, shutdown: &Shutdown, sheets: Option<&SheetWatch>) -> Option<WindowEvent> {
+    loop {
+        if shutdown.is_stopped() {
+            return None;
+        }
 
-    ctx.refresh_windows();
+        if let Some(ev) = ctx.backend.poll_event() {
+            return Some(ev);
+        }
+
+        let fd = ctx.backend.event_fd()?;
+        if ctx.backend.connection_lost() {
+            return None;
+        }
+
+        let mut fds = [
+            libc::pollfd { fd, events: libc::POLLIN, revents: 0 },
+            libc::pollfd { fd: shutdown.pipe.read_fd(), events: libc::POLLIN, revents: 0 },
+            libc::pollfd { fd: sheets.map_or(-1, SheetWatch::fd), events: libc::POLLIN, revents: 0 },
+        ];
+        // retried on EINTR as well, a quiet server may as well be a frozen one
+        let ms = PING_INTERVAL.as_millis() as libc::c_int;
+        if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, ms) } == 0 && !ctx.backend.ping() {
+            return None;
+        }
+        // a stop is told by `is_stopped` above, the pipe only wakes us
+        if fds[1].revents & libc::POLLIN != 0 {
+            shutdown.pipe.drain();
+        }
+        if let (Some(sheets), true) = (sheets, fds[2].revents & libc::POLLIN != 0) {
+            ctx.reload_sheets(&sheets.changed());
+        }
+    }
+}
+
+/// Print changes of windows as events come, until the event source runs out.
+pub fn monitor(ctx: &Context) -> WmResult<()> {
+    monitor_until(ctx, &Shutdown::new()?)
+}
+
+/// Print changes of windows by re-collecting them every `interval` instead
+/// of selecting events on root, runs until a poll fails.
+pub fn watch(ctx: &Context, interval: time::Duration) -> WmResult<()> {
+    ctx.refresh_windows()?;
+    ctx.dump_windows(None);
+
+    let mut last = ctx.snapshot().filtered_only();
+    loop {
+        thread::sleep(interval);
+        ctx.poll_changes(&mut last)?;
+    }
+}
+
+fn monitor_until(ctx: &Context, shutdown: &Shutdown) -> WmResult<()> {
+    ctx.backend.watch_root();
+    let sheets = ctx.watch_sheets();
+
+    ctx.refresh_windows()?;
 
     let need_configure = AtomicBool::new(false);
     let (tx, rx) = mpsc::channel::<Message>();
@@ -743,17 +1681,17 @@ pub fn monitor(ctx: &Context) {
     crossbeam::scope(|scope| {
         {
             scope.spawn(move |_| {
-                let idle_configure_timeout = time::Duration::from_millis(50);
+                let idle_configure_timeout = ctx.debounce;
                 let mut last_checked_time = time::Instant::now();
 
-                let mut raw_cne = None;
+                let mut last_cne = None;
 
                 loop {
                     match rx.recv_timeout(time::Duration::from_millis(10)) {
-                        Ok(Message::LastConfigureEvent(raw)) => { 
+                        Ok(Message::LastConfigureEvent { window, above_sibling }) => {
                             last_checked_time = time::Instant::now();
                             need_configure.store(true, atomic::Ordering::Release);
-                            raw_cne = Some(raw);
+                            last_cne = Some((window, above_sibling));
                         },
                         Ok(Message::Reset) => { 
                             need_configure.store(false, atomic::Ordering::Release);
@@ -763,19 +1701,16 @@ pub fn monitor(ctx: &Context) {
                     }
 
                     if need_configure.load(atomic::Ordering::Acquire) && last_checked_time.elapsed() > idle_configure_timeout {
-                        let raw_cne = raw_cne.unwrap();
-                        let cne = xcb::ConfigureNotifyEvent::new(
-                            raw_cne.event, raw_cne.window, raw_cne.above_sibling,
-                            raw_cne.x, raw_cne.y, raw_cne.width, raw_cne.height, 
-                            raw_cne.border_width,
-                            if raw_cne.override_redirect == 0 {false} else {true});
-
-                        if ctx.is_window_concerned(cne.window()) {
+                        let (window, above_sibling) = last_cne.unwrap();
+
+                        if ctx.state.is_window_concerned(window) {
                             wm_debug!("timedout, reload");
-                            println!("delayed configure {:#x} ", cne.window());
+                            if ctx.output != OutputFormat::Ndjson {
+                                ctx.print(format_args!("delayed configure {} ", window));
+                            }
 
                             let diff = if ctx.show_diff() {
-                                Some(hashset!(cne.window(), cne.above_sibling()))
+                                Some(hashset!(window, above_sibling))
                             } else {
                                 None
                             };
@@ -789,157 +1724,96 @@ pub fn monitor(ctx: &Context) {
             });
         }
 
-        //TODO: name change should invalidate some rules and we need to re-triggerit
-        let handle_property_event = |pn: &xcb::PropertyNotifyEvent| {
-            if pn.atom() == ctx.c.WM_NAME() {
-                ctx.with_window_mut(pn.window(), |w| {
-                    let cookie = ewmh::get_wm_name_unchecked(&ctx.c, w.id);
-                    match cookie.get_reply() {
-                        Ok(reply) => {
-                            w.name = reply.string().to_string();
-                            wm_debug!("name updated {:#x} -> {}", pn.window(), w.name);
-                        },
-                        _ => {}
-                    }
-                });
+        let mut last_configure_xid = WINDOW_NONE;
+        while let Some(first) = next_event(ctx, shutdown, sheets.as_ref()) {
+            // take whatever is queued too and dump once for all of it
+            let mut batch = vec![first];
+            while batch.len() < MAX_BATCH {
+                match ctx.backend.poll_event() {
+                    Some(ev) => batch.push(ev),
+                    None => break,
+                }
             }
-        };
 
+            let mut dump = false;
+            let mut changed = HashSet::new();
+            let mut stop = false;
+            for mut ev in batch {
+                if let WindowEvent::Created { parent, .. } = ev {
+                    if parent != ctx.root {
+                        stop = true;
+                        break;
+                    }
+                }
 
-        let mut last_configure_xid = xcb::WINDOW_NONE;
-        loop {
-            if let Some(ev) = ctx.c.wait_for_event() {
-                //wm_debug!("event: {}", ev.response_type() & !0x80);
-                match ev.response_type() & !0x80 {
-                    xcb::xproto::CREATE_NOTIFY => {
-                        let cne = as_event::<xcb::CreateNotifyEvent>(&ev);
-                        if cne.parent() != ctx.root {
-                            break;
-                        }
-                        println!("create 0x{:x}, parent 0x{:x}", cne.window(), cne.parent());
-
-                        // assumes that window will be at top when created
-                        let new_win = ctx.query_window(cne.window());
-                        ctx.update_with(new_win);
-                        let diff = if ctx.show_diff() {
-                            Some(hashset!(cne.window()))
-                        } else {
-                            None
-                        };
-
-                        ctx.dump_windows(diff);
-                    },
-                    xcb::xproto::DESTROY_NOTIFY => {
-                        let dne = as_event::<xcb::DestroyNotifyEvent>(&ev);
-
-                        if ctx.is_window_concerned(dne.window()) {
-                            println!("destroy 0x{:x}", dne.window());
-                            ctx.remove(dne.window());
-
-                            ctx.dump_windows(None);
-                        }
-                    },
-
-                    xcb::xproto::REPARENT_NOTIFY => {
-                        let rne = as_event::<xcb::ReparentNotifyEvent>(&ev);
-
-                        if ctx.is_window_concerned(rne.window()) {
-                            if rne.parent() != ctx.root {
-                                println!("reparent 0x{:x} to 0x{:x}", rne.window(), rne.parent());
-                                ctx.remove(rne.window());
-
-                                ctx.dump_windows(None);
+                let was_pinned = ctx.bell.is_some() && ctx.state.read_layout().pinned_windows.contains(&ev.window());
+                let concerned = ctx.apply_event(&mut ev);
+                ctx.ring_bell(&ev, was_pinned);
+                if concerned {
+                    match ev {
+                        WindowEvent::Created { window, parent } => {
+                            ctx.print_event(&ev, format_args!("create {}, parent {}", window, parent));
+                            changed.insert(window);
+                            dump = true;
+                        },
+                        WindowEvent::Destroyed { window } => {
+                            ctx.print_event(&ev, format_args!("destroy {}", window));
+                            dump = true;
+                        },
 
+                        WindowEvent::Reparented { window, parent } => {
+                            if parent != ctx.root {
+                                ctx.print_event(&ev, format_args!("reparent {} to {}", window, parent));
                             } else {
-                                println!("reparent 0x{:x} to root", rne.window());
-                                let new_win = ctx.query_window(rne.window());
-                                ctx.update_with(new_win);
-
-                                let diff = if ctx.show_diff() {
-                                    Some(hashset!(rne.window()))
-                                } else {
-                                    None
-                                };
-                                ctx.dump_windows(diff);
+                                ctx.print_event(&ev, format_args!("reparent {} to root", window));
+                                changed.insert(window);
                             }
-                        }
-                    },
-
-                    xproto::CONFIGURE_NOTIFY => {
-                        let cne = as_event::<xcb::ConfigureNotifyEvent>(&ev);
-                        ctx.update_window(cne);
-
-                        if ctx.is_window_concerned(cne.window()) {
-                            if last_configure_xid != cne.window() {
-                                println!("configure 0x{:x} above: 0x{:x}", cne.window(), cne.above_sibling());
-                                let diff = if ctx.show_diff() {
-                                    Some(hashset!(cne.window(), cne.above_sibling()))
-                                } else {
-                                    None
-                                };
-
+                            dump = true;
+                        },
 
-                                ctx.dump_windows(diff);
-                                last_configure_xid = cne.window();
+                        WindowEvent::Configured { window, above_sibling, .. } => {
+                            if last_configure_xid != window {
+                                ctx.print_event(&ev, format_args!("configure {} above: {}", window, above_sibling));
+                                changed.insert(window);
+                                changed.insert(above_sibling);
+                                dump = true;
+                                last_configure_xid = window;
                                 tx.send(Message::Reset).unwrap();
 
                             } else {
-                                let clone: xcb::ffi::xcb_configure_notify_event_t = unsafe {*cne.ptr}.clone();
-                                tx.send(Message::LastConfigureEvent(clone)).unwrap();
+                                tx.send(Message::LastConfigureEvent { window, above_sibling }).unwrap();
                             }
-                        }
-                    },
-
-                    xproto::MAP_NOTIFY => {
-                        let mn = as_event::<xcb::MapNotifyEvent>(&ev);
+                        },
 
-                        if ctx.is_window_concerned(mn.window()) {
-                            ctx.with_window_mut(mn.window(), |win| {
-                                win.attrs.map_state = MapState::Viewable;
-                            });
-                            ctx.update_pin_state(mn.window());
+                        WindowEvent::Mapped { window } => {
+                            ctx.print_event(&ev, format_args!("map {}", window));
+                            changed.insert(window);
+                            dump = true;
+                        },
 
-                            println!("map 0x{:x}", mn.window());
+                        WindowEvent::Unmapped { window } => {
+                            ctx.print_event(&ev, format_args!("unmap {}", window));
+                            dump = true;
+                        },
 
-                            let diff = if ctx.show_diff() {
-                                Some(hashset!(mn.window()))
-                            } else {
-                                None
-                            };
-                            ctx.dump_windows(diff);
-                        }
-                    },
-
-                    xproto::UNMAP_NOTIFY => {
-                        let un = as_event::<xcb::UnmapNotifyEvent>(&ev);
-
-                        if ctx.is_window_concerned(un.window()) {
-                            ctx.with_window_mut(un.window(), |win| {
-                                win.attrs.map_state = MapState::Unmapped;
-                            });
-                            ctx.update_pin_state(un.window());
-                            println!("unmap 0x{:x}", un.window());
-                            ctx.dump_windows(None);
-                        }
-                    },
-
-                    xproto::PROPERTY_NOTIFY => {
-                        let pn = as_event::<xcb::PropertyNotifyEvent>(&ev);
-                        if pn.window() == ctx.root {
-                            if pn.atom() == ctx.c.CLIENT_LIST_STACKING() {
-                                if ctx.update_clients() {
-                                    ctx.dump_windows(None);
-                                }
+                        WindowEvent::PropertyChanged { window, kind } => {
+                            if window != ctx.root {
+                                ctx.print_event(&ev, format_args!("{} of {} changed", kind.name(), window));
                             }
-                        } else {
-                            handle_property_event(pn);
-                        }
-                    },
+                            dump = true;
+                        },
+                    } 
+                }
 
-                    _ => {
-                    },
-                } 
-            };
+                ctx.record_event(&ev);
+            }
+
+            if dump {
+                ctx.dump_windows(if ctx.show_diff() { Some(changed) } else { None });
+            }
+            if stop {
+                break;
+            }
         }
 
         match tx.send(Message::Quit) {
@@ -947,8 +1821,23 @@ pub fn monitor(ctx: &Context) {
             Err(_) => {wm_debug!("send message error")}
         }
     }).unwrap();
+
+    match ctx.backend.timed_out() {
+        Some(t) => Err(WmError::Timeout(t)),
+        None => Ok(()),
+    }
 }
 
+/// format `w` like `wmctrl -lG`, `pos` is its position relative to root
+pub(crate) fn wmctrl_line(w: &Window, pos: (i16, i16), host_width: usize) -> String {
+    let host = w.client_machine.as_deref().unwrap_or("N/A");
+    let title = if w.name.is_empty() { "N/A" } else { w.name.as_str() };
+    format!("0x{:08x} {:2} {:<4} {:<4} {:<4} {:<4} {:>hw$} {}",
+            w.id, w.desktop.unwrap_or(0) as i32, pos.0, pos.1, w.geom.width, w.geom.height,
+            host, title, hw = host_width)
+}
+
+#[cfg(feature = "term")]
 fn get_tty_cols() -> Option<usize> {
     unsafe {
         // winsz = std::mem::uninitialized();
@@ -962,23 +1851,844 @@ fn get_tty_cols() -> Option<usize> {
     }
 }
 
-//TODO: cut off name according to tty columns
-fn win2str(w: &Window, mut colored: bool) -> String {
-    let geom_str = format!("{}", w.geom);
-    let id = format!("0x{:x}", w.id);
-    let attrs = format!("{}", w.attrs);
+pub(crate) fn win2str(w: &Window, theme: Option<&Theme>) -> String {
+    let (theme, name_cols) = listing_style(theme);
+    let mut s = String::new();
+    let _ = write_window(&mut s, w, theme, name_cols);
+    s
+}
 
+/// `theme` for listing windows unless stdout is not a tty, and how many
+/// chars of names fit in with colors
+#[cfg(feature = "term")]
+fn listing_style(theme: Option<&Theme>) -> (Option<&Theme>, usize) {
     if unsafe { libc::isatty(libc::STDOUT_FILENO) } == 0 {
-        colored = false;
-    } 
-    let cols = get_tty_cols().unwrap_or(80) / 2;
+        return (None, 0);
+    }
+    (theme, get_tty_cols().unwrap_or(80) / 2)
+}
+
+/// colors need the `term` feature
+#[cfg(not(feature = "term"))]
+fn listing_style(_theme: Option<&Theme>) -> (Option<&Theme>, usize) {
+    (None, 0)
+}
+
+/// write `w` as a line of listing, with colors names are cut to `name_cols`
+/// chars
+//TODO: cut off name according to tty columns
+fn write_window<W: Write>(out: &mut W, w: &Window, theme: Option<&Theme>, name_cols: usize) -> Result {
+    let t = match theme {
+        Some(t) => t,
+        None => return write!(out, "{}", w),
+    };
+
     //FIXME: try estimate length by bytes, not chars
-    let name = w.name.chars().take(cols).collect::<String>();
+    let name = match w.name.char_indices().nth(name_cols) {
+        Some((end, _)) => &w.name[..end],
+        None => w.name.as_str(),
+    };
+    write!(out, "{}(", Painted(&t.id, w.id))?;
+    if !name.is_empty() {
+        write!(out, "{}", Painted(&t.name, name))?;
+    }
+    write!(out, ") {} {}", Painted(&t.geometry, w.geom), Painted(&t.attrs, &w.attrs))?;
+    if let Some(p) = w.partly_visible() {
+        write!(out, " {}", Painted(&t.attrs, format_args!("{}% visible", p)))?;
+    }
+    if w.xwayland {
+        write!(out, "{}", Painted(&t.xwayland, " XWL"))?;
+    }
+    if let Some(host) = w.remote_host() {
+        write!(out, " {}", Painted(&t.remote, format_args!("@{}", host)))?;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "term")]
+fn colorize() -> bool {
+    SHOULD_COLORIZE.should_colorize()
+}
+
+#[cfg(not(feature = "term"))]
+fn colorize() -> bool {
+    false
+}
+
+/// displays the value in the style unless colors are turned off, e.g. by
+/// CLICOLOR=0
+struct Painted<'a, T>(&'a Style, T);
 
-    if colored {
-        format!("{}({}) {} {}", id.blue(), name.cyan(), geom_str.red(), attrs.green())
-    } else {
-        format!("{}({}) {} {}", id, w.name, geom_str, attrs)
+impl<'a, T: Display> Display for Painted<'a, T> {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        if self.0.is_plain() || !colorize() {
+            write!(f, "{}", self.1)
+        } else {
+            write!(f, "\x1b[{}m{}\x1b[0m", self.0, self.1)
+        }
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::mock::{window, MockBackend};
+
+    fn filtered(ctx: &Context) -> Vec<WindowId> {
+        ctx.state.read_layout().filtered_view.to_vec()
+    }
+
+    #[test]
+    fn test_refresh_with_filter() {
+        let mock = MockBackend::new(1920, 1080);
+        mock.add_window(window(WindowId(0x100), "dde-osd").mapped())
+            .add_window(window(WindowId(0x200), "firefox").mapped())
+            .add_window(window(WindowId(0x300), "dde-dock").mapped());
+
+        let ctx = Context::new(mock, Filter::parse("name = dde*").unwrap());
+        ctx.refresh_windows().unwrap();
+        assert_eq!(filtered(&ctx), vec![WindowId(0x100), WindowId(0x300)]);
+    }
+
+    #[test]
+    fn test_windows() {
+        let mock = MockBackend::new(1920, 1080);
+        mock.add_window(window(WindowId(0x100), "dde-osd").mapped())
+            .add_window(window(WindowId(0x200), "firefox").mapped())
+            .add_window(window(WindowId(0x300), "dde-dock").mapped());
+
+        let ctx = Context::new(mock, Filter::parse("name = dde*").unwrap());
+        ctx.refresh_windows().unwrap();
+
+        let ids = |ws: Vec<Window>| ws.iter().map(|w| w.id).collect::<Vec<_>>();
+        assert_eq!(ids(ctx.windows()), vec![WindowId(0x100), WindowId(0x300)]);
+        assert_eq!(ids(ctx.all_windows()), vec![WindowId(0x100), WindowId(0x200), WindowId(0x300)]);
+        assert_eq!(ctx.window(WindowId(0x200)).map(|w| w.name), Some("firefox".to_string()));
+        assert!(ctx.window(WindowId(0x400)).is_none());
+    }
+
+    #[test]
+    fn test_builder() {
+        let mock = MockBackend::new(1920, 1080);
+        mock.add_window(window(WindowId(0x100), "dde-osd").mapped())
+            .add_window(window(WindowId(0x200), "dde-dock").at(-200, -200).mapped());
+        let mut hidden = window(WindowId(0x300), "dde-hidden").mapped();
+        hidden.attrs.map_state = MapState::Unmapped;
+        mock.add_window(hidden);
+
+        let ctx = Context::builder()
+            .filter(Filter::parse("name = dde*").unwrap())
+            .options(Options::MAPPED_ONLY | Options::OMIT_HIDDEN)
+            .options(Options::MAPPED_ONLY)
+            .output("wmctrl".parse().unwrap())
+            .debounce(time::Duration::from_millis(200))
+            .build(mock);
+        assert_eq!(ctx.options(), Options::MAPPED_ONLY | Options::OMIT_HIDDEN);
+        assert_eq!(ctx.output_format(), OutputFormat::Wmctrl);
+        assert_eq!(ctx.debounce(), time::Duration::from_millis(200));
+        assert!("yaml".parse::<OutputFormat>().is_err());
+
+        ctx.refresh_windows().unwrap();
+        assert_eq!(filtered(&ctx), vec![WindowId(0x100)]);
+    }
+
+    /// collects output of a `Context` for checking
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SharedBuf {
+        fn text(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    #[test]
+    fn test_writer() {
+        let mock = MockBackend::new(1920, 1080);
+        mock.add_window(window(WindowId(0x100), "dde-osd").mapped());
+        let buf = SharedBuf::default();
+        let ctx = Context::builder().writer(buf.clone()).build(mock.clone());
+
+        monitor(&ctx).unwrap();
+        mock.map_window(WindowId(0x100));
+        monitor(&ctx).unwrap();
+        let text = buf.text();
+        assert!(text.starts_with("0: 0x100(dde-osd) "), "{}", text);
+        assert!(text.contains("map 0x100\n"), "{}", text);
+    }
+
+    /// cargo test --release bench_dump_windows -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn bench_dump_windows() {
+        let mock = MockBackend::new(1920, 1080);
+        for i in 0..1000 {
+            mock.add_window(window(WindowId(0x100 + i), &format!("window {}", i)).mapped());
+        }
+        let ctx = Context::builder().writer(io::sink()).options(Options::SHOW_DIFF | Options::COLORFUL).build(mock);
+        ctx.refresh_windows().unwrap();
+
+        let changes: HashSet<WindowId> = (0..1000).step_by(10).map(|i| WindowId(0x100 + i)).collect();
+        let start = time::Instant::now();
+        for _ in 0..100 {
+            ctx.dump_windows(Some(changes.clone()));
+        }
+        println!("dump of 1000 windows: {:?}", start.elapsed() / 100);
+    }
+
+    #[test]
+    fn test_monitor_batch() {
+        let mock = MockBackend::new(1920, 1080);
+        mock.add_window(window(WindowId(0x100), "first").mapped())
+            .add_window(window(WindowId(0x200), "second").mapped());
+        let buf = SharedBuf::default();
+        let ctx = Context::builder().writer(buf.clone()).build(mock.clone());
+
+        // queued events are printed one by one, then listed once
+        mock.unmap_window(WindowId(0x100)).map_window(WindowId(0x100)).unmap_window(WindowId(0x200));
+        monitor(&ctx).unwrap();
+        let text = buf.text();
+        let events: Vec<&str> = text.lines().filter(|l| !l.starts_with("0: ") && !l.starts_with("1: ")).collect();
+        assert_eq!(events, vec!["unmap 0x100", "map 0x100", "unmap 0x200"]);
+        assert_eq!(text.lines().filter(|l| l.starts_with("0: ")).count(), 2, "{}", text);
+    }
+
+    #[test]
+    fn test_monitor_bell() {
+        let path = ::std::env::temp_dir().join(format!("wminspect-bell-{}", ::std::process::id()));
+        let _ = ::std::fs::remove_file(&path);
+        let mock = MockBackend::new(1920, 1080);
+        let mut popup = window(WindowId(0x100), "popup").mapped();
+        popup.attrs.map_state = MapState::Unmapped;
+        mock.add_window(popup).add_window(window(WindowId(0x200), "xterm").mapped()).set_live(true);
+        let cmd = format!("echo $WM_EVENT $WM_WINDOW >> {}", path.display());
+        let ctx = Arc::new(Context::builder()
+            .filter(Filter::parse("name = popup: pin").unwrap())
+            .bell(Bell::Command(cmd))
+            .writer(SharedBuf::default())
+            .build(mock.clone()));
+        let handle = spawn_monitor(ctx.clone()).unwrap();
+
+        let start = time::Instant::now();
+        while ctx.all_windows().len() != 2 {
+            assert!(start.elapsed() < time::Duration::from_secs(5), "not listed");
+            thread::sleep(time::Duration::from_millis(10));
+        }
+        mock.map_window(WindowId(0x100))
+            .unmap_window(WindowId(0x200))
+            .create_window(window(WindowId(0x300), "popup").mapped())
+            .destroy_window(WindowId(0x100))
+            .destroy_window(WindowId(0x200));
+
+        // commands run aside, in whatever order
+        let mut rung = Vec::new();
+        while rung.len() < 3 {
+            assert!(start.elapsed() < time::Duration::from_secs(5), "rung {:?}", rung);
+            thread::sleep(time::Duration::from_millis(10));
+            rung = ::std::fs::read_to_string(&path).unwrap_or_default().lines().map(|l| l.to_string()).collect();
+        }
+        handle.stop();
+        handle.join().unwrap();
+        ::std::fs::remove_file(&path).unwrap();
+        rung.sort();
+        assert_eq!(rung, vec!["create 0x300", "destroy 0x100", "map 0x100"]);
+    }
+
+    #[test]
+    fn test_monitor_pinned() {
+        let mock = MockBackend::new(1920, 1080);
+        mock.add_window(window(WindowId(0x100), "xterm").mapped())
+            .add_window(window(WindowId(0x200), "popup").mapped())
+            .add_window(window(WindowId(0x300), "xclock").mapped());
+        let buf = SharedBuf::default();
+        let ctx = Context::builder()
+            .filter(Filter::parse("name = xterm; name = popup: pin").unwrap())
+            .writer(buf.clone())
+            .build(mock.clone());
+
+        // the filter rule alone would leave popup out, xclock stays out
+        mock.unmap_window(WindowId(0x200)).unmap_window(WindowId(0x300));
+        monitor(&ctx).unwrap();
+        let text = buf.text();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines, vec![
+            "0: 0x100(xterm) 100x100+0+0 Viewable", "1: *0x200(popup) 100x100+0+0 Unmapped",
+            "unmap 0x200",
+            "0: 0x100(xterm) 100x100+0+0 Viewable", "1: *0x200(popup) 100x100+0+0 Unmapped",
+        ], "{}", text);
+    }
+
+    #[test]
+    fn test_monitor_ndjson() {
+        let mock = MockBackend::new(1920, 1080);
+        mock.add_window(window(WindowId(0x100), "dde-osd").mapped());
+        let buf = SharedBuf::default();
+        let ctx = Context::builder().writer(buf.clone()).output(OutputFormat::Ndjson).build(mock.clone());
+
+        mock.unmap_window(WindowId(0x100));
+        monitor(&ctx).unwrap();
+        let text = buf.text();
+        let lines: Vec<serde_json::Value> = text.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+        assert_eq!(lines.len(), 3, "{}", text);
+        assert_eq!(lines[0]["name"], "dde-osd");
+        assert_eq!(lines[1]["type"], "unmap");
+        assert_eq!(lines[2]["attrs"]["map_state"], "Unmapped");
+    }
+
+    #[test]
+    fn test_max_depth() {
+        let mock = MockBackend::new(1920, 1080);
+        let mut input = window(WindowId(0x120), "input").mapped();
+        input.attrs.input_only = true;
+        mock.add_window(window(WindowId(0x100), "frame").mapped())
+            .add_window(window(WindowId(0x200), "other").mapped())
+            .add_child(WindowId(0x100), window(WindowId(0x110), "client").mapped())
+            .add_child(WindowId(0x100), input)
+            .add_child(WindowId(0x110), window(WindowId(0x111), "canvas").mapped());
+        let ids = |ctx: &Context| ctx.all_windows().iter().map(|w| w.id.0).collect::<Vec<_>>();
+
+        let ctx = Context::new(mock.clone(), Filter::new());
+        ctx.refresh_windows().unwrap();
+        assert_eq!(ids(&ctx), vec![0x100, 0x200]);
+
+        let ctx = Context::builder().max_depth(2).build(mock.clone());
+        ctx.refresh_windows().unwrap();
+        assert_eq!(ids(&ctx), vec![0x100, 0x110, 0x120, 0x200]);
+
+        let ctx = Context::builder().max_depth(0).options(Options::NO_INPUT_ONLY).build(mock.clone());
+        ctx.refresh_windows().unwrap();
+        assert_eq!(ctx.max_depth(), 0);
+        assert_eq!(ids(&ctx), vec![0x100, 0x110, 0x111, 0x200]);
+    }
+
+    #[test]
+    fn test_tree() {
+        let mock = MockBackend::new(1920, 1080);
+        mock.add_window(window(WindowId(0x100), "frame").mapped())
+            .add_window(window(WindowId(0x200), "other").at(200, 0).mapped())
+            .add_child(WindowId(0x100), window(WindowId(0x110), "client").mapped())
+            .add_child(WindowId(0x100), window(WindowId(0x120), "title").mapped())
+            .add_child(WindowId(0x110), window(WindowId(0x111), "canvas").mapped());
+        let buf = SharedBuf::default();
+        // canvas goes below frame, client being left out
+        let ctx = Context::builder().max_depth(0).options(Options::TREE)
+            .filter(Filter::parse("name <> client").unwrap())
+            .writer(buf.clone()).build(mock);
+        ctx.refresh_windows().unwrap();
+        assert_eq!(ctx.state.read_layout().filtered_tree(),
+                   vec![(WindowId(0x100), 0), (WindowId(0x111), 1), (WindowId(0x120), 1), (WindowId(0x200), 0)]);
+
+        ctx.dump_windows(None);
+        let names: Vec<String> = buf.text().lines().map(|l| l.split('(').next().unwrap().to_string()).collect();
+        assert_eq!(names, vec!["0: 0x100", "1:   0x111", "2:   0x120", "3: 0x200"]);
+    }
+
+    #[test]
+    fn test_jobs() {
+        let mock = MockBackend::new(1920, 1080);
+        for i in 0..(MIN_JOB_WINDOWS as u32 * 3 + 5) {
+            mock.add_window(window(WindowId(0x100 + i), "w").mapped());
+        }
+        let ids = |ctx: &Context| ctx.all_windows().iter().map(|w| w.id).collect::<Vec<_>>();
+
+        let serial = Context::new(mock.clone(), Filter::new());
+        serial.refresh_windows().unwrap();
+        let parallel = Context::builder().jobs(4).build(mock.clone());
+        parallel.refresh_windows().unwrap();
+        assert_eq!(parallel.jobs(), 4);
+        assert_eq!(ids(&parallel), ids(&serial));
+        assert_eq!(ids(&parallel).len(), MIN_JOB_WINDOWS * 3 + 5);
+    }
+
+    #[test]
+    fn test_request_timeout() {
+        let mock = MockBackend::new(1920, 1080);
+        mock.add_window(window(WindowId(0x100), "first").mapped());
+        let ctx = Context::new(mock.clone(), Filter::new());
+        ctx.refresh_windows().unwrap();
+
+        mock.freeze(time::Duration::from_secs(2));
+        match ctx.refresh_windows() {
+            Err(WmError::Timeout(t)) => assert_eq!(t, time::Duration::from_secs(2)),
+            other => panic!("{:?}", other),
+        }
+        let e = monitor(&ctx).unwrap_err();
+        assert_eq!(e.to_string(), "X server did not answer within 2.0s, giving up");
+    }
+
+    #[test]
+    fn test_poll_changes() {
+        let mock = MockBackend::new(1920, 1080);
+        mock.add_window(window(WindowId(0x100), "first").at(10, 20).mapped());
+        let buf = SharedBuf::default();
+        let ctx = Context::builder().writer(buf.clone()).build(mock.clone());
+        ctx.refresh_windows().unwrap();
+
+        let mut last = ctx.snapshot().filtered_only();
+        assert!(!ctx.poll_changes(&mut last).unwrap());
+        assert!(buf.text().is_empty());
+
+        mock.rename_window(WindowId(0x100), "renamed");
+        assert!(ctx.poll_changes(&mut last).unwrap());
+        assert_eq!(buf.text(), "~ 0x100 name \"first\" -> \"renamed\"\n");
+        assert_eq!(last.windows[0].name, "renamed");
+    }
+
+    #[test]
+    fn test_refresh_omit_hidden() {
+        let mock = MockBackend::new(1920, 1080);
+        mock.add_window(window(WindowId(0x100), "visible").at(10, 10).mapped())
+            .add_window(window(WindowId(0x200), "offscreen").at(-200, -200).mapped());
+
+        let ctx = Context::new(mock.clone(), Filter::new());
+        ctx.set_option(Options::OMIT_HIDDEN);
+        ctx.refresh_windows().unwrap();
+        assert_eq!(filtered(&ctx), vec![WindowId(0x100)]);
+
+        // minimized in place, told by WM_STATE
+        mock.update_window(WindowId(0x100), PropertyKind::WmState, |w| w.attrs.iconified = true);
+        let mut ev = ctx.backend().poll_event().unwrap();
+        assert!(ctx.apply_event(&mut ev));
+        assert!(filtered(&ctx).is_empty());
+        mock.update_window(WindowId(0x100), PropertyKind::WmState, |w| w.attrs.iconified = false);
+        let mut ev = ctx.backend().poll_event().unwrap();
+        assert!(ctx.apply_event(&mut ev));
+
+        // toggled at runtime
+        ctx.toggle_option(Options::OMIT_HIDDEN);
+        assert!(!ctx.omit_hidden());
+        assert_eq!(filtered(&ctx), vec![WindowId(0x100), WindowId(0x200)]);
+        assert_eq!(ctx.state().read_layout().windows.len(), 2);
+    }
+
+    #[test]
+    fn test_refresh_no_special() {
+        let mock = MockBackend::new(1920, 1080);
+        mock.add_window(window(WindowId(0x100), "mutter guard window").mapped())
+            .add_window(window(WindowId(0x200), "KWin").mapped())
+            .add_window(window(WindowId(0x300), "top edge window").mapped())
+            .add_window(window(WindowId(0x400), "xterm").mapped());
+
+        let ctx = Context::new(mock.clone(), Filter::new());
+        ctx.set_option(Options::NO_SPECIAL);
+        ctx.refresh_windows().unwrap();
+        assert_eq!(filtered(&ctx), vec![WindowId(0x200), WindowId(0x300), WindowId(0x400)]);
+
+        let names = special_preset("kwin").unwrap().iter().map(|s| s.to_string())
+            .chain(Some("*edge window".to_string())).collect();
+        let ctx = Context::builder().special_names(names).options(Options::NO_SPECIAL).build(mock);
+        ctx.refresh_windows().unwrap();
+        assert_eq!(filtered(&ctx), vec![WindowId(0x100), WindowId(0x400)]);
+        assert!(special_preset("twm").is_none());
+    }
+
+    #[test]
+    fn test_user_clients_rule() {
+        let mock = MockBackend::new(1920, 1080);
+        mock.add_window(window(WindowId(0x100), "xterm").mapped());
+        let ctx = Context::builder().filter(Filter::parse("clients: pin").unwrap())
+            .options(Options::CLIENTS_ONLY).build(mock.clone());
+        ctx.refresh_windows().unwrap();
+        let user = ctx.state().rules().into_iter().find(|r| r.origin == RuleOrigin::Inline).unwrap();
+
+        // the option gets its own rule instead of taking over the parsed one
+        mock.create_window(window(WindowId(0x200), "xclock").at(200, 0).mapped());
+        let mut ev = ctx.backend().poll_event().unwrap();
+        ctx.apply_event(&mut ev);
+        assert!(ctx.update_clients());
+        let sources: Vec<String> = ctx.state().rules().into_iter().map(|r| r.source).collect();
+        assert_eq!(sources, vec!["clients: pin", "clients"]);
+
+        // only the rule of the option goes, the one parsed keeps its id
+        ctx.clear_option(Options::CLIENTS_ONLY);
+        let rules = ctx.state().rules();
+        assert_eq!(rules.len(), 1);
+        assert_eq!((rules[0].id, rules[0].action), (user.id, Action::Pin));
+    }
+
+    #[test]
+    fn test_options() {
+        let mut opts = Options::MAPPED_ONLY | Options::SHOW_DIFF;
+        assert!(opts.contains(Options::MAPPED_ONLY));
+        assert!(!opts.contains(Options::MAPPED_ONLY | Options::COLORFUL));
+
+        opts.set(Options::COLORFUL);
+        opts.clear(Options::MAPPED_ONLY);
+        opts.toggle(Options::SHOW_DIFF);
+        assert_eq!(opts, Options::COLORFUL);
+        assert_eq!(Options::from_bits_truncate(u32::MAX), Options::all());
+        assert!(vec![Options::COLORFUL].into_iter().collect::<Options>().contains(Options::COLORFUL));
+    }
+
+    #[test]
+    fn test_xwayland_tag() {
+        let mut w = window(WindowId(0x100), "xterm").mapped();
+        assert!(!win2str(&w, None).contains("XWL"));
+        w.xwayland = true;
+        assert!(win2str(&w, None).ends_with(" XWL"));
+    }
+
+    #[test]
+    fn test_remote_tag() {
+        let mut w = window(WindowId(0x100), "xterm").mapped();
+        w.client_machine = Some(local_host().to_string());
+        assert_eq!(w.remote_host(), None);
+        w.client_machine = Some("localhost".to_string());
+        assert_eq!(w.remote_host(), None);
+
+        w.client_machine = Some("build-farm-42.invalid".to_string());
+        assert_eq!(w.remote_host(), Some("build-farm-42.invalid"));
+        assert!(win2str(&w, None).ends_with(" Viewable @build-farm-42.invalid"), "{}", win2str(&w, None));
+        assert!(win2str(&w, Some(&Theme::dark())).contains("@build-farm-42.invalid"));
+    }
+
+    #[test]
+    fn test_wmctrl_line() {
+        let mut w = window(WindowId(0x3c00003), "Terminal").at(10, 20).mapped();
+        w.desktop = Some(1);
+        w.client_machine = Some("host".to_string());
+        assert_eq!(wmctrl_line(&w, (12, 42), 6),
+                   "0x03c00003  1 12   42   100  100    host Terminal");
+
+        w.desktop = Some(0xFFFFFFFF);
+        w.client_machine = None;
+        w.name = "".to_string();
+        assert_eq!(wmctrl_line(&w, (0, 0), 3),
+                   "0x03c00003 -1 0    0    100  100  N/A N/A");
+    }
+
+    #[test]
+    fn test_monitor_metrics() {
+        let mock = MockBackend::new(1920, 1080);
+        mock.add_window(window(WindowId(0x100), "first").mapped());
+
+        let ctx = Context::new(mock.clone(), Filter::new());
+        mock.create_window(window(WindowId(0x200), "second").mapped())
+            .unmap_window(WindowId(0x200));
+        monitor(&ctx).unwrap();
+
+        let text = ctx.metrics().render();
+        assert!(text.contains("wminspect_events_total{type=\"create\"} 1\n"));
+        assert!(text.contains("wminspect_events_total{type=\"unmap\"} 1\n"));
+        assert!(text.contains("wminspect_windows{state=\"viewable\"} 1\n"));
+        assert!(text.contains("wminspect_windows{state=\"unmapped\"} 1\n"));
+        assert!(text.contains("wminspect_refreshes_total 1\n"));
+    }
+
+    #[test]
+    fn test_monitor_events() {
+        let mock = MockBackend::new(1920, 1080);
+        mock.add_window(window(WindowId(0x100), "first").mapped())
+            .add_window(window(WindowId(0x200), "second").mapped());
+
+        let ctx = Context::new(mock.clone(), Filter::new());
+        mock.create_window(window(WindowId(0x300), "third").mapped())
+            .configure_window(WindowId(0x300), Geometry { x: 5, y: 5, width: 50, height: 50 }, WINDOW_NONE)
+            .destroy_window(WindowId(0x100))
+            .rename_window(WindowId(0x200), "renamed");
+
+        monitor(&ctx).unwrap();
+
+        assert_eq!(filtered(&ctx), vec![WindowId(0x300), WindowId(0x200)]);
+        let layout = ctx.state.read_layout();
+        assert_eq!(layout.stack_view.to_vec(), vec![WindowId(0x300), WindowId(0x200)]);
+        assert_eq!(layout.windows[&WindowId(0x300)].geom.width, 50);
+        assert_eq!(layout.windows[&WindowId(0x200)].name, "renamed");
+    }
+
+    #[test]
+    fn test_spawn_monitor_stop() {
+        let mock = MockBackend::new(1920, 1080);
+        mock.add_window(window(WindowId(0x100), "first").mapped()).set_live(true);
+
+        let ctx = Arc::new(Context::new(mock.clone(), Filter::new()));
+        let handle = spawn_monitor(ctx.clone()).unwrap();
+
+        // the queue is drained, yet the monitor keeps waiting in live mode
+        mock.create_window(window(WindowId(0x200), "second").mapped());
+        let start = time::Instant::now();
+        while ctx.window(WindowId(0x200)).is_none() {
+            assert!(start.elapsed() < time::Duration::from_secs(5), "event not handled");
+            thread::sleep(time::Duration::from_millis(10));
+        }
+
+        handle.stop();
+        handle.join().unwrap();
+        assert_eq!(filtered(&ctx), vec![WindowId(0x100), WindowId(0x200)]);
+    }
+
+    #[test]
+    fn test_reload_sheets() {
+        let path = ::std::env::temp_dir().join(format!("wminspect-monitor-{}.rule", ::std::process::id()));
+        ::std::fs::write(&path, "name = first").unwrap();
+        let mut filter = Filter::new();
+        filter.load_sheet(&path).unwrap();
+
+        let mock = MockBackend::new(1920, 1080);
+        mock.add_window(window(WindowId(0x100), "first").mapped())
+            .add_window(window(WindowId(0x200), "second").mapped()).set_live(true);
+        let buf = SharedBuf::default();
+        let ctx = Arc::new(Context::builder().filter(filter).writer(buf.clone()).build(mock));
+        let handle = spawn_monitor(ctx.clone()).unwrap();
+
+        let start = time::Instant::now();
+        while filtered(&ctx) != vec![WindowId(0x100)] {
+            assert!(start.elapsed() < time::Duration::from_secs(5), "not listed");
+            thread::sleep(time::Duration::from_millis(10));
+        }
+        ::std::fs::write(&path, "name = second").unwrap();
+        while filtered(&ctx) != vec![WindowId(0x200)] {
+            assert!(start.elapsed() < time::Duration::from_secs(5), "sheet not reloaded");
+            thread::sleep(time::Duration::from_millis(10));
+        }
+
+        handle.stop();
+        handle.join().unwrap();
+        ::std::fs::remove_file(&path).unwrap();
+        assert!(buf.text().contains(&format!("reloaded {}", path.display())), "{}", buf.text());
+    }
+
+    #[test]
+    fn test_pick() {
+        let mock = MockBackend::new(1920, 1080);
+        let mut w = window(WindowId(0x100), "xterm").mapped();
+        w.class = Some("XTerm".to_string());
+        mock.add_window(w);
+        let ctx = Context::new(mock.clone(), Filter::new());
+        assert!(ctx.pick().is_none());
+
+        mock.set_picked(Some(WindowId(0x100)));
+        let picked = ctx.pick().unwrap();
+        assert_eq!(suggest_rule(&picked), "class = xterm");
+        assert!(Filter::parse(suggest_rule(&picked)).unwrap().apply_to(&picked));
+    }
+
+    #[test]
+    fn test_configured_old_geometry() {
+        let mock = MockBackend::new(1920, 1080);
+        mock.add_window(window(WindowId(0x100), "first").mapped());
+        let ctx = Context::new(mock.clone(), Filter::new());
+        ctx.refresh_windows().unwrap();
+
+        let new = Geometry { x: 5, y: 5, width: 50, height: 50 };
+        mock.configure_window(WindowId(0x100), new, WINDOW_NONE);
+        let mut ev = mock.poll_event().unwrap();
+        assert!(ctx.apply_event(&mut ev));
+
+        match ev {
+            WindowEvent::Configured { old, new: geom, .. } => {
+                assert_eq!(old, Some(Geometry { x: 0, y: 0, width: 100, height: 100 }));
+                assert_eq!(geom, new);
+            },
+            _ => panic!("unexpected {:?}", ev),
+        }
+    }
+
+    #[test]
+    fn test_incremental_filter() {
+        let mock = MockBackend::new(1920, 1080);
+        mock.add_window(window(WindowId(0x100), "first").mapped())
+            .add_window(window(WindowId(0x200), "second").mapped());
+        let ctx = Context::new(mock.clone(), Filter::parse("attrs.map_state = Viewable").unwrap());
+        ctx.refresh_windows().unwrap();
+        let apply = || {
+            let mut ev = mock.poll_event().unwrap();
+            ctx.apply_event(&mut ev)
+        };
+
+        // map state takes windows out of and into the filtered view, with
+        // no refresh in between
+        mock.unmap_window(WindowId(0x100)).unmap_window(WindowId(0x200));
+        assert!(apply() && apply());
+        assert!(filtered(&ctx).is_empty());
+        mock.unmap_window(WindowId(0x200));
+        assert!(!apply());
+
+        mock.map_window(WindowId(0x200)).map_window(WindowId(0x100));
+        assert!(apply() && apply());
+        assert_eq!(filtered(&ctx), vec![WindowId(0x100), WindowId(0x200)]);
+    }
+
+    #[test]
+    fn test_no_names() {
+        let mock = MockBackend::new(1920, 1080);
+        let mut hidden = window(WindowId(0x200), "second").mapped();
+        hidden.attrs.map_state = MapState::Unmapped;
+        mock.add_window(window(WindowId(0x100), "first").mapped()).add_window(hidden);
+        let ctx = Context::builder().options(Options::NO_NAMES | Options::MAPPED_ONLY).build(mock.clone());
+        ctx.refresh_windows().unwrap();
+
+        // only the window passing the filter has its name
+        let names = |ctx: &Context| ctx.state.all_windows().into_iter().map(|w| w.name).collect::<Vec<_>>();
+        assert_eq!(names(&ctx), vec!["first", ""]);
+        mock.map_window(WindowId(0x200));
+        let mut ev = mock.poll_event().unwrap();
+        assert!(ctx.apply_event(&mut ev));
+        assert_eq!(names(&ctx), vec!["first", "second"]);
+
+        // rules on names need them all
+        let ctx = Context::builder().options(Options::NO_NAMES)
+            .filter(Filter::parse("name = first").unwrap()).build(mock.clone());
+        ctx.refresh_windows().unwrap();
+        assert_eq!(names(&ctx), vec!["first", "second"]);
+    }
+
+    #[test]
+    fn test_desktop() {
+        let mock = MockBackend::new(1920, 1080);
+        let on = |id, desktop| {
+            let mut w = window(WindowId(id), "w").mapped();
+            w.desktop = Some(desktop);
+            w
+        };
+        mock.add_window(on(0x100, 0)).add_window(on(0x200, 1)).add_window(on(0x300, 0xFFFFFFFF));
+        let ids = |ctx: &Context| ctx.windows().into_iter().map(|w| w.id.0).collect::<Vec<_>>();
+
+        let ctx = Context::builder().desktop("1".parse().unwrap()).build(mock.clone());
+        ctx.refresh_windows().unwrap();
+        assert_eq!(ids(&ctx), vec![0x200, 0x300]);
+
+        // the current desktop is followed as the window manager switches
+        mock.set_current_desktop(0);
+        mock.poll_event();
+        let ctx = Context::builder().desktop(Desktop::Current).build(mock.clone());
+        ctx.refresh_windows().unwrap();
+        assert_eq!(ids(&ctx), vec![0x100, 0x300]);
+        mock.set_current_desktop(1);
+        let mut ev = mock.poll_event().unwrap();
+        assert!(ctx.apply_event(&mut ev));
+        assert_eq!(ids(&ctx), vec![0x200, 0x300]);
+
+        assert!("next".parse::<Desktop>().is_err());
+    }
+
+    #[test]
+    fn test_anonymize() {
+        let mock = MockBackend::new(1920, 1080);
+        let mut w = window(WindowId(0x100), "secret.odt - LibreOffice").mapped();
+        w.class = Some("libreoffice".to_string());
+        w.client_machine = Some("laptop".to_string());
+        w.pid = Some(4242);
+        mock.add_window(w.clone());
+
+        let anon = w.anonymized();
+        assert_eq!(anon.name, anonymous("name", &w.name));
+        assert!(anon.name.starts_with("name-") && anon.name.len() == 13, "{}", anon.name);
+        assert_eq!(anon.class.as_deref(), Some(anonymous("class", "libreoffice").as_str()));
+        assert_eq!((anon.client_machine, anon.pid), (None, None));
+        assert_eq!(w.anonymized().name, anonymous("name", &w.name));
+
+        let buf = SharedBuf::default();
+        let ctx = Context::builder().options(Options::ANONYMIZE).output(OutputFormat::Json).writer(buf.clone()).build(mock);
+        ctx.refresh_windows().unwrap();
+        ctx.dump_windows(None);
+        let dump = buf.text();
+        assert!(!dump.contains("secret") && !dump.contains("laptop") && !dump.contains("4242"), "{}", dump);
+        assert!(ctx.snapshot().windows[0].name.starts_with("name-"));
+    }
+
+    #[test]
+    fn test_timings() {
+        let mock = MockBackend::new(1920, 1080);
+        mock.add_window(window(WindowId(0x100), "xterm").mapped());
+        let ctx = Context::builder().writer(SharedBuf::default()).build(mock);
+        ctx.refresh_windows().unwrap();
+        ctx.dump_windows(None);
+        let phases: Vec<_> = ctx.timings().phases().into_iter().map(|(p, _)| p).collect();
+        assert_eq!(phases, vec!["filter", "query_tree", "properties", "output"]);
+
+        ctx.timings().reset();
+        ctx.dump_wmctrl();
+        assert!(ctx.timings().to_string().starts_with("query_tree "), "{}", ctx.timings());
+    }
+
+    #[test]
+    fn test_on_output() {
+        let mock = MockBackend::new(3840, 1080);
+        mock.add_output("eDP-1", Geometry { x: 0, y: 0, width: 1920, height: 1080 })
+            .add_output("HDMI-1", Geometry { x: 1920, y: 0, width: 1920, height: 1080 });
+        mock.add_window(window(WindowId(0x100), "left").mapped())
+            .add_window(window(WindowId(0x200), "right").at(2000, 0).mapped())
+            .add_window(window(WindowId(0x300), "across").at(1900, 0).mapped());
+        let ids = |ctx: &Context| ctx.windows().into_iter().map(|w| w.id.0).collect::<Vec<_>>();
+
+        let ctx = Context::builder().on_output("HDMI-1").build(mock.clone());
+        ctx.refresh_windows().unwrap();
+        assert_eq!(ids(&ctx), vec![0x200, 0x300]);
+
+        let ctx = Context::builder().on_output("DP-3").build(mock.clone());
+        ctx.refresh_windows().unwrap();
+        assert!(ids(&ctx).is_empty());
+    }
+
+    #[test]
+    fn test_cached_properties() {
+        let mock = MockBackend::new(1920, 1080);
+        mock.add_window(window(WindowId(0x100), "first").mapped());
+        let ctx = Context::new(mock.clone(), Filter::parse("attrs.map_state = Viewable").unwrap());
+        ctx.refresh_windows().unwrap();
+        let apply = || {
+            let mut ev = mock.poll_event().unwrap();
+            ctx.apply_event(&mut ev)
+        };
+
+        // still in the filtered view, nothing to dump again
+        mock.update_window(WindowId(0x100), PropertyKind::Pid, |w| w.pid = Some(42));
+        assert!(!apply());
+        mock.update_window(WindowId(0x100), PropertyKind::State, |w| {
+            w.pid = Some(7);
+            w.state = vec!["_NET_WM_STATE_HIDDEN".to_string()];
+        });
+        assert!(!apply());
+        let w = ctx.window(WindowId(0x100)).unwrap();
+        assert_eq!(w.pid, Some(42), "only the changed property is refreshed");
+        assert_eq!(w.state, vec!["_NET_WM_STATE_HIDDEN".to_string()]);
+    }
+
+    #[test]
+    fn test_flash() {
+        let mock = MockBackend::new(1920, 1080);
+        mock.add_window(window(WindowId(0x100), "first").at(10, 20).mapped());
+        let ctx = Context::new(mock.clone(), Filter::new());
+
+        ctx.flash(WindowId(0x100), time::Duration::from_millis(1)).unwrap();
+        assert_eq!(mock.highlights(), (vec![Geometry { x: 10, y: 20, width: 100, height: 100 }], 0));
+        assert!(ctx.flash(WindowId(0x200), time::Duration::from_millis(1)).is_err());
+    }
+
+    #[test]
+    fn test_control() {
+        let mock = MockBackend::new(1920, 1080);
+        mock.add_window(window(WindowId(0x100), "first").at(10, 20).mapped());
+        let ctx = Context::new(mock.clone(), Filter::new());
+
+        ctx.control(WindowId(0x100), WindowAction::Close).unwrap();
+        assert!(ctx.control(WindowId(0x200), WindowAction::Raise).is_err());
+        assert_eq!(mock.controls(), vec![(WindowId(0x100), WindowAction::Close)]);
+    }
+
+    #[test]
+    fn test_property_events() {
+        let mock = MockBackend::new(1920, 1080);
+        mock.add_window(window(WindowId(0x100), "first").at(10, 20).mapped());
+        let ctx = Context::new(mock.clone(), Filter::new());
+        ctx.refresh_windows().unwrap();
+
+        mock.set_property(WindowId(0x100), "WM_CLASS", "STRING", "\"first\"");
+        let mut ev = ctx.backend().poll_event().unwrap();
+        assert_eq!(ev, WindowEvent::PropertyChanged { window: WindowId(0x100), kind: PropertyKind::Other });
+        assert!(!ctx.apply_event(&mut ev));
+        let props = ctx.backend().properties(WindowId(0x100));
+        assert_eq!(props.iter().map(|p| p.to_string()).collect::<Vec<_>>(), vec!["WM_CLASS(STRING) = \"first\""]);
+    }
+}
@@ -0,0 +1,147 @@
+extern crate serde_json;
+extern crate tungstenite;
+
+use std::io;
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use self::tungstenite::Message;
+use self::tungstenite::error::Error as WsError;
+
+use super::wm::*;
+use super::event::*;
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum WsMessage<'a> {
+    Event { event: &'a WindowEvent },
+    Dump { windows: &'a [Window] },
+}
+
+/// Publishes monitor events as JSON over websocket.
+///
+/// Every connected client receives `{"type": "event", ...}` messages as the
+/// monitor handles events, and may send a `dump` text message anytime to get
+/// the current filtered windows as `{"type": "dump", "windows": [...]}`.
+pub struct WsPublisher {
+    clients: Mutex<Vec<mpsc::Sender<String>>>,
+    last_dump: Mutex<String>,
+}
+
+impl WsPublisher {
+    /// start accepting websocket clients at `addr` from a background thread
+    pub fn serve<A: ToSocketAddrs>(addr: A) -> io::Result<(Arc<WsPublisher>, SocketAddr)> {
+        let listener = TcpListener::bind(addr)?;
+        let local = listener.local_addr()?;
+
+        let publisher = Arc::new(WsPublisher {
+            clients: Mutex::new(Vec::new()),
+            last_dump: Mutex::new(dump_message(&[])),
+        });
+
+        let p = publisher.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let p = p.clone();
+                        thread::spawn(move || {
+                            if let Err(e) = p.handle_client(stream) {
+                                wm_debug!("ws client: {}", e);
+                            }
+                        });
+                    },
+                    Err(e) => wm_debug!("ws accept: {}", e),
+                }
+            }
+        });
+
+        Ok((publisher, local))
+    }
+
+    fn handle_client(&self, stream: TcpStream) -> Result<(), Box<WsError>> {
+        let mut ws = tungstenite::accept(stream).map_err(|e| match e {
+            tungstenite::HandshakeError::Failure(e) => e,
+            tungstenite::HandshakeError::Interrupted(_) => WsError::ConnectionClosed,
+        })?;
+        // poll for requests in between of pushing events
+        ws.get_mut().set_read_timeout(Some(Duration::from_millis(50))).map_err(WsError::Io)?;
+
+        let (tx, rx) = mpsc::channel();
+        self.clients.lock().unwrap().push(tx);
+
+        loop {
+            match ws.read() {
+                Ok(Message::Text(ref req)) if req.trim() == "dump" => {
+                    let dump = self.last_dump.lock().unwrap().clone();
+                    ws.send(Message::Text(dump))?;
+                },
+                Ok(Message::Close(_)) => return Ok(()),
+                Ok(_) => {},
+                Err(WsError::Io(ref e)) if e.kind() == io::ErrorKind::WouldBlock ||
+                    e.kind() == io::ErrorKind::TimedOut => {},
+                Err(e) => return Err(Box::new(e)),
+            }
+
+            loop {
+                match rx.try_recv() {
+                    Ok(msg) => ws.send(Message::Text(msg))?,
+                    Err(mpsc::TryRecvError::Empty) => break,
+                    Err(mpsc::TryRecvError::Disconnected) => return Ok(()),
+                }
+            }
+        }
+    }
+}
+
+fn dump_message(windows: &[Window]) -> String {
+    serde_json::to_string(&WsMessage::Dump { windows }).unwrap_or_default()
+}
+
+impl EventSink for WsPublisher {
+    fn publish(&self, ev: Option<&WindowEvent>, windows: &[Window]) {
+        *self.last_dump.lock().unwrap() = dump_message(windows);
+
+        if let Some(event) = ev {
+            let msg = match serde_json::to_string(&WsMessage::Event { event }) {
+                Ok(msg) => msg,
+                Err(_) => return,
+            };
+            // drop clients gone away
+            self.clients.lock().unwrap().retain(|tx| tx.send(msg.clone()).is_ok());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpStream;
+    use super::super::backend::WindowId;
+
+    #[test]
+    fn test_publish() {
+        let (publisher, addr) = WsPublisher::serve("127.0.0.1:0").unwrap();
+        let (mut ws, _) = tungstenite::client(format!("ws://{}/", addr),
+                                              TcpStream::connect(addr).unwrap()).unwrap();
+
+        let mut w = Window::new(WindowId(0x100));
+        w.name = "dde-osd".to_string();
+        publisher.publish(None, &[w]);
+
+        ws.send(Message::Text("dump".to_string())).unwrap();
+        let dump = ws.read().unwrap().into_text().unwrap();
+        let dump: serde_json::Value = serde_json::from_str(&dump).unwrap();
+        assert_eq!(dump["type"], "dump");
+        assert_eq!(dump["windows"][0]["name"], "dde-osd");
+
+        publisher.publish(Some(&WindowEvent::Mapped { window: WindowId(0x100) }), &[]);
+        let ev = ws.read().unwrap().into_text().unwrap();
+        let ev: serde_json::Value = serde_json::from_str(&ev).unwrap();
+        assert_eq!(ev["type"], "event");
+        assert_eq!(ev["event"]["type"], "map");
+        assert_eq!(ev["event"]["window"], 0x100);
+    }
+}